@@ -0,0 +1,392 @@
+//! Reader for the headers of AVI (RIFF) files
+//!
+//! Decodes the `hdrl` list with the main header and the per stream `strl` lists, the OpenDML
+//! extended header, and the `INFO` metadata. The first video frame is also inspected to find the
+//! encoder's user data, which tells us if it uses DivX style packed bitstreams.
+
+use crate::imports::*;
+use crate::mp4::FourCc;
+
+/// Maximum size of header list to read into memory
+const MAX_LIST_SIZE: u32 = 16 * 1024 * 1024;
+
+/// Maximum amount of the first video frame to search for user data
+const MAX_FRAME_SCAN: u32 = 64 * 1024;
+
+pub const WAVE_FORMAT_PCM: u16 = 0x0001;
+pub const WAVE_FORMAT_MPEG: u16 = 0x0050;
+pub const WAVE_FORMAT_MPEGLAYER3: u16 = 0x0055;
+
+#[ derive (Debug, Default, Serialize) ]
+pub struct AviInfo {
+	pub micro_sec_per_frame: u32,
+	pub max_bytes_per_sec: u32,
+	pub flags: u32,
+	pub total_frames: u32,
+	pub width: u32,
+	pub height: u32,
+	pub odml_total_frames: Option <u32>,
+	pub streams: Vec <AviStream>,
+	pub info: BTreeMap <String, String>,
+	pub user_data: Vec <String>,
+}
+
+impl AviInfo {
+
+	/// Read the headers from an AVI file
+	pub fn read (mut src: impl Read + Seek) -> anyhow::Result <Self> {
+		let file_size = src.seek (SeekFrom::End (0)) ?;
+		src.seek (SeekFrom::Start (0)) ?;
+		let mut header = [0; 12];
+		src.read_exact (& mut header) ?;
+		any_ensure! (& header [0 .. 4] == b"RIFF" && & header [8 .. 12] == b"AVI ", "Not an AVI file");
+		let riff_end = (le_u32 (& header, 4) as u64 + 8).min (file_size);
+		let mut info = Self::default ();
+		let mut have_hdrl = false;
+		let mut pos = 12;
+		while pos + 8 <= riff_end {
+			src.seek (SeekFrom::Start (pos)) ?;
+			let mut chunk_header = [0; 12];
+			src.read_exact (& mut chunk_header [0 .. 8]) ?;
+			let chunk_id = FourCc::from_slice (& chunk_header [0 .. 4]);
+			let chunk_len = le_u32 (& chunk_header, 4);
+			if & chunk_id.0 == b"LIST" && 4 <= chunk_len {
+				src.read_exact (& mut chunk_header [8 .. 12]) ?;
+				let list_type = FourCc::from_slice (& chunk_header [8 .. 12]);
+				match & list_type.0 {
+					b"hdrl" => {
+						let data = read_list_data (& mut src, list_type, chunk_len - 4) ?;
+						info.parse_hdrl (& data).context ("Error reading hdrl list") ?;
+						have_hdrl = true;
+					},
+					b"INFO" => {
+						let data = read_list_data (& mut src, list_type, chunk_len - 4) ?;
+						info.parse_info (& data);
+					},
+					b"movi" => {
+						info.scan_movi (& mut src, chunk_len - 4).context ("Error reading movi list") ?;
+					},
+					_ => (),
+				}
+			}
+			pos += 8 + chunk_len as u64 + (chunk_len & 1) as u64;
+		}
+		any_ensure! (have_hdrl, "No hdrl list found");
+		Ok (info)
+	}
+
+	pub fn video_stream (& self) -> Option <& AviStream> {
+		self.streams.iter ().find (|stream| stream.video.is_some ())
+	}
+
+	pub fn audio_streams (& self) -> impl Iterator <Item = & AviStream> {
+		self.streams.iter ().filter (|stream| stream.audio.is_some ())
+	}
+
+	pub fn frame_rate (& self) -> Option <f64> {
+		if let Some (stream) = self.video_stream () && stream.scale != 0 && stream.rate != 0 {
+			return Some (stream.rate as f64 / stream.scale as f64);
+		}
+		if self.micro_sec_per_frame == 0 { return None }
+		Some (1_000_000.0 / self.micro_sec_per_frame as f64)
+	}
+
+	/// Total number of video frames, the main header only counts the first RIFF chunk in
+	/// OpenDML files so the extended header takes precedence
+	pub fn num_frames (& self) -> u64 {
+		let stream_frames = self.video_stream ().map (|stream| stream.length).unwrap_or (0);
+		[ self.total_frames, self.odml_total_frames.unwrap_or (0), stream_frames ].into_iter ()
+			.max ()
+			.unwrap_or (0) as u64
+	}
+
+	pub fn duration_secs (& self) -> Option <f64> {
+		let frame_rate = self.frame_rate () ?;
+		let num_frames = self.num_frames ();
+		if num_frames == 0 { return None }
+		Some (num_frames as f64 / frame_rate)
+	}
+
+	/// Returns the user data string if the video uses a packed bitstream, which stores b-frames
+	/// together with the previous frame and needs `mpeg4_unpack_bframes` before remuxing
+	pub fn packed_bitstream (& self) -> Option <& str> {
+		self.user_data.iter ()
+			.find (|user_data| is_packed_user_data (user_data))
+			.map (String::as_str)
+	}
+
+	/// Returns true if there are any variable bitrate mp3 audio streams, these have broken
+	/// timestamps when demuxed from avi
+	pub fn has_vbr_mp3 (& self) -> bool {
+		self.audio_streams ().any (AviStream::is_vbr_mp3)
+	}
+
+	fn parse_hdrl (& mut self, data: & [u8]) -> anyhow::Result <()> {
+		for (chunk_id, chunk_data) in chunks (data) {
+			let chunk_data = chunk_data ?;
+			match & chunk_id.0 {
+				b"avih" => {
+					any_ensure! (40 <= chunk_data.len (), "avih chunk too short");
+					self.micro_sec_per_frame = le_u32 (chunk_data, 0);
+					self.max_bytes_per_sec = le_u32 (chunk_data, 4);
+					self.flags = le_u32 (chunk_data, 12);
+					self.total_frames = le_u32 (chunk_data, 16);
+					self.width = le_u32 (chunk_data, 32);
+					self.height = le_u32 (chunk_data, 36);
+				},
+				b"LIST" if chunk_data.get (0 .. 4) == Some (b"strl") => {
+					let stream = AviStream::parse (& chunk_data [4 .. ])
+						.with_context (|| any_err! ("Error reading stream {}", self.streams.len ())) ?;
+					self.streams.push (stream);
+				},
+				b"LIST" if chunk_data.get (0 .. 4) == Some (b"odml") => {
+					for (odml_id, odml_data) in chunks (& chunk_data [4 .. ]) {
+						let odml_data = odml_data ?;
+						if & odml_id.0 == b"dmlh" && 4 <= odml_data.len () {
+							self.odml_total_frames = Some (le_u32 (odml_data, 0));
+						}
+					}
+				},
+				_ => (),
+			}
+		}
+		Ok (())
+	}
+
+	fn parse_info (& mut self, data: & [u8]) {
+		for (chunk_id, chunk_data) in chunks (data) {
+			let Ok (chunk_data) = chunk_data else { break };
+			let value = String::from_utf8_lossy (chunk_data).trim_end_matches ('\0').trim ().to_owned ();
+			if value.is_empty () { continue }
+			self.info.insert (chunk_id.to_string (), value);
+		}
+	}
+
+	/// Find the first video frame and collect any mpeg-4 user data strings from it
+	fn scan_movi (& mut self, src: & mut (impl Read + Seek), len: u32) -> anyhow::Result <()> {
+		let Some (video_idx) = self.streams.iter ().position (|stream| stream.video.is_some ())
+			else { return Ok (()) };
+		let start = src.stream_position () ?;
+		// truncated files are common, so stop at the end of the file, which counts as no user data
+		let file_len = src.seek (SeekFrom::End (0)) ?;
+		let end = (start + len as u64).min (file_len);
+		let mut pos = start;
+		while pos + 8 <= end {
+			src.seek (SeekFrom::Start (pos)) ?;
+			let mut chunk_header = [0; 8];
+			src.read_exact (& mut chunk_header) ?;
+			let chunk_len = le_u32 (& chunk_header, 4);
+			if & chunk_header [0 .. 4] == b"LIST" {
+				// step inside rec lists
+				pos += 12;
+				continue;
+			}
+			let is_video =
+				& chunk_header [0 .. 2] == format! ("{video_idx:02}").as_bytes ()
+					&& matches! (& chunk_header [2 .. 4], b"dc" | b"db");
+			if is_video && 0 < chunk_len {
+				let frame_len = (chunk_len.min (MAX_FRAME_SCAN) as u64).min (end - pos - 8);
+				let mut frame = vec! [0; frame_len as usize];
+				src.read_exact (& mut frame) ?;
+				self.user_data = mpeg4_user_data (& frame);
+				return Ok (());
+			}
+			pos += 8 + chunk_len as u64 + (chunk_len & 1) as u64;
+		}
+		Ok (())
+	}
+
+}
+
+#[ derive (Debug, Default, Serialize) ]
+pub struct AviStream {
+	pub stream_type: Option <FourCc>,
+	pub handler: Option <FourCc>,
+	pub flags: u32,
+	pub scale: u32,
+	pub rate: u32,
+	pub start: u32,
+	pub length: u32,
+	pub sample_size: u32,
+	pub name: Option <String>,
+	pub video: Option <AviVideoFormat>,
+	pub audio: Option <AviAudioFormat>,
+}
+
+#[ derive (Debug, Serialize) ]
+pub struct AviVideoFormat {
+	pub width: i32,
+	pub height: i32,
+	pub bit_count: u16,
+	pub compression: FourCc,
+}
+
+#[ derive (Debug, Serialize) ]
+pub struct AviAudioFormat {
+	pub format_tag: u16,
+	pub channels: u16,
+	pub samples_per_sec: u32,
+	pub avg_bytes_per_sec: u32,
+	pub block_align: u16,
+	pub bits_per_sample: u16,
+}
+
+impl AviStream {
+
+	fn parse (data: & [u8]) -> anyhow::Result <Self> {
+		let mut stream = Self::default ();
+		for (chunk_id, chunk_data) in chunks (data) {
+			let chunk_data = chunk_data ?;
+			match & chunk_id.0 {
+				b"strh" => {
+					any_ensure! (48 <= chunk_data.len (), "strh chunk too short");
+					stream.stream_type = Some (FourCc::from_slice (& chunk_data [0 .. 4]));
+					stream.handler = Some (FourCc::from_slice (& chunk_data [4 .. 8]));
+					stream.flags = le_u32 (chunk_data, 8);
+					stream.scale = le_u32 (chunk_data, 20);
+					stream.rate = le_u32 (chunk_data, 24);
+					stream.start = le_u32 (chunk_data, 28);
+					stream.length = le_u32 (chunk_data, 32);
+					stream.sample_size = le_u32 (chunk_data, 44);
+				},
+				b"strf" => match stream.stream_type.as_ref ().map (|stream_type| & stream_type.0) {
+					Some (b"vids") => {
+						any_ensure! (40 <= chunk_data.len (), "strf chunk too short for video");
+						stream.video = Some (AviVideoFormat {
+							width: le_u32 (chunk_data, 4) as i32,
+							height: le_u32 (chunk_data, 8) as i32,
+							bit_count: le_u16 (chunk_data, 14),
+							compression: FourCc::from_slice (& chunk_data [16 .. 20]),
+						});
+					},
+					Some (b"auds") => {
+						any_ensure! (14 <= chunk_data.len (), "strf chunk too short for audio");
+						stream.audio = Some (AviAudioFormat {
+							format_tag: le_u16 (chunk_data, 0),
+							channels: le_u16 (chunk_data, 2),
+							samples_per_sec: le_u32 (chunk_data, 4),
+							avg_bytes_per_sec: le_u32 (chunk_data, 8),
+							block_align: le_u16 (chunk_data, 12),
+							bits_per_sample: if 16 <= chunk_data.len () { le_u16 (chunk_data, 14) } else { 0 },
+						});
+					},
+					_ => (),
+				},
+				b"strn" => {
+					let name = String::from_utf8_lossy (chunk_data).trim_end_matches ('\0').to_owned ();
+					if ! name.is_empty () { stream.name = Some (name); }
+				},
+				_ => (),
+			}
+		}
+		Ok (stream)
+	}
+
+	/// Variable bitrate mp3 is stored with a sample size of zero, or with the block align set to
+	/// the frame size rather than one
+	pub fn is_vbr_mp3 (& self) -> bool {
+		let Some (audio) = self.audio.as_ref () else { return false };
+		audio.format_tag == WAVE_FORMAT_MPEGLAYER3
+			&& (self.sample_size == 0 || 1 < audio.block_align)
+	}
+
+	/// Short codec name, using the same names as are shown for Matroska tracks
+	pub fn codec_name (& self) -> String {
+		if let Some (video) = self.video.as_ref () {
+			let compression = video.compression.to_string ().to_ascii_uppercase ();
+			return match compression.as_str () {
+				"3IV2" | "DIVX" | "DX50" | "FMP4" | "MP4V" | "XVID" => "mpeg4/asp",
+				"AVC1" | "H264" | "X264" => "mpeg4/avc",
+				"DIV3" | "DIV4" | "MP43" => "msmpeg4v3",
+				"HEVC" | "HVC1" | "H265" => "mpeg4/hevc",
+				"MJPG" => "mjpeg",
+				"MPG1" => "mpeg1",
+				"MPG2" => "mpeg2",
+				"\0\0\0\0" => "raw",
+				"WMV3" => "wmv3",
+				_ => return compression.trim_end ().to_ascii_lowercase (),
+			}.to_owned ();
+		}
+		if let Some (audio) = self.audio.as_ref () {
+			return match audio.format_tag {
+				WAVE_FORMAT_PCM => "pcm",
+				WAVE_FORMAT_MPEG => "mp2",
+				WAVE_FORMAT_MPEGLAYER3 => "mp3",
+				0x00ff | 0x1600 | 0x1610 | 0x706d => "aac",
+				0x0161 | 0x0162 => "wma",
+				0x2000 => "ac3",
+				0x2001 => "dts",
+				0x566f => "vorbis",
+				0xfffe => "pcm",
+				format_tag => return format! ("0x{format_tag:04x}"),
+			}.to_owned ();
+		}
+		"unknown".to_owned ()
+	}
+
+}
+
+/// Extract user data strings from an mpeg-4 part 2 frame
+fn mpeg4_user_data (frame: & [u8]) -> Vec <String> {
+	let mut result = Vec::new ();
+	let mut pos = 0;
+	while let Some (offset) = frame [pos .. ].windows (4).position (|bytes| bytes == [ 0, 0, 1, 0xb2 ]) {
+		let start = pos + offset + 4;
+		let len = frame [start .. ].windows (3).position (|bytes| bytes == [ 0, 0, 1 ])
+			.unwrap_or (frame.len () - start);
+		let text: String =
+			frame [start .. start + len].iter ()
+				.take_while (|& & byte| byte != 0)
+				.map (|& byte| byte as char)
+				.collect ();
+		if ! text.is_empty () && text.chars ().all (|ch| ch.is_ascii_graphic () || ch == ' ') {
+			result.push (text);
+		}
+		pos = start + len;
+	}
+	result
+}
+
+/// Check for a DivX user data string such as "DivX503b1393p", the trailing "p" indicates a packed
+/// bitstream, this is the same check that ffmpeg uses
+fn is_packed_user_data (user_data: & str) -> bool {
+	let Some (rest) = user_data.strip_prefix ("DivX") else { return false };
+	let rest = rest.trim_start_matches (|ch: char| ch.is_ascii_digit ());
+	let rest = rest.strip_prefix ("Build").or_else (|| rest.strip_prefix ('b')).unwrap_or (rest);
+	let rest = rest.trim_start_matches (|ch: char| ch.is_ascii_digit ());
+	rest.starts_with ('p')
+}
+
+fn read_list_data (src: & mut impl Read, list_type: FourCc, len: u32) -> anyhow::Result <Vec <u8>> {
+	any_ensure! (len <= MAX_LIST_SIZE, "{list_type} list is too large: {len} bytes");
+	let mut data = vec! [0; len as usize];
+	src.read_exact (& mut data)
+		.with_context (|| any_err! ("Error reading {list_type} list")) ?;
+	Ok (data)
+}
+
+/// Iterate over the chunks contained in a slice, lists are returned with their type as the first
+/// four bytes of the data
+fn chunks (data: & [u8]) -> impl Iterator <Item = (FourCc, anyhow::Result <& [u8]>)> {
+	let mut pos = 0;
+	iter::from_fn (move || {
+		if data.len () < pos + 8 { return None }
+		let chunk_id = FourCc::from_slice (& data [pos .. pos + 4]);
+		let chunk_len = le_u32 (data, pos + 4) as usize;
+		if data.len () - pos - 8 < chunk_len {
+			pos = data.len ();
+			return Some ((chunk_id, Err (any_err! ("Invalid size for {chunk_id} chunk: {chunk_len}"))));
+		}
+		let chunk_data = & data [pos + 8 .. pos + 8 + chunk_len];
+		pos += 8 + chunk_len + (chunk_len & 1);
+		Some ((chunk_id, Ok (chunk_data)))
+	})
+}
+
+fn le_u16 (data: & [u8], pos: usize) -> u16 {
+	u16::from_le_bytes ([ data [pos], data [pos + 1] ])
+}
+
+fn le_u32 (data: & [u8], pos: usize) -> u32 {
+	u32::from_le_bytes ([ data [pos], data [pos + 1], data [pos + 2], data [pos + 3] ])
+}
@@ -0,0 +1,1160 @@
+//! Editable chapter model, with readers and writers for common chapter file formats
+//!
+//! Chapters are read natively from matroska files or from ffprobe otherwise, and are written back
+//! natively with [`to_matroska`] once ffmpeg has made the copy, since ffmetadata only holds a
+//! single edition of plain chapters with one name each. The model covers everything in the
+//! matroska Chapters element, including nested chapters, so nothing is lost when they are written
+//! back. Chapters can be limited to certain tracks, which are referred to by UID, but the track
+//! type is generic so that `edit` can refer to tracks by their position instead.
+
+use crate::detect;
+use crate::imports::*;
+use crate::language::LanguageTag;
+use crate::matroska;
+use crate::media;
+
+#[ derive (Clone, Debug, Deserialize, PartialEq, Serialize) ]
+#[ serde (deny_unknown_fields, bound (deserialize = "Track: Deserialize <'de>")) ]
+pub struct Edition <Track = u64> {
+	#[ serde (default, skip_serializing_if = "Option::is_none") ]
+	pub uid: Option <u64>,
+	#[ serde (default) ]
+	pub flag_default: bool,
+	#[ serde (default) ]
+	pub flag_hidden: bool,
+	#[ serde (default) ]
+	pub flag_ordered: bool,
+	#[ serde (default, skip_serializing_if = "Vec::is_empty") ]
+	pub names: Vec <ChapterName>,
+	pub chapters: Vec <Chapter <Track>>,
+}
+
+#[ serde_as ]
+#[ derive (Clone, Debug, Deserialize, PartialEq, Serialize) ]
+#[ serde (deny_unknown_fields, bound (deserialize = "Track: Deserialize <'de>")) ]
+pub struct Chapter <Track = u64> {
+	#[ serde (default, skip_serializing_if = "Option::is_none") ]
+	pub uid: Option <u64>,
+	#[ serde (default, skip_serializing_if = "Option::is_none") ]
+	pub string_uid: Option <String>,
+	pub start: Timestamp,
+	#[ serde (default, skip_serializing_if = "Option::is_none") ]
+	pub end: Option <Timestamp>,
+	#[ serde (default) ]
+	pub names: Vec <ChapterName>,
+	#[ serde (default) ]
+	pub flag_hidden: bool,
+	#[ serde (default = "default_true") ]
+	pub flag_enabled: bool,
+	#[ serde (default, skip_serializing_if = "Option::is_none") ]
+	pub skip_type: Option <SkipType>,
+	/// Segment to play this chapter from, for ordered editions which link to other files
+	#[ serde_as (as = "Option <BlobHex>") ]
+	#[ serde (default, skip_serializing_if = "Option::is_none") ]
+	pub segment_uuid: Option <Vec <u8>>,
+	#[ serde (default, skip_serializing_if = "Option::is_none") ]
+	pub segment_edition_uid: Option <u64>,
+	#[ serde (default, skip_serializing_if = "Option::is_none") ]
+	pub physical_equiv: Option <u64>,
+	/// Tracks the chapter applies to, or all of them if empty
+	#[ serde (default, skip_serializing_if = "Vec::is_empty") ]
+	pub tracks: Vec <Track>,
+	#[ serde (default, skip_serializing_if = "Vec::is_empty") ]
+	pub processes: Vec <ChapterProcess>,
+	/// Nested chapters, which all fall within this one
+	#[ serde (default, skip_serializing_if = "Vec::is_empty") ]
+	pub chapters: Vec <Chapter <Track>>,
+}
+
+#[ derive (Clone, Debug, Deserialize, PartialEq, Serialize) ]
+#[ serde (deny_unknown_fields) ]
+pub struct ChapterName {
+	pub name: String,
+	#[ serde (default, skip_serializing_if = "Option::is_none") ]
+	pub language: Option <String>,
+	/// Further languages the name is in, after the first in `language`
+	#[ serde (default, skip_serializing_if = "Vec::is_empty") ]
+	pub other_languages: Vec <String>,
+	/// ISO 3166-1 country codes, which editions names can't have
+	#[ serde (default, skip_serializing_if = "Vec::is_empty") ]
+	pub countries: Vec <String>,
+}
+
+/// Commands for a chapter codec, such as DVD menus
+#[ serde_as ]
+#[ derive (Clone, Debug, Deserialize, PartialEq, Serialize) ]
+#[ serde (deny_unknown_fields) ]
+pub struct ChapterProcess {
+	pub codec_id: u64,
+	#[ serde_as (as = "Option <BlobHex>") ]
+	#[ serde (default, skip_serializing_if = "Option::is_none") ]
+	pub private: Option <Vec <u8>>,
+	#[ serde (default, skip_serializing_if = "Vec::is_empty") ]
+	pub commands: Vec <ChapterProcessCommand>,
+}
+
+#[ serde_as ]
+#[ derive (Clone, Debug, Deserialize, PartialEq, Serialize) ]
+#[ serde (deny_unknown_fields) ]
+pub struct ChapterProcessCommand {
+	/// When to run the command, 0 during the chapter, 1 before it starts and 2 after it ends
+	pub time: u64,
+	#[ serde_as (as = "BlobHex") ]
+	pub data: Vec <u8>,
+}
+
+#[ derive (Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize) ]
+#[ serde (rename_all = "kebab-case") ]
+pub enum SkipType {
+	NoSkipping,
+	OpeningCredits,
+	EndCredits,
+	Recap,
+	NextPreview,
+	Preview,
+	Advertisement,
+	Intermission,
+}
+
+impl SkipType {
+
+	fn from_value (value: u64) -> anyhow::Result <Self> {
+		use matroska::chapters::ChapterSkipType as Mkv;
+		Ok (match Mkv::from (value) {
+			Mkv::NoSkipping => Self::NoSkipping,
+			Mkv::OpeningCredits => Self::OpeningCredits,
+			Mkv::EndCredits => Self::EndCredits,
+			Mkv::Recap => Self::Recap,
+			Mkv::NextPreview => Self::NextPreview,
+			Mkv::Preview => Self::Preview,
+			Mkv::Advertisement => Self::Advertisement,
+			Mkv::Intermission => Self::Intermission,
+			Mkv::Unknown (value) => any_bail! ("Unknown chapter skip type {value}"),
+		})
+	}
+
+	fn value (self) -> u64 {
+		match self {
+			Self::NoSkipping => 0,
+			Self::OpeningCredits => 1,
+			Self::EndCredits => 2,
+			Self::Recap => 3,
+			Self::NextPreview => 4,
+			Self::Preview => 5,
+			Self::Advertisement => 6,
+			Self::Intermission => 7,
+		}
+	}
+
+}
+
+impl <Track> Edition <Track> {
+
+	/// Every chapter in the edition, including nested chapters, depth first
+	pub fn all_chapters (& self) -> Vec <& Chapter <Track>> {
+		self.chapters.iter ().flat_map (Chapter::all_chapters).collect ()
+	}
+
+	/// Convert track references, failing if a chapter only applied to tracks which are gone, since
+	/// it would then apply to all of them
+	pub fn map_tracks <Other> (
+		self,
+		mut map_fn: impl FnMut (Track) -> anyhow::Result <Option <Other>>,
+	) -> anyhow::Result <Edition <Other>> {
+		Ok (Edition {
+			uid: self.uid,
+			flag_default: self.flag_default,
+			flag_hidden: self.flag_hidden,
+			flag_ordered: self.flag_ordered,
+			names: self.names,
+			chapters: self.chapters.into_iter ()
+				.map (|chapter| chapter.map_tracks (& mut map_fn))
+				.collect::<anyhow::Result <_>> () ?,
+		})
+	}
+
+}
+
+impl <Track> Chapter <Track> {
+
+	/// Plain chapter starting at `start`, with everything else left empty
+	pub fn new (start: Timestamp) -> Self {
+		Self {
+			uid: None,
+			string_uid: None,
+			start,
+			end: None,
+			names: Vec::new (),
+			flag_hidden: false,
+			flag_enabled: true,
+			skip_type: None,
+			segment_uuid: None,
+			segment_edition_uid: None,
+			physical_equiv: None,
+			tracks: Vec::new (),
+			processes: Vec::new (),
+			chapters: Vec::new (),
+		}
+	}
+
+	/// This chapter followed by every chapter nested within it, depth first
+	pub fn all_chapters (& self) -> Vec <& Self> {
+		iter::once (self).chain (self.chapters.iter ().flat_map (Self::all_chapters)).collect ()
+	}
+
+	fn map_tracks <Other> (
+		self,
+		map_fn: & mut impl FnMut (Track) -> anyhow::Result <Option <Other>>,
+	) -> anyhow::Result <Chapter <Other>> {
+		let had_tracks = ! self.tracks.is_empty ();
+		let mut tracks = Vec::new ();
+		for track in self.tracks {
+			if let Some (track) = map_fn (track) ? { tracks.push (track) }
+		}
+		any_ensure! (! had_tracks || ! tracks.is_empty (),
+			"Chapter at {} only applies to tracks which are not being kept", self.start);
+		Ok (Chapter {
+			uid: self.uid,
+			string_uid: self.string_uid,
+			start: self.start,
+			end: self.end,
+			names: self.names,
+			flag_hidden: self.flag_hidden,
+			flag_enabled: self.flag_enabled,
+			skip_type: self.skip_type,
+			segment_uuid: self.segment_uuid,
+			segment_edition_uid: self.segment_edition_uid,
+			physical_equiv: self.physical_equiv,
+			tracks,
+			processes: self.processes,
+			chapters: self.chapters.into_iter ()
+				.map (|chapter| chapter.map_tracks (map_fn))
+				.collect::<anyhow::Result <_>> () ?,
+		})
+	}
+
+}
+
+impl ChapterName {
+
+	/// Name without any language, which matroska takes as english
+	pub fn new (name: String) -> Self {
+		Self { name, language: None, other_languages: Vec::new (), countries: Vec::new () }
+	}
+
+	fn languages (& self) -> impl Iterator <Item = & String> {
+		self.language.iter ().chain (& self.other_languages)
+	}
+
+}
+
+
+/// Chapter time in nanoseconds, written as "H:MM:SS.mmm" or with full precision if needed
+#[ derive (Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd,
+	serde_with::DeserializeFromStr, serde_with::SerializeDisplay) ]
+pub struct Timestamp (pub u64);
+
+impl Timestamp {
+
+	pub fn from_secs (secs: f64) -> Self {
+		Self ((secs * 1_000_000_000.0).round () as u64)
+	}
+
+	/// Format with millisecond precision, as used in OGM chapter files
+	pub fn fmt_millis (self) -> String {
+		let Self (nanos) = self;
+		format! ("{:02}:{:02}:{:02}.{:03}",
+			nanos / 3_600_000_000_000,
+			nanos / 60_000_000_000 % 60,
+			nanos / 1_000_000_000 % 60,
+			nanos / 1_000_000 % 1000)
+	}
+
+	/// Format with nanosecond precision, as used in matroska chapter XML
+	pub fn fmt_nanos (self) -> String {
+		let Self (nanos) = self;
+		format! ("{:02}:{:02}:{:02}.{:09}",
+			nanos / 3_600_000_000_000,
+			nanos / 60_000_000_000 % 60,
+			nanos / 1_000_000_000 % 60,
+			nanos % 1_000_000_000)
+	}
+
+}
+
+impl fmt::Display for Timestamp {
+	fn fmt (& self, fmtr: & mut fmt::Formatter) -> fmt::Result {
+		let Self (nanos) = * self;
+		write! (fmtr, "{}:{:02}:{:02}",
+			nanos / 3_600_000_000_000,
+			nanos / 60_000_000_000 % 60,
+			nanos / 1_000_000_000 % 60) ?;
+		if nanos.is_multiple_of (1_000_000) {
+			write! (fmtr, ".{:03}", nanos / 1_000_000 % 1000)
+		} else {
+			write! (fmtr, ".{:09}", nanos % 1_000_000_000)
+		}
+	}
+}
+
+impl std::str::FromStr for Timestamp {
+	type Err = anyhow::Error;
+	fn from_str (val: & str) -> anyhow::Result <Self> {
+		let err = || any_err! ("Invalid timestamp \"{val}\", expected H:MM:SS.mmm");
+		let (whole, frac) = val.trim ().split_once ('.').unwrap_or ((val.trim (), ""));
+		let mut secs: u64 = 0;
+		let parts: Vec <& str> = whole.split (':').collect ();
+		if parts.len () > 3 { return Err (err ()) }
+		for part in parts {
+			if part.is_empty () || ! part.bytes ().all (|byte| byte.is_ascii_digit ()) {
+				return Err (err ());
+			}
+			secs = secs * 60 + part.parse::<u64> ().map_err (|_| err ()) ?;
+		}
+		if frac.len () > 9 || ! frac.bytes ().all (|byte| byte.is_ascii_digit ()) {
+			return Err (err ());
+		}
+		let frac_nanos =
+			if frac.is_empty () { 0 }
+			else { frac.parse::<u64> ().map_err (|_| err ()) ? * 10_u64.pow (9 - frac.len () as u32) };
+		Ok (Self (secs * 1_000_000_000 + frac_nanos))
+	}
+}
+
+fn default_true () -> bool { true }
+
+
+/// Read chapters from a media file, natively for matroska or from the probed information otherwise
+pub fn read (file_path: & Path, media: & media::MediaInfo) -> anyhow::Result <Vec <Edition>> {
+	if ! matches! (media.file_type, Some (detect::FileType::Matroska)) {
+		return Ok (from_media (media));
+	}
+	let file = BufReader::new (File::open (file_path) ?);
+	let mut reader = matroska::Reader::new (file) ?;
+	if ! reader.has_seek (matroska::elems::CHAPTERS) { return Ok (Vec::new ()) }
+	let chapters = reader.chapters () ?;
+	from_matroska (& chapters)
+}
+
+/// Convert from the matroska element, failing on anything the model can't hold
+pub fn from_matroska (chapters: & matroska::ChaptersElem) -> anyhow::Result <Vec <Edition>> {
+	chapters.editions.iter ()
+		.map (|edition| Ok (Edition {
+			uid: edition.uid,
+			flag_default: edition.flag_default,
+			flag_hidden: edition.flag_hidden,
+			flag_ordered: edition.flag_ordered,
+			names: edition.displays.iter ()
+				.map (|display| ChapterName {
+					name: display.string.clone (),
+					language: display.languages_ietf.first ().cloned (),
+					other_languages: display.languages_ietf.iter ().skip (1).cloned ().collect (),
+					countries: Vec::new (),
+				})
+				.collect (),
+			chapters: edition.atoms.iter ()
+				.map (chapter_from_matroska)
+				.collect::<anyhow::Result <_>> () ?,
+		}))
+		.collect ()
+}
+
+fn chapter_from_matroska (atom: & matroska::chapters::ChapterAtomElem) -> anyhow::Result <Chapter> {
+	Ok (Chapter {
+		uid: Some (atom.uid),
+		string_uid: atom.string_uid.clone (),
+		start: Timestamp (atom.time_start),
+		end: atom.time_end.map (Timestamp),
+		names: atom.displays.iter ()
+			.map (|display| {
+				// the BCP 47 languages replace the ISO 639-2 ones when there are any
+				let mut languages =
+					if display.languages_bcp47.is_empty () { display.languages.iter () }
+					else { display.languages_bcp47.iter () };
+				ChapterName {
+					name: display.string.clone (),
+					language: languages.next ().cloned (),
+					other_languages: languages.cloned ().collect (),
+					countries: display.countries.clone (),
+				}
+			})
+			.collect (),
+		flag_hidden: atom.flag_hidden,
+		flag_enabled: atom.flag_enabled,
+		skip_type: atom.skip_type
+			.map (|skip_type| SkipType::from_value (skip_type.value ()))
+			.transpose ()
+			.with_context (|| any_err! ("In chapter {}", atom.uid)) ?,
+		segment_uuid: atom.segment_uuid.clone (),
+		segment_edition_uid: atom.segment_edition_uid,
+		physical_equiv: atom.physical_equiv,
+		tracks: atom.track.as_ref ().map_or_else (Vec::new, |track| track.track_uids.clone ()),
+		processes: atom.processes.iter ()
+			.map (|process| ChapterProcess {
+				codec_id: process.codec_id,
+				private: process.private.clone (),
+				commands: process.commands.iter ()
+					.map (|command| ChapterProcessCommand { time: command.time, data: command.data.clone () })
+					.collect (),
+			})
+			.collect (),
+		chapters: atom.atoms.iter ()
+			.map (chapter_from_matroska)
+			.collect::<anyhow::Result <_>> () ?,
+	})
+}
+
+pub fn from_media (media: & media::MediaInfo) -> Vec <Edition> {
+	if media.chapters.is_empty () { return Vec::new () }
+	vec! [ Edition {
+		uid: None,
+		flag_default: true,
+		flag_hidden: false,
+		flag_ordered: false,
+		names: Vec::new (),
+		chapters: media.chapters.iter ()
+			.map (|chapter| Chapter {
+				end: chapter.end.map (Timestamp::from_secs),
+				names: chapter.title.iter ().map (|title| ChapterName::new (title.clone ())).collect (),
+				.. Chapter::new (Timestamp::from_secs (chapter.start))
+			})
+			.collect (),
+	} ]
+}
+
+/// Generate evenly spaced chapters, named "Chapter 01" and so on
+pub fn generate (duration: f64, every: f64) -> anyhow::Result <Vec <Edition>> {
+	any_ensure! (every > 0.0, "Chapter interval must be positive");
+	let duration = Timestamp::from_secs (duration);
+	let every = Timestamp::from_secs (every);
+	let mut chapters = Vec::new ();
+	let mut start = 0;
+	while start < duration.0 {
+		let end = u64::min (start + every.0, duration.0);
+		chapters.push (Chapter {
+			end: Some (Timestamp (end)),
+			names: vec! [ ChapterName::new (format! ("Chapter {:02}", chapters.len () + 1)) ],
+			.. Chapter::new (Timestamp (start))
+		});
+		start = end;
+	}
+	Ok (vec! [ Edition {
+		uid: None,
+		flag_default: true,
+		flag_hidden: false,
+		flag_ordered: false,
+		names: Vec::new (),
+		chapters,
+	} ])
+}
+
+/// The edition a player would show, which is the first one flagged as default
+pub fn default_edition <Track> (editions: & [Edition <Track>]) -> Option <& Edition <Track>> {
+	editions.iter ().find (|edition| edition.flag_default).or (editions.first ())
+}
+
+/// Check that chapters make sense before writing them, reporting the location of any problem
+pub fn check <Track> (editions: & [Edition <Track>], path: & str) -> anyhow::Result <()> {
+	let mut edition_uids = BTreeSet::new ();
+	let mut chapter_uids = BTreeSet::new ();
+	for (edition_idx, edition) in editions.iter ().enumerate () {
+		let path = format! ("{path}.{edition_idx}");
+		if let Some (uid) = edition.uid {
+			any_ensure! (uid != 0, "{path}.uid: UIDs can't be zero");
+			any_ensure! (edition_uids.insert (uid), "{path}.uid: Duplicated UID {uid}");
+		}
+		for (name_idx, name) in edition.names.iter ().enumerate () {
+			any_ensure! (name.countries.is_empty (),
+				"{path}.names.{name_idx}.countries: Edition names can't have countries");
+		}
+		any_ensure! (! edition.chapters.is_empty (), "{path}.chapters: Editions need at least one chapter");
+		check_chapters (& edition.chapters, & format! ("{path}.chapters"), & mut chapter_uids) ?;
+	}
+	Ok (())
+}
+
+fn check_chapters <Track> (
+	chapters: & [Chapter <Track>],
+	path: & str,
+	chapter_uids: & mut BTreeSet <u64>,
+) -> anyhow::Result <()> {
+	let mut prev_start = None;
+	for (chapter_idx, chapter) in chapters.iter ().enumerate () {
+		let path = format! ("{path}.{chapter_idx}");
+		if let Some (uid) = chapter.uid {
+			any_ensure! (uid != 0, "{path}.uid: UIDs can't be zero");
+			any_ensure! (chapter_uids.insert (uid), "{path}.uid: Duplicated UID {uid}");
+		}
+		if let Some (end) = chapter.end {
+			any_ensure! (chapter.start <= end, "{path}.end: Chapter ends before it starts");
+		}
+		if let Some (prev_start) = prev_start {
+			any_ensure! (prev_start <= chapter.start, "{path}.start: Chapters are out of order");
+		}
+		prev_start = Some (chapter.start);
+		check_chapters (& chapter.chapters, & format! ("{path}.chapters"), chapter_uids) ?;
+	}
+	Ok (())
+}
+
+/// Convert to the matroska element, giving new random UIDs to editions and chapters without one
+pub fn to_matroska (editions: & [Edition]) -> matroska::ChaptersElem {
+	let mut used_uids: BTreeSet <u64> = editions.iter ()
+		.flat_map (|edition| iter::once (edition.uid)
+			.chain (edition.all_chapters ().into_iter ().map (|chapter| chapter.uid)))
+		.flatten ()
+		.collect ();
+	let mut new_uid = move || loop {
+		let uid = std::hash::BuildHasher::hash_one (& std::hash::RandomState::new (), used_uids.len ());
+		if uid != 0 && used_uids.insert (uid) { return uid }
+	};
+	matroska::ChaptersElem {
+		editions: editions.iter ()
+			.map (|edition| matroska::chapters::EditionEntryElem {
+				uid: Some (edition.uid.unwrap_or_else (& mut new_uid)),
+				flag_hidden: edition.flag_hidden,
+				flag_default: edition.flag_default,
+				flag_ordered: edition.flag_ordered,
+				displays: edition.names.iter ()
+					.map (|name| matroska::chapters::EditionDisplayElem {
+						string: name.name.clone (),
+						languages_ietf: name.languages ()
+							.map (|language| LanguageTag::parse (language)
+								.map_or_else (|_| language.clone (), |tag| tag.bcp47 ()))
+							.collect (),
+					})
+					.collect (),
+				atoms: edition.chapters.iter ()
+					.map (|chapter| chapter_to_matroska (chapter, & mut new_uid))
+					.collect (),
+			})
+			.collect (),
+	}
+}
+
+fn chapter_to_matroska (
+	chapter: & Chapter,
+	new_uid: & mut impl FnMut () -> u64,
+) -> matroska::chapters::ChapterAtomElem {
+	let display = |name: & ChapterName| {
+		let mut languages = Vec::new ();
+		let mut languages_bcp47 = Vec::new ();
+		for language in name.languages () {
+			match LanguageTag::parse (language) {
+				Ok (tag) => {
+					languages.push (tag.iso_639_2 ().to_owned ());
+					languages_bcp47.push (tag.bcp47 ());
+				},
+				Err (_) => {
+					languages.push ("und".to_owned ());
+					languages_bcp47.push (language.clone ());
+				},
+			}
+		}
+		if languages.is_empty () { languages.push ("eng".to_owned ()) }
+		matroska::chapters::ChapterDisplayElem {
+			string: name.name.clone (),
+			languages,
+			languages_bcp47,
+			countries: name.countries.clone (),
+		}
+	};
+	matroska::chapters::ChapterAtomElem {
+		uid: chapter.uid.unwrap_or_else (& mut * new_uid),
+		string_uid: chapter.string_uid.clone (),
+		time_start: chapter.start.0,
+		time_end: chapter.end.map (|end| end.0),
+		flag_hidden: chapter.flag_hidden,
+		flag_enabled: chapter.flag_enabled,
+		segment_uuid: chapter.segment_uuid.clone (),
+		skip_type: chapter.skip_type.map (|skip_type| skip_type.value ().into ()),
+		segment_edition_uid: chapter.segment_edition_uid,
+		physical_equiv: chapter.physical_equiv,
+		track: (! chapter.tracks.is_empty ()).then (|| matroska::chapters::ChapterTrackElem {
+			track_uids: chapter.tracks.clone (),
+		}),
+		displays: chapter.names.iter ().map (display).collect (),
+		processes: chapter.processes.iter ()
+			.map (|process| matroska::chapters::ChapterProcessElem {
+				codec_id: process.codec_id,
+				private: process.private.clone (),
+				commands: process.commands.iter ()
+					.map (|command| matroska::chapters::ChapterProcessCommandElem {
+						time: command.time,
+						data: command.data.clone (),
+					})
+					.collect (),
+			})
+			.collect (),
+		atoms: chapter.chapters.iter ()
+			.map (|chapter| chapter_to_matroska (chapter, new_uid))
+			.collect (),
+	}
+}
+
+/// Work out the end of each chapter, using the start of the next or the end of the file
+fn chapter_ends (chapters: & [Chapter], duration: Option <f64>) -> Vec <Timestamp> {
+	chapters.iter ().enumerate ()
+		.map (|(chapter_idx, chapter)| chapter.end
+			.or_else (|| chapters.get (chapter_idx + 1).map (|next| next.start))
+			.or_else (|| duration.map (Timestamp::from_secs))
+			.unwrap_or (chapter.start)
+			.max (chapter.start))
+		.collect ()
+}
+
+#[ derive (Clone, Copy, Debug, Eq, PartialEq, clap::ValueEnum) ]
+pub enum Format {
+	Ffmetadata,
+	Ogm,
+	Xml,
+	Yaml,
+}
+
+impl Format {
+
+	pub fn from_extension (path: & Path) -> Option <Self> {
+		let extension = path.extension () ?.to_str () ?.to_ascii_lowercase ();
+		match extension.as_str () {
+			"ffmeta" | "ffmetadata" | "meta" => Some (Self::Ffmetadata),
+			"txt" => Some (Self::Ogm),
+			"xml" => Some (Self::Xml),
+			"json" | "yaml" | "yml" => Some (Self::Yaml),
+			_ => None,
+		}
+	}
+
+	pub fn identify (text: & str) -> Option <Self> {
+		let text = text.trim_start_matches ('\u{feff}').trim_start ();
+		if text.starts_with (";FFMETADATA") { return Some (Self::Ffmetadata) }
+		if text.starts_with ("CHAPTER") { return Some (Self::Ogm) }
+		if text.starts_with ('<') { return Some (Self::Xml) }
+		if text.starts_with ('-') || text.starts_with ('[') { return Some (Self::Yaml) }
+		None
+	}
+
+	pub fn read (self, text: & str) -> anyhow::Result <Vec <Edition>> {
+		match self {
+			Self::Ffmetadata => read_ffmetadata (text),
+			Self::Ogm => read_ogm (text),
+			Self::Xml => read_xml (text),
+			Self::Yaml => Ok (serde_yaml::from_str (text) ?),
+		}
+	}
+
+	pub fn write (self, editions: & [Edition], duration: Option <f64>) -> anyhow::Result <String> {
+		match self {
+			Self::Ffmetadata => write_ffmetadata (editions, duration),
+			Self::Ogm => write_ogm (editions),
+			Self::Xml => Ok (write_xml (editions)),
+			Self::Yaml => Ok (serde_yaml::to_string (editions) ?),
+		}
+	}
+
+}
+
+fn single_edition (chapters: Vec <Chapter>) -> Vec <Edition> {
+	if chapters.is_empty () { return Vec::new () }
+	vec! [ Edition {
+		uid: None,
+		flag_default: true,
+		flag_hidden: false,
+		flag_ordered: false,
+		names: Vec::new (),
+		chapters,
+	} ]
+}
+
+/// Read OGM style chapters, with pairs of `CHAPTER01=00:00:00.000` and `CHAPTER01NAME=Name` lines
+pub fn read_ogm (text: & str) -> anyhow::Result <Vec <Edition>> {
+	let mut chapters: Vec <(String, Chapter)> = Vec::new ();
+	for (line_idx, line) in text.lines ().enumerate () {
+		let line = line.trim_start_matches ('\u{feff}').trim ();
+		if line.is_empty () { continue }
+		let err = || any_err! ("Invalid chapter line at line {}: {line}", line_idx + 1);
+		let (key, val) = line.split_once ('=').ok_or_else (err) ?;
+		let key = key.strip_prefix ("CHAPTER").ok_or_else (err) ?;
+		if let Some (num) = key.strip_suffix ("NAME") {
+			let (_, chapter) = chapters.iter_mut ().rev ()
+				.find (|(chapter_num, _)| chapter_num == num)
+				.ok_or_else (|| any_err! ("Chapter name before time at line {}", line_idx + 1)) ?;
+			chapter.names = vec! [ ChapterName::new (val.to_owned ()) ];
+		} else {
+			any_ensure! (! key.is_empty () && key.bytes ().all (|byte| byte.is_ascii_digit ()), err ());
+			let start = val.parse ().with_context (|| any_err! ("At line {}", line_idx + 1)) ?;
+			chapters.push ((key.to_owned (), Chapter::new (start)));
+		}
+	}
+	Ok (single_edition (chapters.into_iter ().map (|(_, chapter)| chapter).collect ()))
+}
+
+pub fn write_ogm (editions: & [Edition]) -> anyhow::Result <String> {
+	let mut text = String::new ();
+	let Some (edition) = default_edition (editions) else { return Ok (text) };
+	for (chapter_idx, chapter) in edition.chapters.iter ().enumerate () {
+		let num = chapter_idx + 1;
+		let name = chapter.names.first ().map (|name| name.name.as_str ()).unwrap_or_default ();
+		writeln! (text, "CHAPTER{num:02}={}", chapter.start.fmt_millis ()) ?;
+		writeln! (text, "CHAPTER{num:02}NAME={name}") ?;
+	}
+	Ok (text)
+}
+
+/// Read the chapter sections from an ffmetadata file, ignoring everything else
+pub fn read_ffmetadata (text: & str) -> anyhow::Result <Vec <Edition>> {
+	let text = text.trim_start_matches ('\u{feff}');
+	any_ensure! (text.starts_with (";FFMETADATA"), "Missing ;FFMETADATA1 header");
+	let mut chapters = Vec::new ();
+	let mut section: Option <String> = None;
+	let mut fields: BTreeMap <String, String> = BTreeMap::new ();
+	let mut finish = |section: Option <String>, fields: & mut BTreeMap <String, String>| {
+		let fields = mem::take (fields);
+		if section.as_deref () != Some ("CHAPTER") { return Ok (()) }
+		let (num, den) = match fields.get ("TIMEBASE") {
+			Some (timebase) => timebase.split_once ('/')
+				.and_then (|(num, den)| Some ((num.parse::<u64> ().ok () ?, den.parse::<u64> ().ok () ?)))
+				.filter (|& (num, den)| num != 0 && den != 0)
+				.ok_or_else (|| any_err! ("Invalid chapter timebase: {timebase}")) ?,
+			None => (1, 1_000_000_000),
+		};
+		let time = |name: & str| -> anyhow::Result <Option <Timestamp>> {
+			let Some (val) = fields.get (name) else { return Ok (None) };
+			let val: u64 = val.parse ().map_err (|_| any_err! ("Invalid chapter {name}: {val}")) ?;
+			Ok (Some (Timestamp ((val as u128 * num as u128 * 1_000_000_000 / den as u128) as u64)))
+		};
+		chapters.push (Chapter {
+			end: time ("END") ?,
+			names: fields.get ("title").iter ()
+				.map (|title| ChapterName::new ((* title).clone ()))
+				.collect (),
+			.. Chapter::new (time ("START") ?.ok_or_else (|| any_err! ("Chapter without START")) ?)
+		});
+		anyhow::Ok (())
+	};
+	let mut lines = text.lines ().skip (1);
+	while let Some (line) = lines.next () {
+		let mut line = line.to_owned ();
+		while line.ends_with ('\\') && ! line.ends_with ("\\\\") {
+			line.pop ();
+			line.push ('\n');
+			line.push_str (lines.next ().unwrap_or_default ());
+		}
+		if line.starts_with (';') || line.starts_with ('#') || line.trim ().is_empty () { continue }
+		if line.starts_with ('[') && line.ends_with (']') {
+			finish (section.take (), & mut fields) ?;
+			section = Some (line [1 .. line.len () - 1].to_owned ());
+			continue;
+		}
+		let Some ((key, val)) = split_ffmetadata (& line) else {
+			any_bail! ("Invalid ffmetadata line: {line}");
+		};
+		fields.insert (key, val);
+	}
+	finish (section, & mut fields) ?;
+	Ok (single_edition (chapters))
+}
+
+/// Split an ffmetadata line on the first unescaped `=`, removing escapes from both sides
+fn split_ffmetadata (line: & str) -> Option <(String, String)> {
+	let mut key = String::new ();
+	let mut val = String::new ();
+	let mut in_val = false;
+	let mut chars = line.chars ();
+	while let Some (ch) = chars.next () {
+		let target = if in_val { & mut val } else { & mut key };
+		match ch {
+			'\\' => target.push (chars.next () ?),
+			'=' if ! in_val => in_val = true,
+			ch => target.push (ch),
+		}
+	}
+	in_val.then_some ((key, val))
+}
+
+fn escape_ffmetadata (val: & str) -> String {
+	let mut escaped = String::new ();
+	for ch in val.chars () {
+		if matches! (ch, '=' | ';' | '#' | '\\' | '\n') { escaped.push ('\\') }
+		escaped.push (ch);
+	}
+	escaped
+}
+
+pub fn write_ffmetadata (editions: & [Edition], duration: Option <f64>) -> anyhow::Result <String> {
+	let mut text = String::from (";FFMETADATA1\n");
+	let Some (edition) = default_edition (editions) else { return Ok (text) };
+	let ends = chapter_ends (& edition.chapters, duration);
+	for (chapter, end) in iter::zip (& edition.chapters, ends) {
+		writeln! (text) ?;
+		writeln! (text, "[CHAPTER]") ?;
+		writeln! (text, "TIMEBASE=1/1000000000") ?;
+		writeln! (text, "START={}", chapter.start.0) ?;
+		writeln! (text, "END={}", end.0) ?;
+		if let Some (name) = chapter.names.first () {
+			writeln! (text, "title={}", escape_ffmetadata (& name.name)) ?;
+		}
+	}
+	Ok (text)
+}
+
+/// Read matroska chapter XML, as used by mkvtoolnix
+pub fn read_xml (text: & str) -> anyhow::Result <Vec <Edition>> {
+	let root = XmlNode::parse (text) ?;
+	any_ensure! (root.name == "Chapters", "Expected Chapters element but got {}", root.name);
+	root.check_children (& ["EditionEntry"]) ?;
+	let mut editions = Vec::new ();
+	for edition_node in & root.children {
+		let mut edition = Edition {
+			uid: None,
+			flag_default: false,
+			flag_hidden: false,
+			flag_ordered: false,
+			names: Vec::new (),
+			chapters: Vec::new (),
+		};
+		for node in & edition_node.children {
+			match node.name.as_str () {
+				"EditionUID" => edition.uid = Some (node.parse_u64 () ?),
+				"EditionFlagDefault" => edition.flag_default = node.parse_bool () ?,
+				"EditionFlagHidden" => edition.flag_hidden = node.parse_bool () ?,
+				"EditionFlagOrdered" => edition.flag_ordered = node.parse_bool () ?,
+				"EditionDisplay" => {
+					node.check_children (& ["EditionString", "EditionLanguageIETF"]) ?;
+					let mut languages = node.children_text ("EditionLanguageIETF");
+					edition.names.push (ChapterName {
+						name: node.child_text ("EditionString").unwrap_or_default ().to_owned (),
+						language: languages.next ().map (str::to_owned),
+						other_languages: languages.map (str::to_owned).collect (),
+						countries: Vec::new (),
+					});
+				},
+				"ChapterAtom" => edition.chapters.push (read_xml_chapter (node) ?),
+				_ => any_bail! ("Unsupported element in EditionEntry: {}", node.name),
+			}
+		}
+		editions.push (edition);
+	}
+	Ok (editions)
+}
+
+fn read_xml_chapter (chapter_node: & XmlNode) -> anyhow::Result <Chapter> {
+	let mut chapter = Chapter::new (Timestamp (0));
+	for node in & chapter_node.children {
+		match node.name.as_str () {
+			"ChapterUID" => chapter.uid = Some (node.parse_u64 () ?),
+			"ChapterStringUID" => chapter.string_uid = Some (node.text.trim ().to_owned ()),
+			"ChapterTimeStart" => chapter.start = node.text.parse () ?,
+			"ChapterTimeEnd" => chapter.end = Some (node.text.parse () ?),
+			"ChapterFlagHidden" => chapter.flag_hidden = node.parse_bool () ?,
+			"ChapterFlagEnabled" => chapter.flag_enabled = node.parse_bool () ?,
+			"ChapterSegmentUID" => chapter.segment_uuid = Some (node.parse_hex () ?),
+			"ChapterSkipType" => chapter.skip_type = Some (SkipType::from_value (node.parse_u64 () ?) ?),
+			"ChapterSegmentEditionUID" => chapter.segment_edition_uid = Some (node.parse_u64 () ?),
+			"ChapterPhysicalEquiv" => chapter.physical_equiv = Some (node.parse_u64 () ?),
+			"ChapterTrack" => {
+				node.check_children (& ["ChapterTrackNumber"]) ?;
+				for node in & node.children { chapter.tracks.push (node.parse_u64 () ?) }
+			},
+			"ChapterDisplay" => {
+				node.check_children (& ["ChapterString", "ChapterLanguage", "ChapLanguageIETF", "ChapterCountry"]) ?;
+				// the IETF languages replace the ISO 639-2 ones when there are any
+				let mut languages: Vec <& str> = node.children_text ("ChapLanguageIETF").collect ();
+				if languages.is_empty () { languages = node.children_text ("ChapterLanguage").collect () }
+				let mut languages = languages.into_iter ().map (str::to_owned);
+				chapter.names.push (ChapterName {
+					name: node.child_text ("ChapterString").unwrap_or_default ().to_owned (),
+					language: languages.next (),
+					other_languages: languages.collect (),
+					countries: node.children_text ("ChapterCountry").map (str::to_owned).collect (),
+				});
+			},
+			"ChapterProcess" => {
+				let mut process = ChapterProcess { codec_id: 0, private: None, commands: Vec::new () };
+				for node in & node.children {
+					match node.name.as_str () {
+						"ChapterProcessCodecID" => process.codec_id = node.parse_u64 () ?,
+						"ChapterProcessPrivate" => process.private = Some (node.parse_hex () ?),
+						"ChapterProcessCommand" => {
+							node.check_children (& ["ChapterProcessTime", "ChapterProcessData"]) ?;
+							process.commands.push (ChapterProcessCommand {
+								time: node.child ("ChapterProcessTime")
+									.ok_or_else (|| any_err! ("ChapterProcessCommand without ChapterProcessTime")) ?
+									.parse_u64 () ?,
+								data: node.child ("ChapterProcessData")
+									.ok_or_else (|| any_err! ("ChapterProcessCommand without ChapterProcessData")) ?
+									.parse_hex () ?,
+							});
+						},
+						_ => any_bail! ("Unsupported element in ChapterProcess: {}", node.name),
+					}
+				}
+				chapter.processes.push (process);
+			},
+			"ChapterAtom" => chapter.chapters.push (read_xml_chapter (node) ?),
+			_ => any_bail! ("Unsupported element in ChapterAtom: {}", node.name),
+		}
+	}
+	Ok (chapter)
+}
+
+pub fn write_xml (editions: & [Edition]) -> String {
+	let mut text = String::new ();
+	xml_line (& mut text, 0, "<?xml version=\"1.0\"?>");
+	xml_line (& mut text, 0, "<!DOCTYPE Chapters SYSTEM \"matroskachapters.dtd\">");
+	xml_line (& mut text, 0, "<Chapters>");
+	for edition in editions {
+		xml_line (& mut text, 1, "<EditionEntry>");
+		if let Some (uid) = edition.uid {
+			xml_line (& mut text, 2, & xml_elem ("EditionUID", & uid.to_string ()));
+		}
+		xml_line (& mut text, 2, & xml_elem ("EditionFlagHidden", if edition.flag_hidden { "1" } else { "0" }));
+		xml_line (& mut text, 2, & xml_elem ("EditionFlagDefault", if edition.flag_default { "1" } else { "0" }));
+		xml_line (& mut text, 2, & xml_elem ("EditionFlagOrdered", if edition.flag_ordered { "1" } else { "0" }));
+		for name in & edition.names {
+			xml_line (& mut text, 2, "<EditionDisplay>");
+			xml_line (& mut text, 3, & xml_elem ("EditionString", & name.name));
+			for language in name.languages () {
+				let language = LanguageTag::parse (language).map_or_else (|_| language.clone (), |tag| tag.bcp47 ());
+				xml_line (& mut text, 3, & xml_elem ("EditionLanguageIETF", & language));
+			}
+			xml_line (& mut text, 2, "</EditionDisplay>");
+		}
+		for chapter in & edition.chapters {
+			write_xml_chapter (& mut text, 2, chapter);
+		}
+		xml_line (& mut text, 1, "</EditionEntry>");
+	}
+	xml_line (& mut text, 0, "</Chapters>");
+	text
+}
+
+fn write_xml_chapter (text: & mut String, indent: usize, chapter: & Chapter) {
+	let flag = |val: bool| if val { "1" } else { "0" };
+	xml_line (text, indent, "<ChapterAtom>");
+	if let Some (uid) = chapter.uid {
+		xml_line (text, indent + 1, & xml_elem ("ChapterUID", & uid.to_string ()));
+	}
+	if let Some (string_uid) = chapter.string_uid.as_ref () {
+		xml_line (text, indent + 1, & xml_elem ("ChapterStringUID", string_uid));
+	}
+	xml_line (text, indent + 1, & xml_elem ("ChapterTimeStart", & chapter.start.fmt_nanos ()));
+	if let Some (end) = chapter.end {
+		xml_line (text, indent + 1, & xml_elem ("ChapterTimeEnd", & end.fmt_nanos ()));
+	}
+	xml_line (text, indent + 1, & xml_elem ("ChapterFlagHidden", flag (chapter.flag_hidden)));
+	xml_line (text, indent + 1, & xml_elem ("ChapterFlagEnabled", flag (chapter.flag_enabled)));
+	if let Some (segment_uuid) = chapter.segment_uuid.as_ref () {
+		xml_line (text, indent + 1, & xml_hex_elem ("ChapterSegmentUID", segment_uuid));
+	}
+	if let Some (skip_type) = chapter.skip_type {
+		xml_line (text, indent + 1, & xml_elem ("ChapterSkipType", & skip_type.value ().to_string ()));
+	}
+	if let Some (segment_edition_uid) = chapter.segment_edition_uid {
+		xml_line (text, indent + 1, & xml_elem ("ChapterSegmentEditionUID", & segment_edition_uid.to_string ()));
+	}
+	if let Some (physical_equiv) = chapter.physical_equiv {
+		xml_line (text, indent + 1, & xml_elem ("ChapterPhysicalEquiv", & physical_equiv.to_string ()));
+	}
+	if ! chapter.tracks.is_empty () {
+		xml_line (text, indent + 1, "<ChapterTrack>");
+		for track in & chapter.tracks {
+			xml_line (text, indent + 2, & xml_elem ("ChapterTrackNumber", & track.to_string ()));
+		}
+		xml_line (text, indent + 1, "</ChapterTrack>");
+	}
+	for name in & chapter.names {
+		xml_line (text, indent + 1, "<ChapterDisplay>");
+		xml_line (text, indent + 2, & xml_elem ("ChapterString", & name.name));
+		for language in name.languages () {
+			match LanguageTag::parse (language) {
+				Ok (tag) => {
+					xml_line (text, indent + 2, & xml_elem ("ChapterLanguage", tag.iso_639_2 ()));
+					xml_line (text, indent + 2, & xml_elem ("ChapLanguageIETF", & tag.bcp47 ()));
+				},
+				Err (_) => xml_line (text, indent + 2, & xml_elem ("ChapLanguageIETF", language)),
+			}
+		}
+		for country in & name.countries {
+			xml_line (text, indent + 2, & xml_elem ("ChapterCountry", country));
+		}
+		xml_line (text, indent + 1, "</ChapterDisplay>");
+	}
+	for process in & chapter.processes {
+		xml_line (text, indent + 1, "<ChapterProcess>");
+		xml_line (text, indent + 2, & xml_elem ("ChapterProcessCodecID", & process.codec_id.to_string ()));
+		if let Some (private) = process.private.as_ref () {
+			xml_line (text, indent + 2, & xml_hex_elem ("ChapterProcessPrivate", private));
+		}
+		for command in & process.commands {
+			xml_line (text, indent + 2, "<ChapterProcessCommand>");
+			xml_line (text, indent + 3, & xml_elem ("ChapterProcessTime", & command.time.to_string ()));
+			xml_line (text, indent + 3, & xml_hex_elem ("ChapterProcessData", & command.data));
+			xml_line (text, indent + 2, "</ChapterProcessCommand>");
+		}
+		xml_line (text, indent + 1, "</ChapterProcess>");
+	}
+	for chapter in & chapter.chapters {
+		write_xml_chapter (text, indent + 1, chapter);
+	}
+	xml_line (text, indent, "</ChapterAtom>");
+}
+
+fn xml_line (text: & mut String, indent: usize, val: & str) {
+	for _ in 0 .. indent { text.push_str ("  ") }
+	text.push_str (val);
+	text.push ('\n');
+}
+
+fn xml_elem (name: & str, val: & str) -> String {
+	format! ("<{name}>{}</{name}>", escape_xml (val))
+}
+
+/// Binary element in the hex form mkvtoolnix uses
+fn xml_hex_elem (name: & str, val: & [u8]) -> String {
+	format! ("<{name} format=\"hex\">{}</{name}>", val.iter ().map (|byte| format! ("{byte:02x}")).join (""))
+}
+
+fn escape_xml (val: & str) -> String {
+	let mut escaped = String::new ();
+	for ch in val.chars () {
+		match ch {
+			'&' => escaped.push_str ("&amp;"),
+			'<' => escaped.push_str ("&lt;"),
+			'>' => escaped.push_str ("&gt;"),
+			'"' => escaped.push_str ("&quot;"),
+			ch => escaped.push (ch),
+		}
+	}
+	escaped
+}
+
+/// Just enough XML to read chapter files: elements and text, ignoring attributes, comments,
+/// processing instructions and doctypes
+#[ derive (Debug, Default) ]
+struct XmlNode {
+	name: String,
+	text: String,
+	children: Vec <XmlNode>,
+}
+
+impl XmlNode {
+
+	fn parse (text: & str) -> anyhow::Result <Self> {
+		let mut stack: Vec <Self> = vec! [ Self::default () ];
+		let mut rest = text.trim_start_matches ('\u{feff}');
+		while ! rest.is_empty () {
+			let Some (tag_start) = rest.find ('<') else {
+				stack.last_mut ().unwrap ().text.push_str (& unescape_xml (rest) ?);
+				break;
+			};
+			stack.last_mut ().unwrap ().text.push_str (& unescape_xml (& rest [ .. tag_start]) ?);
+			rest = & rest [tag_start .. ];
+			let end_marker =
+				if rest.starts_with ("<!--") { "-->" }
+				else if rest.starts_with ("<?") { "?>" }
+				else { ">" };
+			let tag_end = rest.find (end_marker)
+				.ok_or_else (|| any_err! ("Unterminated XML tag")) ? + end_marker.len ();
+			let tag = & rest [1 .. tag_end - 1];
+			rest = & rest [tag_end .. ];
+			if tag.starts_with ('!') || tag.starts_with ('?') { continue }
+			if let Some (name) = tag.strip_prefix ('/') {
+				let node = stack.pop ().filter (|_| ! stack.is_empty ())
+					.ok_or_else (|| any_err! ("Unexpected closing tag: {name}")) ?;
+				any_ensure! (node.name == name.trim (),
+					"Mismatched XML closing tag, expected {} but got {}", node.name, name.trim ());
+				stack.last_mut ().unwrap ().children.push (node);
+				continue;
+			}
+			let self_closing = tag.ends_with ('/');
+			let tag = tag.trim_end_matches ('/');
+			let name = tag.split_whitespace ().next ()
+				.ok_or_else (|| any_err! ("Empty XML tag")) ?;
+			let node = Self { name: name.to_owned (), text: String::new (), children: Vec::new () };
+			if self_closing {
+				stack.last_mut ().unwrap ().children.push (node);
+			} else {
+				stack.push (node);
+			}
+		}
+		any_ensure! (stack.len () == 1, "Unclosed XML element: {}", stack.last ().unwrap ().name);
+		let mut root = stack.pop ().unwrap ();
+		any_ensure! (root.children.len () == 1, "Expected a single XML root element");
+		Ok (root.children.pop ().unwrap ())
+	}
+
+	fn child (& self, name: & str) -> Option <& Self> {
+		self.children.iter ().find (|child| child.name == name)
+	}
+
+	fn child_text (& self, name: & str) -> Option <& str> {
+		self.child (name).map (|child| child.text.trim ())
+	}
+
+	fn children_text (& self, name: & str) -> impl Iterator <Item = & str> {
+		self.children.iter ()
+			.filter (move |child| child.name == name)
+			.map (|child| child.text.trim ())
+	}
+
+	/// Fail on any child element which isn't in `names`, rather than silently dropping it
+	fn check_children (& self, names: & [& str]) -> anyhow::Result <()> {
+		if let Some (child) = self.children.iter ().find (|child| ! names.contains (& child.name.as_str ())) {
+			any_bail! ("Unsupported element in {}: {}", self.name, child.name);
+		}
+		Ok (())
+	}
+
+	fn parse_bool (& self) -> anyhow::Result <bool> {
+		match self.text.trim () {
+			"0" => Ok (false),
+			"1" => Ok (true),
+			val => Err (any_err! ("Invalid flag in {}: {val}", self.name)),
+		}
+	}
+
+	fn parse_u64 (& self) -> anyhow::Result <u64> {
+		self.text.trim ().parse ()
+			.map_err (|_| any_err! ("Invalid number in {}: {}", self.name, self.text.trim ()))
+	}
+
+	/// Binary data as hex digits, ignoring whitespace
+	fn parse_hex (& self) -> anyhow::Result <Vec <u8>> {
+		let digits: Vec <u8> = self.text.bytes ().filter (|byte| ! byte.is_ascii_whitespace ()).collect ();
+		let err = || any_err! ("Invalid hex data in {}", self.name);
+		any_ensure! (digits.len ().is_multiple_of (2), err ());
+		digits.chunks (2)
+			.map (|pair| std::str::from_utf8 (pair).ok ()
+				.and_then (|pair| u8::from_str_radix (pair, 16).ok ())
+				.ok_or_else (err))
+			.collect ()
+	}
+
+}
+
+fn unescape_xml (val: & str) -> anyhow::Result <String> {
+	let mut unescaped = String::new ();
+	let mut rest = val;
+	while let Some (amp) = rest.find ('&') {
+		unescaped.push_str (& rest [ .. amp]);
+		rest = & rest [amp .. ];
+		let semi = rest.find (';').ok_or_else (|| any_err! ("Invalid XML entity")) ?;
+		let entity = & rest [1 .. semi];
+		let ch = match entity {
+			"amp" => '&',
+			"lt" => '<',
+			"gt" => '>',
+			"quot" => '"',
+			"apos" => '\'',
+			_ => entity.strip_prefix ("#x").map (|hex| u32::from_str_radix (hex, 16))
+				.or_else (|| entity.strip_prefix ('#').map (str::parse))
+				.and_then (Result::ok)
+				.and_then (char::from_u32)
+				.ok_or_else (|| any_err! ("Invalid XML entity: &{entity};")) ?,
+		};
+		unescaped.push (ch);
+		rest = & rest [semi + 1 .. ];
+	}
+	unescaped.push_str (rest);
+	Ok (unescaped)
+}
@@ -0,0 +1,83 @@
+use crate::imports::*;
+
+use super::BitReader;
+use super::ChromaFormat;
+
+/// Decoder configuration from an `av1C` box (AV1 codec ISO media file format binding)
+#[ allow (dead_code) ]
+#[ derive (Debug) ]
+pub struct Av1Config {
+	pub profile: u8,
+	pub level_idx: u8,
+	pub tier_high: bool,
+	pub bit_depth: u8,
+	pub chroma_format: ChromaFormat,
+	pub chroma_sample_position: u8,
+	pub initial_presentation_delay: Option <u8>,
+	pub config_obus: Vec <u8>,
+}
+
+impl Av1Config {
+
+	pub fn parse (data: & [u8]) -> anyhow::Result <Self> {
+		let mut reader = BitReader::new (data);
+		let marker = reader.bit () ?;
+		let version = reader.bits (7) ?;
+		any_ensure! (marker && version == 1, "Unsupported av1C version: {version}");
+		let profile = reader.bits (3) ? as u8;
+		let level_idx = reader.bits (5) ? as u8;
+		let tier_high = reader.bit () ?;
+		let high_bitdepth = reader.bit () ?;
+		let twelve_bit = reader.bit () ?;
+		let monochrome = reader.bit () ?;
+		let subsampling_x = reader.bit () ?;
+		let subsampling_y = reader.bit () ?;
+		let chroma_sample_position = reader.bits (2) ? as u8;
+		reader.skip (3) ?;
+		let delay_present = reader.bit () ?;
+		let delay_minus_one = reader.bits (4) ? as u8;
+		let config_obus = reader.bytes (reader.remaining () / 8) ?.to_vec ();
+		Ok (Self {
+			profile,
+			level_idx,
+			tier_high,
+			bit_depth: if twelve_bit { 12 } else if high_bitdepth { 10 } else { 8 },
+			chroma_format: match (monochrome, subsampling_x, subsampling_y) {
+				(true, _, _) => ChromaFormat::Monochrome,
+				(false, true, true) => ChromaFormat::Yuv420,
+				(false, true, false) => ChromaFormat::Yuv422,
+				(false, false, _) => ChromaFormat::Yuv444,
+			},
+			chroma_sample_position,
+			initial_presentation_delay: delay_present.then_some (delay_minus_one + 1),
+			config_obus,
+		})
+	}
+
+	pub fn profile_name (& self) -> & 'static str {
+		match self.profile {
+			0 => "main",
+			1 => "high",
+			2 => "professional",
+			_ => "unknown",
+		}
+	}
+
+	pub fn level (& self) -> String {
+		if self.level_idx == 31 { return "max".to_owned () }
+		super::fmt_level (2 + (self.level_idx as u64 >> 2), self.level_idx as u64 & 3)
+	}
+
+}
+
+impl fmt::Display for Av1Config {
+	fn fmt (& self, fmtr: & mut fmt::Formatter) -> fmt::Result {
+		write! (fmtr, "av1 {}", self.profile_name ()) ?;
+		if self.tier_high { write! (fmtr, " high") ?; }
+		write! (fmtr, " L{} {}bit", self.level (), self.bit_depth) ?;
+		if self.chroma_format != ChromaFormat::Yuv420 {
+			write! (fmtr, " {}", self.chroma_format) ?;
+		}
+		Ok (())
+	}
+}
@@ -0,0 +1,126 @@
+use crate::imports::*;
+
+use super::BitReader;
+use super::ChromaFormat;
+
+/// Decoder configuration from an `avcC` box (ISO/IEC 14496-15)
+#[ allow (dead_code) ]
+#[ derive (Debug) ]
+pub struct AvcConfig {
+	pub profile_idc: u8,
+	pub profile_compatibility: u8,
+	pub level_idc: u8,
+	pub length_size: u8,
+	pub chroma_format: ChromaFormat,
+	pub bit_depth_luma: u8,
+	pub bit_depth_chroma: u8,
+	pub sps: Vec <Vec <u8>>,
+	pub pps: Vec <Vec <u8>>,
+}
+
+impl AvcConfig {
+
+	pub fn parse (data: & [u8]) -> anyhow::Result <Self> {
+		let mut reader = BitReader::new (data);
+		let version = reader.u8 () ?;
+		any_ensure! (version == 1, "Unsupported avcC version: {version}");
+		let profile_idc = reader.u8 () ?;
+		let profile_compatibility = reader.u8 () ?;
+		let level_idc = reader.u8 () ?;
+		reader.skip (6) ?;
+		let length_size = reader.bits (2) ? as u8 + 1;
+		reader.skip (3) ?;
+		let num_sps = reader.bits (5) ?;
+		let mut sps = Vec::new ();
+		for _ in 0 .. num_sps {
+			let len = reader.u16 () ?;
+			sps.push (reader.bytes (len as usize) ?.to_vec ());
+		}
+		let num_pps = reader.u8 () ?;
+		let mut pps = Vec::new ();
+		for _ in 0 .. num_pps {
+			let len = reader.u16 () ?;
+			pps.push (reader.bytes (len as usize) ?.to_vec ());
+		}
+		let mut chroma_format = ChromaFormat::Yuv420;
+		let mut bit_depth_luma = 8;
+		let mut bit_depth_chroma = 8;
+		if has_chroma_info (profile_idc) {
+			if 32 <= reader.remaining () {
+				reader.skip (6) ?;
+				chroma_format = ChromaFormat::from_idc (reader.bits (2) ?);
+				reader.skip (5) ?;
+				bit_depth_luma = reader.bits (3) ? as u8 + 8;
+				reader.skip (5) ?;
+				bit_depth_chroma = reader.bits (3) ? as u8 + 8;
+			} else if let Some (sps) = sps.first () {
+				// many muxers omit the extension, so fall back to the sequence parameter set
+				(chroma_format, bit_depth_luma, bit_depth_chroma) = parse_sps_format (sps) ?;
+			}
+		}
+		Ok (Self {
+			profile_idc,
+			profile_compatibility,
+			level_idc,
+			length_size,
+			chroma_format,
+			bit_depth_luma,
+			bit_depth_chroma,
+			sps,
+			pps,
+		})
+	}
+
+	pub fn profile_name (& self) -> & 'static str {
+		match self.profile_idc {
+			66 if self.profile_compatibility & 0x40 != 0 => "constrained-baseline",
+			66 => "baseline",
+			77 => "main",
+			88 => "extended",
+			100 => "high",
+			110 => "high10",
+			122 => "high422",
+			244 => "high444",
+			44 => "cavlc444",
+			83 | 86 => "scalable",
+			118 | 128 => "multiview",
+			_ => "unknown",
+		}
+	}
+
+	pub fn level (& self) -> String {
+		super::fmt_level (self.level_idc as u64 / 10, self.level_idc as u64 % 10)
+	}
+
+	pub fn parameter_sets (& self) -> String {
+		format! ("{} SPS, {} PPS", self.sps.len (), self.pps.len ())
+	}
+
+}
+
+impl fmt::Display for AvcConfig {
+	fn fmt (& self, fmtr: & mut fmt::Formatter) -> fmt::Result {
+		write! (fmtr, "avc {} L{}", self.profile_name (), self.level ())
+	}
+}
+
+fn has_chroma_info (profile_idc: u8) -> bool {
+	matches! (profile_idc, 100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135)
+}
+
+fn parse_sps_format (sps: & [u8]) -> anyhow::Result <(ChromaFormat, u8, u8)> {
+	let rbsp = super::unescape_rbsp (sps);
+	let mut reader = BitReader::new (& rbsp);
+	reader.skip (8) ?;
+	let profile_idc = reader.u8 () ?;
+	reader.skip (16) ?;
+	let _sps_id = reader.ue () ?;
+	if ! has_chroma_info (profile_idc) {
+		return Ok ((ChromaFormat::Yuv420, 8, 8));
+	}
+	let chroma_format_idc = reader.ue () ?;
+	if chroma_format_idc == 3 { reader.skip (1) ?; }
+	let bit_depth_luma = reader.ue () ? as u8 + 8;
+	let bit_depth_chroma = reader.ue () ? as u8 + 8;
+	Ok ((ChromaFormat::from_idc (chroma_format_idc), bit_depth_luma, bit_depth_chroma))
+}
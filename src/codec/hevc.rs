@@ -0,0 +1,167 @@
+use crate::imports::*;
+
+use super::BitReader;
+use super::ChromaFormat;
+
+/// Decoder configuration from an `hvcC` box (ISO/IEC 14496-15)
+#[ allow (dead_code) ]
+#[ derive (Debug) ]
+pub struct HevcConfig {
+	pub profile_space: u8,
+	pub tier_high: bool,
+	pub profile_idc: u8,
+	pub profile_compatibility: u32,
+	pub constraint_flags: u64,
+	pub level_idc: u8,
+	pub min_spatial_segmentation: u16,
+	pub parallelism_type: u8,
+	pub chroma_format: ChromaFormat,
+	pub bit_depth_luma: u8,
+	pub bit_depth_chroma: u8,
+	pub avg_frame_rate: u16,
+	pub constant_frame_rate: u8,
+	pub num_temporal_layers: u8,
+	pub temporal_id_nested: bool,
+	pub length_size: u8,
+	pub nal_arrays: Vec <HevcNalArray>,
+}
+
+pub struct HevcNalArray {
+	pub complete: bool,
+	pub nal_unit_type: u8,
+	pub nal_units: Vec <Vec <u8>>,
+}
+
+pub const NAL_VPS: u8 = 32;
+pub const NAL_SPS: u8 = 33;
+pub const NAL_PPS: u8 = 34;
+pub const NAL_PREFIX_SEI: u8 = 39;
+pub const NAL_SUFFIX_SEI: u8 = 40;
+
+impl HevcConfig {
+
+	pub fn parse (data: & [u8]) -> anyhow::Result <Self> {
+		let mut reader = BitReader::new (data);
+		let version = reader.u8 () ?;
+		any_ensure! (version == 1, "Unsupported hvcC version: {version}");
+		let profile_space = reader.bits (2) ? as u8;
+		let tier_high = reader.bit () ?;
+		let profile_idc = reader.bits (5) ? as u8;
+		let profile_compatibility = reader.bits (32) ? as u32;
+		let constraint_flags = reader.bits (48) ?;
+		let level_idc = reader.u8 () ?;
+		reader.skip (4) ?;
+		let min_spatial_segmentation = reader.bits (12) ? as u16;
+		reader.skip (6) ?;
+		let parallelism_type = reader.bits (2) ? as u8;
+		reader.skip (6) ?;
+		let chroma_format = ChromaFormat::from_idc (reader.bits (2) ?);
+		reader.skip (5) ?;
+		let bit_depth_luma = reader.bits (3) ? as u8 + 8;
+		reader.skip (5) ?;
+		let bit_depth_chroma = reader.bits (3) ? as u8 + 8;
+		let avg_frame_rate = reader.u16 () ?;
+		let constant_frame_rate = reader.bits (2) ? as u8;
+		let num_temporal_layers = reader.bits (3) ? as u8;
+		let temporal_id_nested = reader.bit () ?;
+		let length_size = reader.bits (2) ? as u8 + 1;
+		let num_arrays = reader.u8 () ?;
+		let mut nal_arrays = Vec::new ();
+		for _ in 0 .. num_arrays {
+			let complete = reader.bit () ?;
+			reader.skip (1) ?;
+			let nal_unit_type = reader.bits (6) ? as u8;
+			let num_nal_units = reader.u16 () ?;
+			let mut nal_units = Vec::new ();
+			for _ in 0 .. num_nal_units {
+				let nal_unit_len = reader.u16 () ?;
+				nal_units.push (reader.bytes (nal_unit_len as usize) ?.to_vec ());
+			}
+			nal_arrays.push (HevcNalArray { complete, nal_unit_type, nal_units });
+		}
+		Ok (Self {
+			profile_space,
+			tier_high,
+			profile_idc,
+			profile_compatibility,
+			constraint_flags,
+			level_idc,
+			min_spatial_segmentation,
+			parallelism_type,
+			chroma_format,
+			bit_depth_luma,
+			bit_depth_chroma,
+			avg_frame_rate,
+			constant_frame_rate,
+			num_temporal_layers,
+			temporal_id_nested,
+			length_size,
+			nal_arrays,
+		})
+	}
+
+	pub fn profile_name (& self) -> & 'static str {
+		match self.profile_idc {
+			1 => "main",
+			2 => "main10",
+			3 => "mainstill",
+			4 => "rext",
+			5 => "high-throughput",
+			6 => "multiview-main",
+			7 => "scalable-main",
+			8 => "3d-main",
+			9 => "scc",
+			10 => "scalable-rext",
+			11 => "high-throughput-scc",
+			_ => "unknown",
+		}
+	}
+
+	pub fn level (& self) -> String {
+		super::fmt_level (self.level_idc as u64 / 30, self.level_idc as u64 % 30 / 3)
+	}
+
+	/// Summary of the parameter sets and SEI messages carried in the configuration
+	pub fn parameter_sets (& self) -> String {
+		self.nal_arrays.iter ()
+			.map (|array| format! (
+				"{num} {name}",
+				num = array.nal_units.len (),
+				name = nal_unit_type_name (array.nal_unit_type)))
+			.join (", ")
+	}
+
+}
+
+impl fmt::Display for HevcConfig {
+	fn fmt (& self, fmtr: & mut fmt::Formatter) -> fmt::Result {
+		write! (fmtr, "hevc {}", self.profile_name ()) ?;
+		if self.tier_high { write! (fmtr, " high") ?; }
+		write! (fmtr, " L{}", self.level ()) ?;
+		if ! matches! (self.profile_idc, 1 ..= 3) {
+			write! (fmtr, " {}bit {}", self.bit_depth_luma, self.chroma_format) ?;
+		}
+		Ok (())
+	}
+}
+
+impl Debug for HevcNalArray {
+	fn fmt (& self, fmtr: & mut fmt::Formatter) -> fmt::Result {
+		fmtr.debug_struct ("HevcNalArray")
+			.field ("complete", & self.complete)
+			.field ("nal_unit_type", & self.nal_unit_type)
+			.field ("nal_units", & super::fmt_nal_units (& self.nal_units))
+			.finish ()
+	}
+}
+
+fn nal_unit_type_name (nal_unit_type: u8) -> & 'static str {
+	match nal_unit_type {
+		NAL_VPS => "VPS",
+		NAL_SPS => "SPS",
+		NAL_PPS => "PPS",
+		NAL_PREFIX_SEI => "prefix SEI",
+		NAL_SUFFIX_SEI => "suffix SEI",
+		_ => "other",
+	}
+}
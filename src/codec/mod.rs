@@ -0,0 +1,186 @@
+use crate::imports::*;
+use crate::mp4::FourCc;
+
+pub mod av1;
+pub mod avc;
+pub mod hevc;
+pub mod opus;
+
+pub use av1::Av1Config;
+pub use avc::AvcConfig;
+pub use hevc::HevcConfig;
+pub use opus::OpusHead;
+
+#[ derive (Debug) ]
+pub enum CodecConfig {
+	Av1 (Av1Config),
+	Avc (AvcConfig),
+	Hevc (HevcConfig),
+	Opus (OpusHead),
+}
+
+impl CodecConfig {
+
+	pub fn from_matroska (codec_id: & str, data: & [u8]) -> anyhow::Result <Option <Self>> {
+		Ok (Some (match codec_id {
+			"V_AV1" => Self::Av1 (Av1Config::parse (data) ?),
+			"V_MPEG4/ISO/AVC" => Self::Avc (AvcConfig::parse (data) ?),
+			"V_MPEGH/ISO/HEVC" => Self::Hevc (HevcConfig::parse (data) ?),
+			"A_OPUS" => Self::Opus (OpusHead::parse (data) ?),
+			_ => return Ok (None),
+		}))
+	}
+
+	pub fn from_mp4 (config_type: FourCc, data: & [u8]) -> anyhow::Result <Option <Self>> {
+		Ok (Some (match & config_type.0 {
+			b"av1C" => Self::Av1 (Av1Config::parse (data) ?),
+			b"avcC" => Self::Avc (AvcConfig::parse (data) ?),
+			b"hvcC" => Self::Hevc (HevcConfig::parse (data) ?),
+			b"dOps" => Self::Opus (OpusHead::parse_dops (data) ?),
+			_ => return Ok (None),
+		}))
+	}
+
+	pub fn parameter_sets (& self) -> Option <String> {
+		match self {
+			Self::Avc (config) => Some (config.parameter_sets ()),
+			Self::Hevc (config) => Some (config.parameter_sets ()),
+			Self::Av1 (_) | Self::Opus (_) => None,
+		}
+	}
+
+}
+
+impl fmt::Display for CodecConfig {
+	fn fmt (& self, fmtr: & mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Av1 (config) => fmt::Display::fmt (config, fmtr),
+			Self::Avc (config) => fmt::Display::fmt (config, fmtr),
+			Self::Hevc (config) => fmt::Display::fmt (config, fmtr),
+			Self::Opus (config) => fmt::Display::fmt (config, fmtr),
+		}
+	}
+}
+
+#[ derive (Clone, Copy, Debug, Eq, PartialEq) ]
+pub enum ChromaFormat {
+	Monochrome,
+	Yuv420,
+	Yuv422,
+	Yuv444,
+}
+
+impl ChromaFormat {
+	fn from_idc (idc: u64) -> Self {
+		match idc {
+			0 => Self::Monochrome,
+			1 => Self::Yuv420,
+			2 => Self::Yuv422,
+			_ => Self::Yuv444,
+		}
+	}
+}
+
+impl fmt::Display for ChromaFormat {
+	fn fmt (& self, fmtr: & mut fmt::Formatter) -> fmt::Result {
+		fmtr.write_str (match * self {
+			Self::Monochrome => "4:0:0",
+			Self::Yuv420 => "4:2:0",
+			Self::Yuv422 => "4:2:2",
+			Self::Yuv444 => "4:4:4",
+		})
+	}
+}
+
+fn fmt_level (major: u64, minor: u64) -> String {
+	if minor == 0 { format! ("{major}") } else { format! ("{major}.{minor}") }
+}
+
+pub struct BitReader <'dat> {
+	data: & 'dat [u8],
+	pos: usize,
+}
+
+impl <'dat> BitReader <'dat> {
+
+	pub fn new (data: & 'dat [u8]) -> Self {
+		Self { data, pos: 0 }
+	}
+
+	pub fn bit (& mut self) -> anyhow::Result <bool> {
+		let Some (& byte) = self.data.get (self.pos / 8) else {
+			any_bail! ("Unexpected end of data");
+		};
+		let val = byte & (0x80 >> (self.pos % 8)) != 0;
+		self.pos += 1;
+		Ok (val)
+	}
+
+	pub fn bits (& mut self, num: u32) -> anyhow::Result <u64> {
+		debug_assert! (num <= 64);
+		let mut val = 0;
+		for _ in 0 .. num {
+			val = val << 1 | self.bit () ? as u64;
+		}
+		Ok (val)
+	}
+
+	pub fn skip (& mut self, num: usize) -> anyhow::Result <()> {
+		any_ensure! (self.pos + num <= self.data.len () * 8, "Unexpected end of data");
+		self.pos += num;
+		Ok (())
+	}
+
+	pub fn bytes (& mut self, num: usize) -> anyhow::Result <& 'dat [u8]> {
+		any_ensure! (self.pos.is_multiple_of (8), "Unaligned byte read");
+		let start = self.pos / 8;
+		let Some (data) = self.data.get (start .. start + num) else {
+			any_bail! ("Unexpected end of data");
+		};
+		self.pos += num * 8;
+		Ok (data)
+	}
+
+	pub fn u8 (& mut self) -> anyhow::Result <u8> {
+		Ok (self.bits (8) ? as u8)
+	}
+
+	pub fn u16 (& mut self) -> anyhow::Result <u16> {
+		Ok (self.bits (16) ? as u16)
+	}
+
+	pub fn ue (& mut self) -> anyhow::Result <u64> {
+		let mut zeros = 0;
+		while ! self.bit () ? {
+			zeros += 1;
+			any_ensure! (zeros < 64, "Invalid exp-golomb code");
+		}
+		Ok ((1 << zeros) - 1 + self.bits (zeros) ?)
+	}
+
+	pub fn remaining (& self) -> usize {
+		(self.data.len () * 8).saturating_sub (self.pos)
+	}
+
+}
+
+/// Remove emulation prevention bytes from a NAL unit payload
+pub fn unescape_rbsp (data: & [u8]) -> Vec <u8> {
+	let mut result = Vec::with_capacity (data.len ());
+	let mut zeros = 0;
+	for & byte in data {
+		if zeros == 2 && byte == 0x03 {
+			zeros = 0;
+			continue;
+		}
+		if byte == 0 { zeros += 1 } else { zeros = 0 }
+		result.push (byte);
+	}
+	result
+}
+
+fn fmt_nal_units (nal_units: & [Vec <u8>]) -> String {
+	nal_units.iter ()
+		.map (|nal_unit| format! ("{} bytes", nal_unit.len ()))
+		.join (", ")
+}
@@ -0,0 +1,104 @@
+use crate::imports::*;
+
+/// Identification header for an Opus stream (RFC 7845)
+#[ allow (dead_code) ]
+#[ derive (Debug) ]
+pub struct OpusHead {
+	pub version: u8,
+	pub channels: u8,
+	pub pre_skip: u16,
+	pub input_sample_rate: u32,
+	pub output_gain: i16,
+	pub mapping_family: u8,
+	pub mapping: Option <OpusChannelMapping>,
+}
+
+#[ allow (dead_code) ]
+#[ derive (Debug) ]
+pub struct OpusChannelMapping {
+	pub stream_count: u8,
+	pub coupled_count: u8,
+	pub channel_mapping: Vec <u8>,
+}
+
+impl OpusHead {
+
+	pub fn parse (data: & [u8]) -> anyhow::Result <Self> {
+		any_ensure! (19 <= data.len (), "OpusHead too short");
+		any_ensure! (& data [0 .. 8] == b"OpusHead", "Invalid OpusHead magic");
+		let version = data [8];
+		any_ensure! (version & 0xf0 == 0, "Unsupported OpusHead version: {version}");
+		let channels = data [9];
+		let pre_skip = u16::from_le_bytes ([ data [10], data [11] ]);
+		let input_sample_rate = u32::from_le_bytes ([ data [12], data [13], data [14], data [15] ]);
+		let output_gain = i16::from_le_bytes ([ data [16], data [17] ]);
+		let mapping_family = data [18];
+		let mapping = if mapping_family != 0 {
+			let end = 21 + channels as usize;
+			any_ensure! (end <= data.len (), "OpusHead channel mapping table truncated");
+			Some (OpusChannelMapping {
+				stream_count: data [19],
+				coupled_count: data [20],
+				channel_mapping: data [21 .. end].to_vec (),
+			})
+		} else { None };
+		Ok (Self {
+			version,
+			channels,
+			pre_skip,
+			input_sample_rate,
+			output_gain,
+			mapping_family,
+			mapping,
+		})
+	}
+
+	/// Parse the Opus specific box from an ISO base media file, which holds the same fields as
+	/// the identification header but big endian and without the magic
+	pub fn parse_dops (data: & [u8]) -> anyhow::Result <Self> {
+		any_ensure! (11 <= data.len (), "dOps box too short");
+		any_ensure! (data [0] == 0, "Unsupported dOps version: {}", data [0]);
+		let channels = data [1];
+		let mapping_family = data [10];
+		let mapping = if mapping_family != 0 {
+			let end = 13 + channels as usize;
+			any_ensure! (end <= data.len (), "dOps channel mapping table truncated");
+			Some (OpusChannelMapping {
+				stream_count: data [11],
+				coupled_count: data [12],
+				channel_mapping: data [13 .. end].to_vec (),
+			})
+		} else { None };
+		Ok (Self {
+			version: 1,
+			channels,
+			pre_skip: u16::from_be_bytes ([ data [2], data [3] ]),
+			input_sample_rate: u32::from_be_bytes ([ data [4], data [5], data [6], data [7] ]),
+			output_gain: i16::from_be_bytes ([ data [8], data [9] ]),
+			mapping_family,
+			mapping,
+		})
+	}
+
+	/// Channel layout name, using the vorbis channel order which applies to families 0 and 1
+	pub fn layout_name (& self) -> String {
+		match (self.mapping_family, self.channels) {
+			(0 | 1, 1) => "mono".to_owned (),
+			(0 | 1, 2) => "stereo".to_owned (),
+			(1, 3) => "3.0".to_owned (),
+			(1, 4) => "quad".to_owned (),
+			(1, 5) => "5.0".to_owned (),
+			(1, 6) => "5.1".to_owned (),
+			(1, 7) => "6.1".to_owned (),
+			(1, 8) => "7.1".to_owned (),
+			(_, channels) => format! ("{channels}ch"),
+		}
+	}
+
+}
+
+impl fmt::Display for OpusHead {
+	fn fmt (& self, fmtr: & mut fmt::Formatter) -> fmt::Result {
+		write! (fmtr, "opus {} family {}", self.layout_name (), self.mapping_family)
+	}
+}
@@ -1,17 +1,34 @@
 use crate::imports::*;
+use crate::subs::encoding;
 
-#[ derive (Clone, Copy, Debug) ]
+#[ derive (Clone, Copy, Debug, Serialize) ]
+#[ serde (rename_all = "kebab-case") ]
 pub enum FileType {
+	Aac,
+	Ac3,
 	AppleVideo,
+	Asf,
 	Avi,
+	Flac,
+	Flv,
 	IsoMedia,
 	Matroska,
+	Mp3,
 	Mp4v1,
 	Mp4v2,
 	Mpeg1,
-	Mpeg2
+	Mpeg2,
+	MpegTs,
+	M2ts,
+	Ogg,
+	QuickTime,
 }
 
+const ASF_HEADER_GUID: [u8; 16] = [
+	0x30, 0x26, 0xb2, 0x75, 0x8e, 0x66, 0xcf, 0x11,
+	0xa6, 0xd9, 0x00, 0xaa, 0x00, 0x62, 0xce, 0x6c,
+];
+
 impl FileType {
 
 	pub fn identify_path (file_path: & Path) -> Result <FileType, IdentifyError> {
@@ -35,36 +52,274 @@ impl FileType {
 				"Unknown RIFF file type: {:02x} {:02x} {:02x} {:02x}",
 				buf [8], buf [9], buf [10], buf [11])));
 		}
-		if 20 <= buf.len () && & buf [0 .. 3] == b"\0\0\0" && & buf [4 .. 8] == b"ftyp" {
-			if & buf [8 .. 12] == b"isom" { return Ok (FileType::IsoMedia) }
-			if & buf [8 .. 12] == b"mp41" { return Ok (FileType::Mp4v1) }
-			if & buf [8 .. 12] == b"mp42" { return Ok (FileType::Mp4v2) }
-			if & buf [8 .. 12] == b"M4V " { return Ok (FileType::AppleVideo) }
-			return Err (IdentifyError::partial (format! (
-				"Unknown ISOM file type: {:02x} {:02x} {:02x} {:02x}",
-				buf [8], buf [9], buf [10], buf [11])));
+		if 16 <= buf.len () && & buf [0 .. 3] == b"\0\0\0" && & buf [4 .. 8] == b"ftyp" {
+			// check the major brand and then the compatible brands for one we know, anything else
+			// with an ftyp box is still iso base media, eg "avc1", "dash", "iso6"
+			let ftyp_len = (u32::from_be_bytes ([ buf [0], buf [1], buf [2], buf [3] ]) as usize)
+				.clamp (16, buf.len ());
+			let brands =
+				iter::once (& buf [8 .. 12])
+					.chain (buf [16 .. ftyp_len].chunks_exact (4));
+			for brand in brands {
+				match brand {
+					b"isom" => return Ok (FileType::IsoMedia),
+					b"mp41" => return Ok (FileType::Mp4v1),
+					b"mp42" => return Ok (FileType::Mp4v2),
+					b"M4V " => return Ok (FileType::AppleVideo),
+					_ => (),
+				}
+			}
+			return Ok (FileType::IsoMedia);
 		}
 		if 5 <= buf.len () && & buf [0 .. 4] == [ 0x00, 0x00, 0x01, 0xba ] {
 			if buf [4] & 0xf0 == 0x20 { return Ok (FileType::Mpeg1) }
 			if buf [4] & 0xc0 == 0x40 { return Ok (FileType::Mpeg2) }
 			return Err (IdentifyError::partial ("Unknown MPEG program stream file type"));
 		}
+		if 4 <= buf.len () && & buf [0 .. 4] == b"OggS" { return Ok (FileType::Ogg) }
+		if 9 <= buf.len () && & buf [0 .. 3] == b"FLV" && buf [3] == 1 { return Ok (FileType::Flv) }
+		if 16 <= buf.len () && buf [0 .. 16] == ASF_HEADER_GUID { return Ok (FileType::Asf) }
+		if is_quicktime (buf) { return Ok (FileType::QuickTime) }
+		if is_transport_stream (buf, 188, 0) { return Ok (FileType::MpegTs) }
+		if is_transport_stream (buf, 192, 4) { return Ok (FileType::M2ts) }
+		if 4 <= buf.len () && & buf [0 .. 4] == b"fLaC" { return Ok (FileType::Flac) }
+		if 3 <= buf.len () && & buf [0 .. 3] == b"ID3" { return Ok (FileType::Mp3) }
+		if is_adts (buf) { return Ok (FileType::Aac) }
+		if is_mpeg_audio (buf) { return Ok (FileType::Mp3) }
+		if 5 <= buf.len () && buf [0] == 0x0b && buf [1] == 0x77 { return Ok (FileType::Ac3) }
 		return Err (IdentifyError::NotRecognised);
 	}
 
+	pub fn is_iso_media (self) -> bool {
+		matches! (self, Self::AppleVideo | Self::IsoMedia | Self::Mp4v1 | Self::Mp4v2 | Self::QuickTime)
+	}
+
 	pub fn needs_timestamp (self) -> bool {
 		match self {
+			Self::Aac => true,
+			Self::Ac3 => true,
 			Self::AppleVideo => false,
+			Self::Asf => true,
 			Self::Avi => true,
+			Self::Flac => true,
+			Self::Flv => false,
 			Self::IsoMedia => false,
 			Self::Matroska => false,
+			Self::Mp3 => true,
 			Self::Mp4v1 => false,
 			Self::Mp4v2 => false,
 			Self::Mpeg1 => true,
 			Self::Mpeg2 => true,
+			Self::MpegTs => true,
+			Self::M2ts => true,
+			Self::Ogg => false,
+			Self::QuickTime => false,
+		}
+	}
+
+	/// Raw elementary audio streams, which have no video track
+	pub fn is_audio_only (self) -> bool {
+		matches! (self, Self::Aac | Self::Ac3 | Self::Flac | Self::Mp3)
+	}
+
+}
+
+/// Check for quicktime atoms without an ftyp, skipping over any leading free space atoms
+fn is_quicktime (buf: & [u8]) -> bool {
+	let mut pos = 0;
+	while pos + 8 <= buf.len () {
+		match & buf [pos + 4 .. pos + 8] {
+			b"moov" | b"mdat" | b"wide" | b"pnot" => return true,
+			b"free" | b"skip" => (),
+			_ => return false,
 		}
+		let len = u32::from_be_bytes ([ buf [pos], buf [pos + 1], buf [pos + 2], buf [pos + 3] ]);
+		if len < 8 { return false }
+		pos += len as usize;
 	}
+	false
+}
+
+/// Check for transport stream sync bytes at every packet, m2ts packets have an extra four byte
+/// timecode before each one
+fn is_transport_stream (buf: & [u8], packet_size: usize, offset: usize) -> bool {
+	let num_packets = buf.len ().saturating_sub (offset).div_ceil (packet_size);
+	2 <= num_packets
+		&& (0 .. num_packets)
+			.map (|idx| offset + idx * packet_size)
+			.filter (|& pos| pos < buf.len ())
+			.all (|pos| buf [pos] == 0x47)
+}
+
+/// Check for an ADTS header, also checking the following frame if it is in the buffer
+fn is_adts (buf: & [u8]) -> bool {
+	let is_header = |buf: & [u8]| 7 <= buf.len ()
+		&& buf [0] == 0xff && buf [1] & 0xf6 == 0xf0
+		&& (buf [2] >> 2) & 0x0f < 13;
+	if ! is_header (buf) { return false }
+	let frame_len = ((buf [3] & 0x03) as usize) << 11 | (buf [4] as usize) << 3 | (buf [5] >> 5) as usize;
+	if frame_len < 7 { return false }
+	buf.len () < frame_len + 7 || is_header (& buf [frame_len .. ])
+}
+
+/// Check for an MPEG audio frame header with valid version, layer, bitrate and sample rate
+fn is_mpeg_audio (buf: & [u8]) -> bool {
+	4 <= buf.len ()
+		&& buf [0] == 0xff && buf [1] & 0xe0 == 0xe0
+		&& (buf [1] >> 3) & 0x03 != 0x01
+		&& (buf [1] >> 1) & 0x03 != 0x00
+		&& buf [2] >> 4 != 0x0f && buf [2] >> 4 != 0x00
+		&& (buf [2] >> 2) & 0x03 != 0x03
+}
+
+/// Files that accompany a video, such as external subtitles, cover images and fonts
+#[ derive (Clone, Copy, Debug, Eq, PartialEq, Serialize) ]
+#[ serde (rename_all = "kebab-case") ]
+pub enum SidecarType {
+	Ass,
+	Jpeg,
+	MicroDvd,
+	OpenType,
+	Pgs,
+	Png,
+	Srt,
+	Ssa,
+	TrueType,
+	VobSubIdx,
+	VobSubSub,
+	WebP,
+	WebVtt,
+}
+
+impl SidecarType {
+
+	pub fn identify_path (file_path: & Path) -> Result <SidecarType, IdentifyError> {
+		let mut buf = vec! [0; 4096];
+		let mut file = File::open (file_path) ?;
+		let bytes_read = file.read (& mut buf) ?;
+		let extension =
+			file_path.extension ()
+				.map (|extension| extension.to_string_lossy ().to_ascii_lowercase ());
+		Self::identify_slice (& buf [ .. bytes_read], extension.as_deref ())
+	}
+
+	/// Identify a sidecar file from its initial contents, the extension is only needed to tell a
+	/// vobsub sub file from any other mpeg program stream
+	pub fn identify_slice (buf: & [u8], extension: Option <& str>) -> Result <SidecarType, IdentifyError> {
+		if 3 <= buf.len () && buf [0 .. 3] == [ 0xff, 0xd8, 0xff ] { return Ok (Self::Jpeg) }
+		if 8 <= buf.len () && & buf [0 .. 8] == b"\x89PNG\r\n\x1a\n" { return Ok (Self::Png) }
+		if 12 <= buf.len () && & buf [0 .. 4] == b"RIFF" && & buf [8 .. 12] == b"WEBP" {
+			return Ok (Self::WebP);
+		}
+		if 4 <= buf.len () && (buf [0 .. 4] == [ 0, 1, 0, 0 ] || & buf [0 .. 4] == b"true") {
+			return Ok (Self::TrueType);
+		}
+		if 4 <= buf.len () && & buf [0 .. 4] == b"OTTO" { return Ok (Self::OpenType) }
+		if 4 <= buf.len () && & buf [0 .. 4] == b"ttcf" { return Ok (Self::TrueType) }
+		if 13 <= buf.len () && & buf [0 .. 2] == b"PG"
+				&& matches! (buf [10], 0x14 | 0x15 | 0x16 | 0x17 | 0x80) {
+			return Ok (Self::Pgs);
+		}
+		if 4 <= buf.len () && buf [0 .. 4] == [ 0x00, 0x00, 0x01, 0xba ] {
+			if extension == Some ("sub") { return Ok (Self::VobSubSub) }
+			return Err (IdentifyError::partial ("MPEG program stream, not a subtitle file"));
+		}
+		let text = decode_text (buf);
+		let mut lines = text.lines ().map (str::trim).skip_while (|line| line.is_empty ());
+		let Some (first_line) = lines.next () else { return Err (IdentifyError::NotRecognised) };
+		if first_line.starts_with ("# VobSub index file") { return Ok (Self::VobSubIdx) }
+		if first_line == "WEBVTT" || first_line.starts_with ("WEBVTT ") || first_line.starts_with ("WEBVTT\t") {
+			return Ok (Self::WebVtt);
+		}
+		if first_line.eq_ignore_ascii_case ("[Script Info]") {
+			let is_ass = text.lines ().any (|line| {
+				let line = line.trim ();
+				line.eq_ignore_ascii_case ("[V4+ Styles]")
+					|| line.get (0 .. 11).is_some_and (|key| key.eq_ignore_ascii_case ("ScriptType:"))
+						&& line [11 .. ].trim ().eq_ignore_ascii_case ("v4.00+")
+			});
+			return Ok (if is_ass { Self::Ass } else { Self::Ssa });
+		}
+		if is_microdvd_line (first_line) { return Ok (Self::MicroDvd) }
+		if first_line.chars ().all (|ch| ch.is_ascii_digit ())
+				&& lines.next ().is_some_and (is_srt_timing_line) {
+			return Ok (Self::Srt);
+		}
+		Err (IdentifyError::NotRecognised)
+	}
+
+	pub fn is_subtitle (self) -> bool {
+		matches! (
+			self,
+			Self::Ass | Self::MicroDvd | Self::Pgs | Self::Srt | Self::Ssa | Self::VobSubIdx
+				| Self::VobSubSub | Self::WebVtt)
+	}
+
+	/// Subtitles stored as text, which need to be UTF-8 in matroska
+	pub fn is_text_subtitle (self) -> bool {
+		matches! (self, Self::Ass | Self::MicroDvd | Self::Srt | Self::Ssa | Self::WebVtt)
+	}
+
+	pub fn name (self) -> & 'static str {
+		match self {
+			Self::Ass => "advanced substation alpha subtitles",
+			Self::Jpeg => "jpeg image",
+			Self::MicroDvd => "microdvd subtitles",
+			Self::OpenType => "opentype font",
+			Self::Pgs => "pgs subtitles",
+			Self::Png => "png image",
+			Self::Srt => "subrip subtitles",
+			Self::Ssa => "substation alpha subtitles",
+			Self::TrueType => "truetype font",
+			Self::VobSubIdx => "vobsub index",
+			Self::VobSubSub => "vobsub data",
+			Self::WebP => "webp image",
+			Self::WebVtt => "webvtt subtitles",
+		}
+	}
+
+}
+
+impl fmt::Display for SidecarType {
+	fn fmt (& self, fmtr: & mut fmt::Formatter) -> fmt::Result {
+		fmtr.write_str (self.name ())
+	}
+}
+
+/// Decode the start of a text file for identification, handling byte order marks and UTF-16, but
+/// treating anything else as UTF-8 since only the ASCII structure matters
+fn decode_text (buf: & [u8]) -> String {
+	let (encoding, _, rest) = encoding::detect (buf);
+	if encoding == encoding::Encoding::UTF_16LE || encoding == encoding::Encoding::UTF_16BE {
+		encoding.decode_lossy (rest)
+	} else {
+		String::from_utf8_lossy (rest).into_owned ()
+	}
+}
+
+/// Check for a microdvd line such as "{100}{200}Some text"
+fn is_microdvd_line (line: & str) -> bool {
+	let mut rest = line;
+	for _ in 0 .. 2 {
+		let Some (after) = rest.strip_prefix ('{') else { return false };
+		let Some ((frame, after)) = after.split_once ('}') else { return false };
+		if ! frame.chars ().all (|ch| ch.is_ascii_digit ()) { return false }
+		rest = after;
+	}
+	true
+}
 
+/// Check for a subrip timing line such as "00:01:02,345 --> 00:01:04,000"
+fn is_srt_timing_line (line: & str) -> bool {
+	let Some ((start, end)) = line.trim ().split_once ("-->") else { return false };
+	let is_time = |time: & str| {
+		let time = time.trim ().split (' ').next ().unwrap_or_default ();
+		let Some ((hms, millis)) = time.split_once ([ ',', '.' ]) else { return false };
+		hms.split (':').count () == 3
+			&& hms.split (':').all (|part| ! part.is_empty () && part.chars ().all (|ch| ch.is_ascii_digit ()))
+			&& ! millis.is_empty () && millis.chars ().all (|ch| ch.is_ascii_digit ())
+	};
+	is_time (start) && is_time (end)
 }
 
 #[ derive (Debug, thiserror::Error) ]
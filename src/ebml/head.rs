@@ -1,7 +1,7 @@
 use crate::imports::*;
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ derive (Debug, Serialize) ]
 pub struct EbmlElem {
 	pub version: u64,
 	pub read_version: u64,
@@ -28,7 +28,7 @@ impl EbmlValue for EbmlElem {
 }
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ derive (Debug, Serialize) ]
 pub struct EbmlDocTypeExtElem {
 	pub name: String,
 	pub version: u64,
@@ -1,3 +1,4 @@
 pub mod head;
 pub mod reader;
 pub mod spec;
+pub mod writer;
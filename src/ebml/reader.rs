@@ -1,9 +1,39 @@
 use crate::imports::*;
 
+/// Binary element data, exactly as stored
 pub type Blob = Vec <u8>;
 
+/// Serialize binary data as a lowercase hex string
+pub struct BlobHex;
+
+impl serde_with::SerializeAs <Vec <u8>> for BlobHex {
+	fn serialize_as <Ser: serde::Serializer> (
+		source: & Vec <u8>,
+		serializer: Ser,
+	) -> Result <Ser::Ok, Ser::Error> {
+		let mut hex = String::with_capacity (source.len () * 2);
+		for byte in source { let _ = write! (& mut hex, "{byte:02x}"); }
+		serializer.serialize_str (& hex)
+	}
+}
+
+impl <'de> serde_with::DeserializeAs <'de, Vec <u8>> for BlobHex {
+	fn deserialize_as <De: serde::Deserializer <'de>> (
+		deserializer: De,
+	) -> Result <Vec <u8>, De::Error> {
+		let hex = String::deserialize (deserializer) ?;
+		let hex: String = hex.chars ().filter (|ch| ! ch.is_ascii_whitespace ()).collect ();
+		if ! hex.len ().is_multiple_of (2) || ! hex.bytes ().all (|byte| byte.is_ascii_hexdigit ()) {
+			return Err (serde::de::Error::custom ("expected an even number of hex digits"));
+		}
+		Ok ((0 .. hex.len ()).step_by (2)
+			.map (|idx| u8::from_str_radix (& hex [idx .. idx + 2], 16).unwrap ())
+			.collect ())
+	}
+}
+
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ derive (Debug, Serialize) ]
 pub struct BlobRef {
 	pub start: u64,
 	pub end: u64,
@@ -57,12 +87,11 @@ pub trait EbmlRead {
 		}
 	}
 
+	/// Binary data exactly as stored. Only strings are padded with zeros, for binary elements such
+	/// as UUIDs, codec private data and block additions they are part of the value, so every `Blob`
+	/// field keeps them.
 	fn binary (& mut self) -> io::Result <Vec <u8>> {
-		let mut data = self.data () ?;
-		while ! data.is_empty () && data [data.len () - 1] == 0 {
-			data.pop ().unwrap ();
-		}
-		Ok (data)
+		self.data ()
 	}
 
 	fn string (& mut self) -> io::Result <String> {
@@ -340,3 +340,83 @@ macro_rules! ebml_elem_read {
 		let mut $name = $spec.field_reader_mul_def ($default);
 	};
 }
+
+#[ macro_export ]
+macro_rules! ebml_enum {
+	(
+		$enum_vis:vis enum $enum_name:ident {
+			$( $var_name:ident = $var_val:literal, $var_display:expr; )*
+		}
+	) => {
+		#[ allow (dead_code) ]
+		#[ derive (Clone, Copy, Eq, Hash, PartialEq) ]
+		$enum_vis enum $enum_name {
+			$( $var_name, )*
+			Unknown (u64),
+		}
+
+		#[ allow (dead_code) ]
+		impl $enum_name {
+			pub const fn value (self) -> u64 {
+				match self {
+					$( Self::$var_name => $var_val, )*
+					Self::Unknown (val) => val,
+				}
+			}
+			pub const fn name (self) -> & 'static str {
+				match self {
+					$( Self::$var_name => $var_display, )*
+					Self::Unknown (_) => "unknown",
+				}
+			}
+		}
+
+		impl From <u64> for $enum_name {
+			fn from (val: u64) -> Self {
+				match val {
+					$( $var_val => Self::$var_name, )*
+					_ => Self::Unknown (val),
+				}
+			}
+		}
+
+		impl From <$enum_name> for u64 {
+			fn from (val: $enum_name) -> u64 {
+				val.value ()
+			}
+		}
+
+		impl ::std::fmt::Display for $enum_name {
+			fn fmt (& self, fmtr: & mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+				match * self {
+					Self::Unknown (val) => write! (fmtr, "unknown ({val})"),
+					_ => fmtr.write_str (self.name ()),
+				}
+			}
+		}
+
+		impl ::std::fmt::Debug for $enum_name {
+			fn fmt (& self, fmtr: & mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+				match * self {
+					Self::Unknown (val) => write! (fmtr, "unknown ({val})"),
+					_ => write! (fmtr, "{} ({})", self.name (), self.value ()),
+				}
+			}
+		}
+
+		impl ::serde::Serialize for $enum_name {
+			fn serialize <Ser: ::serde::Serializer> (
+				& self,
+				serializer: Ser,
+			) -> Result <Ser::Ok, Ser::Error> {
+				serializer.collect_str (self)
+			}
+		}
+
+		impl $crate::ebml::reader::EbmlValue for $enum_name {
+			fn read (reader: & mut dyn $crate::ebml::reader::EbmlRead) -> anyhow::Result <Self> {
+				Ok (reader.unsigned () ?.into ())
+			}
+		}
+	};
+}
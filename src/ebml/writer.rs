@@ -0,0 +1,77 @@
+/// Builds EBML encoded elements in memory
+///
+/// Master elements are written with a placeholder size which is replaced with the real size,
+/// using as few bytes as possible, when they are closed.
+#[ derive (Default) ]
+pub struct EbmlWriter {
+	buf: Vec <u8>,
+	open: Vec <usize>,
+}
+
+impl EbmlWriter {
+
+	pub fn new () -> Self {
+		Self::default ()
+	}
+
+	pub fn start (& mut self, elem_id: u64) {
+		self.buf.extend (encode_id (elem_id));
+		self.open.push (self.buf.len ());
+		self.buf.extend ([0x01, 0, 0, 0, 0, 0, 0, 0]);
+	}
+
+	pub fn end (& mut self) {
+		// only the content of this element follows the placeholder, so it is safe to resize
+		let size_pos = self.open.pop ().unwrap ();
+		let size = (self.buf.len () - size_pos - 8) as u64;
+		let size_bytes = encode_size (size, size_width (size)).unwrap ();
+		self.buf.splice (size_pos .. size_pos + 8, size_bytes);
+	}
+
+	pub fn unsigned (& mut self, elem_id: u64, val: u64) {
+		let bytes = val.to_be_bytes ();
+		let skip = usize::min (val.leading_zeros () as usize / 8, 7);
+		self.binary (elem_id, & bytes [skip .. ]);
+	}
+
+	pub fn boolean (& mut self, elem_id: u64, val: bool) {
+		self.unsigned (elem_id, val as u64);
+	}
+
+	pub fn string (& mut self, elem_id: u64, val: & str) {
+		self.binary (elem_id, val.as_bytes ());
+	}
+
+	pub fn binary (& mut self, elem_id: u64, val: & [u8]) {
+		self.buf.extend (encode_id (elem_id));
+		self.buf.extend (encode_size (val.len () as u64, size_width (val.len () as u64)).unwrap ());
+		self.buf.extend (val);
+	}
+
+	pub fn into_bytes (self) -> Vec <u8> {
+		assert! (self.open.is_empty (), "Unclosed master element");
+		self.buf
+	}
+
+}
+
+/// Encode an element id, which already includes its length marker
+pub fn encode_id (elem_id: u64) -> Vec <u8> {
+	let bytes = elem_id.to_be_bytes ();
+	let skip = usize::min (elem_id.leading_zeros () as usize / 8, 7);
+	bytes [skip .. ].to_vec ()
+}
+
+/// Encode an element size using a specific number of bytes, or `None` if it doesn't fit
+pub fn encode_size (size: u64, width: usize) -> Option <Vec <u8>> {
+	if ! (1 ..= 8).contains (& width) { return None }
+	// all ones is reserved to mean unknown size
+	if (1 << (width * 7)) - 1 <= size { return None }
+	let marked = size | 1 << (width * 7);
+	Some (marked.to_be_bytes () [8 - width .. ].to_vec ())
+}
+
+/// Smallest number of bytes needed to encode an element size
+pub fn size_width (size: u64) -> usize {
+	(1 ..= 8).find (|& width| size < (1 << (width * 7)) - 1).unwrap ()
+}
@@ -249,6 +249,7 @@ pub fn probe (file_path: & Path) -> anyhow::Result <Info> {
 	command.push ("json".into ());
 	command.push ("-show_format".into ());
 	command.push ("-show_streams".into ());
+	command.push ("-show_chapters".into ());
 	command.push ({
 		let mut val = OsString::from ("file:");
 		val.push (file_path);
@@ -265,35 +266,193 @@ pub fn probe (file_path: & Path) -> anyhow::Result <Info> {
 	let data: FfData = serde_json::from_slice (& output.stdout) ?;
 	Ok (Info {
 		duration: data.format.duration,
-		streams: data.streams.iter ()
-			.map (|stream| Ok (Stream {
+		bit_rate: data.format.bit_rate,
+		tags: data.format.tags,
+		streams: data.streams.into_iter ()
+			.map (|mut stream| Stream {
+				index: stream.index,
 				stream_type: match & * stream.codec_type {
 					"attachment" => StreamType::Attachment,
 					"audio" => StreamType::Audio,
-					"data" => StreamType::Data,
 					"subtitle" => StreamType::Subtitle,
 					"video" => StreamType::Video,
-					codec_type => any_bail! ("Invalid codec type: {codec_type}"),
+					_ => StreamType::Data,
 				},
-				codec_name: stream.codec_name.clone (),
-			}))
-			.try_collect () ?,
+				codec_name: stream.codec_name,
+				language: stream.tags.remove ("language"),
+				title: stream.tags.remove ("title"),
+				filename: stream.tags.remove ("filename"),
+				mimetype: stream.tags.remove ("mimetype"),
+				disposition: stream.disposition,
+				channels: stream.channels,
+				channel_layout: stream.channel_layout,
+				sample_rate: stream.sample_rate,
+				width: stream.width,
+				height: stream.height,
+				sample_aspect_ratio: stream.sample_aspect_ratio.as_deref ().and_then (|val| Rational::parse (val, ':')),
+				display_aspect_ratio: stream.display_aspect_ratio.as_deref ().and_then (|val| Rational::parse (val, ':')),
+				pix_fmt: stream.pix_fmt,
+				field_order: stream.field_order,
+				r_frame_rate: stream.r_frame_rate.as_deref ().and_then (|val| Rational::parse (val, '/')),
+				avg_frame_rate: stream.avg_frame_rate.as_deref ().and_then (|val| Rational::parse (val, '/')),
+				bit_rate: stream.bit_rate,
+				color_primaries: stream.color_primaries,
+				color_transfer: stream.color_transfer,
+				color_space: stream.color_space,
+				side_data: stream.side_data_list,
+				tags: stream.tags,
+			})
+			.collect (),
+		chapters: data.chapters.into_iter ()
+			.map (|mut chapter| Chapter {
+				start: chapter.start_time,
+				end: chapter.end_time,
+				title: chapter.tags.remove ("title"),
+			})
+			.collect (),
 	})
 }
 
+/// Read the side data from the first frame of a stream, where HDR10+ metadata and (for some
+/// containers) the static HDR metadata are found
+pub fn probe_frame_side_data (file_path: & Path, stream_index: usize) -> anyhow::Result <Vec <SideData>> {
+	let mut command: Vec <OsString> = Vec::new ();
+	command.push ("-hide_banner".into ());
+	command.push ("-print_format".into ());
+	command.push ("json".into ());
+	command.push ("-select_streams".into ());
+	command.push (stream_index.to_string ().into ());
+	command.push ("-read_intervals".into ());
+	command.push ("%+#1".into ());
+	command.push ("-show_entries".into ());
+	command.push ("frame=side_data_list".into ());
+	command.push ({
+		let mut val = OsString::from ("file:");
+		val.push (file_path);
+		val
+	});
+	let output =
+		process::Command::new ("ffprobe")
+			.args (command)
+			.output () ?;
+	if ! output.status.success () {
+		io::stderr ().write_all (& output.stderr) ?;
+		any_bail! ("Error invoking ffprobe: {}", output.status);
+	}
+	let data: FfFrames = serde_json::from_slice (& output.stdout) ?;
+	Ok (data.frames.into_iter ().flat_map (|frame| frame.side_data_list).collect ())
+}
+
 #[ derive (Debug) ]
 pub struct Info {
-	pub duration: f64,
+	pub duration: Option <f64>,
+	pub bit_rate: Option <u64>,
+	pub tags: BTreeMap <String, String>,
 	pub streams: Vec <Stream>,
+	pub chapters: Vec <Chapter>,
 }
 
 #[ derive (Debug) ]
 pub struct Stream {
+	pub index: usize,
 	pub stream_type: StreamType,
 	pub codec_name: Option <String>,
+	pub language: Option <String>,
+	pub title: Option <String>,
+	pub filename: Option <String>,
+	pub mimetype: Option <String>,
+	pub disposition: BTreeMap <String, u8>,
+	pub channels: Option <u32>,
+	pub channel_layout: Option <String>,
+	pub sample_rate: Option <u32>,
+	pub width: Option <u32>,
+	pub height: Option <u32>,
+	pub sample_aspect_ratio: Option <Rational>,
+	pub display_aspect_ratio: Option <Rational>,
+	pub pix_fmt: Option <String>,
+	pub field_order: Option <String>,
+	pub r_frame_rate: Option <Rational>,
+	pub avg_frame_rate: Option <Rational>,
+	pub bit_rate: Option <u64>,
+	pub color_primaries: Option <String>,
+	pub color_transfer: Option <String>,
+	pub color_space: Option <String>,
+	pub side_data: Vec <SideData>,
+	pub tags: BTreeMap <String, String>,
+}
+
+/// Side data for a stream or frame, with the fields used for HDR metadata
+///
+/// Mastering display values are fractions, eg "34000/50000" for chromaticity or "10000000/10000"
+/// for luminance, while the others are plain numbers.
+#[ derive (Debug, Deserialize) ]
+pub struct SideData {
+	pub side_data_type: String,
+	pub red_x: Option <String>,
+	pub red_y: Option <String>,
+	pub green_x: Option <String>,
+	pub green_y: Option <String>,
+	pub blue_x: Option <String>,
+	pub blue_y: Option <String>,
+	pub white_point_x: Option <String>,
+	pub white_point_y: Option <String>,
+	pub min_luminance: Option <String>,
+	pub max_luminance: Option <String>,
+	pub max_content: Option <u64>,
+	pub max_average: Option <u64>,
+	pub dv_version_major: Option <u8>,
+	pub dv_version_minor: Option <u8>,
+	pub dv_profile: Option <u8>,
+	pub dv_level: Option <u8>,
+	pub rpu_present_flag: Option <u8>,
+	pub el_present_flag: Option <u8>,
+	pub bl_present_flag: Option <u8>,
+	pub dv_bl_signal_compatibility_id: Option <u8>,
 }
 
-#[ derive (Clone, Copy, Debug, Eq, PartialEq) ]
+#[ derive (Debug) ]
+pub struct Chapter {
+	pub start: f64,
+	pub end: f64,
+	pub title: Option <String>,
+}
+
+/// A ratio as reported by ffprobe, eg "24000/1001" for frame rates or "16:9" for aspect ratios
+#[ derive (Clone, Copy, Debug, Eq, PartialEq, Serialize) ]
+pub struct Rational {
+	pub num: u64,
+	pub den: u64,
+}
+
+impl Rational {
+
+	/// Create a ratio in lowest terms, or `None` if either side is zero
+	pub fn new (num: u64, den: u64) -> Option <Self> {
+		if num == 0 || den == 0 { return None }
+		let (mut gcd, mut rem) = (num, den);
+		while rem != 0 { (gcd, rem) = (rem, gcd % rem) }
+		Some (Self { num: num / gcd, den: den / gcd })
+	}
+
+	fn parse (val: & str, sep: char) -> Option <Self> {
+		let (num, den) = val.split_once (sep) ?;
+		Self::new (num.parse ().ok () ?, den.parse ().ok () ?)
+	}
+
+	pub fn as_f64 (self) -> f64 {
+		self.num as f64 / self.den as f64
+	}
+
+}
+
+impl fmt::Display for Rational {
+	fn fmt (& self, fmtr: & mut fmt::Formatter) -> fmt::Result {
+		write! (fmtr, "{}/{}", self.num, self.den)
+	}
+}
+
+#[ derive (Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize) ]
+#[ serde (rename_all = "kebab-case") ]
 pub enum StreamType {
 	Attachment,
 	Audio,
@@ -302,21 +461,94 @@ pub enum StreamType {
 	Video,
 }
 
+impl StreamType {
+
+	/// Stream specifier used by ffmpeg options, eg "a" in "-c:a:0"
+	pub fn specifier (self) -> & 'static str {
+		match self {
+			Self::Attachment => "t",
+			Self::Audio => "a",
+			Self::Data => "d",
+			Self::Subtitle => "s",
+			Self::Video => "v",
+		}
+	}
+
+}
+
 #[ derive (Debug, Deserialize) ]
 struct FfData {
 	format: FfFormat,
 	streams: Vec <FfStream>,
+	#[ serde (default) ]
+	chapters: Vec <FfChapter>,
 }
 
 #[ serde_as ]
 #[ derive (Debug, Deserialize) ]
 struct FfFormat {
-	#[ serde_as (as = "DisplayFromStr") ]
-	duration: f64,
+	#[ serde_as (as = "Option <DisplayFromStr>") ]
+	#[ serde (default) ]
+	duration: Option <f64>,
+	#[ serde_as (as = "Option <DisplayFromStr>") ]
+	#[ serde (default) ]
+	bit_rate: Option <u64>,
+	#[ serde (default) ]
+	tags: BTreeMap <String, String>,
 }
 
+#[ serde_as ]
 #[ derive (Debug, Deserialize) ]
 struct FfStream {
+	index: usize,
 	codec_name: Option <String>,
 	codec_type: String,
+	#[ serde (default) ]
+	tags: BTreeMap <String, String>,
+	#[ serde (default) ]
+	disposition: BTreeMap <String, u8>,
+	channels: Option <u32>,
+	channel_layout: Option <String>,
+	#[ serde_as (as = "Option <DisplayFromStr>") ]
+	#[ serde (default) ]
+	sample_rate: Option <u32>,
+	width: Option <u32>,
+	height: Option <u32>,
+	sample_aspect_ratio: Option <String>,
+	display_aspect_ratio: Option <String>,
+	pix_fmt: Option <String>,
+	field_order: Option <String>,
+	r_frame_rate: Option <String>,
+	avg_frame_rate: Option <String>,
+	#[ serde_as (as = "Option <DisplayFromStr>") ]
+	#[ serde (default) ]
+	bit_rate: Option <u64>,
+	color_primaries: Option <String>,
+	color_transfer: Option <String>,
+	color_space: Option <String>,
+	#[ serde (default) ]
+	side_data_list: Vec <SideData>,
+}
+
+#[ derive (Debug, Deserialize) ]
+struct FfFrames {
+	#[ serde (default) ]
+	frames: Vec <FfFrame>,
+}
+
+#[ derive (Debug, Deserialize) ]
+struct FfFrame {
+	#[ serde (default) ]
+	side_data_list: Vec <SideData>,
+}
+
+#[ serde_as ]
+#[ derive (Debug, Deserialize) ]
+struct FfChapter {
+	#[ serde_as (as = "DisplayFromStr") ]
+	start_time: f64,
+	#[ serde_as (as = "DisplayFromStr") ]
+	end_time: f64,
+	#[ serde (default) ]
+	tags: BTreeMap <String, String>,
 }
@@ -0,0 +1,179 @@
+//! Names of TrueType and OpenType fonts, to find the files for fonts named in subtitles
+//!
+//! Renderers like libass match a font name against the family, full and PostScript names in the
+//! `name` table, so all of these are read. Collections hold several fonts, each with their own
+//! table directory, and the names of all of them are returned. Only the tables needed are read,
+//! so large fonts can be scanned quickly.
+
+use crate::imports::*;
+
+/// Give up on collections claiming more fonts than this
+const MAX_COLLECTION_FONTS: u32 = 256;
+
+#[ derive (Clone, Copy, Debug, Eq, PartialEq) ]
+pub enum FontKind {
+	TrueType,
+	OpenType,
+	Collection,
+}
+
+impl FontKind {
+
+	pub fn identify (magic: & [u8]) -> Option <Self> {
+		match magic.get (0 .. 4) ? {
+			[ 0, 1, 0, 0 ] | b"true" => Some (Self::TrueType),
+			b"OTTO" => Some (Self::OpenType),
+			b"ttcf" => Some (Self::Collection),
+			_ => None,
+		}
+	}
+
+	/// Media type for matroska attachments, as given in RFC 9559
+	pub fn media_type (self) -> & 'static str {
+		match self {
+			Self::TrueType => "font/ttf",
+			Self::OpenType => "font/otf",
+			Self::Collection => "font/collection",
+		}
+	}
+
+}
+
+/// Check for a media type used for fonts in matroska attachments, including the older ones
+pub fn is_font_media_type (media_type: & str) -> bool {
+	media_type.starts_with ("font/") || matches! (media_type,
+		"application/x-truetype-font" | "application/x-font-ttf" | "application/x-font-otf"
+			| "application/vnd.ms-opentype" | "application/font-sfnt")
+}
+
+/// Names a font can be referred to by, for every face in the file
+#[ derive (Clone, Debug, Default) ]
+pub struct FontNames {
+	pub families: Vec <String>,
+	/// Full and PostScript names, which name a single face
+	pub faces: Vec <String>,
+}
+
+impl FontNames {
+
+	/// Check if a font name from subtitles refers to this font, ignoring case and the "@" prefix
+	/// used to ask for vertical text
+	pub fn matches (& self, font_name: & str) -> bool {
+		let font_name = font_name.trim_start_matches ('@');
+		self.families.iter ().chain (& self.faces).any (|name| name.eq_ignore_ascii_case (font_name))
+	}
+
+	fn add (names: & mut Vec <String>, name: String) {
+		if ! names.contains (& name) { names.push (name) }
+	}
+
+}
+
+/// Read the kind of font and the names it can be referred to by
+pub fn read_names (mut src: impl Read + Seek) -> anyhow::Result <(FontKind, FontNames)> {
+	let header = read_at (& mut src, 0, 12) ?;
+	let kind = FontKind::identify (& header)
+		.ok_or_else (|| any_err! ("Not a TrueType or OpenType font")) ?;
+	let offsets = if kind == FontKind::Collection {
+		let num_fonts = read_u32 (& header, 8);
+		any_ensure! (num_fonts <= MAX_COLLECTION_FONTS, "Too many fonts in collection: {num_fonts}");
+		let data = read_at (& mut src, 12, num_fonts as usize * 4) ?;
+		(0 .. num_fonts as usize).map (|font_idx| read_u32 (& data, font_idx * 4) as u64).collect ()
+	} else { vec! [ 0 ] };
+	let mut names = FontNames::default ();
+	for offset in offsets {
+		read_face_names (& mut src, offset, & mut names) ?;
+	}
+	Ok ((kind, names))
+}
+
+fn read_face_names (src: & mut (impl Read + Seek), offset: u64, names: & mut FontNames) -> anyhow::Result <()> {
+
+	// find the name table in the table directory
+
+	let num_tables = read_u16 (& read_at (src, offset + 4, 2) ?, 0) as usize;
+	let directory = read_at (src, offset + 12, num_tables * 16) ?;
+	let Some (record) = directory.chunks_exact (16).find (|record| & record [0 .. 4] == b"name") else {
+		any_bail! ("No name table");
+	};
+	let table = read_at (src, read_u32 (record, 8) as u64, read_u32 (record, 12) as usize) ?;
+	any_ensure! (6 <= table.len (), "Name table is too short");
+
+	// decode the family, full and postscript names, which are repeated for each platform and
+	// language, so only keep distinct ones, the typographic family is ignored since it groups
+	// widths like condensed under the same family, and renderers don't use it
+
+	let num_records = read_u16 (& table, 2) as usize;
+	let strings_offset = read_u16 (& table, 4) as usize;
+	for record in table.get (6 .. 6 + num_records * 12).unwrap_or_default ().chunks_exact (12) {
+		let platform_id = read_u16 (record, 0);
+		let encoding_id = read_u16 (record, 2);
+		let name_id = read_u16 (record, 6);
+		if ! matches! (name_id, 1 | 4 | 6) { continue }
+		let start = strings_offset + read_u16 (record, 10) as usize;
+		let Some (data) = table.get (start .. start + read_u16 (record, 8) as usize) else { continue };
+		let name = match (platform_id, encoding_id) {
+			(0, _) | (3, 0 | 1 | 10) => String::from_utf16_lossy (
+				& data.chunks_exact (2).map (|pair| u16::from_be_bytes ([ pair [0], pair [1] ])).collect::<Vec <_>> ()),
+			// mac roman is only the same as ascii for the first half
+			(1, 0) if data.is_ascii () => String::from_utf8_lossy (data).into_owned (),
+			_ => continue,
+		};
+		let name = name.trim ().to_owned ();
+		if name.is_empty () { continue }
+		FontNames::add (if name_id == 1 { & mut names.families } else { & mut names.faces }, name);
+	}
+	Ok (())
+
+}
+
+fn read_at (src: & mut (impl Read + Seek), pos: u64, len: usize) -> anyhow::Result <Vec <u8>> {
+	src.seek (SeekFrom::Start (pos)) ?;
+	let mut data = vec! [0; len];
+	src.read_exact (& mut data).context ("Font file is truncated") ?;
+	Ok (data)
+}
+
+fn read_u16 (data: & [u8], pos: usize) -> u16 {
+	u16::from_be_bytes ([ data [pos], data [pos + 1] ])
+}
+
+fn read_u32 (data: & [u8], pos: usize) -> u32 {
+	u32::from_be_bytes (data [pos .. pos + 4].try_into ().unwrap ())
+}
+
+/// A font file found in a directory
+pub struct FontFile {
+	pub path: PathBuf,
+	pub kind: FontKind,
+	pub names: FontNames,
+}
+
+/// Font files in a directory and its subdirectories, skipping any which can't be read
+pub fn scan_dir (dir: & Path) -> anyhow::Result <Vec <FontFile>> {
+	let mut fonts = Vec::new ();
+	let mut dirs = vec! [ dir.to_owned () ];
+	while let Some (dir) = dirs.pop () {
+		let mut entries = fs::read_dir (& dir)
+			.with_context (|| any_err! ("Error reading fonts directory: {}", dir.display ())) ?
+			.collect::<io::Result <Vec <_>>> () ?;
+		entries.sort_by_key (fs::DirEntry::path);
+		for entry in entries {
+			let path = entry.path ();
+			if entry.file_type () ?.is_dir () {
+				dirs.push (path);
+				continue;
+			}
+			let is_font = path.extension ()
+				.map (|ext| ext.to_string_lossy ().to_ascii_lowercase ())
+				.is_some_and (|ext| matches! (ext.as_str (), "otc" | "otf" | "ttc" | "ttf"));
+			if ! is_font { continue }
+			match File::open (& path).map_err (anyhow::Error::from)
+					.and_then (|file| read_names (BufReader::new (file))) {
+				Ok ((kind, names)) => fonts.push (FontFile { path, kind, names }),
+				Err (err) => eprintln! ("Warning: Ignoring font {}: {err:#}", path.display ()),
+			}
+		}
+	}
+	Ok (fonts)
+}
@@ -0,0 +1,144 @@
+/// Match a shell style glob pattern against some text
+///
+/// Supports `?`, `*` (which doesn't match `/`), `**` (which does), character classes such as
+/// `[abc]`, `[a-z]` and `[!abc]`, and a backslash to match the next character literally.
+pub fn matches (pattern: & str, text: & str) -> bool {
+	let pattern: Vec <char> = pattern.chars ().collect ();
+	let text: Vec <char> = text.chars ().collect ();
+	matches_real (& pattern, & text)
+}
+
+fn matches_real (pattern: & [char], text: & [char]) -> bool {
+	let Some (& pat_char) = pattern.first () else { return text.is_empty () };
+	match pat_char {
+		'*' if pattern.get (1) == Some (& '*') => {
+			let rest = & pattern [2 .. ];
+			if let Some (rest) = rest.strip_prefix (& [ '/' ]) {
+				// zero or more whole directories
+				matches_real (rest, text)
+					|| (0 .. text.len ()).any (|idx|
+						text [idx] == '/' && matches_real (rest, & text [idx + 1 .. ]))
+			} else {
+				(0 ..= text.len ()).any (|idx| matches_real (rest, & text [idx .. ]))
+			}
+		},
+		'*' => {
+			let rest = & pattern [1 .. ];
+			for idx in 0 ..= text.len () {
+				if matches_real (rest, & text [idx .. ]) { return true }
+				if text.get (idx) == Some (& '/') { break }
+			}
+			false
+		},
+		'\\' if 1 < pattern.len () => {
+			text.first () == Some (& pattern [1])
+				&& matches_real (& pattern [2 .. ], & text [1 .. ])
+		},
+		'?' => {
+			matches! (text.first (), Some (& ch) if ch != '/')
+				&& matches_real (& pattern [1 .. ], & text [1 .. ])
+		},
+		'[' => {
+			let Some (& ch) = text.first () else { return false };
+			let Some ((matched, len)) = match_class (& pattern [1 .. ], ch) else {
+				// no closing bracket, treat literally
+				return ch == '[' && matches_real (& pattern [1 .. ], & text [1 .. ]);
+			};
+			matched && matches_real (& pattern [len + 1 .. ], & text [1 .. ])
+		},
+		_ => {
+			text.first () == Some (& pat_char)
+				&& matches_real (& pattern [1 .. ], & text [1 .. ])
+		},
+	}
+}
+
+/// Match a character class, returning the result and the length of the class including the
+/// closing bracket
+fn match_class (class: & [char], ch: char) -> Option <(bool, usize)> {
+	let (negate, start) = match class.first () {
+		Some ('!' | '^') => (true, 1),
+		_ => (false, 0),
+	};
+	let mut idx = start;
+	let mut matched = false;
+	loop {
+		let & class_char = class.get (idx) ?;
+		if class_char == ']' && start < idx { break }
+		if class.get (idx + 1) == Some (& '-') && class.get (idx + 2).is_some_and (|& end| end != ']') {
+			if (class_char ..= class [idx + 2]).contains (& ch) { matched = true; }
+			idx += 3;
+		} else {
+			if class_char == ch { matched = true; }
+			idx += 1;
+		}
+	}
+	Some ((matched != negate, idx + 1))
+}
+
+#[ cfg (test) ]
+mod tests {
+
+	use super::*;
+
+	#[ test ]
+	fn literal () {
+		assert! (matches ("movie.mkv", "movie.mkv"));
+		assert! (! matches ("movie.mkv", "movie.mp4"));
+		assert! (! matches ("movie", "movie.mkv"));
+		assert! (matches ("", ""));
+	}
+
+	#[ test ]
+	fn star () {
+		assert! (matches ("*.mkv", "movie.mkv"));
+		assert! (matches ("*.mkv", ".mkv"));
+		assert! (matches ("m*e*.mkv", "movie.mkv"));
+		assert! (! matches ("*.mkv", "dir/movie.mkv"));
+		assert! (! matches ("*.mkv", "movie.mkv.part"));
+	}
+
+	#[ test ]
+	fn question () {
+		assert! (matches ("ep??.mkv", "ep01.mkv"));
+		assert! (! matches ("ep??.mkv", "ep1.mkv"));
+		assert! (! matches ("a?b", "a/b"));
+	}
+
+	#[ test ]
+	fn double_star () {
+		assert! (matches ("**/*.mkv", "movie.mkv"));
+		assert! (matches ("**/*.mkv", "a/b/movie.mkv"));
+		assert! (matches ("shows/**/*.mkv", "shows/s01/e01.mkv"));
+		assert! (matches ("shows/**/*.mkv", "shows/e01.mkv"));
+		assert! (! matches ("shows/**/*.mkv", "films/e01.mkv"));
+		assert! (matches ("shows/**", "shows/s01/e01.mkv"));
+		assert! (! matches ("**/*.mkv", "a/b/movie.mp4"));
+	}
+
+	#[ test ]
+	fn classes () {
+		assert! (matches ("ep[0-9].mkv", "ep5.mkv"));
+		assert! (! matches ("ep[0-9].mkv", "epx.mkv"));
+		assert! (matches ("[abc].srt", "b.srt"));
+		assert! (! matches ("[abc].srt", "d.srt"));
+		assert! (matches ("[!abc].srt", "d.srt"));
+		assert! (matches ("[^abc].srt", "d.srt"));
+		assert! (! matches ("[!abc].srt", "a.srt"));
+		assert! (matches ("[]]", "]"));
+		assert! (matches ("[a-]", "-"));
+		assert! (matches ("[ab", "[ab"));
+	}
+
+	#[ test ]
+	fn escapes () {
+		assert! (matches ("\\*.mkv", "*.mkv"));
+		assert! (! matches ("\\*.mkv", "movie.mkv"));
+		assert! (matches ("ep\\?", "ep?"));
+		assert! (! matches ("ep\\?", "ep1"));
+		assert! (matches ("\\[1\\]", "[1]"));
+		assert! (matches ("a\\\\b", "a\\b"));
+		assert! (matches ("trailing\\", "trailing\\"));
+	}
+
+}
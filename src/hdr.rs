@@ -0,0 +1,515 @@
+use crate::codec::BitReader;
+use crate::codec::HevcConfig;
+use crate::codec::hevc;
+use crate::ffmpeg;
+use crate::imports::*;
+use crate::matroska;
+use crate::matroska::tracks::MatrixCoefficients;
+use crate::matroska::tracks::Primaries;
+use crate::matroska::tracks::TransferCharacteristics;
+
+/// Block addition type for Dolby Vision configuration (profiles up to 10)
+pub const BLOCK_ADD_DVCC: u64 = 0x64766343;
+
+/// Block addition type for Dolby Vision configuration (profiles above 10)
+pub const BLOCK_ADD_DVVC: u64 = 0x64767643;
+
+/// Block addition type for ITU-T T.35 metadata, used for HDR10+
+pub const BLOCK_ADD_ITU_T35: u64 = 4;
+
+const SEI_USER_DATA_T35: u64 = 4;
+const SEI_MASTERING_DISPLAY: u64 = 137;
+const SEI_CONTENT_LIGHT: u64 = 144;
+
+const NAL_DOLBY_VISION_RPU: u8 = 62;
+const NAL_DOLBY_VISION_EL: u8 = 63;
+
+/// High dynamic range signalling for a video track, gathered from the container, the codec
+/// configuration and (optionally) SEI messages in the first frames
+#[ derive (Debug) ]
+pub struct HdrInfo {
+	pub primaries: Primaries,
+	pub transfer: TransferCharacteristics,
+	pub matrix: MatrixCoefficients,
+	pub mastering_display: Option <MasteringDisplay>,
+	pub content_light: Option <ContentLight>,
+	pub hdr10_plus: bool,
+	pub dolby_vision: Option <DolbyVisionConfig>,
+}
+
+/// Mastering display colour volume (SMPTE ST 2086), in the units used by HEVC SEI and x265:
+/// chromaticity in steps of 0.00002 and luminance in steps of 0.0001 cd/m²
+#[ derive (Clone, Copy, Debug) ]
+pub struct MasteringDisplay {
+	pub green: (u16, u16),
+	pub blue: (u16, u16),
+	pub red: (u16, u16),
+	pub white_point: (u16, u16),
+	pub luminance_max: u32,
+	pub luminance_min: u32,
+}
+
+#[ derive (Clone, Copy, Debug) ]
+pub struct ContentLight {
+	pub max_cll: u64,
+	pub max_fall: u64,
+}
+
+#[ allow (dead_code) ]
+#[ derive (Clone, Copy, Debug) ]
+pub struct DolbyVisionConfig {
+	pub version_major: u8,
+	pub version_minor: u8,
+	pub profile: u8,
+	pub level: u8,
+	pub rpu_present: bool,
+	pub el_present: bool,
+	pub bl_present: bool,
+	pub bl_compatibility_id: u8,
+}
+
+impl HdrInfo {
+
+	pub fn from_track (track: & matroska::tracks::TrackEntryElem) -> anyhow::Result <Self> {
+		let colour = track.video.as_ref ().and_then (|video| video.colour.as_ref ());
+		let mut hdr = Self {
+			primaries: colour.map_or (Primaries::Unspecified, |colour| colour.primaries),
+			transfer: colour.map_or (
+				TransferCharacteristics::Unspecified,
+				|colour| colour.transfer_characteristics),
+			matrix: colour.map_or (
+				MatrixCoefficients::Unspecified,
+				|colour| colour.matrix_coefficients),
+			mastering_display: colour
+				.and_then (|colour| colour.mastering_metadata.as_ref ())
+				.and_then (MasteringDisplay::from_matroska),
+			content_light: colour.and_then (|colour| Some (ContentLight {
+				max_cll: colour.max_cll ?,
+				max_fall: colour.max_fall.unwrap_or (0),
+			})),
+			hdr10_plus: false,
+			dolby_vision: None,
+		};
+		for mapping in & track.block_addition_mappings {
+			match mapping.id_type {
+				BLOCK_ADD_DVCC | BLOCK_ADD_DVVC => {
+					let Some (extra_data) = mapping.id_extra_data.as_ref () else { continue };
+					hdr.dolby_vision = Some (DolbyVisionConfig::parse (extra_data) ?);
+				},
+				BLOCK_ADD_ITU_T35 => hdr.hdr10_plus = true,
+				_ => (),
+			}
+		}
+		if let Some (codec_private) = track.codec_private.as_ref ()
+				&& track.codec_id == "V_MPEGH/ISO/HEVC" {
+			let config = HevcConfig::parse (codec_private) ?;
+			for nal_array in & config.nal_arrays {
+				for nal_unit in & nal_array.nal_units {
+					hdr.scan_hevc_nal_unit (nal_unit) ?;
+				}
+			}
+		}
+		Ok (hdr)
+	}
+
+	/// Build from the colour properties and side data reported by ffprobe, which uses the same names
+	/// as the ffmpeg colour options
+	pub fn from_ffprobe (stream: & ffmpeg::Stream) -> Self {
+		let find = |name: Option <& String>, max, to_name: fn (u64) -> Option <& 'static str>|
+			name.and_then (|name| (0 ..= max).find (|& val| to_name (val) == Some (name.as_str ())));
+		let mut hdr = Self {
+			primaries: find (stream.color_primaries.as_ref (), 22, |val| x265_primaries (val.into ()))
+				.map_or (Primaries::Unspecified, Primaries::from),
+			transfer: find (stream.color_transfer.as_ref (), 18, |val| ffmpeg_transfer (val.into ()))
+				.map_or (TransferCharacteristics::Unspecified, TransferCharacteristics::from),
+			matrix: find (stream.color_space.as_ref (), 14, |val| ffmpeg_matrix (val.into ()))
+				.map_or (MatrixCoefficients::Unspecified, MatrixCoefficients::from),
+			mastering_display: None,
+			content_light: None,
+			hdr10_plus: false,
+			dolby_vision: None,
+		};
+		hdr.scan_side_data (& stream.side_data);
+		hdr
+	}
+
+	/// Take HDR metadata from stream or frame side data reported by ffprobe
+	pub fn scan_side_data (& mut self, side_data: & [ffmpeg::SideData]) {
+		for side_data in side_data {
+			match side_data.side_data_type.as_str () {
+				"Mastering display metadata" if self.mastering_display.is_none () =>
+					self.mastering_display = MasteringDisplay::from_ffprobe (side_data),
+				"Content light level metadata" if self.content_light.is_none () =>
+					self.content_light = Some (ContentLight {
+						max_cll: side_data.max_content.unwrap_or (0),
+						max_fall: side_data.max_average.unwrap_or (0),
+					}),
+				"DOVI configuration record" if self.dolby_vision.is_none () =>
+					self.dolby_vision = Some (DolbyVisionConfig {
+						version_major: side_data.dv_version_major.unwrap_or (0),
+						version_minor: side_data.dv_version_minor.unwrap_or (0),
+						profile: side_data.dv_profile.unwrap_or (0),
+						level: side_data.dv_level.unwrap_or (0),
+						rpu_present: side_data.rpu_present_flag.unwrap_or (0) != 0,
+						el_present: side_data.el_present_flag.unwrap_or (0) != 0,
+						bl_present: side_data.bl_present_flag.unwrap_or (0) != 0,
+						bl_compatibility_id: side_data.dv_bl_signal_compatibility_id.unwrap_or (0),
+					}),
+				"HDR Dynamic Metadata SMPTE2094-40 (HDR10+)" => self.hdr10_plus = true,
+				_ => (),
+			}
+		}
+	}
+
+	/// Scan the blocks for a track in a cluster for HDR SEI messages and Dolby Vision NAL units
+	pub fn scan_hevc_cluster (
+		& mut self,
+		track: & matroska::tracks::TrackEntryElem,
+		cluster: & matroska::ClusterElem,
+	) -> anyhow::Result <()> {
+		let Some (codec_private) = track.codec_private.as_ref () else { return Ok (()) };
+		let config = HevcConfig::parse (codec_private) ?;
+		let blocks =
+			cluster.simple_blocks.iter ()
+				.chain (cluster.block_groups.iter ().flat_map (|group| group.blocks.iter ()))
+				.filter (|block| block.track_number == track.number && block.lacing () == 0);
+		for block in blocks {
+			let mut data = & block.data [ .. ];
+			while ! data.is_empty () {
+				any_ensure! (config.length_size as usize <= data.len (), "Truncated NAL unit length");
+				let len =
+					data [0 .. config.length_size as usize].iter ()
+						.fold (0_usize, |len, & byte| len << 8 | byte as usize);
+				data = & data [config.length_size as usize .. ];
+				any_ensure! (len <= data.len (), "Truncated NAL unit");
+				self.scan_hevc_nal_unit (& data [ .. len]) ?;
+				data = & data [len .. ];
+			}
+		}
+		Ok (())
+	}
+
+	fn scan_hevc_nal_unit (& mut self, nal_unit: & [u8]) -> anyhow::Result <()> {
+		if nal_unit.len () < 2 { return Ok (()) }
+		let nal_unit_type = nal_unit [0] >> 1 & 0x3f;
+		match nal_unit_type {
+			hevc::NAL_PREFIX_SEI | hevc::NAL_SUFFIX_SEI => (),
+			NAL_DOLBY_VISION_RPU | NAL_DOLBY_VISION_EL => {
+				if self.dolby_vision.is_none () {
+					self.dolby_vision = Some (DolbyVisionConfig::from_rpu (nal_unit_type));
+				}
+				return Ok (());
+			},
+			_ => return Ok (()),
+		}
+		let rbsp = crate::codec::unescape_rbsp (& nal_unit [2 .. ]);
+		let mut reader = BitReader::new (& rbsp);
+		// stop before the rbsp trailing bits
+		while 16 <= reader.remaining () {
+			let payload_type = read_sei_value (& mut reader) ?;
+			let payload_size = read_sei_value (& mut reader) ?;
+			let payload = reader.bytes (payload_size as usize) ?;
+			match payload_type {
+				SEI_MASTERING_DISPLAY if self.mastering_display.is_none () =>
+					self.mastering_display = Some (MasteringDisplay::from_sei (payload) ?),
+				SEI_CONTENT_LIGHT if self.content_light.is_none () => {
+					let mut reader = BitReader::new (payload);
+					self.content_light = Some (ContentLight {
+						max_cll: reader.u16 () ? as u64,
+						max_fall: reader.u16 () ? as u64,
+					});
+				},
+				SEI_USER_DATA_T35 if is_hdr10_plus_payload (payload) => self.hdr10_plus = true,
+				_ => (),
+			}
+		}
+		Ok (())
+	}
+
+	pub fn is_hdr (& self) -> bool {
+		self.is_pq () || self.is_hlg () || self.dolby_vision.is_some ()
+	}
+
+	pub fn is_pq (& self) -> bool {
+		self.transfer == TransferCharacteristics::Pq
+	}
+
+	pub fn is_hlg (& self) -> bool {
+		self.transfer == TransferCharacteristics::Hlg
+	}
+
+	/// Short description of the HDR formats present, eg "DV8.1 HDR10"
+	pub fn badge (& self) -> Option <String> {
+		let mut parts = Vec::new ();
+		if let Some (dolby_vision) = self.dolby_vision.as_ref () {
+			parts.push (dolby_vision.badge ());
+		}
+		if self.hdr10_plus {
+			parts.push ("HDR10+".to_owned ());
+		} else if self.is_pq () {
+			parts.push ("HDR10".to_owned ());
+		}
+		if self.is_hlg () {
+			parts.push ("HLG".to_owned ());
+		}
+		(! parts.is_empty ()).then (|| parts.join (" "))
+	}
+
+	/// Parameters for x265 which preserve the colour signalling and static HDR metadata
+	pub fn x265_params (& self) -> Vec <String> {
+		let mut params = Vec::new ();
+		if let Some (name) = x265_primaries (self.primaries) {
+			params.push (format! ("colorprim={name}"));
+		}
+		if let Some (name) = x265_transfer (self.transfer) {
+			params.push (format! ("transfer={name}"));
+		}
+		if let Some (name) = x265_matrix (self.matrix) {
+			params.push (format! ("colormatrix={name}"));
+		}
+		if let Some (mastering_display) = self.mastering_display.as_ref () {
+			params.push (format! ("master-display={mastering_display}"));
+		}
+		if let Some (content_light) = self.content_light.as_ref () {
+			params.push (format! (
+				"max-cll={},{}",
+				content_light.max_cll,
+				content_light.max_fall));
+		}
+		if self.is_pq () {
+			params.push ("hdr10=1".to_owned ());
+			params.push ("hdr10-opt=1".to_owned ());
+		}
+		if self.is_hdr () {
+			params.push ("repeat-headers=1".to_owned ());
+		}
+		params
+	}
+
+	/// Output options for ffmpeg which set the colour properties in the container
+	pub fn ffmpeg_colour_args (& self, stream: & str) -> Vec <OsString> {
+		let mut args: Vec <OsString> = Vec::new ();
+		if let Some (name) = x265_primaries (self.primaries) {
+			args.push (format! ("-color_primaries:{stream}").into ());
+			args.push (name.into ());
+		}
+		if let Some (name) = ffmpeg_transfer (self.transfer) {
+			args.push (format! ("-color_trc:{stream}").into ());
+			args.push (name.into ());
+		}
+		if let Some (name) = ffmpeg_matrix (self.matrix) {
+			args.push (format! ("-colorspace:{stream}").into ());
+			args.push (name.into ());
+		}
+		args
+	}
+
+}
+
+impl MasteringDisplay {
+
+	fn from_matroska (meta: & matroska::tracks::MasteringMetadataElem) -> Option <Self> {
+		let chroma = |val: f64| (val * 50_000.0).round () as u16;
+		let lum = |val: f64| (val * 10_000.0).round () as u32;
+		Some (Self {
+			green: (chroma (meta.primary_g_chromaticity_x ?), chroma (meta.primary_g_chromaticity_y ?)),
+			blue: (chroma (meta.primary_b_chromaticity_x ?), chroma (meta.primary_b_chromaticity_y ?)),
+			red: (chroma (meta.primary_r_chromaticity_x ?), chroma (meta.primary_r_chromaticity_y ?)),
+			white_point: (
+				chroma (meta.white_point_chromaticity_x ?),
+				chroma (meta.white_point_chromaticity_y ?)),
+			luminance_max: lum (meta.luminance_max ?),
+			luminance_min: lum (meta.luminance_min ?),
+		})
+	}
+
+	fn from_ffprobe (side_data: & ffmpeg::SideData) -> Option <Self> {
+		let fraction = |val: Option <& String>, scale: f64| {
+			let (num, den) = val ?.split_once ('/') ?;
+			let (num, den): (f64, f64) = (num.parse ().ok () ?, den.parse ().ok () ?);
+			(den != 0.0).then (|| (num / den * scale).round ())
+		};
+		let chroma = |val| fraction (val, 50_000.0).map (|val| val as u16);
+		let lum = |val| fraction (val, 10_000.0).map (|val| val as u32);
+		Some (Self {
+			green: (chroma (side_data.green_x.as_ref ()) ?, chroma (side_data.green_y.as_ref ()) ?),
+			blue: (chroma (side_data.blue_x.as_ref ()) ?, chroma (side_data.blue_y.as_ref ()) ?),
+			red: (chroma (side_data.red_x.as_ref ()) ?, chroma (side_data.red_y.as_ref ()) ?),
+			white_point: (
+				chroma (side_data.white_point_x.as_ref ()) ?,
+				chroma (side_data.white_point_y.as_ref ()) ?),
+			luminance_max: lum (side_data.max_luminance.as_ref ()) ?,
+			luminance_min: lum (side_data.min_luminance.as_ref ()) ?,
+		})
+	}
+
+	fn from_sei (payload: & [u8]) -> anyhow::Result <Self> {
+		let mut reader = BitReader::new (payload);
+		let mut xy = || -> anyhow::Result <(u16, u16)> { Ok ((reader.u16 () ?, reader.u16 () ?)) };
+		let green = xy () ?;
+		let blue = xy () ?;
+		let red = xy () ?;
+		let white_point = xy () ?;
+		Ok (Self {
+			green,
+			blue,
+			red,
+			white_point,
+			luminance_max: reader.bits (32) ? as u32,
+			luminance_min: reader.bits (32) ? as u32,
+		})
+	}
+
+}
+
+impl fmt::Display for MasteringDisplay {
+	fn fmt (& self, fmtr: & mut fmt::Formatter) -> fmt::Result {
+		write! (
+			fmtr,
+			"G({},{})B({},{})R({},{})WP({},{})L({},{})",
+			self.green.0, self.green.1,
+			self.blue.0, self.blue.1,
+			self.red.0, self.red.1,
+			self.white_point.0, self.white_point.1,
+			self.luminance_max, self.luminance_min)
+	}
+}
+
+impl DolbyVisionConfig {
+
+	pub fn parse (data: & [u8]) -> anyhow::Result <Self> {
+		let mut reader = BitReader::new (data);
+		let version_major = reader.u8 () ?;
+		let version_minor = reader.u8 () ?;
+		let profile = reader.bits (7) ? as u8;
+		let level = reader.bits (6) ? as u8;
+		let rpu_present = reader.bit () ?;
+		let el_present = reader.bit () ?;
+		let bl_present = reader.bit () ?;
+		let bl_compatibility_id = reader.bits (4) ? as u8;
+		Ok (Self {
+			version_major,
+			version_minor,
+			profile,
+			level,
+			rpu_present,
+			el_present,
+			bl_present,
+			bl_compatibility_id,
+		})
+	}
+
+	/// Placeholder configuration when we only know dolby vision NAL units are present
+	fn from_rpu (nal_unit_type: u8) -> Self {
+		Self {
+			version_major: 0,
+			version_minor: 0,
+			profile: 0,
+			level: 0,
+			rpu_present: true,
+			el_present: nal_unit_type == NAL_DOLBY_VISION_EL,
+			bl_present: true,
+			bl_compatibility_id: 0,
+		}
+	}
+
+	/// True if the base layer can't be displayed correctly without the dolby vision metadata
+	pub fn needs_rpu (& self) -> bool {
+		self.bl_present && self.bl_compatibility_id == 0 && self.profile == 5
+	}
+
+	pub fn badge (& self) -> String {
+		match (self.profile, self.bl_compatibility_id) {
+			(0, _) => "DV".to_owned (),
+			(profile, 0) => format! ("DV{profile}"),
+			(profile, compat) => format! ("DV{profile}.{compat}"),
+		}
+	}
+
+}
+
+fn read_sei_value (reader: & mut BitReader) -> anyhow::Result <u64> {
+	let mut val = 0;
+	loop {
+		let byte = reader.u8 () ?;
+		val += byte as u64;
+		if byte != 0xff { return Ok (val) }
+	}
+}
+
+fn is_hdr10_plus_payload (payload: & [u8]) -> bool {
+	// country code, provider code, provider oriented code, application identifier
+	6 <= payload.len () && payload [0 .. 6] == [ 0xb5, 0x00, 0x3c, 0x00, 0x01, 0x04 ]
+}
+
+fn x265_primaries (primaries: Primaries) -> Option <& 'static str> {
+	Some (match primaries {
+		Primaries::Bt709 => "bt709",
+		Primaries::Bt470M => "bt470m",
+		Primaries::Bt470Bg => "bt470bg",
+		Primaries::Smpte170M => "smpte170m",
+		Primaries::Smpte240M => "smpte240m",
+		Primaries::Film => "film",
+		Primaries::Bt2020 => "bt2020",
+		Primaries::Smpte428 => "smpte428",
+		Primaries::DciP3 => "smpte431",
+		Primaries::DisplayP3 => "smpte432",
+		_ => return None,
+	})
+}
+
+fn x265_transfer (transfer: TransferCharacteristics) -> Option <& 'static str> {
+	Some (match transfer {
+		TransferCharacteristics::Bt470M => "bt470m",
+		TransferCharacteristics::Bt470Bg => "bt470bg",
+		other => return ffmpeg_transfer (other),
+	})
+}
+
+fn ffmpeg_transfer (transfer: TransferCharacteristics) -> Option <& 'static str> {
+	Some (match transfer {
+		TransferCharacteristics::Bt709 => "bt709",
+		TransferCharacteristics::Bt470M => "gamma22",
+		TransferCharacteristics::Bt470Bg => "gamma28",
+		TransferCharacteristics::Smpte170M => "smpte170m",
+		TransferCharacteristics::Smpte240M => "smpte240m",
+		TransferCharacteristics::Linear => "linear",
+		TransferCharacteristics::Log => "log100",
+		TransferCharacteristics::LogSqrt => "log316",
+		TransferCharacteristics::Iec61966_2_4 => "iec61966-2-4",
+		TransferCharacteristics::Bt1361 => "bt1361e",
+		TransferCharacteristics::Iec61966_2_1 => "iec61966-2-1",
+		TransferCharacteristics::Bt2020TenBit => "bt2020-10",
+		TransferCharacteristics::Bt2020TwelveBit => "bt2020-12",
+		TransferCharacteristics::Pq => "smpte2084",
+		TransferCharacteristics::Smpte428 => "smpte428",
+		TransferCharacteristics::Hlg => "arib-std-b67",
+		_ => return None,
+	})
+}
+
+fn x265_matrix (matrix: MatrixCoefficients) -> Option <& 'static str> {
+	Some (match matrix {
+		MatrixCoefficients::Identity => "gbr",
+		other => return ffmpeg_matrix (other),
+	})
+}
+
+fn ffmpeg_matrix (matrix: MatrixCoefficients) -> Option <& 'static str> {
+	Some (match matrix {
+		MatrixCoefficients::Identity => "rgb",
+		MatrixCoefficients::Bt709 => "bt709",
+		MatrixCoefficients::Fcc => "fcc",
+		MatrixCoefficients::Bt470Bg => "bt470bg",
+		MatrixCoefficients::Smpte170M => "smpte170m",
+		MatrixCoefficients::Smpte240M => "smpte240m",
+		MatrixCoefficients::YCoCg => "ycgco",
+		MatrixCoefficients::Bt2020Ncl => "bt2020nc",
+		MatrixCoefficients::Bt2020Cl => "bt2020c",
+		MatrixCoefficients::Smpte2085 => "smpte2085",
+		MatrixCoefficients::ChromaDerivedNcl => "chroma-derived-nc",
+		MatrixCoefficients::ChromaDerivedCl => "chroma-derived-c",
+		MatrixCoefficients::Bt2100ICtCp => "ictcp",
+		_ => return None,
+	})
+}
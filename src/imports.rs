@@ -8,10 +8,13 @@ pub use itertools::Itertools;
 pub use paste::paste;
 
 pub use serde::Deserialize;
+pub use serde::Serialize;
 pub use serde_with::DisplayFromStr;
 pub use serde_with::serde_as;
 
-pub use std::ffi::OsStr;
+pub use std::collections::BTreeMap;
+pub use std::collections::BTreeSet;
+pub use std::env;
 pub use std::ffi::OsString;
 pub use std::fmt;
 pub use std::fmt::Debug;
@@ -45,7 +48,10 @@ pub use tokio::sync::oneshot as tok_oneshot;
 
 pub use crate::ebml_elem_spec;
 pub use crate::ebml_elem_read;
+pub use crate::ebml_enum;
 pub use crate::ebml::reader::Blob;
+pub use crate::ebml::reader::BlobHex;
+pub use crate::ebml::reader::BlobRef;
 pub use crate::ebml::reader::EbmlRead;
 pub use crate::ebml::reader::EbmlReader;
 pub use crate::ebml::reader::EbmlValue;
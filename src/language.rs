@@ -0,0 +1,247 @@
+//! ISO 639 language codes and BCP 47 language tags
+//!
+//! Matroska stores a language twice, as an ISO 639-2 bibliographic code in `Language` and as a
+//! BCP 47 tag in `LanguageBCP47`, and files in the wild use either, or the terminology codes, or
+//! two letter ISO 639-1 codes. Everything here parses to a `LanguageTag`, which compares by the
+//! language it refers to, so "ger", "deu", "de" and "de-DE" all count as German, and "zh" counts
+//! as the same as "cmn" or "yue", which matroska can only store as "chi".
+
+use crate::imports::*;
+
+/// One line for each ISO 639-2 language, with the bibliographic code, the terminology code, the
+/// ISO 639-1 code or "-" if there isn't one, and the English name, separated by tabs. Generated
+/// from the iso-codes project, with a few names reworded to read naturally.
+const LANGUAGES: & str = include_str! ("language.txt");
+
+/// One line for each ISO 639-3 language which isn't also in ISO 639-2, such as "yue" for Cantonese,
+/// with the code, the ISO 639-3 macrolanguage it belongs to or "-" if none, and the English name,
+/// separated by tabs. Generated from the iso-codes project and the ISO 639-3 macrolanguage table.
+const LANGUAGES_639_3: & str = include_str! ("language-639-3.txt");
+
+/// A language from ISO 639-2 or ISO 639-3
+#[ derive (Clone, Copy, Debug, Eq, PartialEq) ]
+pub struct Language {
+	/// ISO 639-2/B code, as used by matroska, eg "ger", which is the code of the macrolanguage for
+	/// languages only in ISO 639-3, eg "chi" for "yue", or "und" if they don't belong to one
+	pub code: & 'static str,
+	/// ISO 639-2/T or ISO 639-3 code, eg "deu"
+	pub code_t: & 'static str,
+	/// ISO 639-1 code, eg "de"
+	pub code_1: Option <& 'static str>,
+	/// ISO 639-3 code of the macrolanguage this belongs to, eg "zho" for "yue"
+	pub macrolanguage: Option <& 'static str>,
+	pub name: & 'static str,
+}
+
+impl Language {
+
+	/// Look up an ISO 639-1, ISO 639-2/B, ISO 639-2/T or ISO 639-3 code, ignoring case
+	pub fn find (code: & str) -> Option <Self> {
+		Self::find_639_2 (code).or_else (|| LANGUAGES_639_3.lines ()
+			.filter_map (|line| {
+				let mut parts = line.split ('\t');
+				let (Some (code_t), Some (macrolanguage), Some (name)) = (parts.next (), parts.next (), parts.next ())
+				else { return None };
+				Some ((code_t, Some (macrolanguage).filter (|& macrolanguage| macrolanguage != "-"), name))
+			})
+			.find (|& (code_t, _, _)| code_t.eq_ignore_ascii_case (code))
+			.map (|(code_t, macrolanguage, name)| Self {
+				code: macrolanguage.and_then (Self::find_639_2).map_or ("und", |macrolanguage| macrolanguage.code),
+				code_t,
+				code_1: None,
+				macrolanguage,
+				name,
+			}))
+	}
+
+	fn find_639_2 (code: & str) -> Option <Self> {
+		LANGUAGES.lines ()
+			.filter_map (|line| {
+				let mut parts = line.split ('\t');
+				let (Some (code), Some (code_t), Some (code_1), Some (name)) =
+					(parts.next (), parts.next (), parts.next (), parts.next ())
+				else { return None };
+				Some (Self {
+					code,
+					code_t,
+					code_1: Some (code_1).filter (|& code_1| code_1 != "-"),
+					macrolanguage: None,
+					name,
+				})
+			})
+			.find (|language|
+				language.code.eq_ignore_ascii_case (code)
+					|| language.code_t.eq_ignore_ascii_case (code)
+					|| language.code_1.is_some_and (|code_1| code_1.eq_ignore_ascii_case (code)))
+	}
+
+	/// Primary subtag for BCP 47, which is the ISO 639-1 code if there is one
+	pub fn subtag (& self) -> & 'static str {
+		self.code_1.unwrap_or (self.code_t)
+	}
+
+}
+
+/// A BCP 47 language tag whose primary language is in ISO 639-2 or ISO 639-3
+#[ derive (Clone, Debug, Eq, PartialEq) ]
+pub struct LanguageTag {
+	pub language: Language,
+	/// Script, region and any other subtags, in canonical case, eg "Hant-TW"
+	subtags: Vec <String>,
+}
+
+impl LanguageTag {
+
+	/// Parse any ISO 639 code or BCP 47 tag, accepting underscores as separators, eg "pt_BR"
+	pub fn parse (val: & str) -> anyhow::Result <Self> {
+		let mut parts = val.trim ().split (['-', '_']);
+		let primary = parts.next ().unwrap_or_default ();
+		let language = Language::find (primary)
+			.filter (|_| matches! (primary.len (), 2 | 3))
+			.ok_or_else (|| any_err! ("Unknown language: {val}")) ?;
+		let mut subtags = Vec::new ();
+		let mut extension = false;
+		for (idx, part) in parts.enumerate () {
+			any_ensure! (
+				(1 ..= 8).contains (& part.len ()) && part.bytes ().all (|byte| byte.is_ascii_alphanumeric ()),
+				"Invalid language tag: {val}");
+			// script and region only come before any extension or private use
+			if part.len () == 1 { extension = true }
+			subtags.push (match part.len () {
+				4 if ! extension && idx == 0 && part.bytes ().all (|byte| byte.is_ascii_alphabetic ()) =>
+					part [ .. 1].to_ascii_uppercase () + & part [1 .. ].to_ascii_lowercase (),
+				2 if ! extension => part.to_ascii_uppercase (),
+				_ => part.to_ascii_lowercase (),
+			});
+		}
+		Ok (Self { language, subtags })
+	}
+
+	/// Code for the matroska `Language` element and for ffmpeg, which only know ISO 639-2
+	pub fn iso_639_2 (& self) -> & 'static str {
+		self.language.code
+	}
+
+	/// Tag for the matroska `LanguageBCP47` element
+	pub fn bcp47 (& self) -> String {
+		iter::once (self.language.subtag ())
+			.chain (self.subtags.iter ().map (String::as_str))
+			.join ("-")
+	}
+
+	/// Name for display, with any script or region, eg "Portuguese (pt-BR)"
+	pub fn display_name (& self) -> String {
+		if self.subtags.is_empty () { self.language.name.to_owned () }
+		else { format! ("{} ({})", self.language.name, self.bcp47 ()) }
+	}
+
+	/// Check if this refers to the same language as another, ignoring script and region, where a
+	/// macrolanguage counts as the same as each of its members, eg "zh" and "cmn", but the members
+	/// don't count as the same as each other
+	pub fn same_language (& self, other: & Self) -> bool {
+		self.language == other.language
+			|| self.language.macrolanguage == Some (other.language.code_t)
+			|| other.language.macrolanguage == Some (self.language.code_t)
+	}
+
+}
+
+impl fmt::Display for LanguageTag {
+	fn fmt (& self, fmtr: & mut fmt::Formatter) -> fmt::Result {
+		fmtr.write_str (& self.bcp47 ())
+	}
+}
+
+/// ISO 639-2 code for a language code or tag, for ffmpeg and the matroska `Language` element, or
+/// "und" if it isn't one we know
+pub fn iso_639_2 (val: & str) -> & 'static str {
+	LanguageTag::parse (val).map_or ("und", |tag| tag.iso_639_2 ())
+}
+
+/// Check that a BCP 47 tag is well formed, whether or not the language is one we know, eg "x-klingon"
+/// or "qfz-Latn"
+pub fn is_well_formed (val: & str) -> bool {
+	let mut parts = val.split ('-');
+	let primary = parts.next ().unwrap_or_default ();
+	let valid_primary = match primary.len () {
+		1 => primary.eq_ignore_ascii_case ("x"),
+		2 | 3 | 5 ..= 8 => true,
+		_ => false,
+	} && primary.bytes ().all (|byte| byte.is_ascii_alphabetic ());
+	// private use needs at least one subtag after the "x"
+	let parts: Vec <& str> = parts.collect ();
+	valid_primary
+		&& ! (primary.len () == 1 && parts.is_empty ())
+		&& parts.iter ().all (|part|
+			(1 ..= 8).contains (& part.len ()) && part.bytes ().all (|byte| byte.is_ascii_alphanumeric ()))
+}
+
+/// Check if two language codes or tags refer to the same language, in any of the forms accepted
+/// by `LanguageTag::parse`, falling back to comparing them as they are
+pub fn same_language (left: & str, right: & str) -> bool {
+	match (LanguageTag::parse (left), LanguageTag::parse (right)) {
+		(Ok (left), Ok (right)) => left.same_language (& right),
+		_ => left.eq_ignore_ascii_case (right),
+	}
+}
+
+/// Name of a language for display, or the code itself if it isn't known
+pub fn display_name (code: & str) -> String {
+	LanguageTag::parse (code).map_or_else (|_| code.to_owned (), |tag| tag.display_name ())
+}
+
+#[ cfg (test) ]
+mod tests {
+
+	use super::*;
+
+	#[ test ]
+	fn every_form_of_a_code () {
+		for code in [ "ger", "deu", "de", "GER", "de-DE" ] {
+			let tag = LanguageTag::parse (code).unwrap ();
+			assert_eq! (tag.iso_639_2 (), "ger", "{code}");
+			assert! (same_language (code, "ger"), "{code}");
+			assert! (same_language (code, "deu"), "{code}");
+			assert! (same_language (code, "de"), "{code}");
+		}
+		assert! (! same_language ("ger", "dut"));
+	}
+
+	#[ test ]
+	fn canonical_tags () {
+		assert_eq! (LanguageTag::parse ("pt_BR").unwrap ().bcp47 (), "pt-BR");
+		assert_eq! (LanguageTag::parse ("por-br").unwrap ().bcp47 (), "pt-BR");
+		assert_eq! (LanguageTag::parse ("ZH-hant-tw").unwrap ().bcp47 (), "zh-Hant-TW");
+		assert_eq! (LanguageTag::parse ("pt_BR").unwrap ().display_name (), "Portuguese (pt-BR)");
+	}
+
+	#[ test ]
+	fn macrolanguages () {
+		assert_eq! (iso_639_2 ("cmn"), "chi");
+		assert_eq! (iso_639_2 ("yue"), "chi");
+		assert_eq! (iso_639_2 ("arb"), "ara");
+		assert_eq! (iso_639_2 ("pes"), "per");
+		assert_eq! (LanguageTag::parse ("yue-HK").unwrap ().bcp47 (), "yue-HK");
+		assert! (same_language ("zh", "cmn"));
+		assert! (same_language ("yue", "chi"));
+		assert! (same_language ("ara", "arb"));
+		assert! (! same_language ("cmn", "yue"));
+		// no macrolanguage
+		assert_eq! (iso_639_2 ("aaa"), "und");
+	}
+
+	#[ test ]
+	fn private_use () {
+		assert! (LanguageTag::parse ("x-klingon").is_err ());
+		assert! (is_well_formed ("x-klingon"));
+		assert_eq! (iso_639_2 ("x-klingon"), "und");
+		assert! (same_language ("x-klingon", "X-Klingon"));
+		assert! (! same_language ("x-klingon", "eng"));
+		let tag = LanguageTag::parse ("en-x-custom").unwrap ();
+		assert_eq! (tag.bcp47 (), "en-x-custom");
+		assert! (tag.same_language (& LanguageTag::parse ("eng").unwrap ()));
+		assert! (! is_well_formed ("x"));
+		assert! (! is_well_formed ("en--US"));
+	}
+
+}
@@ -1,8 +1,19 @@
+mod avi;
+mod chapters;
+mod codec;
 mod detect;
 mod ebml;
 mod ffmpeg;
+mod fonts;
+mod glob;
+mod hdr;
 mod imports;
+mod language;
 mod matroska;
+mod media;
+mod mp4;
+mod subs;
+mod tags;
 mod tool;
 
 fn main () -> anyhow::Result <()> {
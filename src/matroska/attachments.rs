@@ -0,0 +1,47 @@
+use crate::imports::*;
+
+#[ allow (dead_code) ]
+#[ derive (Debug, Serialize) ]
+pub struct AttachmentsElem {
+	pub files: Vec <AttachedFileElem>,
+}
+
+impl EbmlValue for AttachmentsElem {
+	ebml_elem_read! {
+		spec = elems::Attachments;
+		mul req files = elems::AttachedFile;
+	}
+}
+
+#[ allow (dead_code) ]
+#[ derive (Debug, Serialize) ]
+pub struct AttachedFileElem {
+	pub description: Option <String>,
+	pub name: String,
+	pub media_type: String,
+	pub data: BlobRef,
+	pub uid: u64,
+}
+
+impl EbmlValue for AttachedFileElem {
+	ebml_elem_read! {
+		spec = elems::AttachedFile;
+		one opt description = elems::FileDescription;
+		one req name = elems::FileName;
+		one req media_type = elems::FileMediaType;
+		one req data = elems::FileData;
+		one req uid = elems::FileUid;
+	}
+}
+
+ebml_elem_spec! {
+	pub mod elems {
+		pub elem Attachments = 0x1941a469, "Attachments", AttachmentsElem;
+		pub elem AttachedFile = 0x61a7, "AttachedFile", AttachedFileElem;
+		pub elem FileDescription = 0x467e, "FileDescription", String;
+		pub elem FileName = 0x466e, "FileName", String;
+		pub elem FileMediaType = 0x4660, "FileMediaType", String;
+		pub elem FileData = 0x465c, "FileData", BlobRef;
+		pub elem FileUid = 0x46ae, "FileUID", u64;
+	}
+}
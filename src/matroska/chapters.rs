@@ -1,9 +1,10 @@
+use crate::ebml::writer::EbmlWriter;
 use crate::imports::*;
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ derive (Clone, Debug, Serialize) ]
 pub struct ChaptersElem {
-	editions: Vec <EditionEntryElem>,
+	pub editions: Vec <EditionEntryElem>,
 }
 
 impl EbmlValue for ChaptersElem {
@@ -14,7 +15,7 @@ impl EbmlValue for ChaptersElem {
 }
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ derive (Clone, Debug, Serialize) ]
 pub struct EditionEntryElem {
 	pub uid: Option <u64>,
 	pub flag_hidden: bool,
@@ -36,18 +37,23 @@ impl EbmlValue for EditionEntryElem {
 	}
 }
 
-#[ derive (Debug) ]
+#[ derive (Clone, Debug, Serialize) ]
 pub struct EditionDisplayElem {
+	pub string: String,
+	pub languages_ietf: Vec <String>,
 }
 
 impl EbmlValue for EditionDisplayElem {
 	ebml_elem_read! {
 		spec = elems::EditionDisplay;
+		one req string = elems::EditionString;
+		mul opt languages_ietf = elems::EditionLanguageIetf;
 	}
 }
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ serde_as ]
+#[ derive (Clone, Debug, Serialize) ]
 pub struct ChapterAtomElem {
 	pub uid: u64,
 	pub string_uid: Option <String>,
@@ -55,12 +61,15 @@ pub struct ChapterAtomElem {
 	pub time_end: Option <u64>,
 	pub flag_hidden: bool,
 	pub flag_enabled: bool,
+	#[ serde_as (as = "Option <BlobHex>") ]
 	pub segment_uuid: Option <Vec <u8>>,
-	pub skip_type: Option <u64>,
+	pub skip_type: Option <ChapterSkipType>,
 	pub segment_edition_uid: Option <u64>,
 	pub physical_equiv: Option <u64>,
 	pub track: Option <ChapterTrackElem>,
 	pub displays: Vec <ChapterDisplayElem>,
+	pub processes: Vec <ChapterProcessElem>,
+	pub atoms: Vec <ChapterAtomElem>,
 }
 
 impl EbmlValue for ChapterAtomElem {
@@ -78,21 +87,25 @@ impl EbmlValue for ChapterAtomElem {
 		one opt physical_equiv = elems::ChapterPhysicalEquiv;
 		one opt track = elems::ChapterTrack;
 		mul opt displays = elems::ChapterDisplay;
+		mul opt processes = elems::ChapterProcess;
+		mul opt atoms = elems::ChapterAtom;
 	}
 }
 
-#[ derive (Debug) ]
+#[ derive (Clone, Debug, Serialize) ]
 pub struct ChapterTrackElem {
+	pub track_uids: Vec <u64>,
 }
 
 impl EbmlValue for ChapterTrackElem {
 	ebml_elem_read! {
 		spec = elems::ChapterTrack;
+		mul req track_uids = elems::ChapterTrackUid;
 	}
 }
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ derive (Clone, Debug, Serialize) ]
 pub struct ChapterDisplayElem {
 	pub string: String,
 	pub languages: Vec <String>,
@@ -110,6 +123,205 @@ impl EbmlValue for ChapterDisplayElem {
 	}
 }
 
+#[ serde_as ]
+#[ derive (Clone, Debug, Serialize) ]
+pub struct ChapterProcessElem {
+	pub codec_id: u64,
+	#[ serde_as (as = "Option <BlobHex>") ]
+	pub private: Option <Vec <u8>>,
+	pub commands: Vec <ChapterProcessCommandElem>,
+}
+
+impl EbmlValue for ChapterProcessElem {
+	ebml_elem_read! {
+		spec = elems::ChapterProcess;
+		one def codec_id = elems::ChapterProcessCodecId, & 0;
+		one opt private = elems::ChapterProcessPrivate;
+		mul opt commands = elems::ChapterProcessCommand;
+	}
+}
+
+#[ serde_as ]
+#[ derive (Clone, Debug, Serialize) ]
+pub struct ChapterProcessCommandElem {
+	pub time: u64,
+	#[ serde_as (as = "BlobHex") ]
+	pub data: Vec <u8>,
+}
+
+impl EbmlValue for ChapterProcessCommandElem {
+	ebml_elem_read! {
+		spec = elems::ChapterProcessCommand;
+		one req time = elems::ChapterProcessTime;
+		one req data = elems::ChapterProcessData;
+	}
+}
+
+impl ChaptersElem {
+
+	pub fn write (& self, writer: & mut EbmlWriter) {
+		writer.start (elems::CHAPTERS);
+		for edition in & self.editions {
+			edition.write (writer);
+		}
+		writer.end ();
+	}
+
+	/// Convert the track UIDs which chapters are limited to, failing if a chapter only applied to
+	/// tracks which are gone, since it would then apply to all of them
+	pub fn map_track_uids (
+		& mut self,
+		mut map_fn: impl FnMut (u64) -> anyhow::Result <Option <u64>>,
+	) -> anyhow::Result <()> {
+		for edition in & mut self.editions {
+			for atom in & mut edition.atoms {
+				atom.map_track_uids (& mut map_fn) ?;
+			}
+		}
+		Ok (())
+	}
+
+}
+
+impl EditionEntryElem {
+
+	pub fn write (& self, writer: & mut EbmlWriter) {
+		writer.start (elems::EDITION_ENTRY);
+		if let Some (uid) = self.uid { writer.unsigned (elems::EDITION_UID, uid) }
+		writer.boolean (elems::EDITION_FLAG_HIDDEN, self.flag_hidden);
+		writer.boolean (elems::EDITION_FLAG_DEFAULT, self.flag_default);
+		writer.boolean (elems::EDITION_FLAG_ORDERED, self.flag_ordered);
+		for display in & self.displays {
+			display.write (writer);
+		}
+		for atom in & self.atoms {
+			atom.write (writer);
+		}
+		writer.end ();
+	}
+
+}
+
+impl EditionDisplayElem {
+
+	pub fn write (& self, writer: & mut EbmlWriter) {
+		writer.start (elems::EDITION_DISPLAY);
+		writer.string (elems::EDITION_STRING, & self.string);
+		for language in & self.languages_ietf {
+			writer.string (elems::EDITION_LANGUAGE_IETF, language);
+		}
+		writer.end ();
+	}
+
+}
+
+impl ChapterAtomElem {
+
+	pub fn write (& self, writer: & mut EbmlWriter) {
+		writer.start (elems::CHAPTER_ATOM);
+		writer.unsigned (elems::CHAPTER_UID, self.uid);
+		if let Some (string_uid) = self.string_uid.as_ref () {
+			writer.string (elems::CHAPTER_STRING_UID, string_uid);
+		}
+		writer.unsigned (elems::CHAPTER_TIME_START, self.time_start);
+		if let Some (time_end) = self.time_end { writer.unsigned (elems::CHAPTER_TIME_END, time_end) }
+		writer.boolean (elems::CHAPTER_FLAG_HIDDEN, self.flag_hidden);
+		writer.boolean (elems::CHAPTER_FLAG_ENABLED, self.flag_enabled);
+		if let Some (segment_uuid) = self.segment_uuid.as_ref () {
+			writer.binary (elems::CHAPTER_SEGMENT_UUID, segment_uuid);
+		}
+		if let Some (skip_type) = self.skip_type {
+			writer.unsigned (elems::CHAPTER_SKIP_TYPE, skip_type.value ());
+		}
+		if let Some (segment_edition_uid) = self.segment_edition_uid {
+			writer.unsigned (elems::CHAPTER_SEGMENT_EDITION_UID, segment_edition_uid);
+		}
+		if let Some (physical_equiv) = self.physical_equiv {
+			writer.unsigned (elems::CHAPTER_PHYSICAL_EQUIV, physical_equiv);
+		}
+		if let Some (track) = self.track.as_ref () {
+			writer.start (elems::CHAPTER_TRACK);
+			for & track_uid in & track.track_uids { writer.unsigned (elems::CHAPTER_TRACK_UID, track_uid) }
+			writer.end ();
+		}
+		for display in & self.displays {
+			display.write (writer);
+		}
+		for process in & self.processes {
+			process.write (writer);
+		}
+		for atom in & self.atoms {
+			atom.write (writer);
+		}
+		writer.end ();
+	}
+
+	fn map_track_uids (
+		& mut self,
+		map_fn: & mut impl FnMut (u64) -> anyhow::Result <Option <u64>>,
+	) -> anyhow::Result <()> {
+		if let Some (track) = self.track.as_mut () {
+			let mut track_uids = Vec::new ();
+			for & track_uid in & track.track_uids {
+				if let Some (track_uid) = map_fn (track_uid) ? { track_uids.push (track_uid) }
+			}
+			any_ensure! (! track_uids.is_empty (),
+				"Chapter {} only applies to tracks which are not being kept", self.uid);
+			track.track_uids = track_uids;
+		}
+		for atom in & mut self.atoms {
+			atom.map_track_uids (map_fn) ?;
+		}
+		Ok (())
+	}
+
+}
+
+impl ChapterDisplayElem {
+
+	pub fn write (& self, writer: & mut EbmlWriter) {
+		writer.start (elems::CHAPTER_DISPLAY);
+		writer.string (elems::CHAP_STRING, & self.string);
+		for language in & self.languages { writer.string (elems::CHAP_LANGUAGE, language) }
+		for language in & self.languages_bcp47 { writer.string (elems::CHAP_LANGUAGE_BCP47, language) }
+		for country in & self.countries { writer.string (elems::CHAP_COUNTRY, country) }
+		writer.end ();
+	}
+
+}
+
+impl ChapterProcessElem {
+
+	pub fn write (& self, writer: & mut EbmlWriter) {
+		writer.start (elems::CHAPTER_PROCESS);
+		writer.unsigned (elems::CHAPTER_PROCESS_CODEC_ID, self.codec_id);
+		if let Some (private) = self.private.as_ref () {
+			writer.binary (elems::CHAPTER_PROCESS_PRIVATE, private);
+		}
+		for command in & self.commands {
+			writer.start (elems::CHAPTER_PROCESS_COMMAND);
+			writer.unsigned (elems::CHAPTER_PROCESS_TIME, command.time);
+			writer.binary (elems::CHAPTER_PROCESS_DATA, & command.data);
+			writer.end ();
+		}
+		writer.end ();
+	}
+
+}
+
+ebml_enum! {
+	pub enum ChapterSkipType {
+		NoSkipping = 0, "no skipping";
+		OpeningCredits = 1, "opening credits";
+		EndCredits = 2, "end credits";
+		Recap = 3, "recap";
+		NextPreview = 4, "next preview";
+		Preview = 5, "preview";
+		Advertisement = 6, "advertisement";
+		Intermission = 7, "intermission";
+	}
+}
+
 ebml_elem_spec! {
 	pub mod elems {
 		pub elem Chapters = 0x1043a770, "Chapters", ChaptersElem;
@@ -129,7 +341,7 @@ ebml_elem_spec! {
 		pub elem ChapterFlagHidden = 0x98, "ChapterFlagHidden", bool;
 		pub elem ChapterFlagEnabled = 0x4598, "ChapterFlagEnabled", bool;
 		pub elem ChapterSegmentUuid = 0x6e67, "ChapterSegmentUuid", Blob;
-		pub elem ChapterSkipType = 0x4588, "ChapterSkipType", u64;
+		pub elem ChapterSkipType = 0x4588, "ChapterSkipType", ChapterSkipType;
 		pub elem ChapterSegmentEditionUid = 0x6ebc, "ChapterSegmentEditionUid", u64;
 		pub elem ChapterPhysicalEquiv = 0x63c3, "ChapterPhysicalEquiv", u64;
 		pub elem ChapterTrack = 0x8f, "ChapterTrack", ChapterTrackElem;
@@ -139,10 +351,10 @@ ebml_elem_spec! {
 		pub elem ChapLanguage = 0x437c, "ChapLanguage", String;
 		pub elem ChapLanguageBcp47 = 0x437d, "ChapLanguageBCP47", String;
 		pub elem ChapCountry = 0x437e, "ChapCountry", String;
-		pub elem ChapterProcess = 0x6944, "ChapterProcess", () /*ChapProcessElem*/;
+		pub elem ChapterProcess = 0x6944, "ChapterProcess", ChapterProcessElem;
 		pub elem ChapterProcessCodecId = 0x6955, "ChapProcessCodecID", u64;
 		pub elem ChapterProcessPrivate = 0x450d, "ChapProcessPrivate", Blob;
-		pub elem ChapterProcessCommand = 0x6911, "ChapProcessCommand", () /*ChapProcessCommandElem*/;
+		pub elem ChapterProcessCommand = 0x6911, "ChapProcessCommand", ChapterProcessCommandElem;
 		pub elem ChapterProcessTime = 0x6922, "ChapProcessTime", u64;
 		pub elem ChapterProcessData = 0x6933, "ChapProcessData", Blob;
 	}
@@ -1,7 +1,7 @@
 use crate::imports::*;
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ derive (Debug, Serialize) ]
 pub struct ClusterElem {
 	pub timestamp: u64,
 	pub position: Option <u64>,
@@ -22,13 +22,15 @@ impl EbmlValue for ClusterElem {
 }
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ serde_as ]
+#[ derive (Debug, Serialize) ]
 pub struct BlockGroupElem {
 	pub blocks: Vec <BlockData>,
 	pub block_additions: Option <BlockAdditionsElem>,
 	pub block_duration: Option <u64>,
 	pub reference_priority: u64,
 	pub reference_blocks: Vec <i64>,
+	#[ serde_as (as = "Option <BlobHex>") ]
 	pub codec_state: Option <Blob>,
 	pub discard_padding: Option <i64>,
 }
@@ -47,7 +49,7 @@ impl EbmlValue for BlockGroupElem {
 }
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ derive (Debug, Serialize) ]
 pub struct BlockAdditionsElem {
 	pub mores: Vec <BlockMoreElem>,
 }
@@ -60,8 +62,10 @@ impl EbmlValue for BlockAdditionsElem {
 }
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ serde_as ]
+#[ derive (Debug, Serialize) ]
 pub struct BlockMoreElem {
+	#[ serde_as (as = "BlobHex") ]
 	pub additional: Blob,
 	pub add_id: u64,
 }
@@ -86,7 +90,7 @@ pub struct BlockData {
 impl BlockData {
 	pub fn keyframe (& self) -> bool { self.flags & 0x80 != 0 }
 	pub fn invisible (& self) -> bool { self.flags & 0x40 != 0 }
-	pub fn lacing (& self) -> u8 { self.flags & 0x60 >> 1 as u8 }
+	pub fn lacing (& self) -> u8 { (self.flags & 0x06) >> 1 }
 	pub fn discardable (& self) -> bool { self.flags & 0x01 != 0 }
 }
 
@@ -112,6 +116,18 @@ impl EbmlValue for BlockData {
 	}
 }
 
+impl Serialize for BlockData {
+	fn serialize <Ser: serde::Serializer> (& self, serializer: Ser) -> Result <Ser::Ok, Ser::Error> {
+		use serde::ser::SerializeStruct as _;
+		let mut state = serializer.serialize_struct ("BlockData", 4) ?;
+		state.serialize_field ("track_number", & self.track_number) ?;
+		state.serialize_field ("timestamp", & self.timestamp) ?;
+		state.serialize_field ("flags", & self.flags) ?;
+		state.serialize_field ("size", & self.data.len ()) ?;
+		state.end ()
+	}
+}
+
 impl Debug for BlockData {
 	fn fmt (& self, fmtr: & mut fmt::Formatter) -> fmt::Result {
 		fmtr.debug_struct ("BlockData")
@@ -1,7 +1,7 @@
 use crate::imports::*;
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ derive (Debug, Serialize) ]
 pub struct CuesElem {
 	pub points: Vec <CuePointElem>,
 }
@@ -14,7 +14,7 @@ impl EbmlValue for CuesElem {
 }
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ derive (Debug, Serialize) ]
 pub struct CuePointElem {
 	pub time: u64,
 	pub track_positions: Vec <CueTrackPositionsElem>,
@@ -29,7 +29,7 @@ impl EbmlValue for CuePointElem {
 }
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ derive (Debug, Serialize) ]
 pub struct CueTrackPositionsElem {
 	pub track: u64,
 	pub cluster_position: u64,
@@ -54,7 +54,7 @@ impl EbmlValue for CueTrackPositionsElem {
 }
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ derive (Debug, Serialize) ]
 pub struct CueReferenceElem {
 	pub ref_time: u64,
 }
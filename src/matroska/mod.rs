@@ -2,11 +2,13 @@ pub mod attachments;
 pub mod chapters;
 pub mod cluster;
 pub mod cues;
+pub mod patch;
 pub mod reader;
 pub mod segment;
 pub mod tags;
 pub mod tracks;
 
+pub use attachments::AttachmentsElem;
 pub use chapters::ChaptersElem;
 pub use cluster::ClusterElem;
 pub use cues::CuesElem;
@@ -14,10 +16,12 @@ pub use reader::Reader;
 pub use segment::InfoElem;
 pub use segment::SeekHeadElem;
 pub use tags::TagsElem;
+pub use tracks::TrackType;
 pub use tracks::TracksElem;
 
 pub mod elems {
 	use super::*;
+	pub use attachments::elems::ATTACHMENTS;
 	pub use chapters::elems::CHAPTERS;
 	pub use cluster::elems::CLUSTER;
 	pub use cues::elems::CUES;
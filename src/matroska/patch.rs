@@ -0,0 +1,383 @@
+//! In place updates to matroska files
+//!
+//! A replacement element is written over the old one if it fits in the space, along with any
+//! Void directly after it. Otherwise the elements between it and the first cluster are moved down
+//! into any Void there, which ffmpeg reserves when given [`HEADER_PADDING`], and failing that it is
+//! appended to the end of the segment and the old one is turned into Void, so cluster and cue
+//! positions stay valid. Tracks are never appended, since players expect them before the clusters.
+//! The seek head is rewritten in the space it already occupies plus any Void directly after it,
+//! which muxers normally reserve for this.
+
+use crate::ebml;
+use crate::ebml::head::elems as head_elems;
+use crate::ebml::writer::EbmlWriter;
+use crate::imports::*;
+use crate::language::LanguageTag;
+use crate::matroska;
+use crate::matroska::segment::elems as segment_elems;
+use crate::matroska::tracks::elems as tracks_elems;
+use crate::media;
+
+/// Space to ask ffmpeg to reserve after the headers with `-metadata_header_padding`, so tracks,
+/// chapters and tags can be rewritten in place later
+pub const HEADER_PADDING: u64 = 4096;
+
+/// Replace all Tags elements in a file, or remove them if `tags` is empty
+pub fn replace_tags (file_path: & Path, tags: & matroska::TagsElem) -> anyhow::Result <()> {
+	let new_elem = (! tags.tags.is_empty ()).then (|| {
+		let mut writer = EbmlWriter::new ();
+		tags.write (& mut writer);
+		writer.into_bytes ()
+	});
+	replace_elem (file_path, matroska::elems::TAGS, new_elem, true)
+		.with_context (|| any_err! ("Error writing tags to {}", file_path.display ()))
+}
+
+/// Replace all Chapters elements in a file, or remove them if `chapters` has no editions
+pub fn replace_chapters (file_path: & Path, chapters: & matroska::ChaptersElem) -> anyhow::Result <()> {
+	let new_elem = (! chapters.editions.is_empty ()).then (|| {
+		let mut writer = EbmlWriter::new ();
+		chapters.write (& mut writer);
+		writer.into_bytes ()
+	});
+	replace_elem (file_path, matroska::elems::CHAPTERS, new_elem, true)
+		.with_context (|| any_err! ("Error writing chapters to {}", file_path.display ()))
+}
+
+/// Changes to make to a track entry which ffmpeg can't write itself
+#[ derive (Clone, Debug, Default) ]
+pub struct TrackUpdate {
+	pub enabled: Option <bool>,
+	/// Language code or tag to write in place of the one ffmpeg wrote, which is only ever ISO 639-2
+	pub language: Option <String>,
+}
+
+impl TrackUpdate {
+
+	/// Update which puts back the language of a stream copied by ffmpeg
+	pub fn for_stream (stream: & media::MediaStream) -> Self {
+		Self { language: stream.language.clone (), .. Self::default () }
+	}
+
+}
+
+/// Apply `updates` to the track entries in the order they appear in Tracks, which is the order of
+/// the output streams when ffmpeg wrote the file. The language of every track is written as both
+/// an ISO 639-2 code in `Language` and a BCP 47 tag in `LanguageBCP47`, from the update or else
+/// whichever the muxer wrote. Languages which can't be parsed are kept as they are, or for one
+/// from an update, written as given in `LanguageBCP47` with "und" in `Language`.
+///
+/// ffmpeg only ever writes the ISO 639-2 code of a language, so anything which copies streams with
+/// it passes [`TrackUpdate::for_stream`] for each one to put the full tags back afterwards.
+pub fn update_tracks (file_path: & Path, updates: & [TrackUpdate]) -> anyhow::Result <()> {
+	let new_elem = (|| {
+		let file = File::open (file_path) ?;
+		let layout = Layout::read (& file) ?;
+		let Some (& (_, tracks_pos, _)) = layout.elems.iter ()
+			.find (|& & (elem_id, _, _)| elem_id == matroska::elems::TRACKS)
+		else { any_bail! ("No tracks found") };
+		let mut reader = EbmlReader::new (BufReader::new (file)) ?;
+		reader.jump (tracks_pos) ?;
+		reader.read () ?;
+		reader.nest ();
+		let mut writer = EbmlWriter::new ();
+		writer.start (matroska::elems::TRACKS);
+		let mut changed = false;
+		let mut num_entries = 0;
+		let mut updates_iter = updates.iter ();
+		while let Some ((elem_id, _, _)) = reader.read () ? {
+			// checksums would no longer match, so they are dropped
+			if elem_id == head_elems::CRC32 { reader.skip () ?; changed = true; continue }
+			if elem_id != tracks_elems::TRACK_ENTRY {
+				writer.binary (elem_id, & reader.data () ?);
+				continue;
+			}
+			let update = updates_iter.next ().cloned ().unwrap_or_default ();
+			num_entries += 1;
+			reader.nest ();
+			let mut children = Vec::new ();
+			let mut language = None;
+			let mut language_bcp47 = None;
+			let mut enabled = None;
+			while let Some ((child_id, _, _)) = reader.read () ? {
+				if child_id == tracks_elems::FLAG_ENABLED {
+					enabled = Some (reader.boolean () ?);
+					continue;
+				}
+				let data = reader.data () ?;
+				let text = || String::from_utf8_lossy (& data).trim_end_matches ('\0').to_owned ();
+				match child_id {
+					tracks_elems::LANGUAGE => language = Some (text ()),
+					tracks_elems::LANGUAGE_BCP47 => language_bcp47 = Some (text ()),
+					head_elems::CRC32 => changed = true,
+					_ => children.push ((child_id, data)),
+				}
+			}
+			reader.unnest () ?;
+			writer.start (tracks_elems::TRACK_ENTRY);
+			for (child_id, data) in & children {
+				writer.binary (* child_id, data);
+			}
+			// enabled is the default so it is only written when a track is disabled
+			if let Some (new_enabled) = update.enabled {
+				changed |= enabled.unwrap_or (true) != new_enabled;
+				enabled = Some (new_enabled);
+			}
+			if enabled == Some (false) { writer.boolean (tracks_elems::FLAG_ENABLED, false) }
+			// language defaults to english when neither is present
+			let tag = LanguageTag::parse (update.language.as_ref ()
+				.or (language_bcp47.as_ref ()).or (language.as_ref ()).map_or ("eng", String::as_str));
+			match (tag, update.language.as_ref ()) {
+				(Ok (tag), _) => {
+					let bcp47 = tag.bcp47 ();
+					changed |= language.as_deref () != Some (tag.iso_639_2 ())
+						|| language_bcp47.as_deref () != Some (bcp47.as_str ());
+					writer.string (tracks_elems::LANGUAGE, tag.iso_639_2 ());
+					writer.string (tracks_elems::LANGUAGE_BCP47, & bcp47);
+				},
+				(Err (_), Some (new_language)) => {
+					changed |= language.as_deref () != Some ("und")
+						|| language_bcp47.as_ref () != Some (new_language);
+					writer.string (tracks_elems::LANGUAGE, "und");
+					writer.string (tracks_elems::LANGUAGE_BCP47, new_language);
+				},
+				(Err (_), None) => {
+					if let Some (language) = language.as_ref () { writer.string (tracks_elems::LANGUAGE, language) }
+					if let Some (language_bcp47) = language_bcp47.as_ref () {
+						writer.string (tracks_elems::LANGUAGE_BCP47, language_bcp47);
+					}
+				},
+			}
+			writer.end ();
+		}
+		writer.end ();
+		any_ensure! (updates.is_empty () || updates.len () == num_entries,
+			"Expected {} tracks, found {num_entries}", updates.len ());
+		anyhow::Ok (changed.then (|| writer.into_bytes ()))
+	}) ().with_context (|| any_err! ("Error reading tracks from {}", file_path.display ())) ?;
+	let Some (new_elem) = new_elem else { return Ok (()) };
+	replace_elem (file_path, matroska::elems::TRACKS, Some (new_elem), false)
+		.with_context (|| any_err! ("Error writing tracks to {}", file_path.display ()))
+}
+
+/// Layout of the top level of a matroska file, as far as we need it for patching
+struct Layout {
+	segment_size_pos: u64,
+	segment_size_width: usize,
+	segment_data_pos: u64,
+	segment_end: u64,
+	seek_head_pos: u64,
+	seek_head_space: u64,
+	seek_head: matroska::SeekHeadElem,
+	elems: Vec <(u64, u64, u64)>,
+}
+
+impl Layout {
+
+	fn read (file: & File) -> anyhow::Result <Self> {
+		let mut reader = EbmlReader::new (BufReader::new (file)) ?;
+		let Some ((ebml_id, _, _)) = reader.read () ? else { any_bail! ("Error reading ebml header") };
+		any_ensure! (ebml_id == ebml::head::elems::EBML, "Expected EBML, got 0x{ebml_id:x}");
+		reader.skip () ?;
+		let Some ((segment_id, segment_pos, segment_len)) = reader.read () ?
+		else { any_bail! ("Error reading segment") };
+		any_ensure! (segment_id == matroska::elems::SEGMENT, "Expected Segment, got 0x{segment_id:x}");
+		let segment_size_pos = segment_pos + ebml::writer::encode_id (segment_id).len () as u64;
+		let segment_data_pos = reader.position ();
+		let segment_end = segment_data_pos + segment_len;
+		reader.nest ();
+		let mut seek_head = None;
+		let mut elems = Vec::new ();
+		while let Some ((elem_id, elem_pos, elem_len)) = reader.read () ? {
+			let elem_end = reader.position () + elem_len;
+			if elem_id == matroska::elems::SEEK_HEAD && seek_head.is_none () {
+				seek_head = Some ((elem_pos, elem_end, matroska::SeekHeadElem::read (& mut reader) ?));
+			} else {
+				reader.skip () ?;
+			}
+			elems.push ((elem_id, elem_pos, elem_end));
+		}
+		let Some ((seek_head_pos, mut seek_head_end, seek_head)) = seek_head
+		else { any_bail! ("No seek head found") };
+		if let Some (& (_, _, void_end)) = elems.iter ()
+				.find (|& & (elem_id, elem_pos, _)| elem_id == head_elems::VOID && elem_pos == seek_head_end) {
+			seek_head_end = void_end;
+		}
+		let file_len = file.metadata () ?.len ();
+		any_ensure! (segment_end == file_len, "Segment does not extend to the end of the file");
+		Ok (Self {
+			segment_size_pos,
+			segment_size_width: (segment_data_pos - segment_size_pos) as usize,
+			segment_data_pos,
+			segment_end,
+			seek_head_pos,
+			seek_head_space: seek_head_end - seek_head_pos,
+			seek_head,
+			elems,
+		})
+	}
+
+}
+
+fn replace_elem (
+	file_path: & Path,
+	elem_id: u64,
+	new_elem: Option <Vec <u8>>,
+	may_append: bool,
+) -> anyhow::Result <()> {
+
+	let mut file = File::options ().read (true).write (true).open (file_path) ?;
+	let layout = Layout::read (& file) ?;
+
+	// write over a single old element if the new one fits, so elements which players look for near
+	// the start stay there, a void needs at least two bytes so filling all but one doesn't work
+
+	let old_elems: Vec <(u64, u64)> = layout.elems.iter ()
+		.filter (|& & (old_id, _, _)| old_id == elem_id)
+		.map (|& (_, old_pos, old_end)| (old_pos, old_end))
+		.collect ();
+	if let (Some (new_elem), & [ (old_pos, old_end) ]) = (new_elem.as_ref (), old_elems.as_slice ()) {
+		let space_end = layout.elems.iter ()
+			.find (|& & (void_id, void_pos, _)| void_id == head_elems::VOID && void_pos == old_end)
+			.map_or (old_end, |& (_, _, void_end)| void_end);
+		let remain = (space_end - old_pos).checked_sub (new_elem.len () as u64);
+		if let Some (remain) = remain && remain != 1 {
+			file.seek (SeekFrom::Start (old_pos)) ?;
+			file.write_all (new_elem) ?;
+			if 0 < remain { file.write_all (& void_header (remain)) ? }
+			file.flush () ?;
+			return Ok (());
+		}
+
+		// otherwise move the elements after it down into any void before the first cluster, which
+		// only the seek head refers to, and write the new one in the space this makes
+
+		let run: Vec <(u64, u64, u64)> = layout.elems.iter ()
+			.skip_while (|& & (_, run_pos, _)| run_pos < old_end)
+			.take_while (|& & (run_id, _, _)|
+				run_id != matroska::elems::CLUSTER && run_id != matroska::elems::SEEK_HEAD)
+			.copied ()
+			.collect ();
+		let space_end = run.last ().map_or (old_end, |& (_, _, run_end)| run_end);
+		let moved: Vec <(u64, u64, u64)> = run.into_iter ()
+			.filter (|& (run_id, _, _)| run_id != head_elems::VOID)
+			.collect ();
+		let moved_len: u64 = moved.iter ().map (|& (_, run_pos, run_end)| run_end - run_pos).sum ();
+		let remain = (space_end - old_pos).checked_sub (new_elem.len () as u64 + moved_len);
+		if let Some (remain) = remain && remain != 1 {
+			let mut new_pos = old_pos + new_elem.len () as u64;
+			let mut moved_data = Vec::new ();
+			let mut seeks: Vec <(u64, u64)> = layout.seek_head.seeks.iter ()
+				.map (|seek| (seek.id, seek.position))
+				.collect ();
+			for & (_, run_pos, run_end) in & moved {
+				let mut data = vec! [ 0; (run_end - run_pos) as usize ];
+				file.seek (SeekFrom::Start (run_pos)) ?;
+				file.read_exact (& mut data) ?;
+				for seek in & mut seeks {
+					if layout.segment_data_pos + seek.1 == run_pos {
+						seek.1 = new_pos - layout.segment_data_pos;
+					}
+				}
+				new_pos += data.len () as u64;
+				moved_data.push (data);
+			}
+			let seek_head = encode_seek_head (& seeks, layout.seek_head_space)
+				.ok_or_else (|| any_err! ("Not enough space to update seek head")) ?;
+			file.seek (SeekFrom::Start (old_pos)) ?;
+			file.write_all (new_elem) ?;
+			for data in & moved_data { file.write_all (data) ? }
+			if 0 < remain { file.write_all (& void_header (remain)) ? }
+			file.seek (SeekFrom::Start (layout.seek_head_pos)) ?;
+			file.write_all (& seek_head) ?;
+			file.flush () ?;
+			return Ok (());
+		}
+
+	}
+
+	any_ensure! (may_append, "Not enough space to update in place");
+
+	// work out the new seek head and segment size before writing anything, reusing the space at
+	// the end of the segment if that is where the old element is
+
+	let append_pos = match layout.elems.last () {
+		Some (& (last_id, last_pos, _)) if last_id == elem_id => last_pos,
+		_ => layout.segment_end,
+	};
+	let mut seeks: Vec <(u64, u64)> = layout.seek_head.seeks.iter ()
+		.filter (|seek| seek.id != elem_id)
+		.map (|seek| (seek.id, seek.position))
+		.collect ();
+	if new_elem.is_some () {
+		seeks.push ((elem_id, append_pos - layout.segment_data_pos));
+	}
+	let seek_head = encode_seek_head (& seeks, layout.seek_head_space)
+		.ok_or_else (|| any_err! ("Not enough space to update seek head")) ?;
+	let new_len = new_elem.as_ref ().map_or (0, Vec::len) as u64;
+	let segment_size = ebml::writer::encode_size (
+			append_pos - layout.segment_data_pos + new_len,
+			layout.segment_size_width)
+		.ok_or_else (|| any_err! ("Segment size does not fit")) ?;
+
+	// append the new element, then update references to it and remove the old ones
+
+	if let Some (new_elem) = new_elem {
+		file.seek (SeekFrom::Start (append_pos)) ?;
+		file.write_all (& new_elem) ?;
+	}
+	file.set_len (append_pos + new_len) ?;
+	file.seek (SeekFrom::Start (layout.seek_head_pos)) ?;
+	file.write_all (& seek_head) ?;
+	file.seek (SeekFrom::Start (layout.segment_size_pos)) ?;
+	file.write_all (& segment_size) ?;
+	for & (old_id, old_pos, old_end) in & layout.elems {
+		if old_id != elem_id || append_pos <= old_pos { continue }
+		file.seek (SeekFrom::Start (old_pos)) ?;
+		file.write_all (& void_header (old_end - old_pos)) ?;
+	}
+	file.flush () ?;
+
+	Ok (())
+
+}
+
+/// Encode a seek head padded with Void to exactly fill the available space
+fn encode_seek_head (seeks: & [(u64, u64)], space: u64) -> Option <Vec <u8>> {
+	let mut writer = EbmlWriter::new ();
+	for & (seek_id, seek_position) in seeks {
+		writer.start (segment_elems::SEEK);
+		writer.binary (segment_elems::SEEK_ID, & ebml::writer::encode_id (seek_id));
+		writer.unsigned (segment_elems::SEEK_POSITION, seek_position);
+		writer.end ();
+	}
+	let content = writer.into_bytes ();
+	let content_len = content.len () as u64;
+	let id = ebml::writer::encode_id (matroska::elems::SEEK_HEAD);
+	let mut size_width = ebml::writer::size_width (content_len);
+	let min_len = id.len () as u64 + size_width as u64 + content_len;
+	if space < min_len { return None }
+	// a void needs at least two bytes, so use a wider size instead of leaving a single byte
+	if space - min_len == 1 { size_width += 1 }
+	let mut bytes = id;
+	bytes.extend (ebml::writer::encode_size (content_len, size_width) ?);
+	bytes.extend (content);
+	let remain = space - bytes.len () as u64;
+	if 0 < remain {
+		let void_start = bytes.len ();
+		bytes.extend (void_header (remain));
+		bytes.resize (void_start + remain as usize, 0);
+	}
+	Some (bytes)
+}
+
+/// Header for a Void element which occupies exactly `total` bytes, including the header
+fn void_header (total: u64) -> Vec <u8> {
+	let mut header = vec! [ 0xec ];
+	let size = (1 ..= 8).rev ()
+		.filter (|& width| width < total)
+		.find_map (|width| ebml::writer::encode_size (total - 1 - width, width as usize))
+		.unwrap ();
+	header.extend (size);
+	header
+}
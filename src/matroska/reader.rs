@@ -10,6 +10,8 @@ pub struct Reader <Src: BufRead + Seek> {
 	segment_info: Option <Arc <matroska::InfoElem>>,
 	tracks: Option <Arc <matroska::TracksElem>>,
 	tags: Option <Arc <matroska::TagsElem>>,
+	chapters: Option <Arc <matroska::ChaptersElem>>,
+	attachments: Option <Arc <matroska::AttachmentsElem>>,
 }
 
 impl <Src: BufRead + Seek> Reader <Src> {
@@ -43,7 +45,7 @@ impl <Src: BufRead + Seek> Reader <Src> {
 		let segment_pos = reader.position ();
 		anyhow::ensure! (
 			segment_id == matroska::elems::SEGMENT,
-			"Expected Segment, got 0x{segment_id}");
+			"Expected Segment, got 0x{segment_id:x}");
 		reader.nest ();
 
 		// read seek head
@@ -53,7 +55,7 @@ impl <Src: BufRead + Seek> Reader <Src> {
 		};
 		anyhow::ensure! (
 			seek_head_id == matroska::elems::SEEK_HEAD,
-			"Expected SeekHead, got 0x{seek_head_id}");
+			"Expected SeekHead, got 0x{seek_head_id:x}");
 		let seek_head = Arc::new (matroska::SeekHeadElem::read (& mut reader) ?);
 
 		Ok (Self {
@@ -64,6 +66,8 @@ impl <Src: BufRead + Seek> Reader <Src> {
 			segment_info: None,
 			tracks: None,
 			tags: None,
+			chapters: None,
+			attachments: None,
 		})
 
 	}
@@ -92,7 +96,7 @@ impl <Src: BufRead + Seek> Reader <Src> {
 		};
 		anyhow::ensure! (
 			info_id == matroska::elems::INFO,
-			"Expected Info, got 0x{info_id}");
+			"Expected Info, got 0x{info_id:x}");
 		let segment_info = Arc::new (matroska::InfoElem::read (& mut self.reader) ?);
 		self.segment_info = Some (Arc::clone (& segment_info));
 		Ok (segment_info)
@@ -113,7 +117,7 @@ impl <Src: BufRead + Seek> Reader <Src> {
 		};
 		anyhow::ensure! (
 			tracks_id == matroska::elems::TRACKS,
-			"Expected Tracks, got 0x{tracks_id}");
+			"Expected Tracks, got 0x{tracks_id:x}");
 		let tracks = Arc::new (matroska::TracksElem::read (& mut self.reader) ?);
 		self.tracks = Some (Arc::clone (& tracks));
 		Ok (tracks)
@@ -133,10 +137,72 @@ impl <Src: BufRead + Seek> Reader <Src> {
 		};
 		anyhow::ensure! (
 			tags_id == matroska::elems::TAGS,
-			"Expected Tags, got 0x{tags_id}");
+			"Expected Tags, got 0x{tags_id:x}");
 		let tags = Arc::new (matroska::TagsElem::read (& mut self.reader) ?);
 		self.tags = Some (Arc::clone (& tags));
 		Ok (tags)
 	}
 
+	pub fn chapters (& mut self) -> anyhow::Result <Arc <matroska::ChaptersElem>> {
+		if let Some (chapters) = self.chapters.as_ref () {
+			return Ok (Arc::clone (chapters));
+		}
+		let Some (seek_chapters) =
+			self.seek_head.seeks.iter ()
+				.find (|seek| seek.id == matroska::elems::CHAPTERS)
+		else { any_bail! ("Chapters not found in seek head") };
+		self.reader.jump (self.segment_pos + seek_chapters.position) ?;
+		let Some ((chapters_id, _, _)) = self.reader.read () ? else {
+			any_bail! ("Error reading chapters");
+		};
+		anyhow::ensure! (
+			chapters_id == matroska::elems::CHAPTERS,
+			"Expected Chapters, got 0x{chapters_id:x}");
+		let chapters = Arc::new (matroska::ChaptersElem::read (& mut self.reader) ?);
+		self.chapters = Some (Arc::clone (& chapters));
+		Ok (chapters)
+	}
+
+	pub fn attachments (& mut self) -> anyhow::Result <Arc <matroska::AttachmentsElem>> {
+		if let Some (attachments) = self.attachments.as_ref () {
+			return Ok (Arc::clone (attachments));
+		}
+		let Some (seek_attachments) =
+			self.seek_head.seeks.iter ()
+				.find (|seek| seek.id == matroska::elems::ATTACHMENTS)
+		else { any_bail! ("Attachments not found in seek head") };
+		self.reader.jump (self.segment_pos + seek_attachments.position) ?;
+		let Some ((attachments_id, _, _)) = self.reader.read () ? else {
+			any_bail! ("Error reading attachments");
+		};
+		anyhow::ensure! (
+			attachments_id == matroska::elems::ATTACHMENTS,
+			"Expected Attachments, got 0x{attachments_id:x}");
+		let attachments = Arc::new (matroska::AttachmentsElem::read (& mut self.reader) ?);
+		self.attachments = Some (Arc::clone (& attachments));
+		Ok (attachments)
+	}
+
+	/// Check if the seek head has an entry for a top level element
+	pub fn has_seek (& self, elem_id: u64) -> bool {
+		self.seek_head.seeks.iter ().any (|seek| seek.id == elem_id)
+	}
+
+	pub fn first_cluster (& mut self) -> anyhow::Result <Option <matroska::ClusterElem>> {
+		self.reader.jump (self.segment_pos) ?;
+		self.next_cluster ()
+	}
+
+	/// Read the cluster after the last one read, must be called after [`Self::first_cluster`]
+	/// without reading anything else in between
+	pub fn next_cluster (& mut self) -> anyhow::Result <Option <matroska::ClusterElem>> {
+		while let Some ((elem_id, _, _)) = self.reader.read () ? {
+			if elem_id == matroska::elems::CLUSTER {
+				return Ok (Some (matroska::ClusterElem::read (& mut self.reader) ?));
+			}
+			self.reader.skip () ?;
+		}
+		Ok (None)
+	}
+
 }
@@ -1,7 +1,7 @@
 use crate::imports::*;
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ derive (Debug, Serialize) ]
 pub struct SeekHeadElem {
 	pub seeks: Vec <SeekElem>,
 }
@@ -14,7 +14,7 @@ impl EbmlValue for SeekHeadElem {
 }
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ derive (Debug, Serialize) ]
 pub struct SeekElem {
 	pub id: u64,
 	pub position: u64,
@@ -29,14 +29,19 @@ impl EbmlValue for SeekElem {
 }
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ serde_as ]
+#[ derive (Debug, Serialize) ]
 pub struct InfoElem {
+	#[ serde_as (as = "Option <BlobHex>") ]
 	pub uuid: Option <Blob>,
 	pub filename: Option <String>,
+	#[ serde_as (as = "Option <BlobHex>") ]
 	pub prev_uuid: Option <Blob>,
 	pub prev_filename: Option <String>,
+	#[ serde_as (as = "Option <BlobHex>") ]
 	pub next_uuid: Option <Blob>,
 	pub next_filename: Option <String>,
+	#[ serde_as (as = "Vec <BlobHex>") ]
 	pub families: Vec <Blob>,
 	pub chapter_translates: Vec <ChapterTranslateElem>,
 	pub timestamp_scale: u64,
@@ -68,8 +73,10 @@ impl EbmlValue for InfoElem {
 }
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ serde_as ]
+#[ derive (Debug, Serialize) ]
 pub struct ChapterTranslateElem {
+	#[ serde_as (as = "BlobHex") ]
 	pub id: Vec <u8>,
 	pub codec: u64,
 	pub edition_uids: Vec <u64>,
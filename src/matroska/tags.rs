@@ -1,7 +1,8 @@
+use crate::ebml::writer::EbmlWriter;
 use crate::imports::*;
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ derive (Debug, Serialize) ]
 pub struct TagsElem {
 	pub tags: Vec <TagElem>,
 }
@@ -14,7 +15,7 @@ impl EbmlValue for TagsElem {
 }
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ derive (Debug, Serialize) ]
 pub struct TagElem {
 	pub targets: TargetsElem,
 	pub simple_tags: Vec <SimpleTagElem>,
@@ -29,7 +30,7 @@ impl EbmlValue for TagElem {
 }
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ derive (Debug, Serialize) ]
 pub struct TargetsElem {
 	pub type_value: u64,
 	pub target_type: Option <String>,
@@ -52,14 +53,17 @@ impl EbmlValue for TargetsElem {
 }
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ serde_as ]
+#[ derive (Debug, Serialize) ]
 pub struct SimpleTagElem {
 	pub name: String,
 	pub language: String,
 	pub language_bcp47: Option <String>,
 	pub default: bool,
 	pub string: Option <String>,
+	#[ serde_as (as = "Option <BlobHex>") ]
 	pub binary: Option <Blob>,
+	pub children: Vec <SimpleTagElem>,
 }
 
 impl EbmlValue for SimpleTagElem {
@@ -71,9 +75,76 @@ impl EbmlValue for SimpleTagElem {
 		one def default = elems::TagDefault, & true;
 		one opt string = elems::TagString;
 		one opt binary = elems::TagBinary;
+		mul opt children = elems::SimpleTag;
 	}
 }
 
+impl TagsElem {
+
+	pub fn write (& self, writer: & mut EbmlWriter) {
+		writer.start (elems::TAGS);
+		for tag in & self.tags {
+			tag.write (writer);
+		}
+		writer.end ();
+	}
+
+}
+
+impl TagElem {
+
+	pub fn write (& self, writer: & mut EbmlWriter) {
+		writer.start (elems::TAG);
+		self.targets.write (writer);
+		for simple_tag in & self.simple_tags {
+			simple_tag.write (writer);
+		}
+		writer.end ();
+	}
+
+}
+
+impl TargetsElem {
+
+	pub fn write (& self, writer: & mut EbmlWriter) {
+		writer.start (elems::TARGETS);
+		writer.unsigned (elems::TARGET_TYPE_VALUE, self.type_value);
+		if let Some (target_type) = self.target_type.as_ref () {
+			writer.string (elems::TARGET_TYPE, target_type);
+		}
+		for & uid in & self.track_uids { writer.unsigned (elems::TAG_TRACK_UID, uid) }
+		for & uid in & self.edition_uids { writer.unsigned (elems::TAG_EDITION_UID, uid) }
+		for & uid in & self.chapter_uids { writer.unsigned (elems::TAG_CHAPTER_UID, uid) }
+		for & uid in & self.attachment_uids { writer.unsigned (elems::TAG_ATTACHMENT_UID, uid) }
+		writer.end ();
+	}
+
+}
+
+impl SimpleTagElem {
+
+	pub fn write (& self, writer: & mut EbmlWriter) {
+		writer.start (elems::SIMPLE_TAG);
+		writer.string (elems::TAG_NAME, & self.name);
+		writer.string (elems::TAG_LANGUAGE, & self.language);
+		if let Some (language_bcp47) = self.language_bcp47.as_ref () {
+			writer.string (elems::TAG_LANGUAGE_BCP47, language_bcp47);
+		}
+		writer.boolean (elems::TAG_DEFAULT, self.default);
+		if let Some (string) = self.string.as_ref () {
+			writer.string (elems::TAG_STRING, string);
+		}
+		if let Some (binary) = self.binary.as_ref () {
+			writer.binary (elems::TAG_BINARY, binary);
+		}
+		for child in & self.children {
+			child.write (writer);
+		}
+		writer.end ();
+	}
+
+}
+
 ebml_elem_spec! {
 	pub mod elems {
 		pub elem Tags = 0x1254c367, "Tags", TagsElem;
@@ -1,7 +1,8 @@
+use crate::codec::CodecConfig;
 use crate::imports::*;
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ derive (Debug, Serialize) ]
 pub struct TracksElem {
 	pub entries: Vec <TrackEntryElem>,
 }
@@ -14,11 +15,12 @@ impl EbmlValue for TracksElem {
 }
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ serde_as ]
+#[ derive (Debug, Serialize) ]
 pub struct TrackEntryElem {
 	pub number: u64,
 	pub uid: u64,
-	pub track_type: u64,
+	pub track_type: TrackType,
 	pub flag_enabled: bool,
 	pub flag_default: bool,
 	pub flag_forced: bool,
@@ -38,6 +40,7 @@ pub struct TrackEntryElem {
 	pub language: String,
 	pub language_bcp47: Option <String>,
 	pub codec_id: String,
+	#[ serde_as (as = "Option <BlobHex>") ]
 	pub codec_private: Option <Vec <u8>>,
 	pub codec_name: Option <String>,
 	pub codec_decode_all: bool,
@@ -88,12 +91,24 @@ impl EbmlValue for TrackEntryElem {
 	}
 }
 
+impl TrackEntryElem {
+
+	/// Parse the codec private data, for codecs where this is supported
+	pub fn codec_config (& self) -> anyhow::Result <Option <CodecConfig>> {
+		let Some (codec_private) = self.codec_private.as_ref () else { return Ok (None) };
+		CodecConfig::from_matroska (& self.codec_id, codec_private)
+	}
+
+}
+
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ serde_as ]
+#[ derive (Debug, Serialize) ]
 pub struct BlockAdditionMappingElem {
 	pub id_value: Option <u64>,
 	pub id_name: Option <String>,
 	pub id_type: u64,
+	#[ serde_as (as = "Option <BlobHex>") ]
 	pub id_extra_data: Option <Blob>,
 }
 
@@ -108,8 +123,10 @@ impl EbmlValue for BlockAdditionMappingElem {
 }
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ serde_as ]
+#[ derive (Debug, Serialize) ]
 pub struct TrackTranslateElem {
+	#[ serde_as (as = "BlobHex") ]
 	pub track_id: Blob,
 	pub codec: u64,
 	pub edition_uids: Vec <u64>,
@@ -125,11 +142,12 @@ impl EbmlValue for TrackTranslateElem {
 }
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ serde_as ]
+#[ derive (Debug, Serialize) ]
 pub struct VideoElem {
-	pub flag_interlaced: u64,
-	pub field_order: u64,
-	pub stereo_mode: u64,
+	pub flag_interlaced: FlagInterlaced,
+	pub field_order: FieldOrder,
+	pub stereo_mode: StereoMode,
 	pub alpha_mode: u64,
 	pub pixel_width: u64,
 	pub pixel_height: u64,
@@ -140,6 +158,7 @@ pub struct VideoElem {
 	pub display_width: Option <u64>,
 	pub display_height: Option <u64>,
 	pub display_unit: u64,
+	#[ serde_as (as = "Option <BlobHex>") ]
 	pub uncompressed_four_cc: Option <Vec <u8>>,
 	pub colour: Option <ColourElem>,
 }
@@ -147,9 +166,9 @@ pub struct VideoElem {
 impl EbmlValue for VideoElem {
 	ebml_elem_read! {
 		spec = elems::Video;
-		one def flag_interlaced = elems::FlagInterlaced, & 0;
-		one def field_order = elems::FieldOrder, & 2;
-		one def stereo_mode = elems::StereoMode, & 0;
+		one def flag_interlaced = elems::FlagInterlaced, & FlagInterlaced::Undetermined;
+		one def field_order = elems::FieldOrder, & FieldOrder::Undetermined;
+		one def stereo_mode = elems::StereoMode, & StereoMode::Mono;
 		one def alpha_mode = elems::AlphaMode, & 0;
 		one req pixel_width = elems::PixelWidth;
 		one req pixel_height = elems::PixelHeight;
@@ -166,9 +185,9 @@ impl EbmlValue for VideoElem {
 }
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ derive (Debug, Serialize) ]
 pub struct ColourElem {
-	pub matrix_coefficients: u64,
+	pub matrix_coefficients: MatrixCoefficients,
 	pub bits_per_channel: u64,
 	pub chroma_subsampling_horz: Option <u64>,
 	pub chroma_subsampling_vert: Option <u64>,
@@ -177,8 +196,8 @@ pub struct ColourElem {
 	pub chroma_siting_horz: u64,
 	pub chroma_siting_vert: u64,
 	pub range: u64,
-	pub transfer_characteristics: u64,
-	pub primaries: u64,
+	pub transfer_characteristics: TransferCharacteristics,
+	pub primaries: Primaries,
 	pub max_cll: Option <u64>,
 	pub max_fall: Option <u64>,
 	pub mastering_metadata: Option <MasteringMetadataElem>,
@@ -187,7 +206,7 @@ pub struct ColourElem {
 impl EbmlValue for ColourElem {
 	ebml_elem_read! {
 		spec = elems::Colour;
-		one def matrix_coefficients = elems::MatrixCoefficients, & 2;
+		one def matrix_coefficients = elems::MatrixCoefficients, & MatrixCoefficients::Unspecified;
 		one def bits_per_channel = elems::BitsPerChannel, & 0;
 		one opt chroma_subsampling_horz = elems::ChromaSubsamplingHorz;
 		one opt chroma_subsampling_vert = elems::ChromaSubsamplingVert;
@@ -196,8 +215,9 @@ impl EbmlValue for ColourElem {
 		one def chroma_siting_horz = elems::ChromaSitingHorz, & 0;
 		one def chroma_siting_vert = elems::ChromaSitingVert, & 0;
 		one def range = elems::Range, & 0;
-		one def transfer_characteristics = elems::TransferCharacteristics, & 2;
-		one def primaries = elems::Primaries, & 2;
+		one def transfer_characteristics = elems::TransferCharacteristics,
+			& TransferCharacteristics::Unspecified;
+		one def primaries = elems::Primaries, & Primaries::Unspecified;
 		one opt max_cll = elems::MaxCll;
 		one opt max_fall = elems::MaxFall;
 		one opt mastering_metadata = elems::MasteringMetadata;
@@ -205,7 +225,7 @@ impl EbmlValue for ColourElem {
 }
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ derive (Debug, Serialize) ]
 pub struct MasteringMetadataElem {
 	pub primary_r_chromaticity_x: Option <f64>,
 	pub primary_r_chromaticity_y: Option <f64>,
@@ -236,7 +256,7 @@ impl EbmlValue for MasteringMetadataElem {
 }
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ derive (Debug, Serialize) ]
 pub struct AudioElem {
 	pub sampling_frequency: f64,
 	pub output_sampling_frequency: Option <f64>,
@@ -256,7 +276,7 @@ impl EbmlValue for AudioElem {
 	}
 }
 
-#[ derive (Debug) ]
+#[ derive (Debug, Serialize) ]
 pub struct TrackOperationElem {
 	// TODO
 }
@@ -268,7 +288,7 @@ impl EbmlValue for TrackOperationElem {
 }
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ derive (Debug, Serialize) ]
 pub struct ContentEncodingsElem {
 	pub encodings: Vec <ContentEncodingElem>,
 }
@@ -281,7 +301,7 @@ impl EbmlValue for ContentEncodingsElem {
 }
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ derive (Debug, Serialize) ]
 pub struct ContentEncodingElem {
 	pub order: u64,
 	pub scope: u64,
@@ -302,24 +322,28 @@ impl EbmlValue for ContentEncodingElem {
 }
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ serde_as ]
+#[ derive (Debug, Serialize) ]
 pub struct ContentCompressionElem {
-	pub algo: u64,
+	pub algo: ContentCompAlgo,
+	#[ serde_as (as = "Option <BlobHex>") ]
 	pub settings: Option <Blob>,
 }
 
 impl EbmlValue for ContentCompressionElem {
 	ebml_elem_read! {
 		spec = elems::ContentCompression;
-		one def algo = elems::ContentCompAlgo, & 0;
+		one def algo = elems::ContentCompAlgo, & ContentCompAlgo::Zlib;
 		one opt settings = elems::ContentCompSettings;
 	}
 }
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ serde_as ]
+#[ derive (Debug, Serialize) ]
 pub struct ContentEncryptionElem {
 	pub algo: u64,
+	#[ serde_as (as = "Option <BlobHex>") ]
 	pub key_id: Option <Blob>,
 	pub aes_settings: Option <ContentEncAesSettingsElem>,
 }
@@ -334,7 +358,7 @@ impl EbmlValue for ContentEncryptionElem {
 }
 
 #[ allow (dead_code) ]
-#[ derive (Debug) ]
+#[ derive (Debug, Serialize) ]
 pub struct ContentEncAesSettingsElem {
 	pub cipher_mode: u64,
 }
@@ -346,13 +370,132 @@ impl EbmlValue for ContentEncAesSettingsElem {
 	}
 }
 
+ebml_enum! {
+	pub enum TrackType {
+		Video = 1, "video";
+		Audio = 2, "audio";
+		Complex = 3, "complex";
+		Logo = 16, "logo";
+		Subtitle = 17, "subtitle";
+		Buttons = 18, "buttons";
+		Control = 32, "control";
+		Metadata = 33, "metadata";
+	}
+}
+
+ebml_enum! {
+	pub enum FlagInterlaced {
+		Undetermined = 0, "undetermined";
+		Interlaced = 1, "interlaced";
+		Progressive = 2, "progressive";
+	}
+}
+
+ebml_enum! {
+	pub enum FieldOrder {
+		Progressive = 0, "progressive";
+		TopFieldFirst = 1, "top field first";
+		Undetermined = 2, "undetermined";
+		BottomFieldFirst = 6, "bottom field first";
+		BottomFieldFirstSwapped = 9, "bottom field first (swapped)";
+		TopFieldFirstSwapped = 14, "top field first (swapped)";
+	}
+}
+
+ebml_enum! {
+	pub enum StereoMode {
+		Mono = 0, "mono";
+		SideBySideLeftFirst = 1, "side by side (left eye first)";
+		TopBottomRightFirst = 2, "top-bottom (right eye first)";
+		TopBottomLeftFirst = 3, "top-bottom (left eye first)";
+		CheckboardRightFirst = 4, "checkboard (right eye first)";
+		CheckboardLeftFirst = 5, "checkboard (left eye first)";
+		RowInterleavedRightFirst = 6, "row interleaved (right eye first)";
+		RowInterleavedLeftFirst = 7, "row interleaved (left eye first)";
+		ColumnInterleavedRightFirst = 8, "column interleaved (right eye first)";
+		ColumnInterleavedLeftFirst = 9, "column interleaved (left eye first)";
+		AnaglyphCyanRed = 10, "anaglyph (cyan/red)";
+		SideBySideRightFirst = 11, "side by side (right eye first)";
+		AnaglyphGreenMagenta = 12, "anaglyph (green/magenta)";
+		LacedLeftFirst = 13, "both eyes laced (left eye first)";
+		LacedRightFirst = 14, "both eyes laced (right eye first)";
+	}
+}
+
+ebml_enum! {
+	pub enum MatrixCoefficients {
+		Identity = 0, "identity";
+		Bt709 = 1, "BT.709";
+		Unspecified = 2, "unspecified";
+		Fcc = 4, "US FCC 73.682";
+		Bt470Bg = 5, "BT.470BG";
+		Smpte170M = 6, "SMPTE 170M";
+		Smpte240M = 7, "SMPTE 240M";
+		YCoCg = 8, "YCoCg";
+		Bt2020Ncl = 9, "BT.2020 non-constant luminance";
+		Bt2020Cl = 10, "BT.2020 constant luminance";
+		Smpte2085 = 11, "SMPTE ST 2085";
+		ChromaDerivedNcl = 12, "chroma-derived non-constant luminance";
+		ChromaDerivedCl = 13, "chroma-derived constant luminance";
+		Bt2100ICtCp = 14, "BT.2100 ICtCp";
+	}
+}
+
+ebml_enum! {
+	pub enum TransferCharacteristics {
+		Bt709 = 1, "BT.709";
+		Unspecified = 2, "unspecified";
+		Bt470M = 4, "BT.470M";
+		Bt470Bg = 5, "BT.470BG";
+		Smpte170M = 6, "SMPTE 170M";
+		Smpte240M = 7, "SMPTE 240M";
+		Linear = 8, "linear";
+		Log = 9, "log";
+		LogSqrt = 10, "log sqrt";
+		Iec61966_2_4 = 11, "IEC 61966-2-4";
+		Bt1361 = 12, "BT.1361 extended colour gamut";
+		Iec61966_2_1 = 13, "IEC 61966-2-1 (sRGB)";
+		Bt2020TenBit = 14, "BT.2020 10 bit";
+		Bt2020TwelveBit = 15, "BT.2020 12 bit";
+		Pq = 16, "PQ/SMPTE 2084";
+		Smpte428 = 17, "SMPTE ST 428-1";
+		Hlg = 18, "HLG/ARIB STD-B67";
+	}
+}
+
+ebml_enum! {
+	pub enum Primaries {
+		Bt709 = 1, "BT.709";
+		Unspecified = 2, "unspecified";
+		Bt470M = 4, "BT.470M";
+		Bt470Bg = 5, "BT.470BG";
+		Smpte170M = 6, "SMPTE 170M";
+		Smpte240M = 7, "SMPTE 240M";
+		Film = 8, "film";
+		Bt2020 = 9, "BT.2020";
+		Smpte428 = 10, "SMPTE ST 428-1";
+		DciP3 = 11, "DCI-P3";
+		DisplayP3 = 12, "Display P3";
+		Jedec22 = 22, "JEDEC P22 phosphors";
+	}
+}
+
+ebml_enum! {
+	pub enum ContentCompAlgo {
+		Zlib = 0, "zlib";
+		Bzlib = 1, "bzlib";
+		Lzo1x = 2, "lzo1x";
+		HeaderStripping = 3, "header stripping";
+	}
+}
+
 ebml_elem_spec! {
 	pub mod elems {
 		pub elem Tracks = 0x1654ae6b, "Tracks", TracksElem;
 		pub elem TrackEntry = 0xae, "TrackEntry", TrackEntryElem;
 		pub elem TrackNumber = 0xd7, "TrackNumber", u64;
 		pub elem TrackUid = 0x73c5, "TrackUID", u64;
-		pub elem TrackType = 0x83, "TrackType", u64;
+		pub elem TrackType = 0x83, "TrackType", TrackType;
 		pub elem FlagEnabled = 0xb9, "FlagEnabled", bool;
 		pub elem FlagDefault = 0x88, "FlagDefault", bool;
 		pub elem FlagForced = 0x55aa, "FlagForced", bool;
@@ -386,9 +529,9 @@ ebml_elem_spec! {
 		pub elem TrackTranslateCodec = 0x66bf, "TrackTranslateCodec", u64;
 		pub elem TrackTranslateEditionUid = 0x66fc, "TrackTranslateEditionUID", u64;
 		pub elem Video = 0xe0, "Video", VideoElem;
-		pub elem FlagInterlaced = 0x9a, "FlagInterlaced", u64;
-		pub elem FieldOrder = 0x9d, "FieldOrder", u64;
-		pub elem StereoMode = 0x53b8, "StereoMode", u64;
+		pub elem FlagInterlaced = 0x9a, "FlagInterlaced", FlagInterlaced;
+		pub elem FieldOrder = 0x9d, "FieldOrder", FieldOrder;
+		pub elem StereoMode = 0x53b8, "StereoMode", StereoMode;
 		pub elem AlphaMode = 0x53c0, "AlphaMode", u64;
 		pub elem PixelWidth = 0xb0, "PixelWidth", u64;
 		pub elem PixelHeight = 0xba, "PixelHeight", u64;
@@ -401,7 +544,7 @@ ebml_elem_spec! {
 		pub elem DisplayUnit = 0x54b2, "DisplayUnit", u64;
 		pub elem UncompressedFourCc = 0x2eb524, "UncompressedFourCC", Blob;
 		pub elem Colour = 0x55b0, "Colour", ColourElem;
-		pub elem MatrixCoefficients = 0x55b1, "MatrixCoefficients", u64;
+		pub elem MatrixCoefficients = 0x55b1, "MatrixCoefficients", MatrixCoefficients;
 		pub elem BitsPerChannel = 0x55b2, "BitsPerChannel", u64;
 		pub elem ChromaSubsamplingHorz = 0x55b3, "ChromaSubsamplingHorz", u64;
 		pub elem ChromaSubsamplingVert = 0x55b4, "ChromaSubsamplingVert", u64;
@@ -410,8 +553,8 @@ ebml_elem_spec! {
 		pub elem ChromaSitingHorz = 0x55b7, "ChromaSitingHorz", u64;
 		pub elem ChromaSitingVert = 0x55b8, "ChromaSitingVert", u64;
 		pub elem Range = 0x55b9, "Range", u64;
-		pub elem TransferCharacteristics = 0x55ba, "TransferCharacteristics", u64;
-		pub elem Primaries = 0x55bb, "Primaries", u64;
+		pub elem TransferCharacteristics = 0x55ba, "TransferCharacteristics", TransferCharacteristics;
+		pub elem Primaries = 0x55bb, "Primaries", Primaries;
 		pub elem MaxCll = 0x55bc, "MaxCLL", u64;
 		pub elem MaxFall = 0x55bd, "MaxFALL", u64;
 		pub elem MasteringMetadata = 0x55d0, "MasteringMetadata", MasteringMetadataElem;
@@ -450,7 +593,7 @@ ebml_elem_spec! {
 		pub elem ContentEncodingScope = 0x5032, "ContentEncodingScope", u64;
 		pub elem ContentEncodingType = 0x5033, "ContentEncodingType", u64;
 		pub elem ContentCompression = 0x5034, "ContentCompression", ContentCompressionElem;
-		pub elem ContentCompAlgo = 0x4254, "ContentCompAlgo", u64;
+		pub elem ContentCompAlgo = 0x4254, "ContentCompAlgo", ContentCompAlgo;
 		pub elem ContentCompSettings = 0x4255, "ContentCompSettings", Blob;
 		pub elem ContentEncryption = 0x5035, "ContentEncryption", ContentEncryptionElem;
 		pub elem ContentEncAlgo = 0x47e1, "ContentEncAlgo", u64;
@@ -0,0 +1,452 @@
+//! Container independent description of a media file
+//!
+//! Matroska files are read natively, which gives us codec configuration and HDR metadata, while
+//! anything else is passed to ffprobe. Streams are listed in the order ffmpeg sees them, so their
+//! indexes can be used directly in `-map` options.
+
+use crate::codec::CodecConfig;
+use crate::detect;
+use crate::ffmpeg;
+use crate::hdr;
+use crate::imports::*;
+use crate::language;
+use crate::matroska;
+
+pub use crate::ffmpeg::StreamType;
+
+#[ derive (Debug, Serialize) ]
+pub struct MediaInfo {
+	pub file_type: Option <detect::FileType>,
+	pub duration: Option <f64>,
+	pub bit_rate: Option <u64>,
+	pub title: Option <String>,
+	pub streams: Vec <MediaStream>,
+	pub chapters: Vec <MediaChapter>,
+	pub attachments: Vec <MediaAttachment>,
+	pub tags: BTreeMap <String, String>,
+}
+
+#[ derive (Debug, Serialize) ]
+pub struct MediaStream {
+	pub index: usize,
+	pub type_index: usize,
+	pub stream_type: StreamType,
+	pub uid: Option <u64>,
+	pub codec: String,
+	pub codec_id: Option <String>,
+	#[ serde (skip) ]
+	pub codec_config: Option <CodecConfig>,
+	pub language: Option <String>,
+	pub title: Option <String>,
+	pub disposition: Disposition,
+	pub channels: Option <u32>,
+	pub channel_layout: Option <String>,
+	pub sample_rate: Option <u32>,
+	pub width: Option <u32>,
+	pub height: Option <u32>,
+	pub sample_aspect_ratio: Option <ffmpeg::Rational>,
+	pub display_aspect_ratio: Option <ffmpeg::Rational>,
+	pub pix_fmt: Option <String>,
+	pub field_order: Option <String>,
+	pub frame_rate: Option <f64>,
+	pub bit_rate: Option <u64>,
+	#[ serde (skip) ]
+	pub hdr: Option <hdr::HdrInfo>,
+	pub tags: BTreeMap <String, String>,
+}
+
+#[ derive (Clone, Copy, Debug, Default, Eq, PartialEq, Serialize) ]
+pub struct Disposition {
+	pub enabled: bool,
+	pub default: bool,
+	pub forced: bool,
+	pub hearing_impaired: bool,
+	pub visual_impaired: bool,
+	pub descriptions: bool,
+	pub original: bool,
+	pub commentary: bool,
+}
+
+#[ derive (Debug, Serialize) ]
+pub struct MediaChapter {
+	pub start: f64,
+	pub end: Option <f64>,
+	pub title: Option <String>,
+}
+
+#[ derive (Debug, Serialize) ]
+pub struct MediaAttachment {
+	pub name: String,
+	pub media_type: Option <String>,
+	pub description: Option <String>,
+	pub size: Option <u64>,
+}
+
+impl MediaInfo {
+
+	/// Read information about a media file, natively if possible or otherwise with ffprobe
+	pub fn probe (file_path: & Path) -> anyhow::Result <Self> {
+		let file_display = file_path.display ();
+		let file_type = match detect::FileType::identify_path (file_path) {
+			Ok (file_type) => Some (file_type),
+			Err (detect::IdentifyError::NotRecognised) => None,
+			Err (err) =>
+				return Err (err).with_context (|| any_err! ("Error identifying file: {file_display}")),
+		};
+		if let Some (detect::FileType::Matroska) = file_type {
+			return Self::from_matroska (file_path)
+				.with_context (|| any_err! ("Error reading matroska file: {file_display}"));
+		}
+		let mut info = Self::from_ffprobe (file_path)
+			.with_context (|| any_err! ("Error probing file: {file_display}")) ?;
+		info.file_type = file_type;
+		Ok (info)
+	}
+
+	pub fn from_matroska (file_path: & Path) -> anyhow::Result <Self> {
+		let file = BufReader::new (File::open (file_path) ?);
+		let mut reader = matroska::Reader::new (file) ?;
+		let segment_info = reader.segment_info () ?;
+		let tracks = reader.tracks () ?;
+		let tags =
+			if reader.has_seek (matroska::elems::TAGS) { Some (reader.tags () ?) }
+			else { None };
+		let chapters =
+			if reader.has_seek (matroska::elems::CHAPTERS) { Some (reader.chapters () ?) }
+			else { None };
+		let attachments =
+			if reader.has_seek (matroska::elems::ATTACHMENTS) { Some (reader.attachments () ?) }
+			else { None };
+		let timestamp_scale = segment_info.timestamp_scale as f64 / 1_000_000_000.0;
+		let mut info = Self {
+			file_type: Some (detect::FileType::Matroska),
+			duration: segment_info.duration.map (|duration| duration * timestamp_scale),
+			bit_rate: None,
+			title: segment_info.title.clone (),
+			streams: Vec::new (),
+			chapters: Vec::new (),
+			attachments: Vec::new (),
+			tags: BTreeMap::new (),
+		};
+		let mut first_cluster = None;
+		for (track_idx, track) in tracks.entries.iter ().enumerate () {
+			let stream_type = match track.track_type {
+				matroska::TrackType::Video => StreamType::Video,
+				matroska::TrackType::Audio => StreamType::Audio,
+				matroska::TrackType::Subtitle => StreamType::Subtitle,
+				_ => StreamType::Data,
+			};
+			let codec_config = track.codec_config ().unwrap_or_else (|err| {
+				eprintln! ("Error parsing codec private data for track {track_idx}: {err}");
+				None
+			});
+			let mut hdr = (stream_type == StreamType::Video)
+				.then (|| hdr::HdrInfo::from_track (track))
+				.transpose ()
+				.unwrap_or_else (|err| {
+					eprintln! ("Error parsing HDR metadata for track {track_idx}: {err}");
+					None
+				});
+			if let Some (hdr) = hdr.as_mut () && track.codec_id == "V_MPEGH/ISO/HEVC" {
+				if first_cluster.is_none () {
+					first_cluster = Some (reader.first_cluster ());
+				}
+				let scan_result = match first_cluster.as_ref ().unwrap () {
+					Ok (Some (cluster)) => hdr.scan_hevc_cluster (track, cluster),
+					Ok (None) => Ok (()),
+					Err (err) => Err (any_err! ("{err}")),
+				};
+				if let Err (err) = scan_result {
+					eprintln! ("Error scanning video for HDR metadata: {err}");
+				}
+			}
+			let mut stream = MediaStream {
+				index: info.streams.len (),
+				type_index: info.streams.iter ()
+					.filter (|stream| stream.stream_type == stream_type)
+					.count (),
+				stream_type,
+				uid: Some (track.uid),
+				codec: matroska_codec_name (& track.codec_id),
+				codec_id: Some (track.codec_id.clone ()),
+				codec_config,
+				language: Some (track.language_bcp47.as_ref ().unwrap_or (& track.language).clone ())
+					.filter (|language| language != "und"),
+				title: track.name.clone (),
+				disposition: Disposition {
+					enabled: track.flag_enabled,
+					default: track.flag_default,
+					forced: track.flag_forced,
+					hearing_impaired: track.flag_hearing_impaired.unwrap_or (false),
+					visual_impaired: track.flag_visual_impaired.unwrap_or (false),
+					descriptions: track.flag_text_descriptions.unwrap_or (false),
+					original: track.flag_original.unwrap_or (false),
+					commentary: track.flag_commentary.unwrap_or (false),
+				},
+				channels: track.audio.as_ref ().map (|audio| audio.channels as u32),
+				channel_layout: None,
+				sample_rate: track.audio.as_ref ().map (|audio|
+					audio.output_sampling_frequency.unwrap_or (audio.sampling_frequency) as u32),
+				width: track.video.as_ref ().map (|video| video.pixel_width as u32),
+				height: track.video.as_ref ().map (|video| video.pixel_height as u32),
+				sample_aspect_ratio: track.video.as_ref ()
+					.filter (|video| video.display_unit == 0)
+					.and_then (|video| ffmpeg::Rational::new (
+						video.display_width ? * video.pixel_height,
+						video.display_height ? * video.pixel_width)),
+				display_aspect_ratio: track.video.as_ref ()
+					.filter (|video| video.display_unit == 0 || video.display_unit == 3)
+					.and_then (|video| ffmpeg::Rational::new (
+						video.display_width ?,
+						video.display_height ?)),
+				pix_fmt: None,
+				field_order: None,
+				frame_rate: track.default_duration
+					.filter (|_| stream_type == StreamType::Video)
+					.map (|default_duration| 1_000_000_000.0 / default_duration as f64),
+				bit_rate: None,
+				hdr,
+				tags: BTreeMap::new (),
+			};
+			if let Some (tags) = tags.as_ref () {
+				for tag in tags.tags.iter ()
+						.filter (|tag| tag.targets.track_uids.contains (& track.uid)) {
+					for simple_tag in & tag.simple_tags {
+						let Some (string) = simple_tag.string.as_ref () else { continue };
+						stream.tags.insert (simple_tag.name.clone (), string.clone ());
+					}
+				}
+			}
+			info.streams.push (stream);
+		}
+		if let Some (tags) = tags.as_ref () {
+			for tag in tags.tags.iter ().filter (|tag| is_global_target (& tag.targets)) {
+				for simple_tag in & tag.simple_tags {
+					let Some (string) = simple_tag.string.as_ref () else { continue };
+					info.tags.insert (simple_tag.name.clone (), string.clone ());
+				}
+			}
+		}
+		if let Some (chapters) = chapters.as_ref ()
+				&& let Some (edition) = chapters.editions.iter ().find (|edition| edition.flag_default)
+					.or (chapters.editions.first ()) {
+			for atom in edition.atoms.iter ().filter (|atom| atom.flag_enabled && ! atom.flag_hidden) {
+				info.chapters.push (MediaChapter {
+					start: atom.time_start as f64 / 1_000_000_000.0,
+					end: atom.time_end.map (|time_end| time_end as f64 / 1_000_000_000.0),
+					title: atom.displays.first ().map (|display| display.string.clone ()),
+				});
+			}
+		}
+		if let Some (attachments) = attachments.as_ref () {
+			for file in & attachments.files {
+				info.attachments.push (MediaAttachment {
+					name: file.name.clone (),
+					media_type: Some (file.media_type.clone ()),
+					description: file.description.clone (),
+					size: Some (file.data.end - file.data.start),
+				});
+			}
+		}
+		Ok (info)
+	}
+
+	pub fn from_ffprobe (file_path: & Path) -> anyhow::Result <Self> {
+		let probe = ffmpeg::probe (file_path) ?;
+		let mut info = Self {
+			file_type: None,
+			duration: probe.duration,
+			bit_rate: probe.bit_rate,
+			title: None,
+			streams: Vec::new (),
+			chapters: Vec::new (),
+			attachments: Vec::new (),
+			tags: probe.tags,
+		};
+		info.title = info.tags.remove ("title");
+		for probe_stream in probe.streams {
+			if probe_stream.stream_type == StreamType::Attachment {
+				info.attachments.push (MediaAttachment {
+					name: probe_stream.filename.clone ()
+						.unwrap_or_else (|| format! ("attachment-{}", probe_stream.index)),
+					media_type: probe_stream.mimetype.clone (),
+					description: probe_stream.title.clone (),
+					size: None,
+				});
+			}
+			let mut hdr = (probe_stream.stream_type == StreamType::Video)
+				.then (|| hdr::HdrInfo::from_ffprobe (& probe_stream));
+			// HDR10+ is only signalled in the frames, as is the static metadata in some containers
+			if let Some (hdr) = hdr.as_mut () && hdr.is_pq () && probe_stream.codec_name.as_deref () == Some ("hevc") {
+				match ffmpeg::probe_frame_side_data (file_path, probe_stream.index) {
+					Ok (side_data) => hdr.scan_side_data (& side_data),
+					Err (err) => eprintln! ("Error scanning video for HDR metadata: {err}"),
+				}
+			}
+			let flag = |name: & str| probe_stream.disposition.get (name).is_some_and (|& val| val != 0);
+			let stream = MediaStream {
+				index: probe_stream.index,
+				type_index: info.streams.iter ()
+					.filter (|stream| stream.stream_type == probe_stream.stream_type)
+					.count (),
+				stream_type: probe_stream.stream_type,
+				uid: None,
+				codec: probe_stream.codec_name.clone ().unwrap_or_else (|| "unknown".to_owned ()),
+				codec_id: None,
+				codec_config: None,
+				language: probe_stream.language.clone ().filter (|language| language != "und"),
+				title: probe_stream.title.clone (),
+				disposition: Disposition {
+					enabled: true,
+					default: flag ("default"),
+					forced: flag ("forced"),
+					hearing_impaired: flag ("hearing_impaired"),
+					visual_impaired: flag ("visual_impaired"),
+					descriptions: flag ("descriptions"),
+					original: flag ("original"),
+					commentary: flag ("comment"),
+				},
+				channels: probe_stream.channels,
+				channel_layout: probe_stream.channel_layout,
+				sample_rate: probe_stream.sample_rate,
+				width: probe_stream.width,
+				height: probe_stream.height,
+				sample_aspect_ratio: probe_stream.sample_aspect_ratio,
+				display_aspect_ratio: probe_stream.display_aspect_ratio,
+				pix_fmt: probe_stream.pix_fmt,
+				field_order: probe_stream.field_order,
+				frame_rate: probe_stream.avg_frame_rate
+					.or (probe_stream.r_frame_rate)
+					.filter (|_| probe_stream.stream_type == StreamType::Video)
+					.map (ffmpeg::Rational::as_f64),
+				bit_rate: probe_stream.bit_rate,
+				hdr,
+				tags: probe_stream.tags,
+			};
+			info.streams.push (stream);
+		}
+		for chapter in probe.chapters {
+			info.chapters.push (MediaChapter {
+				start: chapter.start,
+				end: Some (chapter.end),
+				title: chapter.title,
+			});
+		}
+		Ok (info)
+	}
+
+	pub fn duration_micros (& self) -> Option <u64> {
+		self.duration.map (|duration| (duration * 1_000_000.0) as u64)
+	}
+
+	pub fn streams_of_type (& self, stream_type: StreamType) -> impl Iterator <Item = & MediaStream> {
+		self.streams.iter ().filter (move |stream| stream.stream_type == stream_type)
+	}
+
+}
+
+impl MediaStream {
+
+	/// Stream specifier for ffmpeg options, eg "a:1" for the second audio stream
+	pub fn specifier (& self) -> String {
+		format! ("{}:{}", self.stream_type.specifier (), self.type_index)
+	}
+
+	pub fn is_text_subtitle (& self) -> bool {
+		matches! (self.codec.as_str (), "ass" | "mov_text" | "ssa" | "subrip" | "text" | "webvtt")
+	}
+
+	/// VobSub and PGS subtitles, which matroska can hold but can't be converted to text
+	pub fn is_bitmap_subtitle (& self) -> bool {
+		matches! (self.codec.as_str (), "dvd_subtitle" | "hdmv_pgs_subtitle")
+	}
+
+	/// Codec to use when copying this stream into a matroska file, mp4 text subtitles have no
+	/// matroska equivalent so are converted to subrip
+	pub fn matroska_copy_codec (& self) -> & 'static str {
+		match self.codec.as_str () {
+			"mov_text" => "srt",
+			_ => "copy",
+		}
+	}
+
+	/// Language and flags, for verbose output
+	pub fn describe (& self) -> String {
+		let mut result = String::new ();
+		result.push_str (& language::display_name (self.language.as_deref ().unwrap_or ("und")));
+		if ! self.disposition.enabled { result.push_str (", disabled"); }
+		if ! self.disposition.default { result.push_str (", non-default"); }
+		if self.disposition.forced { result.push_str (", forced"); }
+		if self.disposition.hearing_impaired { result.push_str (", hearing impaired"); }
+		if self.disposition.visual_impaired { result.push_str (", visual impaired"); }
+		if self.disposition.descriptions { result.push_str (", text_descriptions"); }
+		if self.disposition.original { result.push_str (", original"); }
+		if self.disposition.commentary { result.push_str (", commentary"); }
+		result
+	}
+
+}
+
+impl Disposition {
+
+	/// Value for ffmpeg's `-disposition` option
+	pub fn ffmpeg_value (& self) -> String {
+		let flags: Vec <& str> = [
+			(self.default, "default"),
+			(self.forced, "forced"),
+			(self.hearing_impaired, "hearing_impaired"),
+			(self.visual_impaired, "visual_impaired"),
+			(self.descriptions, "descriptions"),
+			(self.original, "original"),
+			(self.commentary, "comment"),
+		].into_iter ()
+			.filter (|& (flag, _)| flag)
+			.map (|(_, name)| name)
+			.collect ();
+		if flags.is_empty () { "0".to_owned () } else { flags.join ("+") }
+	}
+
+}
+
+fn is_global_target (targets: & matroska::tags::TargetsElem) -> bool {
+	targets.track_uids.is_empty ()
+		&& targets.edition_uids.is_empty ()
+		&& targets.chapter_uids.is_empty ()
+		&& targets.attachment_uids.is_empty ()
+}
+
+/// Map a matroska codec id to the name ffmpeg uses for the codec
+pub fn matroska_codec_name (codec_id: & str) -> String {
+	match codec_id {
+		"A_AAC" | "A_AAC/MPEG2/LC" | "A_AAC/MPEG4/LC" | "A_AAC/MPEG4/LC/SBR" => "aac",
+		"A_AC3" => "ac3",
+		"A_ALAC" => "alac",
+		"A_DTS" => "dts",
+		"A_EAC3" => "eac3",
+		"A_FLAC" => "flac",
+		"A_MPEG/L2" => "mp2",
+		"A_MPEG/L3" => "mp3",
+		"A_OPUS" => "opus",
+		"A_PCM/FLOAT/IEEE" => "pcm_f32le",
+		"A_PCM/INT/BIG" => "pcm_s16be",
+		"A_PCM/INT/LIT" => "pcm_s16le",
+		"A_TRUEHD" => "truehd",
+		"A_VORBIS" => "vorbis",
+		"S_HDMV/PGS" => "hdmv_pgs_subtitle",
+		"S_TEXT/ASS" => "ass",
+		"S_TEXT/SSA" => "ssa",
+		"S_TEXT/UTF8" => "subrip",
+		"S_TEXT/WEBVTT" => "webvtt",
+		"S_VOBSUB" => "dvd_subtitle",
+		"V_AV1" => "av1",
+		"V_MPEG1" => "mpeg1video",
+		"V_MPEG2" => "mpeg2video",
+		"V_MPEG4/ISO/ASP" => "mpeg4",
+		"V_MPEG4/ISO/AVC" => "h264",
+		"V_MPEGH/ISO/HEVC" => "hevc",
+		"V_MS/VFW/FOURCC" => "vfw",
+		"V_VP8" => "vp8",
+		"V_VP9" => "vp9",
+		_ => return codec_id.to_ascii_lowercase (),
+	}.to_owned ()
+}
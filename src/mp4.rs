@@ -0,0 +1,617 @@
+//! Minimal reader for ISO base media files (MP4, M4V, MOV)
+//!
+//! Only the metadata needed to summarise a file is decoded: the `ftyp` brands, the `mvhd` movie
+//! header, and for each `trak` the track header, media header, handler and first sample entry.
+//! Titles are taken from either the iTunes style `udta/meta/ilst` atoms or the QuickTime style
+//! `udta/©nam` atom.
+
+use crate::codec::CodecConfig;
+use crate::imports::*;
+
+/// Refuse to load a `moov` box larger than this into memory
+const MAX_MOOV_SIZE: u64 = 256 * 1024 * 1024;
+
+#[ derive (Clone, Copy, Eq, Hash, Ord, PartialEq, PartialOrd) ]
+pub struct FourCc (pub [u8; 4]);
+
+impl FourCc {
+	pub fn from_slice (data: & [u8]) -> Self {
+		Self ([ data [0], data [1], data [2], data [3] ])
+	}
+}
+
+impl fmt::Display for FourCc {
+	fn fmt (& self, fmtr: & mut fmt::Formatter) -> fmt::Result {
+		for & byte in & self.0 {
+			if byte.is_ascii_graphic () || byte == b' ' {
+				fmtr.write_char (byte as char) ?;
+			} else if byte == 0xa9 {
+				fmtr.write_char ('©') ?;
+			} else {
+				write! (fmtr, "\\x{byte:02x}") ?;
+			}
+		}
+		Ok (())
+	}
+}
+
+impl fmt::Debug for FourCc {
+	fn fmt (& self, fmtr: & mut fmt::Formatter) -> fmt::Result {
+		write! (fmtr, "\"{self}\"")
+	}
+}
+
+impl Serialize for FourCc {
+	fn serialize <Ser: serde::Serializer> (& self, serializer: Ser) -> Result <Ser::Ok, Ser::Error> {
+		serializer.collect_str (self)
+	}
+}
+
+#[ derive (Debug, Serialize) ]
+pub struct Mp4Info {
+	pub major_brand: FourCc,
+	pub minor_version: u32,
+	pub compatible_brands: Vec <FourCc>,
+	pub timescale: u32,
+	pub duration: u64,
+	pub title: Option <String>,
+	pub tracks: Vec <Mp4Track>,
+}
+
+impl Mp4Info {
+
+	/// Read the metadata from an ISO base media file
+	pub fn read (mut src: impl Read + Seek) -> anyhow::Result <Self> {
+		let file_size = src.seek (SeekFrom::End (0)) ?;
+		let mut pos = 0;
+		let mut ftyp = None;
+		let mut moov = None;
+		while pos + 8 <= file_size {
+			src.seek (SeekFrom::Start (pos)) ?;
+			let mut header = [0; 16];
+			src.read_exact (& mut header [0 .. 8]) ?;
+			let box_type = FourCc::from_slice (& header [4 .. 8]);
+			let (header_len, box_len) = match u32::from_be_bytes (header [0 .. 4].try_into ().unwrap ()) {
+				0 => (8, file_size - pos),
+				1 => {
+					src.read_exact (& mut header [8 .. 16]) ?;
+					(16, u64::from_be_bytes (header [8 .. 16].try_into ().unwrap ()))
+				},
+				len => (8, len as u64),
+			};
+			any_ensure! (
+				header_len <= box_len && box_len <= file_size - pos,
+				"Invalid size for {box_type} box at {pos}: {box_len}");
+			let data_len = box_len - header_len;
+			match & box_type.0 {
+				b"ftyp" => ftyp = Some (read_box_data (& mut src, box_type, data_len) ?),
+				b"moov" => {
+					any_ensure! (
+						data_len <= MAX_MOOV_SIZE,
+						"Movie box is too large: {data_len} bytes");
+					moov = Some (read_box_data (& mut src, box_type, data_len) ?);
+					break;
+				},
+				_ => (),
+			}
+			pos += box_len;
+		}
+		let Some (moov) = moov else { any_bail! ("No moov box found") };
+		let mut info = Self {
+			major_brand: FourCc (* b"    "),
+			minor_version: 0,
+			compatible_brands: Vec::new (),
+			timescale: 0,
+			duration: 0,
+			title: None,
+			tracks: Vec::new (),
+		};
+		if let Some (ftyp) = ftyp {
+			info.parse_ftyp (& ftyp).context ("Error reading ftyp box") ?;
+		}
+		info.parse_moov (& moov).context ("Error reading moov box") ?;
+		Ok (info)
+	}
+
+	pub fn duration_secs (& self) -> Option <f64> {
+		if self.timescale == 0 || self.duration == 0 { return None }
+		Some (self.duration as f64 / self.timescale as f64)
+	}
+
+	pub fn tracks_of_type (& self, track_type: Mp4TrackType) -> impl Iterator <Item = & Mp4Track> {
+		self.tracks.iter ().filter (move |track| track.track_type () == track_type)
+	}
+
+	fn parse_ftyp (& mut self, data: & [u8]) -> anyhow::Result <()> {
+		let mut data = ByteReader::new (data);
+		self.major_brand = data.four_cc () ?;
+		self.minor_version = data.u32 () ?;
+		while 4 <= data.remaining () {
+			self.compatible_brands.push (data.four_cc () ?);
+		}
+		Ok (())
+	}
+
+	fn parse_moov (& mut self, data: & [u8]) -> anyhow::Result <()> {
+		for (box_type, box_data) in boxes (data) {
+			let box_data = box_data ?;
+			match & box_type.0 {
+				b"mvhd" => self.parse_mvhd (box_data).context ("Error reading mvhd box") ?,
+				b"trak" => self.tracks.push (Mp4Track::parse (box_data).context ("Error reading trak box") ?),
+				b"udta" => self.title = parse_udta_title (box_data) ?,
+				b"mvex" => {
+					// fragmented files may only give the total duration in the movie extends header
+					if self.duration == 0 && let Some (duration) = parse_mvex_duration (box_data) ? {
+						self.duration = duration;
+					}
+				},
+				_ => (),
+			}
+		}
+		Ok (())
+	}
+
+	fn parse_mvhd (& mut self, data: & [u8]) -> anyhow::Result <()> {
+		let mut data = ByteReader::new (data);
+		let (version, _flags) = data.full_box () ?;
+		if version == 1 {
+			data.skip (16) ?;
+			self.timescale = data.u32 () ?;
+			self.duration = data.u64 () ?;
+			if self.duration == u64::MAX { self.duration = 0; }
+		} else {
+			data.skip (8) ?;
+			self.timescale = data.u32 () ?;
+			self.duration = data.u32 () ? as u64;
+			if self.duration == u32::MAX as u64 { self.duration = 0; }
+		}
+		Ok (())
+	}
+
+}
+
+#[ derive (Clone, Copy, Debug, Eq, PartialEq, Serialize) ]
+#[ serde (rename_all = "kebab-case") ]
+pub enum Mp4TrackType {
+	Video,
+	Audio,
+	Subtitle,
+	Other,
+}
+
+#[ serde_as ]
+#[ derive (Debug, Default, Serialize) ]
+pub struct Mp4Track {
+	pub track_id: u32,
+	pub enabled: bool,
+	pub handler_type: Option <FourCc>,
+	pub handler_name: Option <String>,
+	pub name: Option <String>,
+	pub timescale: u32,
+	pub duration: u64,
+	pub language: Option <String>,
+	pub width: u32,
+	pub height: u32,
+	pub codec: Option <FourCc>,
+	pub object_type: Option <u8>,
+	pub channels: Option <u16>,
+	pub sample_rate: Option <u32>,
+	pub codec_config_type: Option <FourCc>,
+	#[ serde_as (as = "Option <BlobHex>") ]
+	pub codec_private: Option <Vec <u8>>,
+}
+
+impl Mp4Track {
+
+	fn parse (data: & [u8]) -> anyhow::Result <Self> {
+		let mut track = Self::default ();
+		for (box_type, box_data) in boxes (data) {
+			let box_data = box_data ?;
+			match & box_type.0 {
+				b"tkhd" => track.parse_tkhd (box_data).context ("Error reading tkhd box") ?,
+				b"mdia" => track.parse_mdia (box_data) ?,
+				b"udta" => track.name = parse_udta_title (box_data) ?,
+				_ => (),
+			}
+		}
+		Ok (track)
+	}
+
+	pub fn track_type (& self) -> Mp4TrackType {
+		let Some (handler_type) = self.handler_type else { return Mp4TrackType::Other };
+		match & handler_type.0 {
+			b"vide" => Mp4TrackType::Video,
+			b"soun" => Mp4TrackType::Audio,
+			b"clcp" | b"sbtl" | b"subp" | b"subt" | b"text" => Mp4TrackType::Subtitle,
+			_ => Mp4TrackType::Other,
+		}
+	}
+
+	/// Short codec name, using the same names as are shown for Matroska tracks
+	pub fn codec_name (& self) -> String {
+		let Some (codec) = self.codec else { return "unknown".to_owned () };
+		match & codec.0 {
+			b"ac-3" => "ac3",
+			b"ac-4" => "ac4",
+			b"alac" => "alac",
+			b"av01" => "av1",
+			b"avc1" | b"avc3" => "mpeg4/avc",
+			b"c608" => "cea-608",
+			b"dtsc" | b"dtse" | b"dtsh" | b"dtsl" => "dts",
+			b"dvh1" | b"dvhe" => "mpeg4/hevc",
+			b"ec-3" => "eac3",
+			b"fLaC" => "flac",
+			b"hev1" | b"hvc1" => "mpeg4/hevc",
+			b"mp4a" => match self.object_type {
+				Some (0x40 | 0x66 | 0x67 | 0x68) => "aac",
+				Some (0x69 | 0x6b) => "mp3",
+				Some (0xa5) => "ac3",
+				Some (0xa6) => "eac3",
+				Some (0xa9 | 0xac) => "dts",
+				Some (0xad) => "opus",
+				_ => "mp4a",
+			},
+			b"mp4v" => match self.object_type {
+				Some (0x20) => "mpeg4/asp",
+				Some (0x60 ..= 0x65) => "mpeg2",
+				Some (0x6a) => "mpeg1",
+				_ => "mp4v",
+			},
+			b"mp4s" => "mpeg4/systems",
+			b".mp3" => "mp3",
+			b"Opus" => "opus",
+			b"stpp" => "ttml",
+			b"tx3g" => "tx3g",
+			b"vp08" => "vp8",
+			b"vp09" => "vp9",
+			b"wvtt" => "webvtt",
+			_ => return codec.to_string ().trim_end ().to_owned (),
+		}.to_owned ()
+	}
+
+	/// Decoded codec configuration, if present and of a supported type
+	pub fn codec_config (& self) -> anyhow::Result <Option <CodecConfig>> {
+		let (Some (config_type), Some (codec_private)) = (self.codec_config_type, self.codec_private.as_ref ())
+			else { return Ok (None) };
+		CodecConfig::from_mp4 (config_type, codec_private)
+	}
+
+	fn parse_tkhd (& mut self, data: & [u8]) -> anyhow::Result <()> {
+		let mut data = ByteReader::new (data);
+		let (version, flags) = data.full_box () ?;
+		self.enabled = flags & 1 != 0;
+		if version == 1 {
+			data.skip (16) ?;
+			self.track_id = data.u32 () ?;
+			data.skip (12) ?;
+		} else {
+			data.skip (8) ?;
+			self.track_id = data.u32 () ?;
+			data.skip (8) ?;
+		}
+		data.skip (52) ?;
+		self.width = data.u32 () ? >> 16;
+		self.height = data.u32 () ? >> 16;
+		Ok (())
+	}
+
+	fn parse_mdia (& mut self, data: & [u8]) -> anyhow::Result <()> {
+		for (box_type, box_data) in boxes (data) {
+			let box_data = box_data ?;
+			match & box_type.0 {
+				b"mdhd" => self.parse_mdhd (box_data).context ("Error reading mdhd box") ?,
+				b"hdlr" => self.parse_hdlr (box_data).context ("Error reading hdlr box") ?,
+				b"minf" => {
+					let Some (stbl) = find_box (box_data, b"stbl") ? else { continue };
+					let Some (stsd) = find_box (stbl, b"stsd") ? else { continue };
+					self.parse_stsd (stsd).context ("Error reading stsd box") ?;
+				},
+				_ => (),
+			}
+		}
+		Ok (())
+	}
+
+	fn parse_mdhd (& mut self, data: & [u8]) -> anyhow::Result <()> {
+		let mut data = ByteReader::new (data);
+		let (version, _flags) = data.full_box () ?;
+		if version == 1 {
+			data.skip (16) ?;
+			self.timescale = data.u32 () ?;
+			self.duration = data.u64 () ?;
+		} else {
+			data.skip (8) ?;
+			self.timescale = data.u32 () ?;
+			self.duration = data.u32 () ? as u64;
+		}
+		let language = data.u16 () ?;
+		if language < 0x400 {
+			// quicktime language codes, only "english" is worth mapping
+			if language == 0 { self.language = Some ("eng".to_owned ()); }
+		} else if language != 0x7fff {
+			let language: String =
+				[ language >> 10 & 0x1f, language >> 5 & 0x1f, language & 0x1f ].into_iter ()
+					.map (|val| (val as u8 + 0x60) as char)
+					.collect ();
+			if language.chars ().all (|ch| ch.is_ascii_lowercase ()) {
+				self.language = Some (language);
+			}
+		}
+		Ok (())
+	}
+
+	fn parse_hdlr (& mut self, data: & [u8]) -> anyhow::Result <()> {
+		let mut data = ByteReader::new (data);
+		let (_version, _flags) = data.full_box () ?;
+		data.skip (4) ?;
+		self.handler_type = Some (data.four_cc () ?);
+		data.skip (12) ?;
+		let name = data.rest ();
+		// quicktime uses a pascal string, iso uses a null terminated one
+		let name = match name.first () {
+			Some (& len) if len as usize + 1 == name.len () && len != 0 => & name [1 .. ],
+			_ => name,
+		};
+		let name = name.split (|& byte| byte == 0).next ().unwrap_or_default ();
+		let name = String::from_utf8_lossy (name).trim ().to_owned ();
+		if ! name.is_empty () { self.handler_name = Some (name); }
+		Ok (())
+	}
+
+	fn parse_stsd (& mut self, data: & [u8]) -> anyhow::Result <()> {
+		let mut data = ByteReader::new (data);
+		let (_version, _flags) = data.full_box () ?;
+		let num_entries = data.u32 () ?;
+		if num_entries == 0 { return Ok (()) }
+		let Some ((codec, entry_data)) = boxes (data.rest ()).next () else { return Ok (()) };
+		let entry_data = entry_data ?;
+		self.codec = Some (codec);
+		let mut entry = ByteReader::new (entry_data);
+		entry.skip (8) ?;
+		let children = match self.track_type () {
+			Mp4TrackType::Video => {
+				entry.skip (16) ?;
+				let width = entry.u16 () ? as u32;
+				let height = entry.u16 () ? as u32;
+				if width != 0 && height != 0 {
+					self.width = width;
+					self.height = height;
+				}
+				entry.skip (50) ?;
+				entry.rest ()
+			},
+			Mp4TrackType::Audio => {
+				let version = entry.u16 () ?;
+				entry.skip (6) ?;
+				let channels = entry.u16 () ?;
+				entry.skip (6) ?;
+				let sample_rate = entry.u32 () ? >> 16;
+				match version {
+					1 => entry.skip (16) ?,
+					2 => {
+						entry.skip (4) ?;
+						let sample_rate = f64::from_bits (entry.u64 () ?);
+						let channels = entry.u32 () ?;
+						entry.skip (20) ?;
+						self.sample_rate = Some (sample_rate as u32);
+						self.channels = Some (channels as u16);
+					},
+					_ => (),
+				}
+				if version != 2 {
+					self.channels = Some (channels);
+					self.sample_rate = Some (sample_rate);
+				}
+				entry.rest ()
+			},
+			_ => return Ok (()),
+		};
+		for (box_type, box_data) in boxes (children) {
+			let Ok (box_data) = box_data else { break };
+			match & box_type.0 {
+				b"av1C" | b"avcC" | b"dOps" | b"hvcC" => {
+					self.codec_config_type = Some (box_type);
+					self.codec_private = Some (box_data.to_vec ());
+				},
+				b"esds" => self.object_type = parse_esds_object_type (box_data),
+				b"sinf" => {
+					// encrypted entries give the original format in the protection scheme info
+					if let Some (frma) = find_box (box_data, b"frma") ? && 4 <= frma.len () {
+						self.codec = Some (FourCc::from_slice (frma));
+					}
+				},
+				b"wave" => {
+					// quicktime wraps the elementary stream descriptor in a wave atom
+					if let Some (esds) = find_box (box_data, b"esds") ? {
+						self.object_type = parse_esds_object_type (esds);
+					}
+				},
+				_ => (),
+			}
+		}
+		Ok (())
+	}
+
+}
+
+fn read_box_data (src: & mut impl Read, box_type: FourCc, len: u64) -> anyhow::Result <Vec <u8>> {
+	let mut data = vec! [0; len as usize];
+	src.read_exact (& mut data)
+		.with_context (|| any_err! ("Error reading {box_type} box")) ?;
+	Ok (data)
+}
+
+/// Extract the title from a user data box
+fn parse_udta_title (data: & [u8]) -> anyhow::Result <Option <String>> {
+	for (box_type, box_data) in boxes (data) {
+		let box_data = box_data ?;
+		match & box_type.0 {
+			b"\xa9nam" => {
+				// quicktime text atom, 16 bit length and language followed by the text
+				let mut data = ByteReader::new (box_data);
+				let len = data.u16 () ? as usize;
+				data.skip (2) ?;
+				let text = data.bytes (len.min (data.remaining ())) ?;
+				return Ok (Some (String::from_utf8_lossy (text).into_owned ()));
+			},
+			b"name" => {
+				return Ok (Some (String::from_utf8_lossy (box_data).trim_end_matches ('\0').to_owned ()));
+			},
+			b"meta" => {
+				// iso meta boxes are full boxes, quicktime ones are not
+				let box_data = if box_data.get (4 .. 8) == Some (b"hdlr") { box_data } else {
+					box_data.get (4 .. ).unwrap_or_default ()
+				};
+				let Some (ilst) = find_box (box_data, b"ilst") ? else { continue };
+				let Some (name) = find_box (ilst, b"\xa9nam") ? else { continue };
+				let Some (name_data) = find_box (name, b"data") ? else { continue };
+				let Some (text) = name_data.get (8 .. ) else { continue };
+				return Ok (Some (String::from_utf8_lossy (text).into_owned ()));
+			},
+			_ => (),
+		}
+	}
+	Ok (None)
+}
+
+fn parse_mvex_duration (data: & [u8]) -> anyhow::Result <Option <u64>> {
+	let Some (mehd) = find_box (data, b"mehd") ? else { return Ok (None) };
+	let mut data = ByteReader::new (mehd);
+	let (version, _flags) = data.full_box () ?;
+	Ok (Some (if version == 1 { data.u64 () ? } else { data.u32 () ? as u64 }))
+}
+
+/// Get the object type indication from an elementary stream descriptor box
+fn parse_esds_object_type (data: & [u8]) -> Option <u8> {
+	let mut data = ByteReader::new (data);
+	data.full_box ().ok () ?;
+	let mut tag = data.u8 ().ok () ?;
+	loop {
+		let len = data.descriptor_len ().ok () ?;
+		match tag {
+			// es descriptor, skip the optional fields and continue with the contents
+			0x03 => {
+				data.skip (2).ok () ?;
+				let flags = data.u8 ().ok () ?;
+				if flags & 0x80 != 0 { data.skip (2).ok () ?; }
+				if flags & 0x40 != 0 {
+					let url_len = data.u8 ().ok () ?;
+					data.skip (url_len as usize).ok () ?;
+				}
+				if flags & 0x20 != 0 { data.skip (2).ok () ?; }
+			},
+			// decoder config descriptor
+			0x04 => return data.u8 ().ok (),
+			_ => data.skip (len).ok () ?,
+		}
+		tag = data.u8 ().ok () ?;
+	}
+}
+
+fn find_box <'dat> (data: & 'dat [u8], box_type: & [u8; 4]) -> anyhow::Result <Option <& 'dat [u8]>> {
+	for (child_type, child_data) in boxes (data) {
+		let child_data = child_data ?;
+		if & child_type.0 == box_type { return Ok (Some (child_data)) }
+	}
+	Ok (None)
+}
+
+/// Iterate over the boxes contained in a slice
+fn boxes (data: & [u8]) -> impl Iterator <Item = (FourCc, anyhow::Result <& [u8]>)> {
+	let mut pos = 0;
+	iter::from_fn (move || {
+		if data.len () < pos + 8 { return None }
+		let box_type = FourCc::from_slice (& data [pos + 4 .. pos + 8]);
+		let (header_len, box_len) = match u32::from_be_bytes (data [pos .. pos + 4].try_into ().unwrap ()) {
+			0 => (8, data.len () - pos),
+			1 => {
+				let Some (len) = data.get (pos + 8 .. pos + 16) else {
+					pos = data.len ();
+					return Some ((box_type, Err (any_err! ("Truncated {box_type} box header"))));
+				};
+				(16, u64::from_be_bytes (len.try_into ().unwrap ()) as usize)
+			},
+			len => (8, len as usize),
+		};
+		if box_len < header_len || data.len () - pos < box_len {
+			pos = data.len ();
+			return Some ((box_type, Err (any_err! ("Invalid size for {box_type} box: {box_len}"))));
+		}
+		let box_data = & data [pos + header_len .. pos + box_len];
+		pos += box_len;
+		Some ((box_type, Ok (box_data)))
+	})
+}
+
+struct ByteReader <'dat> {
+	data: & 'dat [u8],
+	pos: usize,
+}
+
+impl <'dat> ByteReader <'dat> {
+
+	fn new (data: & 'dat [u8]) -> Self {
+		Self { data, pos: 0 }
+	}
+
+	fn bytes (& mut self, num: usize) -> anyhow::Result <& 'dat [u8]> {
+		let Some (data) = self.data.get (self.pos .. self.pos + num) else {
+			any_bail! ("Unexpected end of data");
+		};
+		self.pos += num;
+		Ok (data)
+	}
+
+	fn skip (& mut self, num: usize) -> anyhow::Result <()> {
+		self.bytes (num) ?;
+		Ok (())
+	}
+
+	fn rest (& mut self) -> & 'dat [u8] {
+		let data = & self.data [self.pos .. ];
+		self.pos = self.data.len ();
+		data
+	}
+
+	fn remaining (& self) -> usize {
+		self.data.len () - self.pos
+	}
+
+	fn u8 (& mut self) -> anyhow::Result <u8> {
+		Ok (self.bytes (1) ? [0])
+	}
+
+	fn u16 (& mut self) -> anyhow::Result <u16> {
+		Ok (u16::from_be_bytes (self.bytes (2) ?.try_into ().unwrap ()))
+	}
+
+	fn u32 (& mut self) -> anyhow::Result <u32> {
+		Ok (u32::from_be_bytes (self.bytes (4) ?.try_into ().unwrap ()))
+	}
+
+	fn u64 (& mut self) -> anyhow::Result <u64> {
+		Ok (u64::from_be_bytes (self.bytes (8) ?.try_into ().unwrap ()))
+	}
+
+	fn four_cc (& mut self) -> anyhow::Result <FourCc> {
+		Ok (FourCc::from_slice (self.bytes (4) ?))
+	}
+
+	/// Read the version and flags from the start of a full box
+	fn full_box (& mut self) -> anyhow::Result <(u8, u32)> {
+		let val = self.u32 () ?;
+		Ok (((val >> 24) as u8, val & 0xff_ffff))
+	}
+
+	/// Read the variable length size of an mpeg-4 descriptor
+	fn descriptor_len (& mut self) -> anyhow::Result <usize> {
+		let mut len = 0;
+		for _ in 0 .. 4 {
+			let byte = self.u8 () ?;
+			len = len << 7 | (byte & 0x7f) as usize;
+			if byte & 0x80 == 0 { break }
+		}
+		Ok (len)
+	}
+
+}
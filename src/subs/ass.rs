@@ -0,0 +1,285 @@
+//! Advanced SubStation Alpha subtitles, also used for the older SubStation Alpha format
+//!
+//! Events are read using the field order from the "Format" line in the events section. Every other
+//! section is kept in the header, ahead of the events section, which is also the layout matroska
+//! expects in the codec private data.
+
+use crate::imports::*;
+
+use super::Event;
+use super::EventExtra;
+use super::Format;
+use super::Subtitles;
+use super::Time;
+
+/// Fields of a dialogue line other than the times and text, kept as text to avoid any changes
+#[ derive (Clone, Debug) ]
+pub struct AssEvent {
+	pub comment: bool,
+	pub layer: String,
+	pub style: String,
+	pub name: String,
+	pub margin_l: String,
+	pub margin_r: String,
+	pub margin_v: String,
+	pub effect: String,
+}
+
+impl Default for AssEvent {
+	fn default () -> Self {
+		Self {
+			comment: false,
+			layer: "0".to_owned (),
+			style: "Default".to_owned (),
+			name: String::new (),
+			margin_l: "0".to_owned (),
+			margin_r: "0".to_owned (),
+			margin_v: "0".to_owned (),
+			effect: String::new (),
+		}
+	}
+}
+
+pub const DEFAULT_EVENT_FORMAT: & str =
+	"Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text";
+
+/// Header for subtitles converted from other formats, the same as ffmpeg uses
+pub const DEFAULT_HEADER: & str = "\
+[Script Info]
+ScriptType: v4.00+
+PlayResX: 384
+PlayResY: 288
+ScaledBorderAndShadow: yes
+
+[V4+ Styles]
+Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, \
+Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, \
+Alignment, MarginL, MarginR, MarginV, Encoding
+Style: Default,Arial,16,&Hffffff,&Hffffff,&H0,&H0,0,0,0,0,100,100,0,0,1,1,0,2,10,10,10,0
+
+[Events]
+Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text
+";
+
+/// Parts of a style which can be expressed in other formats
+#[ derive (Clone, Debug) ]
+pub struct AssStyle {
+	pub name: String,
+	pub font_name: String,
+	pub bold: bool,
+	pub italic: bool,
+	pub underline: bool,
+	pub strike: bool,
+	pub color: Option <[u8; 3]>,
+	pub top: bool,
+}
+
+/// Read the styles from the header, for either "[V4+ Styles]" or the older "[V4 Styles]"
+pub fn styles (header: & str) -> Vec <AssStyle> {
+	let mut styles = Vec::new ();
+	let mut section = "";
+	let mut format: Vec <String> = Vec::new ();
+	for line in header.lines () {
+		let line = line.trim_end ();
+		if line.starts_with ('[') {
+			section = line;
+			format.clear ();
+			continue;
+		}
+		let legacy = section.eq_ignore_ascii_case ("[v4 styles]");
+		if ! legacy && ! section.eq_ignore_ascii_case ("[v4+ styles]") { continue }
+		if let Some (rest) = line.strip_prefix ("Format:") {
+			format = parse_format (rest);
+			continue;
+		}
+		let Some (rest) = line.strip_prefix ("Style:") else { continue };
+		let values: Vec <& str> = rest.trim_start ().splitn (format.len ().max (1), ',').collect ();
+		let field = |name: & str| format.iter ()
+			.position (|field| field.eq_ignore_ascii_case (name))
+			.and_then (|idx| values.get (idx))
+			.map (|val| val.trim ())
+			.unwrap_or_default ();
+		let flag = |name: & str| field (name).parse::<i64> ().is_ok_and (|val| val != 0);
+		let alignment = field ("Alignment").parse::<u32> ().unwrap_or (2);
+		styles.push (AssStyle {
+			name: field ("Name").to_owned (),
+			font_name: field ("Fontname").to_owned (),
+			bold: flag ("Bold"),
+			italic: flag ("Italic"),
+			underline: flag ("Underline"),
+			strike: flag ("StrikeOut"),
+			color: super::markup::parse_ass_color (field ("PrimaryColour")),
+			// ssa numbers alignments differently, with 5 to 7 for the top of the screen
+			top: if legacy { (5 ..= 7).contains (& alignment) } else { 7 <= alignment },
+		});
+	}
+	styles
+}
+
+/// Find the style used by an event, falling back to "Default" as renderers do
+pub fn find_style <'sty> (styles: & 'sty [AssStyle], name: & str) -> Option <& 'sty AssStyle> {
+	let name = name.trim_start_matches ('*');
+	styles.iter ().find (|style| style.name == name)
+		.or_else (|| styles.iter ().find (|style| style.name == "Default"))
+}
+
+pub fn parse (text: & str) -> anyhow::Result <Subtitles> {
+	let mut header = Vec::new ();
+	let mut events = Vec::new ();
+	let mut event_format = None;
+	let mut in_events = false;
+	for (line_idx, line) in text.lines ().enumerate () {
+		let line = line.trim_start_matches ('\u{feff}').trim_end ();
+		if line.starts_with ('[') {
+			in_events = line.eq_ignore_ascii_case ("[events]");
+			if in_events { continue }
+		}
+		if ! in_events {
+			header.push (line);
+			continue;
+		}
+		let Some ((kind, rest)) = line.split_once (':') else { continue };
+		let comment = match kind {
+			"Format" => { event_format = Some (parse_format (rest)); continue },
+			"Dialogue" => false,
+			"Comment" => true,
+			_ => continue,
+		};
+		let format = event_format.get_or_insert_with (|| parse_format (DEFAULT_EVENT_FORMAT));
+		let values: Vec <& str> = rest.trim_start ().splitn (format.len (), ',').collect ();
+		any_ensure! (values.len () == format.len (),
+			"Expected {} fields at line {}: {line}", format.len (), line_idx + 1);
+		let mut event = Event {
+			start: Time::default (),
+			end: Time::default (),
+			text: String::new (),
+			extra: EventExtra::None,
+		};
+		let mut ass_event = AssEvent { comment, .. AssEvent::default () };
+		for (field, value) in format.iter ().zip (values) {
+			match field.as_str () {
+				"Start" | "End" => {
+					let time = Time::parse_hms (value, '.')
+						.ok_or_else (|| any_err! ("Invalid time at line {}: {value}", line_idx + 1)) ?;
+					if field == "Start" { event.start = time } else { event.end = time }
+				},
+				"Text" => event.text = value.to_owned (),
+				"Layer" | "Marked" => ass_event.layer = value.to_owned (),
+				"Style" => ass_event.style = value.to_owned (),
+				"Name" | "Actor" => ass_event.name = value.to_owned (),
+				"MarginL" => ass_event.margin_l = value.to_owned (),
+				"MarginR" => ass_event.margin_r = value.to_owned (),
+				"MarginV" => ass_event.margin_v = value.to_owned (),
+				"Effect" => ass_event.effect = value.to_owned (),
+				_ => (),
+			}
+		}
+		event.extra = EventExtra::Ass (ass_event);
+		events.push (event);
+	}
+	while header.last ().is_some_and (|line| line.is_empty ()) { header.pop (); }
+	let format = event_format.unwrap_or_else (|| parse_format (DEFAULT_EVENT_FORMAT));
+	let header = format! ("{}\n\n[Events]\nFormat: {}\n", header.join ("\n"), format.join (", "));
+	Ok (Subtitles { format: Format::Ass, header, events })
+}
+
+fn parse_format (val: & str) -> Vec <String> {
+	val.split (',').map (|field| field.trim ().to_owned ()).collect ()
+}
+
+/// Field order for events, from the format line in the events section of the header
+pub fn event_format (header: & str) -> Option <Vec <String>> {
+	let mut in_events = false;
+	let mut format = None;
+	for line in header.lines () {
+		if line.starts_with ('[') {
+			in_events = line.trim_end ().eq_ignore_ascii_case ("[events]");
+		} else if in_events && let Some (rest) = line.strip_prefix ("Format:") {
+			format = Some (parse_format (rest));
+		}
+	}
+	format
+}
+
+pub fn write (subs: & Subtitles) -> String {
+	let mut output = subs.header.trim_end ().to_owned ();
+	output.push ('\n');
+	let format = match event_format (& subs.header) {
+		Some (format) => format,
+		None => {
+			output.push_str (& format! ("\n[Events]\nFormat: {DEFAULT_EVENT_FORMAT}\n"));
+			parse_format (DEFAULT_EVENT_FORMAT)
+		},
+	};
+	for event in & subs.events {
+		output.push_str (& write_event (& format, event));
+		output.push ('\n');
+	}
+	output
+}
+
+/// Format an event as a dialogue or comment line
+pub fn write_event (format: & [String], event: & Event) -> String {
+	let default_event = AssEvent::default ();
+	let ass_event = match & event.extra {
+		EventExtra::Ass (ass_event) => ass_event,
+		_ => & default_event,
+	};
+	let values: Vec <String> = format.iter ()
+		.map (|field| match field.as_str () {
+			"Start" => format_time (event.start),
+			"End" => format_time (event.end),
+			"Text" => event.text.replace ('\n', "\\N"),
+			"Layer" | "Marked" => ass_event.layer.clone (),
+			"Style" => ass_event.style.clone (),
+			"Name" | "Actor" => ass_event.name.clone (),
+			"MarginL" => ass_event.margin_l.clone (),
+			"MarginR" => ass_event.margin_r.clone (),
+			"MarginV" => ass_event.margin_v.clone (),
+			"Effect" => ass_event.effect.clone (),
+			_ => String::new (),
+		})
+		.collect ();
+	let kind = if ass_event.comment { "Comment" } else { "Dialogue" };
+	format! ("{kind}: {}", values.join (","))
+}
+
+/// Format a time as "H:MM:SS.CC", rounding to the nearest centisecond
+pub fn format_time (time: Time) -> String {
+	let (hours, mins, secs, millis) = Time ((time.0 + 5) / 10 * 10).parts ();
+	format! ("{hours}:{mins:02}:{secs:02}.{:02}", millis / 10)
+}
+
+/// Fonts used by the events, from their styles and any "\fn" or "\r" overrides, without
+/// duplicates and in the order they are first used
+pub fn font_names (subs: & Subtitles) -> Vec <String> {
+	let styles = styles (& subs.header);
+	let mut names: Vec <String> = Vec::new ();
+	let mut add = |name: & str| {
+		let name = name.trim ().trim_start_matches ('@');
+		if ! name.is_empty () && ! names.iter ().any (|other| other.eq_ignore_ascii_case (name)) {
+			names.push (name.to_owned ());
+		}
+	};
+	for event in & subs.events {
+		let EventExtra::Ass (ass_event) = & event.extra else { continue };
+		if ass_event.comment { continue }
+		if let Some (style) = find_style (& styles, & ass_event.style) { add (& style.font_name) }
+		let mut rest = event.text.as_str ();
+		while let Some (start) = rest.find ('{') {
+			let Some (len) = rest [start .. ].find ('}') else { break };
+			for tag in super::markup::split_override_tags (& rest [start + 1 .. start + len]) {
+				let tag = tag.trim ();
+				if let Some (font_name) = tag.strip_prefix ("fn") {
+					add (font_name);
+				} else if let Some (style_name) = tag.strip_prefix ('r')
+						&& ! style_name.is_empty ()
+						&& let Some (style) = find_style (& styles, style_name) {
+					add (& style.font_name);
+				}
+			}
+			rest = & rest [start + len + 1 .. ];
+		}
+	}
+	names
+}
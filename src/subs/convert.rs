@@ -0,0 +1,78 @@
+//! Conversion between subtitle formats
+//!
+//! Text is converted through [`Markup`], so bold, italic, underline, strike-through and colours
+//! are kept where the target format can express them. ASS styles are applied to each event when
+//! converting to another format, positioning is reduced to top or bottom of the screen, and the
+//! speaker moves between the ASS name field and WebVTT voice spans.
+
+use super::Event;
+use super::EventExtra;
+use super::Format;
+use super::Subtitles;
+use super::ass;
+use super::markup::Markup;
+use super::markup::Token;
+use super::vtt;
+
+impl Subtitles {
+
+	pub fn convert (& self, format: Format) -> Self {
+		if format == self.format { return self.clone () }
+		let styles = if self.format == Format::Ass { ass::styles (& self.header) } else { Vec::new () };
+		let mut events: Vec <Event> = self.events.iter ()
+			.filter_map (|event| {
+				let mut markup = Markup::parse (self.format, & event.text);
+				match & event.extra {
+					EventExtra::Ass (ass_event) => {
+						if ass_event.comment { return None }
+						if let Some (style) = ass::find_style (& styles, & ass_event.style) {
+							let style_tokens = [
+								(style.bold, Token::Bold (true)),
+								(style.italic, Token::Italic (true)),
+								(style.underline, Token::Underline (true)),
+								(style.strike, Token::Strike (true)),
+								(style.color.is_some_and (|color| color != [ 0xff; 3 ]), Token::Color (style.color)),
+							];
+							markup.tokens.splice (0 .. 0, style_tokens.into_iter ()
+								.filter (|& (applies, _)| applies)
+								.map (|(_, token)| token));
+							markup.top = markup.top.or (Some (style.top));
+						}
+						if ! ass_event.name.is_empty () { markup.voice = Some (ass_event.name.clone ()) }
+					},
+					EventExtra::WebVtt (cue) => {
+						markup.top = markup.top.or (vtt::is_top (& cue.settings).then_some (true));
+					},
+					EventExtra::None => (),
+				}
+				let extra = match format {
+					Format::Ass => EventExtra::Ass (ass::AssEvent {
+						name: markup.voice.clone ().unwrap_or_default (),
+						.. ass::AssEvent::default ()
+					}),
+					Format::Srt => EventExtra::None,
+					Format::WebVtt => EventExtra::WebVtt (vtt::VttCue {
+						id: None,
+						settings: if markup.top == Some (true) { "line:0".to_owned () } else { String::new () },
+					}),
+				};
+				if format == Format::WebVtt { markup.top = None }
+				Some (Event {
+					start: event.start,
+					end: event.end,
+					text: markup.write (format),
+					extra,
+				})
+			})
+			.collect ();
+		// ass events can be in any order, but the others are expected to be sorted
+		if format != Format::Ass { events.sort_by_key (|event| event.start) }
+		let header = match format {
+			Format::Ass => ass::DEFAULT_HEADER.to_owned (),
+			Format::Srt => String::new (),
+			Format::WebVtt => "WEBVTT".to_owned (),
+		};
+		Self { format, header, events }
+	}
+
+}
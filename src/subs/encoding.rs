@@ -0,0 +1,164 @@
+//! Character encoding detection and conversion for text subtitles
+//!
+//! Matroska requires UTF-8 for text subtitles, but files found in the wild are often in a legacy
+//! code page. Byte order marks are trusted, then valid UTF-8 is accepted as it is, and anything
+//! else is guessed from the byte frequencies with `chardetng`, which knows all the legacy
+//! encodings web browsers do, from the windows code pages to shift-jis, gbk, big5 and euc-kr.
+
+use crate::imports::*;
+
+/// A character encoding from the WHATWG encoding standard, as implemented by `encoding_rs`
+#[ derive (Clone, Copy, Debug, Eq, PartialEq) ]
+pub struct Encoding (& 'static encoding_rs::Encoding);
+
+impl Encoding {
+
+	pub const UTF_8: Self = Self (encoding_rs::UTF_8);
+	pub const UTF_16LE: Self = Self (encoding_rs::UTF_16LE);
+	pub const UTF_16BE: Self = Self (encoding_rs::UTF_16BE);
+
+	/// Look up an encoding by any of its WHATWG labels, such as "latin1", "cp1251" or "big5", and a
+	/// few common names which aren't labels there
+	pub fn for_label (label: & str) -> anyhow::Result <Self> {
+		let label = label.trim ().to_ascii_lowercase ();
+		let label = match label.as_str () {
+			"utf16le" => "utf-16le",
+			"utf16be" => "utf-16be",
+			"cp932" => "shift_jis",
+			"cp936" => "gbk",
+			"cp949" => "euc-kr",
+			"cp950" => "big5",
+			label => label,
+		};
+		match encoding_rs::Encoding::for_label (label.as_bytes ()) {
+			// the replacement encoding turns everything into a single error character
+			Some (encoding) if encoding != encoding_rs::REPLACEMENT => Ok (Self (encoding)),
+			_ => any_bail! ("Unknown character encoding \"{label}\""),
+		}
+	}
+
+	pub fn name (self) -> & 'static str {
+		self.0.name ()
+	}
+
+	/// Decode text in this encoding, failing if it isn't valid
+	pub fn decode (self, data: & [u8]) -> anyhow::Result <String> {
+		let mut decoder = self.0.new_decoder_without_bom_handling ();
+		let mut text = String::with_capacity (data.len () * 3);
+		match decoder.decode_to_string_without_replacement (data, & mut text, true) {
+			(encoding_rs::DecoderResult::InputEmpty, _) => Ok (text),
+			(_, pos) => any_bail! ("Invalid byte sequence at {pos}"),
+		}
+	}
+
+	/// Decode text in this encoding, replacing anything invalid
+	pub fn decode_lossy (self, data: & [u8]) -> String {
+		self.0.decode_without_bom_handling (data).0.into_owned ()
+	}
+
+}
+
+impl fmt::Display for Encoding {
+	fn fmt (& self, fmtr: & mut fmt::Formatter) -> fmt::Result {
+		fmtr.write_str (& self.name ().to_ascii_lowercase ())
+	}
+}
+
+/// How the encoding of a file was decided
+#[ derive (Clone, Copy, Debug, Eq, PartialEq) ]
+pub enum Detection {
+	Specified,
+	ByteOrderMark,
+	Valid,
+	Guessed,
+}
+
+/// Text decoded from a subtitle file, with what was done to it
+#[ derive (Debug) ]
+pub struct Decoded {
+	pub text: String,
+	pub encoding: Encoding,
+	pub detection: Detection,
+	pub had_bom: bool,
+	pub had_crlf: bool,
+}
+
+impl Decoded {
+
+	/// Check if the original file was already UTF-8 with unix line endings and no byte order mark
+	pub fn is_unchanged (& self) -> bool {
+		self.encoding == Encoding::UTF_8 && ! self.had_bom && ! self.had_crlf
+	}
+
+	/// Describe the encoding and any changes made, eg "windows-1252 (guessed), crlf"
+	pub fn describe (& self) -> String {
+		let mut desc = self.encoding.to_string ();
+		match self.detection {
+			Detection::Specified => (),
+			Detection::ByteOrderMark => desc.push_str (" (bom)"),
+			Detection::Valid => (),
+			Detection::Guessed => desc.push_str (" (guessed)"),
+		}
+		if self.had_bom && self.detection != Detection::ByteOrderMark { desc.push_str (", bom") }
+		if self.had_crlf { desc.push_str (", crlf") }
+		desc
+	}
+
+}
+
+/// Decode a text subtitle file, detecting the encoding unless it is specified, then strip any byte
+/// order mark and normalise line endings
+pub fn decode (data: & [u8], encoding: Option <Encoding>) -> anyhow::Result <Decoded> {
+	let (encoding, detection, data) = match encoding {
+		Some (encoding) => (encoding, Detection::Specified, data),
+		None => detect (data),
+	};
+	let text = encoding.decode (data)
+		.with_context (|| any_err! ("Invalid {encoding} text")) ?;
+	let had_bom = text.starts_with ('\u{feff}') || detection == Detection::ByteOrderMark;
+	let text = text.strip_prefix ('\u{feff}').unwrap_or (& text);
+	let had_crlf = text.contains ('\r');
+	let text = if had_crlf { text.replace ("\r\n", "\n").replace ('\r', "\n") } else { text.to_owned () };
+	Ok (Decoded { text, encoding, detection, had_bom, had_crlf })
+}
+
+/// Work out the encoding of some text, returning the data with any byte order mark removed
+pub fn detect (data: & [u8]) -> (Encoding, Detection, & [u8]) {
+	if let Some (rest) = data.strip_prefix (b"\xef\xbb\xbf") {
+		return (Encoding::UTF_8, Detection::ByteOrderMark, rest);
+	}
+	if let Some (rest) = data.strip_prefix (b"\xff\xfe") {
+		return (Encoding::UTF_16LE, Detection::ByteOrderMark, rest);
+	}
+	if let Some (rest) = data.strip_prefix (b"\xfe\xff") {
+		return (Encoding::UTF_16BE, Detection::ByteOrderMark, rest);
+	}
+	if let Some (encoding) = guess_utf16 (data) {
+		return (encoding, Detection::Guessed, data);
+	}
+	if str::from_utf8 (data).is_ok () {
+		return (Encoding::UTF_8, Detection::Valid, data);
+	}
+	(guess_legacy (data), Detection::Guessed, data)
+}
+
+/// Text in UTF-16 without a byte order mark still has lots of zero bytes, since most of the
+/// characters used in subtitles are in the first 256 code points, even for CJK text which has
+/// plenty of digits and punctuation
+fn guess_utf16 (data: & [u8]) -> Option <Encoding> {
+	let pairs = & data [ .. usize::min (data.len (), 4096) & ! 1];
+	if pairs.len () < 16 { return None }
+	let num_pairs = pairs.len () / 2;
+	let zeros_even = pairs.iter ().step_by (2).filter (|& & byte| byte == 0).count ();
+	let zeros_odd = pairs.iter ().skip (1).step_by (2).filter (|& & byte| byte == 0).count ();
+	if num_pairs < zeros_odd * 3 && zeros_even * 10 < num_pairs { return Some (Encoding::UTF_16LE) }
+	if num_pairs < zeros_even * 3 && zeros_odd * 10 < num_pairs { return Some (Encoding::UTF_16BE) }
+	None
+}
+
+/// Guess a legacy encoding from the byte frequencies, as web browsers do for unlabelled pages
+fn guess_legacy (data: & [u8]) -> Encoding {
+	let mut detector = chardetng::EncodingDetector::new (chardetng::Iso2022JpDetection::Deny);
+	detector.feed (data, true);
+	Encoding (detector.guess (None, chardetng::Utf8Detection::Deny))
+}
@@ -0,0 +1,129 @@
+//! Read text subtitle tracks from matroska files, block by block
+//!
+//! ASS blocks hold "ReadOrder,Layer,Style,Name,MarginL,MarginR,MarginV,Effect,Text", and the
+//! header comes from the codec private data. WebVTT blocks hold the cue text, with the settings and
+//! identifier in a block addition.
+
+use crate::imports::*;
+use crate::matroska;
+
+use super::Event;
+use super::EventExtra;
+use super::Format;
+use super::Subtitles;
+use super::Time;
+use super::ass;
+use super::vtt;
+
+/// Format used for a matroska codec id, or `None` if it isn't a text subtitle codec we handle
+pub fn format_for_codec (codec_id: & str) -> Option <Format> {
+	match codec_id {
+		"S_TEXT/ASS" | "S_TEXT/SSA" => Some (Format::Ass),
+		"S_TEXT/UTF8" => Some (Format::Srt),
+		"S_TEXT/WEBVTT" => Some (Format::WebVtt),
+		_ => None,
+	}
+}
+
+/// Track number and codec of each subtitle track, in the order ffmpeg numbers them
+pub fn subtitle_tracks (file_path: & Path) -> anyhow::Result <Vec <(u64, String)>> {
+	let file = BufReader::new (File::open (file_path) ?);
+	let mut reader = matroska::Reader::new (file) ?;
+	Ok (reader.tracks () ?.entries.iter ()
+		.filter (|track| track.track_type == matroska::TrackType::Subtitle)
+		.map (|track| (track.number, track.codec_id.clone ()))
+		.collect ())
+}
+
+/// Read a text subtitle track from a matroska file, identified by its track number
+pub fn read_track (file_path: & Path, track_number: u64) -> anyhow::Result <Subtitles> {
+
+	let file = BufReader::new (File::open (file_path) ?);
+	let mut reader = matroska::Reader::new (file) ?;
+	let timestamp_scale = reader.segment_info () ?.timestamp_scale as i64;
+	let tracks = reader.tracks () ?;
+	let track = tracks.entries.iter ()
+		.find (|track| track.number == track_number)
+		.ok_or_else (|| any_err! ("No track number {track_number}")) ?;
+	let format = format_for_codec (& track.codec_id)
+		.ok_or_else (|| any_err! ("Unsupported subtitle codec: {}", track.codec_id)) ?;
+	any_ensure! (track.content_encodings.is_none (),
+		"Compressed or encrypted subtitle tracks are not supported");
+	let header = track.codec_private.as_ref ()
+		.map (|data| String::from_utf8_lossy (data).into_owned ())
+		.unwrap_or_default ();
+
+	// collect blocks with their read order, which is only used for ass
+
+	let to_time = |ticks: i64| Time (ticks * timestamp_scale / 1_000_000);
+	let mut blocks: Vec <(i64, Event)> = Vec::new ();
+	let mut missing_end = Vec::new ();
+	let mut cluster = reader.first_cluster () ?;
+	while let Some (cluster_elem) = cluster {
+		let block_groups = cluster_elem.block_groups.iter ()
+			.flat_map (|group| group.blocks.iter ().map (move |block| (block, Some (group))));
+		let simple_blocks = cluster_elem.simple_blocks.iter ().map (|block| (block, None));
+		for (block, group) in simple_blocks.chain (block_groups) {
+			if block.track_number != track_number { continue }
+			any_ensure! (block.lacing () == 0, "Laced subtitle blocks are not supported");
+			let start_ticks = cluster_elem.timestamp as i64 + block.timestamp as i64;
+			let duration = group.and_then (|group| group.block_duration);
+			let data = String::from_utf8_lossy (& block.data);
+			let data = data.trim_end_matches ('\0');
+			let mut event = Event {
+				start: to_time (start_ticks),
+				end: to_time (start_ticks + duration.unwrap_or (0) as i64),
+				text: String::new (),
+				extra: EventExtra::None,
+			};
+			let mut read_order = 0;
+			match format {
+				Format::Ass => {
+					let values: Vec <& str> = data.splitn (9, ',').collect ();
+					any_ensure! (values.len () == 9, "Expected 9 fields in ASS block: {data}");
+					read_order = values [0].trim ().parse ().unwrap_or (0);
+					event.text = values [8].to_owned ();
+					event.extra = EventExtra::Ass (ass::AssEvent {
+						comment: false,
+						layer: values [1].to_owned (),
+						style: values [2].to_owned (),
+						name: values [3].to_owned (),
+						margin_l: values [4].to_owned (),
+						margin_r: values [5].to_owned (),
+						margin_v: values [6].to_owned (),
+						effect: values [7].to_owned (),
+					});
+				},
+				Format::Srt => event.text = data.replace ("\r\n", "\n"),
+				Format::WebVtt => {
+					event.text = data.replace ("\r\n", "\n");
+					let addition = group
+						.and_then (|group| group.block_additions.as_ref ())
+						.and_then (|additions| additions.mores.iter ().find (|more| more.add_id == 1))
+						.map (|more| String::from_utf8_lossy (& more.additional).into_owned ())
+						.unwrap_or_default ();
+					let mut addition_lines = addition.lines ();
+					let settings = addition_lines.next ().unwrap_or_default ().trim ().to_owned ();
+					let id = addition_lines.next ().map (str::trim).filter (|id| ! id.is_empty ()).map (str::to_owned);
+					event.extra = EventExtra::WebVtt (vtt::VttCue { id, settings });
+				},
+			}
+			if duration.is_none () { missing_end.push (blocks.len ()) }
+			blocks.push ((read_order, event));
+		}
+		cluster = reader.next_cluster () ?;
+	}
+
+	// blocks without a duration last until the next one starts
+
+	for & block_idx in & missing_end {
+		let next_start = blocks.get (block_idx + 1).map (|(_, event)| event.start);
+		let event = & mut blocks [block_idx].1;
+		event.end = next_start.unwrap_or (Time (event.start.0 + 2000));
+	}
+
+	if format == Format::Ass { blocks.sort_by_key (|& (read_order, _)| read_order) }
+	let events = blocks.into_iter ().map (|(_, event)| event).collect ();
+	Ok (Subtitles { format, header, events })
+
+}
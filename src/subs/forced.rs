@@ -0,0 +1,212 @@
+//! Spot subtitle tracks which only cover foreign dialogue, and so should be flagged as forced
+//!
+//! Discs often carry such a track alongside the full one, but rarely set the flag. The blocks of
+//! every subtitle track are read, including bitmap tracks, so this works on the number of events,
+//! how much of the runtime they cover and how big the track is, rather than on the text. A track
+//! is likely forced when it has far fewer events or far less data than another track in the same
+//! language, or covers hardly any of the runtime while there is another track in that language. A
+//! lone track which covers little is more likely a sparse film than a forced track.
+
+use crate::imports::*;
+use crate::language;
+use crate::matroska;
+
+use super::Time;
+
+/// A track with less than this fraction of the events or size of another is likely forced
+const PEER_RATIO: f64 = 0.25;
+
+/// A track covering less than this fraction of the runtime is likely forced, if it has a peer
+const MIN_COVERAGE: f64 = 0.05;
+
+/// Events without a duration last until the next block on the track, but no longer than this
+const MAX_OPEN_DURATION: Time = Time (10_000);
+
+#[ derive (Clone, Debug) ]
+pub struct TrackStats {
+	/// Index among the subtitle tracks, counting from zero
+	pub subs_idx: usize,
+	pub codec_id: String,
+	pub language: String,
+	pub title: Option <String>,
+	pub flag_forced: bool,
+	pub events: usize,
+	pub bytes: u64,
+	/// Time with at least one event showing
+	pub coverage: Time,
+}
+
+#[ derive (Clone, Debug) ]
+pub enum ForcedReason {
+	Title,
+	FewerEvents { other_idx: usize, ratio: f64 },
+	SmallerSize { other_idx: usize, ratio: f64 },
+	LowCoverage { ratio: f64 },
+}
+
+impl fmt::Display for ForcedReason {
+	fn fmt (& self, fmtr: & mut fmt::Formatter) -> fmt::Result {
+		match * self {
+			Self::Title => write! (fmtr, "title says forced"),
+			Self::FewerEvents { other_idx, ratio } =>
+				write! (fmtr, "{:.0}% of the events of subs-{other_idx}", ratio * 100.0),
+			Self::SmallerSize { other_idx, ratio } =>
+				write! (fmtr, "{:.0}% of the size of subs-{other_idx}", ratio * 100.0),
+			Self::LowCoverage { ratio } => write! (fmtr, "shown for {:.1}% of the runtime", ratio * 100.0),
+		}
+	}
+}
+
+/// Read the blocks of every subtitle track in a matroska file, returning statistics for each
+/// track in the order ffmpeg numbers them, along with the runtime
+pub fn read_stats (file_path: & Path) -> anyhow::Result <(Vec <TrackStats>, Time)> {
+
+	let file = BufReader::new (File::open (file_path) ?);
+	let mut reader = matroska::Reader::new (file) ?;
+	let segment_info = reader.segment_info () ?;
+	let timestamp_scale = segment_info.timestamp_scale as i64;
+	let tracks = reader.tracks () ?;
+	let subs_tracks: Vec <_> = tracks.entries.iter ()
+		.filter (|track| track.track_type == matroska::TrackType::Subtitle)
+		.collect ();
+
+	// collect the start, duration and size of each block, and whether it clears the screen
+
+	let to_time = |ticks: i64| Time (ticks * timestamp_scale / 1_000_000);
+	let mut blocks: Vec <Vec <BlockInfo>> = vec! [ Vec::new (); subs_tracks.len () ];
+	let mut last_time = Time (0);
+	let mut cluster = reader.first_cluster () ?;
+	while let Some (cluster_elem) = cluster {
+		let block_groups = cluster_elem.block_groups.iter ()
+			.flat_map (|group| group.blocks.iter ().map (move |block| (block, group.block_duration)));
+		let simple_blocks = cluster_elem.simple_blocks.iter ().map (|block| (block, None));
+		for (block, duration) in simple_blocks.chain (block_groups) {
+			let start_ticks = cluster_elem.timestamp as i64 + block.timestamp as i64;
+			last_time = last_time.max (to_time (start_ticks + duration.unwrap_or (0) as i64));
+			let Some (track_idx) = subs_tracks.iter ().position (|track| track.number == block.track_number)
+				else { continue };
+			let track = subs_tracks [track_idx];
+			// compressed blocks can't be looked into, but still count towards the size
+			let clear = track.content_encodings.is_none () && is_clear (& track.codec_id, & block.data);
+			blocks [track_idx].push (BlockInfo {
+				start: to_time (start_ticks),
+				duration: duration.map (|duration| to_time (duration as i64)),
+				size: block.data.len () as u64,
+				clear,
+			});
+		}
+		cluster = reader.next_cluster () ?;
+	}
+	let runtime = segment_info.duration
+		.map (|duration| to_time (duration as i64))
+		.unwrap_or (last_time);
+
+	// work out the events and how much of the runtime they cover
+
+	let stats = subs_tracks.iter ().zip (blocks).enumerate ()
+		.map (|(subs_idx, (track, mut blocks))| {
+			blocks.sort_by_key (|block| block.start);
+			let mut spans: Vec <(Time, Time)> = Vec::new ();
+			for (block_idx, block) in blocks.iter ().enumerate () {
+				if block.clear { continue }
+				let start = block.start;
+				let end = match block.duration {
+					Some (duration) => Time (start.0 + duration.0),
+					None => blocks.get (block_idx + 1)
+						.map_or (Time (start.0 + 2000), |next| next.start)
+						.min (Time (start.0 + MAX_OPEN_DURATION.0)),
+				};
+				spans.push ((start, end));
+			}
+			let mut coverage = Time (0);
+			let mut covered_to = Time (i64::MIN);
+			for & (start, end) in & spans {
+				let start = start.max (covered_to);
+				if start < end {
+					coverage = Time (coverage.0 + end.0 - start.0);
+					covered_to = end;
+				}
+			}
+			TrackStats {
+				subs_idx,
+				codec_id: track.codec_id.clone (),
+				language: track.language_bcp47.as_ref ().unwrap_or (& track.language).clone (),
+				title: track.name.clone (),
+				flag_forced: track.flag_forced,
+				events: spans.len (),
+				bytes: blocks.iter ().map (|block| block.size).sum (),
+				coverage,
+			}
+		})
+		.collect ();
+
+	Ok ((stats, runtime))
+
+}
+
+#[ derive (Clone, Copy) ]
+struct BlockInfo {
+	start: Time,
+	duration: Option <Time>,
+	size: u64,
+	/// Only clears the screen, so doesn't count as an event
+	clear: bool,
+}
+
+/// Check for a block which only clears the screen, which PGS uses to end each caption
+fn is_clear (codec_id: & str, data: & [u8]) -> bool {
+	match codec_id {
+		"S_HDMV/PGS" => {
+			// a presentation composition segment with no composition objects
+			let mut pos = 0;
+			while pos + 3 <= data.len () {
+				let segment_type = data [pos];
+				let segment_len = u16::from_be_bytes ([ data [pos + 1], data [pos + 2] ]) as usize;
+				if segment_type == 0x16 {
+					return data.get (pos + 3 + 10).is_some_and (|& num_objects| num_objects == 0);
+				}
+				pos += 3 + segment_len;
+			}
+			false
+		},
+		codec_id if codec_id.starts_with ("S_TEXT/") =>
+			data.iter ().all (|& byte| byte == 0 || byte.is_ascii_whitespace ()),
+		_ => false,
+	}
+}
+
+/// Reason each track is likely to be forced, if it is, comparing it with others in the same
+/// language
+pub fn likely_forced (tracks: & [TrackStats], runtime: Time) -> Vec <Option <ForcedReason>> {
+	tracks.iter ()
+		.map (|track| {
+			if track.events == 0 { return None }
+			if track.title.as_ref ().is_some_and (|title| title.to_lowercase ().contains ("forced")) {
+				return Some (ForcedReason::Title);
+			}
+			let peers = || tracks.iter ()
+				.filter (|other| other.subs_idx != track.subs_idx && 0 < other.events)
+				.filter (|other| language::same_language (& other.language, & track.language));
+			if let Some (fullest) = peers ().max_by_key (|other| other.events) {
+				let ratio = track.events as f64 / fullest.events as f64;
+				if ratio < PEER_RATIO {
+					return Some (ForcedReason::FewerEvents { other_idx: fullest.subs_idx, ratio });
+				}
+			}
+			// sizes only compare between tracks of the same codec
+			if let Some (largest) = peers ()
+					.filter (|other| other.codec_id == track.codec_id)
+					.max_by_key (|other| other.bytes) {
+				let ratio = track.bytes as f64 / largest.bytes as f64;
+				if ratio < PEER_RATIO {
+					return Some (ForcedReason::SmallerSize { other_idx: largest.subs_idx, ratio });
+				}
+			}
+			if 0 < runtime.0 && peers ().next ().is_some () {
+				let ratio = track.coverage.0 as f64 / runtime.0 as f64;
+				if ratio < MIN_COVERAGE { return Some (ForcedReason::LowCoverage { ratio }) }
+			}
+			None
+		})
+		.collect ()
+}
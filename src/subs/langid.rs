@@ -0,0 +1,307 @@
+//! Work out the language of subtitle text offline, from character n-gram profiles
+//!
+//! Each profile lists the most common one to three character sequences in a language, most common
+//! first, with words padded by spaces so that the starts and ends of words count. Text is compared
+//! by how far each of its own most common sequences is from the same rank in a profile, as
+//! described by Cavnar and Trenkle, and the confidence comes from how far ahead the best match is
+//! of the next one. Dialogue is short and informal, so this needs a few lines at least, and close
+//! languages like Danish and Norwegian are often told apart with little confidence.
+
+use crate::imports::*;
+use crate::language;
+use crate::matroska;
+
+use super::Subtitles;
+use super::extract;
+use super::markup::Markup;
+
+/// One line for each language, with the ISO 639-2 code, the BCP 47 tag and the name, then a tab
+/// and the sequences, space separated with underscores for spaces. Generated from a few hundred
+/// words of sample dialogue in each language, keeping the most common sequences.
+const PROFILES: & str = include_str! ("langid.txt");
+
+/// Number of sequences from the text to compare, which is also the size of each profile
+const PROFILE_SIZE: usize = 400;
+
+/// Don't guess with fewer letters than this
+const MIN_LETTERS: usize = 20;
+
+/// Stop reading a track once this much text has been collected
+const MAX_SAMPLE_CHARS: usize = 20_000;
+
+/// Stop reading clusters this far into a file, in milliseconds, so that a track with little text
+/// doesn't mean reading the whole film
+const MAX_SCAN_MILLIS: u64 = 20 * 60 * 1000;
+
+/// Lead over the next best language which gives a confidence of one half
+const CONFIDENCE_SCALE: f64 = 0.1;
+
+/// Confidence needed to use a guess without asking
+pub const MIN_CONFIDENCE: f64 = 0.6;
+
+#[ derive (Clone, Copy, Debug) ]
+pub struct Guess {
+	/// ISO 639-2 code, eg "fre"
+	pub code: & 'static str,
+	/// BCP 47 tag, eg "fr"
+	pub tag: & 'static str,
+	pub name: & 'static str,
+	/// From zero to one, how far ahead this language was of the next best
+	pub confidence: f64,
+}
+
+impl Guess {
+
+	pub fn is_confident (& self) -> bool {
+		MIN_CONFIDENCE <= self.confidence
+	}
+
+	/// Check if a language code or tag refers to the language guessed, ignoring any region
+	pub fn matches (& self, language: & str) -> bool {
+		language::same_language (language, self.code)
+	}
+
+}
+
+impl fmt::Display for Guess {
+	fn fmt (& self, fmtr: & mut fmt::Formatter) -> fmt::Result {
+		write! (fmtr, "{} ({}, {:.0}%)", self.name, self.code, self.confidence * 100.0)
+	}
+}
+
+/// Guess the language of some text, or `None` if there isn't enough of it
+pub fn identify (text: & str) -> Option <Guess> {
+	if text.chars ().filter (|& ch| ! is_separator (ch)).count () < MIN_LETTERS { return None }
+	let ranked = rank_ngrams (text);
+	let mut distances: Vec <(usize, & str)> = PROFILES.lines ()
+		.filter_map (|line| {
+			let (head, ngrams) = line.split_once ('\t') ?;
+			let profile: BTreeMap <String, usize> = ngrams.split (' ')
+				.enumerate ()
+				.map (|(rank, ngram)| (ngram.replace ('_', " "), rank))
+				.collect ();
+			let distance = ranked.iter ().enumerate ()
+				.map (|(rank, ngram)| profile.get (ngram).map_or (PROFILE_SIZE, |& other| rank.abs_diff (other)))
+				.sum ();
+			Some ((distance, head))
+		})
+		.collect ();
+	distances.sort ();
+	let & [ (best, head), (next, _), .. ] = distances.as_slice () else { return None };
+	let mut head_parts = head.splitn (3, ' ');
+	let (Some (code), Some (tag), Some (name)) = (head_parts.next (), head_parts.next (), head_parts.next ())
+		else { return None };
+	let lead = (next - best) as f64 / next.max (1) as f64;
+	Some (Guess { code, tag, name, confidence: lead / (lead + CONFIDENCE_SCALE) })
+}
+
+/// Most common sequences in the text, most common first and then in order, to match the profiles
+fn rank_ngrams (text: & str) -> Vec <String> {
+	let mut counts: BTreeMap <String, usize> = BTreeMap::new ();
+	for word in text.to_lowercase ().split (is_separator).filter (|word| ! word.is_empty ()) {
+		let padded: Vec <char> = iter::once (' ').chain (word.chars ()).chain (iter::once (' ')).collect ();
+		for len in 1 ..= 3 {
+			for ngram in padded.windows (len) {
+				if ngram == [ ' ' ] { continue }
+				* counts.entry (ngram.iter ().collect ()).or_default () += 1;
+			}
+		}
+	}
+	let mut ranked: Vec <(String, usize)> = counts.into_iter ().collect ();
+	ranked.sort_by (|(left, left_count), (right, right_count)|
+		right_count.cmp (left_count).then_with (|| left.cmp (right)));
+	ranked.into_iter ().take (PROFILE_SIZE).map (|(ngram, _)| ngram).collect ()
+}
+
+/// Characters which separate words, everything else counts as part of one so that scripts which
+/// use combining marks, or no spaces at all, still work
+fn is_separator (ch: char) -> bool {
+	ch.is_whitespace () || ch.is_numeric () || (ch.is_ascii () && ! ch.is_ascii_alphabetic ())
+		|| matches! (ch,
+			'\u{a0}' ..= '\u{bf}' | '\u{d7}' | '\u{f7}' | '\u{5be}' | '\u{5c0}' | '\u{5c3}'
+				| '\u{5f3}' ..= '\u{5f4}' | '\u{60c}' | '\u{61b}' | '\u{61f}' | '\u{2000}' ..= '\u{206f}'
+				| '\u{3000}' ..= '\u{303f}' | '\u{ff01}' ..= '\u{ff20}' | '\u{ff3b}' ..= '\u{ff40}'
+				| '\u{ff5b}' ..= '\u{ff65}')
+}
+
+/// Text of the events without markup, one per line, for identifying the language
+pub fn subtitles_text (subtitles: & Subtitles) -> String {
+	let mut text = String::new ();
+	for event in & subtitles.events {
+		if let super::EventExtra::Ass (ass_event) = & event.extra && ass_event.comment { continue }
+		text.push_str (& Markup::parse (subtitles.format, & event.text).plain_text ());
+		text.push ('\n');
+		if MAX_SAMPLE_CHARS <= text.len () { break }
+	}
+	text
+}
+
+/// Language guessed for a text subtitle track in a matroska file
+#[ derive (Clone, Debug) ]
+pub struct TrackGuess {
+	/// Index among the subtitle tracks, counting from zero
+	pub subs_idx: usize,
+	/// Language the track is tagged with
+	pub language: String,
+	pub guess: Option <Guess>,
+}
+
+struct TrackSample {
+	subs_idx: usize,
+	track_number: u64,
+	format: super::Format,
+	language: String,
+	text: String,
+}
+
+/// Guess the language of each text subtitle track in a matroska file
+///
+/// Only enough of each track to identify it is read, and never more than the first twenty minutes
+/// of the file, so tracks with little text in that time get no guess.
+pub fn identify_tracks (file_path: & Path) -> anyhow::Result <Vec <TrackGuess>> {
+
+	let file = BufReader::new (File::open (file_path) ?);
+	let mut reader = matroska::Reader::new (file) ?;
+	let timestamp_scale = reader.segment_info () ?.timestamp_scale;
+	let tracks = reader.tracks () ?;
+	let mut samples: Vec <TrackSample> = tracks.entries.iter ()
+		.filter (|track| track.track_type == matroska::TrackType::Subtitle)
+		.enumerate ()
+		.filter (|(_, track)| track.content_encodings.is_none ())
+		.filter_map (|(subs_idx, track)| Some (TrackSample {
+			subs_idx,
+			track_number: track.number,
+			format: extract::format_for_codec (& track.codec_id) ?,
+			language: track.language_bcp47.as_ref ().unwrap_or (& track.language).clone (),
+			text: String::new (),
+		}))
+		.collect ();
+
+	let mut cluster = if samples.is_empty () { None } else { reader.first_cluster () ? };
+	while let Some (cluster_elem) = cluster {
+		if MAX_SCAN_MILLIS < cluster_elem.timestamp.saturating_mul (timestamp_scale) / 1_000_000 { break }
+		let blocks = cluster_elem.simple_blocks.iter ()
+			.chain (cluster_elem.block_groups.iter ().flat_map (|group| & group.blocks));
+		for block in blocks {
+			let Some (sample) = samples.iter_mut ().find (|sample| sample.track_number == block.track_number)
+				else { continue };
+			if MAX_SAMPLE_CHARS <= sample.text.len () { continue }
+			let data = String::from_utf8_lossy (& block.data);
+			let data = data.trim_end_matches ('\0');
+			// ass blocks have eight fields before the text
+			let data =
+				if sample.format == super::Format::Ass { data.splitn (9, ',').nth (8).unwrap_or_default () }
+				else { data };
+			sample.text.push_str (& Markup::parse (sample.format, data).plain_text ());
+			sample.text.push ('\n');
+		}
+		if samples.iter ().all (|sample| MAX_SAMPLE_CHARS <= sample.text.len ()) { break }
+		cluster = reader.next_cluster () ?;
+	}
+
+	Ok (samples.into_iter ()
+		.map (|sample| TrackGuess {
+			subs_idx: sample.subs_idx,
+			guess: identify (& sample.text),
+			language: sample.language,
+		})
+		.collect ())
+
+}
+
+#[ cfg (test) ]
+mod tests {
+
+	use super::*;
+
+	/// Short dialogue in each language with a profile, written separately from the samples the
+	/// profiles came from
+	const SAMPLES: & [(& str, & str)] = & [
+		("ara", "لا أعرف ماذا حدث الليلة الماضية. هل رأيت أخي في المدينة؟ قال إنه سيعود قبل المساء لكنه لم يتصل بي حتى الآن."),
+		("chi", "我不知道昨天晚上发生了什么。你在城里看到我哥哥了吗？他说他会在天黑以前回来，可是到现在还没有给我打电话。"),
+		("cze", "Nevím, co se stalo minulou noc. Viděl jsi mého bratra ve městě? Říkal, že se vrátí před večerem, ale ještě mi nezavolal."),
+		("dan", "Jeg ved ikke, hvad der skete i nat. Så du min bror inde i byen? Han sagde, at han ville komme hjem før aften, men han har ikke ringet endnu."),
+		("dut", "Ik weet niet wat er gisteravond is gebeurd. Heb je mijn broer in de stad gezien? Hij zei dat hij voor de avond terug zou zijn, maar hij heeft nog niet gebeld."),
+		("eng", "I don't know what happened last night. Did you see my brother in town? He said he would be back before dark, but he still hasn't called me."),
+		("fin", "En tiedä, mitä eilen illalla tapahtui. Näitkö veljeni kaupungilla? Hän sanoi tulevansa takaisin ennen iltaa, mutta ei ole vieläkään soittanut minulle."),
+		("fre", "Je ne sais pas ce qui s'est passé hier soir. Tu as vu mon frère en ville ? Il a dit qu'il rentrerait avant la nuit, mais il ne m'a toujours pas appelé."),
+		("ger", "Ich weiß nicht, was letzte Nacht passiert ist. Hast du meinen Bruder in der Stadt gesehen? Er hat gesagt, dass er vor dem Abend zurück ist, aber er hat mich noch nicht angerufen."),
+		("gre", "Δεν ξέρω τι έγινε χθες το βράδυ. Είδες τον αδερφό μου στην πόλη; Είπε ότι θα γυρίσει πριν νυχτώσει, αλλά ακόμα δεν μου έχει τηλεφωνήσει."),
+		("heb", "אני לא יודע מה קרה אתמול בלילה. ראית את אחי בעיר? הוא אמר שהוא יחזור לפני הערב, אבל הוא עדיין לא התקשר אליי."),
+		("hrv", "Ne znam što se dogodilo sinoć. Jesi li vidio mog brata u gradu? Rekao je da će se vratiti prije mraka, ali me još uvijek nije nazvao."),
+		("hun", "Nem tudom, mi történt tegnap éjjel. Láttad a bátyámat a városban? Azt mondta, hogy sötétedés előtt visszajön, de még mindig nem hívott fel."),
+		("ind", "Aku tidak tahu apa yang terjadi tadi malam. Apakah kamu melihat kakakku di kota? Dia bilang akan pulang sebelum gelap, tapi sampai sekarang dia belum menelepon."),
+		("ita", "Non so cosa sia successo ieri sera. Hai visto mio fratello in città? Ha detto che sarebbe tornato prima di sera, ma non mi ha ancora chiamato."),
+		("jpn", "昨日の夜に何があったのか分からない。町で兄を見かけなかった？暗くなる前に帰ってくるって言ってたのに、まだ電話もしてこないんだ。"),
+		("kor", "어젯밤에 무슨 일이 있었는지 모르겠어. 시내에서 우리 형 봤어? 어두워지기 전에 돌아온다고 했는데 아직도 전화를 안 했어."),
+		("nor", "Jeg vet ikke hva som skjedde i går kveld. Så du broren min i byen? Han sa at han skulle komme hjem før det ble mørkt, men han har ikke ringt meg ennå."),
+		("pol", "Nie wiem, co się stało wczoraj wieczorem. Widziałeś mojego brata w mieście? Powiedział, że wróci przed zmrokiem, ale wciąż do mnie nie zadzwonił."),
+		("por", "Eu não sei o que aconteceu ontem à noite. Você viu o meu irmão na cidade? Ele disse que voltaria antes de escurecer, mas ainda não me ligou."),
+		("rum", "Nu știu ce s-a întâmplat aseară. L-ai văzut pe fratele meu în oraș? A spus că se întoarce înainte de întuneric, dar încă nu m-a sunat."),
+		("rus", "Я не знаю, что случилось вчера вечером. Ты видел моего брата в городе? Он сказал, что вернётся до темноты, но до сих пор мне не позвонил."),
+		("spa", "No sé qué pasó anoche. ¿Viste a mi hermano en el pueblo? Dijo que volvería antes de que oscureciera, pero todavía no me ha llamado."),
+		("swe", "Jag vet inte vad som hände i går kväll. Såg du min bror inne i stan? Han sa att han skulle komma hem innan det blev mörkt, men han har inte ringt mig än."),
+		("tha", "ฉันไม่รู้ว่าเกิดอะไรขึ้นเมื่อคืนนี้ เธอเห็นพี่ชายของฉันในเมืองไหม เขาบอกว่าจะกลับมาก่อนมืด แต่จนถึงตอนนี้เขายังไม่โทรหาฉันเลย"),
+		("tur", "Dün gece ne olduğunu bilmiyorum. Ağabeyimi şehirde gördün mü? Hava kararmadan döneceğini söyledi ama hâlâ beni aramadı."),
+		("ukr", "Я не знаю, що сталося вчора ввечері. Ти бачив мого брата в місті? Він сказав, що повернеться до темряви, але досі мені не зателефонував."),
+		("vie", "Tôi không biết chuyện gì đã xảy ra tối qua. Bạn có thấy anh trai tôi ở trong thành phố không? Anh ấy nói sẽ về trước khi trời tối, nhưng đến giờ vẫn chưa gọi cho tôi."),
+	];
+
+	#[ test ]
+	fn every_profile () {
+		let codes: Vec <& str> = PROFILES.lines ()
+			.filter_map (|line| line.split (' ').next ())
+			.collect ();
+		assert_eq! (codes, SAMPLES.iter ().map (|& (code, _)| code).collect::<Vec <_>> ());
+		for & (code, text) in SAMPLES {
+			let guess = identify (text).unwrap ();
+			assert_eq! (guess.code, code, "{text}");
+		}
+	}
+
+	#[ test ]
+	fn too_little_text () {
+		assert! (identify ("").is_none ());
+		assert! (identify ("Oui, merci.").is_none ());
+		assert! (identify ("12:30 ... !!! ?? 45 -- 99").is_none ());
+	}
+
+	#[ test ]
+	fn confident () {
+		for & (code, text) in SAMPLES {
+			// these two are close enough that a few lines are often not enough to be sure
+			if matches! (code, "dan" | "nor") { continue }
+			let guess = identify (text).unwrap ();
+			assert! (guess.is_confident (), "{guess}: {text}");
+		}
+	}
+
+	#[ test ]
+	fn mixed_text_is_not_confident () {
+		let text = SAMPLES.iter ()
+			.filter (|& & (code, _)| matches! (code, "dan" | "nor"))
+			.map (|& (_, text)| text)
+			.join ("\n");
+		let guess = identify (& text).unwrap ();
+		assert! (! guess.is_confident (), "{guess}");
+	}
+
+	#[ test ]
+	fn confidence_threshold () {
+		let guess = |confidence| Guess { code: "fre", tag: "fr", name: "French", confidence };
+		assert! (guess (MIN_CONFIDENCE).is_confident ());
+		assert! (guess (1.0).is_confident ());
+		assert! (! guess (MIN_CONFIDENCE - 0.01).is_confident ());
+		assert! (! guess (0.0).is_confident ());
+	}
+
+	#[ test ]
+	fn matches_ignoring_form_and_region () {
+		let guess = Guess { code: "por", tag: "pt", name: "Portuguese", confidence: 1.0 };
+		assert! (guess.matches ("por"));
+		assert! (guess.matches ("pt"));
+		assert! (guess.matches ("pt-BR"));
+		assert! (! guess.matches ("spa"));
+	}
+
+}
@@ -0,0 +1,437 @@
+//! Inline formatting in subtitle text, in a form which can be written out in any of the formats
+//!
+//! SubRip and WebVTT use HTML like tags, and SubRip files often contain ASS override blocks as
+//! well, which most players understand. ASS uses override blocks like "{\i1}". Only formatting
+//! which all three formats can express is kept, anything else is dropped on conversion.
+
+use crate::imports::*;
+
+use super::Format;
+
+#[ derive (Clone, Debug, Eq, PartialEq) ]
+pub enum Token {
+	Text (String),
+	LineBreak,
+	Bold (bool),
+	Italic (bool),
+	Underline (bool),
+	Strike (bool),
+	/// Text colour as RGB, or `None` to go back to the default
+	Color (Option <[u8; 3]>),
+}
+
+/// Parsed text of an event, with the formatting which applies to the whole event
+#[ derive (Clone, Debug, Default) ]
+pub struct Markup {
+	pub tokens: Vec <Token>,
+	/// Placed at the top of the screen, if this was specified either way
+	pub top: Option <bool>,
+	/// Speaker, from a WebVTT voice span
+	pub voice: Option <String>,
+}
+
+/// Colours which WebVTT has predefined classes for
+const VTT_COLORS: & [(& str, [u8; 3])] = & [
+	("white", [ 0xff, 0xff, 0xff ]),
+	("lime", [ 0x00, 0xff, 0x00 ]),
+	("cyan", [ 0x00, 0xff, 0xff ]),
+	("red", [ 0xff, 0x00, 0x00 ]),
+	("yellow", [ 0xff, 0xff, 0x00 ]),
+	("magenta", [ 0xff, 0x00, 0xff ]),
+	("blue", [ 0x00, 0x00, 0xff ]),
+	("black", [ 0x00, 0x00, 0x00 ]),
+];
+
+impl Markup {
+
+	pub fn parse (format: Format, text: & str) -> Self {
+		let mut markup = Self::default ();
+		match format {
+			Format::Ass => markup.parse_ass (text),
+			Format::Srt => markup.parse_tags (text, false),
+			Format::WebVtt => markup.parse_tags (text, true),
+		}
+		markup
+	}
+
+	pub fn write (& self, format: Format) -> String {
+		match format {
+			Format::Ass => self.write_ass (),
+			Format::Srt => self.write_tags (false),
+			Format::WebVtt => self.write_tags (true),
+		}
+	}
+
+	/// Text without any formatting, with line breaks as "\n"
+	pub fn plain_text (& self) -> String {
+		self.tokens.iter ()
+			.filter_map (|token| match token {
+				Token::Text (text) => Some (text.as_str ()),
+				Token::LineBreak => Some ("\n"),
+				_ => None,
+			})
+			.collect ()
+	}
+
+	fn push_text (& mut self, text: & str) {
+		if text.is_empty () { return }
+		if let Some (Token::Text (last)) = self.tokens.last_mut () {
+			last.push_str (text);
+		} else {
+			self.tokens.push (Token::Text (text.to_owned ()));
+		}
+	}
+
+	fn parse_ass (& mut self, text: & str) {
+		let mut rest = text;
+		while let Some (pos) = rest.find (['{', '\\']) {
+			self.push_text (& rest [ .. pos]);
+			rest = & rest [pos .. ];
+			if rest.starts_with ('{') {
+				let Some (end) = rest.find ('}') else { break };
+				self.parse_override (& rest [1 .. end]);
+				rest = & rest [end + 1 .. ];
+			} else {
+				match rest.as_bytes ().get (1) {
+					Some (b'N') => self.tokens.push (Token::LineBreak),
+					// soft line breaks only apply with some wrap styles, so treat them as spaces
+					Some (b'n') => self.push_text (" "),
+					Some (b'h') => self.push_text ("\u{a0}"),
+					_ => { self.push_text ("\\"); rest = & rest [1 .. ]; continue },
+				}
+				rest = & rest [2 .. ];
+			}
+		}
+		self.push_text (rest);
+	}
+
+	/// Parse the contents of an ASS override block, eg "\b1\c&H0000FF&"
+	fn parse_override (& mut self, block: & str) {
+		for tag in split_override_tags (block) {
+			let tag = tag.trim ();
+			// font names can start with anything, so check for them first
+			if tag.starts_with ("fn") { continue }
+			let name_len = tag.bytes ()
+				.enumerate ()
+				.take_while (|& (idx, byte)| byte.is_ascii_alphabetic () || (idx == 0 && byte.is_ascii_digit ()))
+				.count ();
+			let (name, value) = tag.split_at (name_len);
+			let value = value.trim ();
+			let flag = || value.parse::<u32> ().is_ok_and (|value| value != 0);
+			match name {
+				"b" => self.tokens.push (Token::Bold (flag ())),
+				"i" => self.tokens.push (Token::Italic (flag ())),
+				"u" => self.tokens.push (Token::Underline (flag ())),
+				"s" => self.tokens.push (Token::Strike (flag ())),
+				"c" | "1c" => self.tokens.push (Token::Color (parse_ass_color (value))),
+				"an" => if let Ok (align) = value.parse::<u32> () { self.top = Some (7 <= align) },
+				"a" => if let Ok (align) = value.parse::<u32> () { self.top = Some ((5 ..= 7).contains (& align)) },
+				"r" => self.tokens.extend ([
+					Token::Bold (false),
+					Token::Italic (false),
+					Token::Underline (false),
+					Token::Strike (false),
+					Token::Color (None),
+				]),
+				_ => (),
+			}
+		}
+	}
+
+	fn parse_tags (& mut self, text: & str, vtt: bool) {
+		let mut class_color = false;
+		let mut rest = text;
+		while let Some (pos) = rest.find (['<', '{', '&', '\n']) {
+			self.push_text (& rest [ .. pos]);
+			rest = & rest [pos .. ];
+			match rest.as_bytes () [0] {
+				b'<' => {
+					let Some (end) = rest.find ('>') else { break };
+					let tag = & rest [1 .. end];
+					rest = & rest [end + 1 .. ];
+					let (closing, tag) = match tag.strip_prefix ('/') {
+						Some (tag) => (true, tag),
+						None => (false, tag),
+					};
+					let (name, annotation) = tag.split_once (char::is_whitespace).unwrap_or ((tag, ""));
+					let mut classes = name.split ('.');
+					let name = classes.next ().unwrap ().to_ascii_lowercase ();
+					match (name.as_str (), closing) {
+						("b", _) => self.tokens.push (Token::Bold (! closing)),
+						("i", _) => self.tokens.push (Token::Italic (! closing)),
+						("u", _) => self.tokens.push (Token::Underline (! closing)),
+						("s", _) => self.tokens.push (Token::Strike (! closing)),
+						("font", false) => if let Some (color) = parse_font_color (annotation) {
+							self.tokens.push (Token::Color (Some (color)));
+						},
+						("font", true) => self.tokens.push (Token::Color (None)),
+						("c", false) => if let Some (color) = classes
+								.find_map (|class| VTT_COLORS.iter ().find (|& & (name, _)| name == class)) {
+							self.tokens.push (Token::Color (Some (color.1)));
+							class_color = true;
+						},
+						("c", true) if class_color => {
+							self.tokens.push (Token::Color (None));
+							class_color = false;
+						},
+						("v", false) if vtt && ! annotation.trim ().is_empty () =>
+							self.voice = Some (annotation.trim ().to_owned ()),
+						_ => (),
+					}
+				},
+				b'{' if ! vtt && rest [1 .. ].starts_with ('\\') && rest.contains ('}') => {
+					let end = rest.find ('}').unwrap ();
+					self.parse_override (& rest [1 .. end]);
+					rest = & rest [end + 1 .. ];
+				},
+				b'&' if vtt => {
+					let entity = [
+						("&amp;", "&"), ("&lt;", "<"), ("&gt;", ">"), ("&nbsp;", "\u{a0}"),
+						("&lrm;", "\u{200e}"), ("&rlm;", "\u{200f}"),
+					].into_iter ().find (|& (entity, _)| rest.starts_with (entity));
+					let (entity, text) = entity.unwrap_or (("&", "&"));
+					self.push_text (text);
+					rest = & rest [entity.len () .. ];
+				},
+				b'\n' => {
+					self.tokens.push (Token::LineBreak);
+					rest = & rest [1 .. ];
+				},
+				_ => {
+					self.push_text (& rest [ .. 1]);
+					rest = & rest [1 .. ];
+				},
+			}
+		}
+		self.push_text (rest);
+	}
+
+	fn write_ass (& self) -> String {
+		let mut output = String::new ();
+		let mut pending = String::new ();
+		if self.top == Some (true) { pending.push_str ("\\an8") }
+		let mut state = State::default ();
+		for token in & self.tokens {
+			match token {
+				Token::Text (text) => {
+					if ! pending.is_empty () {
+						output.push_str (& format! ("{{{pending}}}"));
+						pending.clear ();
+					}
+					output.push_str (text);
+				},
+				Token::LineBreak => output.push_str ("\\N"),
+				token => if let Some (span) = state.update (token) {
+					let (name, on) = match span {
+						(Span::Bold, on) => ("b", on),
+						(Span::Italic, on) => ("i", on),
+						(Span::Underline, on) => ("u", on),
+						(Span::Strike, on) => ("s", on),
+						(Span::Color ([ red, green, blue ]), true) => {
+							pending.push_str (& format! ("\\c&H{blue:02X}{green:02X}{red:02X}&"));
+							continue;
+						},
+						(Span::Color (_), false) => { pending.push_str ("\\c"); continue },
+					};
+					pending.push_str (& format! ("\\{name}{}", u8::from (on)));
+				},
+			}
+		}
+		output
+	}
+
+	/// Write as SubRip or WebVTT, closing and reopening tags as needed so they nest properly
+	fn write_tags (& self, vtt: bool) -> String {
+		let mut output = String::new ();
+		if ! vtt && self.top == Some (true) { output.push_str ("{\\an8}") }
+		if vtt && let Some (voice) = self.voice.as_ref () {
+			output.push_str (& format! ("<v {voice}>"));
+		}
+		let mut state = State::default ();
+		let mut open: Vec <Span> = Vec::new ();
+		for token in & self.tokens {
+			match token {
+				Token::Text (text) if vtt => output.push_str (
+					& text.replace ('&', "&amp;").replace ('<', "&lt;").replace ('>', "&gt;")),
+				Token::Text (text) => output.push_str (text),
+				Token::LineBreak => output.push ('\n'),
+				token => {
+					let Some ((span, on)) = state.update (token) else { continue };
+					// any colour change closes the current colour first
+					if let Some (pos) = open.iter ().position (|open_span|
+							std::mem::discriminant (open_span) == std::mem::discriminant (& span)) {
+						let reopen = open.split_off (pos + 1);
+						for open_span in reopen.iter ().rev () { output.push_str (& open_span.close_tag (vtt)) }
+						output.push_str (& open.pop ().unwrap ().close_tag (vtt));
+						for open_span in reopen {
+							output.push_str (& open_span.open_tag (vtt));
+							open.push (open_span);
+						}
+					}
+					if on && ! span.open_tag (vtt).is_empty () {
+						output.push_str (& span.open_tag (vtt));
+						open.push (span);
+					}
+				},
+			}
+		}
+		for open_span in open.iter ().rev () { output.push_str (& open_span.close_tag (vtt)) }
+		output
+	}
+
+}
+
+/// Split an override block into tags, ignoring backslashes inside parentheses, as in "\t(\b1)"
+pub fn split_override_tags (block: & str) -> Vec <& str> {
+	let mut tags = Vec::new ();
+	let mut depth = 0_u32;
+	let mut start = None;
+	for (pos, ch) in block.char_indices () {
+		match ch {
+			'(' => depth += 1,
+			')' => depth = depth.saturating_sub (1),
+			'\\' if depth == 0 => {
+				if let Some (start) = start { tags.push (& block [start .. pos]) }
+				start = Some (pos + 1);
+			},
+			_ => (),
+		}
+	}
+	if let Some (start) = start { tags.push (& block [start .. ]) }
+	tags
+}
+
+/// Parse an ASS colour, "&HBBGGRR&" with optional alpha, or a decimal number in SSA files
+pub fn parse_ass_color (val: & str) -> Option <[u8; 3]> {
+	let val = val.trim ().trim_end_matches ('&');
+	let val = match val.strip_prefix ("&H").or_else (|| val.strip_prefix ("&h")) {
+		Some (hex) => u32::from_str_radix (hex, 16).ok () ?,
+		None => val.parse::<i64> ().ok () ? as u32,
+	};
+	Some ([ val as u8, (val >> 8) as u8, (val >> 16) as u8 ])
+}
+
+fn parse_font_color (attrs: & str) -> Option <[u8; 3]> {
+	let (_, val) = attrs.split_once ("color=") ?;
+	let val = val.trim_start_matches (['"', '\'']);
+	let val = & val [ .. val.find (['"', '\'', ' ']).unwrap_or (val.len ())];
+	if let Some (hex) = val.strip_prefix ('#') && hex.len () == 6 {
+		let rgb = u32::from_str_radix (hex, 16).ok () ?;
+		return Some ([ (rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8 ]);
+	}
+	VTT_COLORS.iter ().find (|& & (name, _)| name.eq_ignore_ascii_case (val)).map (|& (_, color)| color)
+}
+
+#[ derive (Clone, Copy, Debug, Eq, PartialEq) ]
+enum Span {
+	Bold,
+	Italic,
+	Underline,
+	Strike,
+	Color ([u8; 3]),
+}
+
+impl Span {
+
+	fn open_tag (self, vtt: bool) -> String {
+		match self {
+			Self::Bold => "<b>".to_owned (),
+			Self::Italic => "<i>".to_owned (),
+			Self::Underline => "<u>".to_owned (),
+			Self::Strike if vtt => String::new (),
+			Self::Strike => "<s>".to_owned (),
+			Self::Color (color) if vtt => VTT_COLORS.iter ()
+				.find (|& & (_, vtt_color)| vtt_color == color)
+				.map (|& (name, _)| format! ("<c.{name}>"))
+				.unwrap_or_default (),
+			Self::Color ([ red, green, blue ]) => format! ("<font color=\"#{red:02x}{green:02x}{blue:02x}\">"),
+		}
+	}
+
+	fn close_tag (self, vtt: bool) -> String {
+		match self {
+			Self::Bold => "</b>".to_owned (),
+			Self::Italic => "</i>".to_owned (),
+			Self::Underline => "</u>".to_owned (),
+			Self::Strike => "</s>".to_owned (),
+			Self::Color (_) if vtt => "</c>".to_owned (),
+			Self::Color (_) => "</font>".to_owned (),
+		}
+	}
+
+}
+
+/// Current formatting while writing, so tokens which change nothing can be skipped
+#[ derive (Default) ]
+struct State {
+	bold: bool,
+	italic: bool,
+	underline: bool,
+	strike: bool,
+	color: Option <[u8; 3]>,
+}
+
+impl State {
+
+	/// Apply a formatting token, returning the span which changed and whether it is now on
+	fn update (& mut self, token: & Token) -> Option <(Span, bool)> {
+		let (flag, span, on) = match * token {
+			Token::Bold (on) => (& mut self.bold, Span::Bold, on),
+			Token::Italic (on) => (& mut self.italic, Span::Italic, on),
+			Token::Underline (on) => (& mut self.underline, Span::Underline, on),
+			Token::Strike (on) => (& mut self.strike, Span::Strike, on),
+			Token::Color (color) => {
+				if self.color == color { return None }
+				let prev = self.color;
+				self.color = color;
+				return Some (match color {
+					Some (color) => (Span::Color (color), true),
+					None => (Span::Color (prev.unwrap ()), false),
+				});
+			},
+			Token::Text (_) | Token::LineBreak => return None,
+		};
+		if * flag == on { return None }
+		* flag = on;
+		Some ((span, on))
+	}
+
+}
+
+/// Find tags which are opened but never closed, or never finished, in the text of an event
+pub fn unclosed_tags (format: Format, text: & str) -> Vec <String> {
+	let mut unclosed = Vec::new ();
+	if format != Format::Ass {
+		let mut open: Vec <String> = Vec::new ();
+		let mut rest = text;
+		while let Some (pos) = rest.find ('<') {
+			rest = & rest [pos + 1 .. ];
+			let Some (end) = rest.find ('>') else {
+				unclosed.push ("<".to_owned ());
+				break;
+			};
+			let tag = & rest [ .. end];
+			rest = & rest [end + 1 .. ];
+			let (closing, tag) = match tag.strip_prefix ('/') {
+				Some (tag) => (true, tag),
+				None => (false, tag),
+			};
+			let name = tag.split (|ch: char| ch == '.' || ch.is_whitespace ()).next ().unwrap ().to_ascii_lowercase ();
+			// voice spans may be left open, and timestamps have no end tag
+			if ! matches! (name.as_str (), "b" | "i" | "u" | "s" | "font" | "c" | "ruby" | "rt" | "lang") { continue }
+			if closing {
+				if let Some (pos) = open.iter ().rposition (|open_name| open_name == & name) { open.remove (pos); }
+			} else {
+				open.push (name);
+			}
+		}
+		unclosed.extend (open.into_iter ().map (|name| format! ("<{name}>")));
+	}
+	if (format == Format::Ass || format == Format::Srt)
+			&& let Some (pos) = text.rfind ('{')
+			&& ! text [pos .. ].contains ('}')
+			&& (format == Format::Ass || text [pos + 1 .. ].starts_with ('\\')) {
+		unclosed.push ("{".to_owned ());
+	}
+	unclosed
+}
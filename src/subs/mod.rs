@@ -0,0 +1,154 @@
+//! Native handling of text subtitles
+//!
+//! Subtitles are held as a list of events with the text left in the markup of the original format,
+//! along with whatever comes before the events, so that files can be rewritten without losing
+//! anything we don't understand.
+
+use crate::detect;
+use crate::imports::*;
+
+pub mod ass;
+pub mod convert;
+pub mod encoding;
+pub mod extract;
+pub mod forced;
+pub mod langid;
+pub mod markup;
+pub mod retime;
+pub mod srt;
+pub mod validate;
+pub mod vtt;
+
+#[ derive (Clone, Copy, Debug, Eq, PartialEq, clap::ValueEnum) ]
+pub enum Format {
+	#[ value (name = "ass", alias = "ssa") ]
+	Ass,
+	#[ value (name = "srt", alias = "subrip") ]
+	Srt,
+	#[ value (name = "webvtt", alias = "vtt") ]
+	WebVtt,
+}
+
+impl Format {
+
+	/// Format for a sidecar file, SSA is handled as ASS since they only differ in the header
+	pub fn from_sidecar (sidecar_type: detect::SidecarType) -> Option <Self> {
+		match sidecar_type {
+			detect::SidecarType::Ass | detect::SidecarType::Ssa => Some (Self::Ass),
+			detect::SidecarType::Srt => Some (Self::Srt),
+			detect::SidecarType::WebVtt => Some (Self::WebVtt),
+			_ => None,
+		}
+	}
+
+	pub fn extension (self) -> & 'static str {
+		match self {
+			Self::Ass => "ass",
+			Self::Srt => "srt",
+			Self::WebVtt => "vtt",
+		}
+	}
+
+	pub fn name (self) -> & 'static str {
+		match self {
+			Self::Ass => "ass",
+			Self::Srt => "subrip",
+			Self::WebVtt => "webvtt",
+		}
+	}
+
+}
+
+impl fmt::Display for Format {
+	fn fmt (& self, fmtr: & mut fmt::Formatter) -> fmt::Result {
+		fmtr.write_str (self.name ())
+	}
+}
+
+#[ derive (Clone, Debug) ]
+pub struct Subtitles {
+	pub format: Format,
+	/// Everything before the events, which holds the script info and styles for ASS, and the
+	/// header, styles and regions for WebVTT
+	pub header: String,
+	pub events: Vec <Event>,
+}
+
+#[ derive (Clone, Debug) ]
+pub struct Event {
+	pub start: Time,
+	pub end: Time,
+	/// Text in the markup of the format, with lines separated by "\n", except for ASS which uses its
+	/// own "\N" line breaks
+	pub text: String,
+	pub extra: EventExtra,
+}
+
+/// Parts of an event which only exist in some formats
+#[ derive (Clone, Debug, Default) ]
+pub enum EventExtra {
+	#[ default ]
+	None,
+	Ass (ass::AssEvent),
+	WebVtt (vtt::VttCue),
+}
+
+/// Subtitle timestamp in milliseconds, which can be negative after retiming
+#[ derive (Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd) ]
+pub struct Time (pub i64);
+
+impl Time {
+
+	/// Split into hours, minutes, seconds and milliseconds, treating negative times as zero
+	fn parts (self) -> (i64, i64, i64, i64) {
+		let Self (millis) = self;
+		let millis = millis.max (0);
+		(millis / 3_600_000, millis / 60_000 % 60, millis / 1000 % 60, millis % 1000)
+	}
+
+	/// Parse hours, minutes and seconds with a fraction, eg "1:02:03.45", hours are optional
+	fn parse_hms (val: & str, frac_sep: char) -> Option <Self> {
+		let (hms, frac) = val.trim ().split_once (frac_sep) ?;
+		let hms: Vec <& str> = hms.split (':').collect ();
+		let (hours, mins, secs) = match hms [ .. ] {
+			[ hours, mins, secs ] => (hours, mins, secs),
+			[ mins, secs ] => ("0", mins, secs),
+			_ => return None,
+		};
+		let num = |val: & str| (! val.is_empty () && val.bytes ().all (|byte| byte.is_ascii_digit ()))
+			.then (|| val.parse::<i64> ().ok ()).flatten ();
+		let (hours, mins, secs) = (num (hours) ?, num (mins) ?, num (secs) ?);
+		if 59 < mins || 59 < secs || frac.is_empty () || 9 < frac.len () { return None }
+		let frac_millis = num (frac) ? * 1000 / 10_i64.pow (frac.len () as u32);
+		Some (Self (((hours * 60 + mins) * 60 + secs) * 1000 + frac_millis))
+	}
+
+}
+
+impl fmt::Display for Time {
+	fn fmt (& self, fmtr: & mut fmt::Formatter) -> fmt::Result {
+		let sign = if self.0 < 0 { "-" } else { "" };
+		let (hours, mins, secs, millis) = Self (self.0.abs ()).parts ();
+		write! (fmtr, "{sign}{hours}:{mins:02}:{secs:02}.{millis:03}")
+	}
+}
+
+impl Subtitles {
+
+	pub fn parse (format: Format, text: & str) -> anyhow::Result <Self> {
+		match format {
+			Format::Ass => ass::parse (text),
+			Format::Srt => srt::parse (text),
+			Format::WebVtt => vtt::parse (text),
+		}
+	}
+
+	pub fn write (& self) -> String {
+		match self.format {
+			Format::Ass => ass::write (self),
+			Format::Srt => srt::write (self),
+			Format::WebVtt => vtt::write (self),
+		}
+	}
+
+}
@@ -0,0 +1,91 @@
+//! Linear retiming of subtitles, to fix a constant offset, a frame rate change or both
+
+use crate::imports::*;
+
+use super::Subtitles;
+use super::Time;
+
+/// Maps each time `t` to `t * scale + offset`, with the offset in milliseconds
+#[ derive (Clone, Copy, Debug, PartialEq) ]
+pub struct Retime {
+	pub scale: f64,
+	pub offset: f64,
+}
+
+impl Retime {
+
+	pub fn offset (offset_millis: f64) -> Self {
+		Self { scale: 1.0, offset: offset_millis }
+	}
+
+	/// Convert subtitles timed for a release at one frame rate to another, eg from 25 to 23.976 for
+	/// subtitles made for a PAL release, which is sped up
+	pub fn frame_rates (from: f64, to: f64) -> anyhow::Result <Self> {
+		any_ensure! (0.0 < from && 0.0 < to, "Frame rates must be positive");
+		Ok (Self { scale: from / to, offset: 0.0 })
+	}
+
+	/// Fit two points, each a time in the subtitles and the time in the video it should be at
+	pub fn anchors (first: (Time, Time), second: (Time, Time)) -> anyhow::Result <Self> {
+		let ((subs_0, video_0), (subs_1, video_1)) = (first, second);
+		any_ensure! (subs_0 != subs_1, "Anchor points must be at different times in the subtitles");
+		let scale = (video_1.0 - video_0.0) as f64 / (subs_1.0 - subs_0.0) as f64;
+		any_ensure! (0.0 < scale, "Anchor points must be in the same order in the subtitles and video");
+		Ok (Self { scale, offset: video_0.0 as f64 - subs_0.0 as f64 * scale })
+	}
+
+	/// Apply this retime followed by another
+	pub fn then (self, other: Self) -> Self {
+		Self {
+			scale: self.scale * other.scale,
+			offset: self.offset * other.scale + other.offset,
+		}
+	}
+
+	pub fn apply (self, time: Time) -> Time {
+		Time ((time.0 as f64 * self.scale + self.offset).round () as i64)
+	}
+
+}
+
+impl fmt::Display for Retime {
+	fn fmt (& self, fmtr: & mut fmt::Formatter) -> fmt::Result {
+		write! (fmtr, "scale {:.6}, offset {:+.3}s", self.scale, self.offset / 1000.0)
+	}
+}
+
+impl Subtitles {
+
+	/// Retime every event, dropping any which now end before the start and moving any which start
+	/// before it to zero, returning the number dropped
+	pub fn retime (& mut self, retime: Retime) -> usize {
+		let num_events = self.events.len ();
+		for event in & mut self.events {
+			event.start = retime.apply (event.start);
+			event.end = retime.apply (event.end);
+		}
+		self.events.retain (|event| 0 < event.end.0);
+		for event in & mut self.events {
+			event.start = Time (event.start.0.max (0));
+		}
+		num_events - self.events.len ()
+	}
+
+}
+
+/// Parse a time given as an argument, either seconds like "-1.5" or "[H:]MM:SS.mmm", with an
+/// optional sign
+pub fn parse_time (val: & str) -> Option <Time> {
+	let (sign, val) = match val.strip_prefix ('-') {
+		Some (rest) => (-1, rest),
+		None => (1, val.strip_prefix ('+').unwrap_or (val)),
+	};
+	let millis = if val.contains (':') {
+		let val = if val.contains (['.', ',']) { val.to_owned () } else { format! ("{val}.0") };
+		Time::parse_hms (& val.replace (',', "."), '.') ?.0
+	} else {
+		let secs: f64 = val.parse ().ok ().filter (|secs: & f64| secs.is_finite ()) ?;
+		(secs * 1000.0).round () as i64
+	};
+	Some (Time (sign * millis))
+}
@@ -0,0 +1,77 @@
+//! SubRip subtitles
+//!
+//! Cues are separated by blank lines, each with an optional number, a timing line such as
+//! "00:00:01,000 --> 00:00:02,500" and then the text. Some files use a full stop before the
+//! milliseconds or add position coordinates after the timing, both of which are accepted.
+
+use crate::imports::*;
+
+use super::Event;
+use super::EventExtra;
+use super::Format;
+use super::Subtitles;
+use super::Time;
+
+pub fn parse (text: & str) -> anyhow::Result <Subtitles> {
+	let mut events = Vec::new ();
+	let mut lines = text.lines ().enumerate ().peekable ();
+	while let Some ((line_idx, line)) = lines.next () {
+		let line = line.trim_start_matches ('\u{feff}').trim_end ();
+		if line.is_empty () { continue }
+		// skip the cue number if present
+		let (line_idx, timing) =
+			if line.trim ().bytes ().all (|byte| byte.is_ascii_digit ()) {
+				match lines.next () {
+					Some ((line_idx, line)) => (line_idx, line.trim_end ()),
+					None => break,
+				}
+			} else { (line_idx, line) };
+		let (start, end) = parse_timing (timing)
+			.ok_or_else (|| any_err! ("Invalid timing at line {}: {timing}", line_idx + 1)) ?;
+		let mut cue_lines = Vec::new ();
+		while let Some (& (_, line)) = lines.peek () {
+			if line.trim ().is_empty () { break }
+			cue_lines.push (line.trim_end ());
+			lines.next ();
+		}
+		events.push (Event {
+			start,
+			end,
+			text: cue_lines.join ("\n"),
+			extra: EventExtra::None,
+		});
+	}
+	Ok (Subtitles { format: Format::Srt, header: String::new (), events })
+}
+
+fn parse_timing (line: & str) -> Option <(Time, Time)> {
+	let (start, rest) = line.split_once ("-->") ?;
+	let end = rest.split_whitespace ().next () ?;
+	Some ((parse_time (start) ?, parse_time (end) ?))
+}
+
+fn parse_time (val: & str) -> Option <Time> {
+	let val = val.trim ();
+	Time::parse_hms (val, ',').or_else (|| Time::parse_hms (val, '.'))
+}
+
+pub fn write (subs: & Subtitles) -> String {
+	let mut output = String::new ();
+	for (event_idx, event) in subs.events.iter ().enumerate () {
+		if event_idx != 0 { output.push ('\n') }
+		output.push_str (& format! ("{}\n{} --> {}\n",
+			event_idx + 1,
+			format_time (event.start),
+			format_time (event.end)));
+		for line in event.text.lines () {
+			output.push_str (line);
+			output.push ('\n');
+		}
+	}
+	output
+}
+
+pub fn format_time (time: Time) -> String {
+	let (hours, mins, secs, millis) = time.parts ();
+	format! ("{hours:02}:{mins:02}:{secs:02},{millis:03}")
+}
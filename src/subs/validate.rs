@@ -0,0 +1,93 @@
+//! Checks for common mistakes in subtitles
+//!
+//! None of these stop subtitles from being used, but they usually mean something went wrong when
+//! they were made or retimed. ASS events only count as overlapping if they share a layer and
+//! style, since signs and dialogue often overlap on purpose.
+
+use crate::imports::*;
+
+use super::EventExtra;
+use super::Subtitles;
+use super::Time;
+use super::markup;
+use super::markup::Markup;
+
+#[ derive (Clone, Debug) ]
+pub struct Problem {
+	/// Index of the event, counting from zero
+	pub event_idx: usize,
+	pub start: Time,
+	pub kind: ProblemKind,
+}
+
+#[ derive (Clone, Debug) ]
+pub enum ProblemKind {
+	Overlap { other_idx: usize },
+	NegativeDuration,
+	Empty,
+	UnclosedTag (String),
+}
+
+impl fmt::Display for Problem {
+	fn fmt (& self, fmtr: & mut fmt::Formatter) -> fmt::Result {
+		write! (fmtr, "Event {} at {}: ", self.event_idx + 1, self.start) ?;
+		match & self.kind {
+			ProblemKind::Overlap { other_idx } => write! (fmtr, "Overlaps event {}", other_idx + 1),
+			ProblemKind::NegativeDuration => write! (fmtr, "Ends before it starts"),
+			ProblemKind::Empty => write! (fmtr, "No text"),
+			ProblemKind::UnclosedTag (tag) => write! (fmtr, "Unclosed {tag}"),
+		}
+	}
+}
+
+impl Subtitles {
+
+	pub fn validate (& self) -> Vec <Problem> {
+		let mut problems = Vec::new ();
+		let mut add = |event_idx: usize, kind| problems.push (Problem {
+			event_idx,
+			start: self.events [event_idx].start,
+			kind,
+		});
+
+		// check each event on its own
+
+		let mut active = Vec::new ();
+		for (event_idx, event) in self.events.iter ().enumerate () {
+			let overlap_key = match & event.extra {
+				EventExtra::Ass (ass_event) if ass_event.comment => continue,
+				EventExtra::Ass (ass_event) => Some ((ass_event.layer.as_str (), ass_event.style.as_str ())),
+				_ => None,
+			};
+			if event.end < event.start { add (event_idx, ProblemKind::NegativeDuration) }
+			if Markup::parse (self.format, & event.text).plain_text ().trim ().is_empty () {
+				add (event_idx, ProblemKind::Empty);
+			}
+			for tag in markup::unclosed_tags (self.format, & event.text) {
+				add (event_idx, ProblemKind::UnclosedTag (tag));
+			}
+			active.push ((event_idx, overlap_key));
+		}
+
+		// check for overlaps in start order, against the latest ending event so far with the same key
+
+		active.sort_by_key (|& (event_idx, _)| self.events [event_idx].start);
+		let mut latest: BTreeMap <Option <(& str, & str)>, usize> = BTreeMap::new ();
+		for (event_idx, overlap_key) in active {
+			let event = & self.events [event_idx];
+			if let Some (& other_idx) = latest.get (& overlap_key) {
+				let other = & self.events [other_idx];
+				if event.start < other.end {
+					add (event_idx, ProblemKind::Overlap { other_idx });
+				}
+				if other.end < event.end { latest.insert (overlap_key, event_idx); }
+			} else {
+				latest.insert (overlap_key, event_idx);
+			}
+		}
+
+		problems.sort_by_key (|problem| problem.event_idx);
+		problems
+	}
+
+}
@@ -0,0 +1,114 @@
+//! WebVTT subtitles
+//!
+//! The file starts with a "WEBVTT" line, then blocks separated by blank lines. Style, region and
+//! note blocks before the first cue are kept as the header, while notes between cues are dropped.
+//! Each cue has an optional identifier, a timing line with optional settings, and the text.
+
+use crate::imports::*;
+
+use super::Event;
+use super::EventExtra;
+use super::Format;
+use super::Subtitles;
+use super::Time;
+
+/// Identifier and settings for a cue, eg "line:90% align:start"
+#[ derive (Clone, Debug, Default) ]
+pub struct VttCue {
+	pub id: Option <String>,
+	pub settings: String,
+}
+
+pub fn parse (text: & str) -> anyhow::Result <Subtitles> {
+	let text = text.trim_start_matches ('\u{feff}');
+	any_ensure! (text.starts_with ("WEBVTT"), "Missing WEBVTT header");
+	let lines: Vec <& str> = text.lines ().map (str::trim_end).collect ();
+	let mut header = Vec::new ();
+	let mut events = Vec::new ();
+	let mut line_idx = 0;
+	while line_idx < lines.len () {
+		if lines [line_idx].is_empty () { line_idx += 1; continue }
+		let block_start = line_idx;
+		while line_idx < lines.len () && ! lines [line_idx].is_empty () { line_idx += 1 }
+		let block = & lines [block_start .. line_idx];
+		let timing_idx = block.iter ().take (2).position (|line| line.contains ("-->"));
+		let Some (timing_idx) = timing_idx else {
+			if events.is_empty () {
+				if ! header.is_empty () { header.push ("") }
+				header.extend (block);
+			}
+			continue;
+		};
+		let timing = block [timing_idx];
+		let (start, end, settings) = parse_timing (timing)
+			.ok_or_else (|| any_err! ("Invalid timing at line {}: {timing}", block_start + timing_idx + 1)) ?;
+		events.push (Event {
+			start,
+			end,
+			text: block [timing_idx + 1 .. ].join ("\n"),
+			extra: EventExtra::WebVtt (VttCue {
+				id: (timing_idx == 1).then (|| block [0].to_owned ()),
+				settings: settings.to_owned (),
+			}),
+		});
+	}
+	Ok (Subtitles { format: Format::WebVtt, header: header.join ("\n"), events })
+}
+
+fn parse_timing (line: & str) -> Option <(Time, Time, & str)> {
+	let (start, rest) = line.split_once ("-->") ?;
+	let rest = rest.trim_start ();
+	let (end, settings) = rest.split_once (char::is_whitespace).unwrap_or ((rest, ""));
+	Some ((Time::parse_hms (start, '.') ?, Time::parse_hms (end, '.') ?, settings.trim ()))
+}
+
+pub fn write (subs: & Subtitles) -> String {
+	let mut output = String::new ();
+	if subs.header.is_empty () {
+		output.push_str ("WEBVTT\n");
+	} else {
+		output.push_str (subs.header.trim_end ());
+		output.push ('\n');
+	}
+	for event in & subs.events {
+		output.push ('\n');
+		let cue = match & event.extra {
+			EventExtra::WebVtt (cue) => Some (cue),
+			_ => None,
+		};
+		if let Some (id) = cue.and_then (|cue| cue.id.as_ref ()) {
+			output.push_str (id);
+			output.push ('\n');
+		}
+		output.push_str (& format! ("{} --> {}", format_time (event.start), format_time (event.end)));
+		if let Some (cue) = cue && ! cue.settings.is_empty () {
+			output.push (' ');
+			output.push_str (& cue.settings);
+		}
+		output.push ('\n');
+		for line in event.text.lines () {
+			output.push_str (line);
+			output.push ('\n');
+		}
+	}
+	output
+}
+
+/// Check if cue settings place it in the top half of the screen, either as a line number counting
+/// from the top or a percentage
+pub fn is_top (settings: & str) -> bool {
+	settings.split_whitespace ()
+		.filter_map (|setting| setting.strip_prefix ("line:"))
+		.any (|line| {
+			let line = line.split (',').next ().unwrap ();
+			match line.strip_suffix ('%') {
+				Some (percent) => percent.parse::<f64> ().is_ok_and (|percent| percent < 50.0),
+				None => line.parse::<i64> ().is_ok_and (|line| 0 <= line),
+			}
+		})
+}
+
+pub fn format_time (time: Time) -> String {
+	let (hours, mins, secs, millis) = time.parts ();
+	format! ("{hours:02}:{mins:02}:{secs:02}.{millis:03}")
+}
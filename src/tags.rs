@@ -0,0 +1,193 @@
+//! Editable tag model, covering matroska target levels and nested simple tags
+//!
+//! Tags are written natively by replacing the Tags element of a finished matroska file, since
+//! ffmpeg can only write flat strings at the default level. Targets refer to tracks, editions,
+//! chapters and attachments by UID, but the track type is generic so that `edit` can refer to
+//! tracks by their position instead.
+
+use crate::imports::*;
+use crate::language::LanguageTag;
+use crate::matroska;
+use crate::matroska::tags::SimpleTagElem;
+use crate::matroska::tags::TagElem;
+use crate::matroska::tags::TargetsElem;
+
+#[ derive (Clone, Debug, Deserialize, Serialize) ]
+#[ serde (deny_unknown_fields, bound (deserialize = "Track: Deserialize <'de>")) ]
+pub struct Tag <Track = u64> {
+	#[ serde (default = "default_level") ]
+	pub level: u64,
+	#[ serde (default, rename = "type", skip_serializing_if = "Option::is_none") ]
+	pub target_type: Option <String>,
+	#[ serde (default, skip_serializing_if = "Vec::is_empty") ]
+	pub tracks: Vec <Track>,
+	#[ serde (default, skip_serializing_if = "Vec::is_empty") ]
+	pub editions: Vec <u64>,
+	#[ serde (default, skip_serializing_if = "Vec::is_empty") ]
+	pub chapters: Vec <u64>,
+	#[ serde (default, skip_serializing_if = "Vec::is_empty") ]
+	pub attachments: Vec <u64>,
+	pub tags: Vec <SimpleTag>,
+}
+
+#[ serde_as ]
+#[ derive (Clone, Debug, Deserialize, Serialize) ]
+#[ serde (deny_unknown_fields) ]
+pub struct SimpleTag {
+	pub name: String,
+	#[ serde (default, skip_serializing_if = "Option::is_none") ]
+	pub value: Option <String>,
+	#[ serde_as (as = "Option <BlobHex>") ]
+	#[ serde (default, skip_serializing_if = "Option::is_none") ]
+	pub binary: Option <Vec <u8>>,
+	#[ serde (default, skip_serializing_if = "Option::is_none") ]
+	pub language: Option <String>,
+	#[ serde (default = "default_true", skip_serializing_if = "is_true") ]
+	pub default: bool,
+	#[ serde (default, skip_serializing_if = "Vec::is_empty") ]
+	pub tags: Vec <SimpleTag>,
+}
+
+fn default_level () -> u64 { 50 }
+fn default_true () -> bool { true }
+fn is_true (val: & bool) -> bool { * val }
+
+/// Target levels defined by the matroska spec, with the usual target type for each
+pub const LEVELS: & [(u64, & str)] = & [
+	(70, "COLLECTION"),
+	(60, "SEASON"),
+	(50, "EPISODE"),
+	(40, "PART"),
+	(30, "TRACK"),
+	(20, "SCENE"),
+	(10, "SHOT"),
+];
+
+impl <Track> Tag <Track> {
+
+	/// Convert track references, returning `None` if the tag only targeted tracks which are gone
+	pub fn map_tracks <Other> (
+		self,
+		mut map_fn: impl FnMut (Track) -> anyhow::Result <Option <Other>>,
+	) -> anyhow::Result <Option <Tag <Other>>> {
+		let had_tracks = ! self.tracks.is_empty ();
+		let mut tracks = Vec::new ();
+		for track in self.tracks {
+			if let Some (track) = map_fn (track) ? { tracks.push (track) }
+		}
+		if had_tracks && tracks.is_empty () { return Ok (None) }
+		Ok (Some (Tag {
+			level: self.level,
+			target_type: self.target_type,
+			tracks,
+			editions: self.editions,
+			chapters: self.chapters,
+			attachments: self.attachments,
+			tags: self.tags,
+		}))
+	}
+
+}
+
+/// Check tags against the matroska spec, reporting the location of any problem
+pub fn check <Track> (tags: & [Tag <Track>], path: & str) -> anyhow::Result <()> {
+	for (tag_idx, tag) in tags.iter ().enumerate () {
+		let path = format! ("{path}.{tag_idx}");
+		any_ensure! (LEVELS.iter ().any (|& (level, _)| level == tag.level),
+			"{path}.level: Invalid target level {}, expected one of {}",
+			tag.level,
+			LEVELS.iter ().map (|& (level, name)| format! ("{level} ({name})")).join (", "));
+		any_ensure! (! tag.tags.is_empty (), "{path}.tags: At least one tag is required");
+		check_simple (& tag.tags, & format! ("{path}.tags")) ?;
+	}
+	Ok (())
+}
+
+fn check_simple (simple_tags: & [SimpleTag], path: & str) -> anyhow::Result <()> {
+	for (simple_idx, simple_tag) in simple_tags.iter ().enumerate () {
+		let path = format! ("{path}.{simple_idx}");
+		any_ensure! (! simple_tag.name.is_empty (), "{path}.name: Tag name must not be empty");
+		any_ensure! (simple_tag.value.is_none () || simple_tag.binary.is_none (),
+			"{path}: Tag can't have both a value and binary data");
+		check_simple (& simple_tag.tags, & format! ("{path}.tags")) ?;
+	}
+	Ok (())
+}
+
+/// Read tags natively from a matroska file
+pub fn read (file_path: & Path) -> anyhow::Result <Vec <Tag>> {
+	let file = BufReader::new (File::open (file_path) ?);
+	let mut reader = matroska::Reader::new (file) ?;
+	if ! reader.has_seek (matroska::elems::TAGS) { return Ok (Vec::new ()) }
+	let tags = reader.tags () ?;
+	Ok (from_matroska (& tags))
+}
+
+/// Replace the tags in a matroska file
+pub fn write (file_path: & Path, tags: & [Tag]) -> anyhow::Result <()> {
+	matroska::patch::replace_tags (file_path, & to_matroska (tags))
+}
+
+pub fn from_matroska (tags: & matroska::TagsElem) -> Vec <Tag> {
+	tags.tags.iter ()
+		.map (|tag| Tag {
+			level: tag.targets.type_value,
+			target_type: tag.targets.target_type.clone (),
+			tracks: tag.targets.track_uids.clone (),
+			editions: tag.targets.edition_uids.clone (),
+			chapters: tag.targets.chapter_uids.clone (),
+			attachments: tag.targets.attachment_uids.clone (),
+			tags: tag.simple_tags.iter ().map (simple_from_matroska).collect (),
+		})
+		.collect ()
+}
+
+fn simple_from_matroska (simple_tag: & SimpleTagElem) -> SimpleTag {
+	SimpleTag {
+		name: simple_tag.name.clone (),
+		value: simple_tag.string.clone (),
+		binary: simple_tag.binary.clone (),
+		language: simple_tag.language_bcp47.clone ()
+			.or_else (|| Some (simple_tag.language.clone ()).filter (|language| language != "und")),
+		default: simple_tag.default,
+		tags: simple_tag.children.iter ().map (simple_from_matroska).collect (),
+	}
+}
+
+pub fn to_matroska (tags: & [Tag]) -> matroska::TagsElem {
+	matroska::TagsElem {
+		tags: tags.iter ()
+			.map (|tag| TagElem {
+				targets: TargetsElem {
+					type_value: tag.level,
+					target_type: tag.target_type.clone (),
+					track_uids: tag.tracks.clone (),
+					edition_uids: tag.editions.clone (),
+					chapter_uids: tag.chapters.clone (),
+					attachment_uids: tag.attachments.clone (),
+				},
+				simple_tags: tag.tags.iter ().map (simple_to_matroska).collect (),
+			})
+			.collect (),
+	}
+}
+
+fn simple_to_matroska (simple_tag: & SimpleTag) -> SimpleTagElem {
+	// known languages go in both TagLanguage and TagLanguageBCP47, anything else only in the latter
+	let (language, language_bcp47) = match simple_tag.language.as_ref () {
+		Some (language) => match LanguageTag::parse (language) {
+			Ok (tag) => (tag.iso_639_2 ().to_owned (), Some (tag.bcp47 ())),
+			Err (_) => ("und".to_owned (), Some (language.clone ())),
+		},
+		None => ("und".to_owned (), None),
+	};
+	SimpleTagElem {
+		name: simple_tag.name.clone (),
+		language,
+		language_bcp47,
+		default: simple_tag.default,
+		string: simple_tag.value.clone (),
+		binary: simple_tag.binary.clone (),
+		children: simple_tag.tags.iter ().map (simple_to_matroska).collect (),
+	}
+}
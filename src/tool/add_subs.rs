@@ -1,19 +1,27 @@
+use crate::detect;
 use crate::ffmpeg;
+use crate::fonts;
 use crate::imports::*;
+use crate::language::LanguageTag;
 use crate::matroska;
+use crate::media;
+use crate::subs;
+use crate::subs::encoding;
+use crate::subs::retime::Retime;
 
 #[ derive (Debug, clap::Args) ]
-#[ command (about = "Reencode video as x265 (optionally) and audio as opus" )]
+#[ command (about = "Add subtitle files to a media file as new tracks" )]
 pub struct Args {
 
 	#[ clap (name = "SOURCE", help = "File to add subtitles to") ]
 	source_path: PathBuf,
 
-	#[ clap (name = "SUBS", help = "Subtitles to add") ]
-	subs_path: PathBuf,
+	#[ clap (name = "SUBS", required = true) ]
+	#[ clap (help = "Subtitles to add, language and flags are taken from names like Movie.en.forced.srt") ]
+	subs_paths: Vec <PathBuf>,
 
-	#[ clap (long, help = "Subtitle track language tag") ]
-	lang: String,
+	#[ clap (long, help = "Subtitle track language, as an ISO 639 code or BCP 47 tag, default is to take it from the file name or the text") ]
+	lang: Option <String>,
 
 	#[ clap (long, help = "Subtitle track title") ]
 	title: Option <String>,
@@ -39,6 +47,43 @@ pub struct Args {
 	#[ clap (long, help = "Mark subtitles as commentary") ]
 	commentary: bool,
 
+	#[ clap (long, value_name = "LABEL") ]
+	#[ clap (help = "Character encoding of text subtitles, eg windows-1251 or gbk, default is to detect it") ]
+	encoding: Option <String>,
+
+	#[ clap (long, help = "Show the subtitle tracks which would be added without writing anything") ]
+	dry_run: bool,
+
+	#[ clap (long, value_enum, value_name = "FORMAT", help = "Convert text subtitles to another format") ]
+	convert_to: Option <subs::Format>,
+
+	#[ clap (long, help = "Don't attach the fonts used by ASS subtitles") ]
+	no_fonts: bool,
+
+	#[ command (flatten) ]
+	fonts: super::subs::FontsArgs,
+
+	#[ command (flatten) ]
+	retime: super::subs::RetimeArgs,
+
+}
+
+/// A subtitle file to add, with the metadata for its track
+struct NewSubs {
+	path: PathBuf,
+	subs_type: detect::SidecarType,
+	codec: & 'static str,
+	/// Contents of text subtitles, converted to UTF-8
+	decoded: Option <encoding::Decoded>,
+	/// Text subtitles were retimed or converted, so need writing out even if the encoding was unchanged
+	rewritten: bool,
+	/// Format text subtitles were converted to
+	converted: Option <subs::Format>,
+	/// Fonts used by ASS subtitles
+	fonts: Vec <String>,
+	language: LanguageTag,
+	title: Option <String>,
+	disposition: media::Disposition,
 }
 
 pub fn invoke (args: Args) -> anyhow::Result <()> {
@@ -49,18 +94,67 @@ pub fn invoke (args: Args) -> anyhow::Result <()> {
 		any_bail! ("Specified file has no name: {}", args.source_path.display ());
 	};
 
+	// check subtitles and work out their metadata
+
+	let retime = args.retime.to_retime () ?;
+	if let Some (retime) = retime { eprintln! ("Retiming: {retime}") }
+	let new_subs: Vec <NewSubs> = args.subs_paths.iter ()
+		.map (|subs_path| check_subs (& args, subs_path, retime))
+		.collect::<anyhow::Result <_>> () ?;
+
+	// find fonts used by new ass subtitles which aren't attached already
+
+	let new_fonts = if args.no_fonts { Vec::new () } else { find_fonts (& args, & new_subs) ? };
+
+	if args.dry_run {
+		print_table (& new_subs);
+		for font in & new_fonts {
+			println! ("Attach font: {} ({})", font.path.display (), font.names.faces.first ().map_or ("", String::as_str));
+		}
+		return Ok (());
+	}
+
+	// write text subtitles which aren't already plain UTF-8, or were changed, to temporary files
+
+	let mut temps = Vec::new ();
+	let mut subs_inputs = Vec::new ();
+	for subs in & new_subs {
+		let Some (decoded) = subs.decoded.as_ref () else {
+			subs_inputs.push (subs.path.clone ());
+			continue;
+		};
+		eprintln! ("Subtitle encoding: {}: {}", subs.path.display (), decoded.describe ());
+		if decoded.is_unchanged () && ! subs.rewritten {
+			subs_inputs.push (subs.path.clone ());
+			continue;
+		}
+		let mut temp =
+			tempfile::Builder::new ()
+				.prefix ("jp-media-tool-subs-")
+				.suffix (& match subs.converted {
+					Some (format) => OsString::from (format! (".{}", format.extension ())),
+					None => subs.path.extension ().map (|ext| {
+						let mut val = OsString::from (".");
+						val.push (ext);
+						val
+					}).unwrap_or_default (),
+				})
+				.tempfile () ?;
+		temp.write_all (decoded.text.as_bytes ()) ?;
+		temp.flush () ?;
+		subs_inputs.push (temp.path ().to_owned ());
+		temps.push (temp);
+	}
+
 	let mut dest_name = args.source_path.file_stem ().unwrap ().to_owned ();
-	dest_name.push (format! ("-subs-{}", args.lang));
+	dest_name.push (format! ("-subs-{}",
+		new_subs.iter ().map (|subs| subs.language.bcp47 ()).unique ().join ("-")));
 
 	// open source file
 
-	let file = BufReader::new (File::open (& args.source_path) ?);
-	let mut reader = matroska::Reader::new (file) ?;
-
-	let info = reader.segment_info () ?;
-	let duration_micros = info.duration.map (|duration| (info.timestamp_scale as f64 * duration / 1000.0) as u64);
+	let media = media::MediaInfo::probe (& args.source_path) ?;
+	let duration_micros = media.duration_micros ();
 
-	let tracks = reader.tracks () ?;
 	let mut command: Vec <OsString> = Vec::new ();
 	command.push ("-i".into ());
 	command.push ({
@@ -68,87 +162,80 @@ pub fn invoke (args: Args) -> anyhow::Result <()> {
 		val.push (& args.source_path);
 		val
 	});
-	command.push ("-i".into ());
-	command.push ({
-		let mut val = OsString::from ("file:");
-		val.push (args.subs_path);
-		val
-	});
-
-	// do video
-
-	let video_tracks: Vec <_> =
-		tracks.entries.iter ()
-			.filter (|track| track.track_type == 1)
-			.collect ();
-	for (video_idx, _video_track) in video_tracks.iter ().enumerate () {
-		command.push ("-map".into ());
-		command.push (format! ("0:v:{video_idx}").into ());
-		command.push (format! ("-c:v:{video_idx}").into ());
-		command.push ("copy".into ());
+	for subs_input in & subs_inputs {
+		command.push ("-i".into ());
+		command.push ({
+			let mut val = OsString::from ("file:");
+			val.push (subs_input);
+			val
+		});
 	}
 
-	// do audio
-
-	let audio_tracks: Vec <_> =
-		tracks.entries.iter ()
-			.filter (|track| track.track_type == 2)
-			.collect ();
-	for (audio_idx, _audio_track) in audio_tracks.iter ().enumerate () {
-		command.push ("-map".into ());
-		command.push (format! ("0:a:{audio_idx}").into ());
-		command.push (format! ("-c:a:{audio_idx}").into ());
-		command.push ("copy".into ());
+	// copy existing video, audio and subtitles
+
+	let mut track_updates = Vec::new ();
+	for stream_type in [
+		media::StreamType::Video,
+		media::StreamType::Audio,
+		media::StreamType::Subtitle,
+	] {
+		for stream in media.streams_of_type (stream_type) {
+			let specifier = stream.specifier ();
+			command.push ("-map".into ());
+			command.push (format! ("0:{specifier}").into ());
+			command.push (format! ("-c:{specifier}").into ());
+			command.push (stream.matroska_copy_codec ().into ());
+			track_updates.push (matroska::patch::TrackUpdate::for_stream (stream));
+		}
 	}
 
-	// do subtitles
+	// new subtitles
 
-	let subs_tracks: Vec <_> =
-		tracks.entries.iter ()
-			.filter (|track| track.track_type == 17)
-			.collect ();
-	for (subs_idx, _subs_track) in subs_tracks.iter ().enumerate () {
+	let num_subs = media.streams_of_type (media::StreamType::Subtitle).count ();
+	for (subs_idx, subs) in new_subs.iter ().enumerate () {
+		let new_subs_idx = num_subs + subs_idx;
 		command.push ("-map".into ());
-		command.push (format! ("0:s:{subs_idx}").into ());
-		command.push (format! ("-c:s:{subs_idx}").into ());
-		command.push ("copy".into ());
+		command.push (format! ("{}:s:0", subs_idx + 1).into ());
+		command.push (format! ("-c:s:{new_subs_idx}").into ());
+		command.push (subs.codec.into ());
+		command.push (format! ("-metadata:s:s:{new_subs_idx}").into ());
+		command.push (format! ("language={}", subs.language.iso_639_2 ()).into ());
+		if let Some (title) = subs.title.as_ref () {
+			command.push (format! ("-metadata:s:s:{new_subs_idx}").into ());
+			command.push (format! ("title={title}").into ());
+		}
+		command.push (format! ("-disposition:s:{new_subs_idx}").into ());
+		command.push (subs.disposition.ffmpeg_value ().into ());
+		track_updates.push (matroska::patch::TrackUpdate {
+			language: Some (subs.language.bcp47 ()),
+			.. matroska::patch::TrackUpdate::default ()
+		});
 	}
 
-	// new subtitles
+	// keep existing attachments and add fonts
 
-	let new_subs_idx = subs_tracks.len ();
 	command.push ("-map".into ());
-	command.push ("1:s:0".into ());
-	command.push (format! ("-c:s:{new_subs_idx}").into ());
+	command.push ("0:t?".into ());
+	command.push ("-c:t".into ());
 	command.push ("copy".into ());
-	command.push (format! ("-metadata:s:s:{new_subs_idx}").into ());
-	command.push (format! ("language={}", args.lang).into ());
-	if let Some (title) = args.title.as_ref () {
-		command.push (format! ("-metadata:s:s:{new_subs_idx}").into ());
-		command.push (format! ("title={title}").into ());
-	}
-	let mut dispositions: Vec <OsString> = Vec::new ();
-	if args.default { dispositions.push ("default".into ()); }
-	if args.forced { dispositions.push ("forced".into ()); }
-	if args.hearing_impaired { dispositions.push ("hearing_impaired".into ()); }
-	if args.visual_impaired { dispositions.push ("visual_impaired".into ()); }
-	if args.descriptions { dispositions.push ("descriptions".into ()); }
-	if args.original { dispositions.push ("original".into ()); }
-	if args.commentary { dispositions.push ("commentary".into ()); }
-	let mut dispositions_str = OsString::new ();
-	for disposition in dispositions {
-		if ! dispositions_str.is_empty () { dispositions_str.push ("+"); }
-		dispositions_str.push (disposition);
-	}
-	command.push (format! ("-disposition:s:{new_subs_idx}").into ());
-	if ! dispositions_str.is_empty () {
-		command.push (dispositions_str);
-	} else {
-		command.push ("0".into ());
+	for (font_idx, font) in new_fonts.iter ().enumerate () {
+		let attachment_idx = media.attachments.len () + font_idx;
+		command.push ("-attach".into ());
+		command.push (font.path.clone ().into ());
+		command.push (format! ("-metadata:s:t:{attachment_idx}").into ());
+		command.push (format! ("mimetype={}", font.kind.media_type ()).into ());
+		command.push (format! ("-metadata:s:t:{attachment_idx}").into ());
+		command.push ({
+			let mut val = OsString::from ("filename=");
+			val.push (font.path.file_name ().unwrap ());
+			val
+		});
 	}
 
 	// do conversion
 
+	command.push ("-metadata_header_padding".into ());
+	command.push (matroska::patch::HEADER_PADDING.to_string ().into ());
 	command.push ("-f".into ());
 	command.push ("matroska".into ());
 
@@ -159,13 +246,237 @@ pub fn invoke (args: Args) -> anyhow::Result <()> {
 	}
 	command.push ({
 		let mut val = OsString::from ("file:");
-		val.push (dest_path);
+		val.push (& dest_path);
 		val
 	});
 
 	let source_display = source_name.to_string_lossy ();
 	ffmpeg::convert_progress (& source_display, duration_micros, command) ?;
+	drop (temps);
+	if let Err (err) = matroska::patch::update_tracks (& dest_path, & track_updates) {
+		eprintln! ("Warning: {err:#}");
+	}
 
     Ok (())
 
 }
+
+/// Check a subtitle file, and combine the metadata from its name with that from the arguments
+fn check_subs (args: & Args, subs_path: & Path, retime: Option <Retime>) -> anyhow::Result <NewSubs> {
+
+	// check subtitle format
+
+	let subs_display = subs_path.display ();
+	let subs_type = match detect::SidecarType::identify_path (subs_path) {
+		Ok (subs_type) => subs_type,
+		Err (detect::IdentifyError::NotRecognised) =>
+			any_bail! ("Unsupported subtitle format: {subs_display}"),
+		Err (err) =>
+			return Err (err).with_context (|| any_err! ("Error identifying subtitles: {subs_display}")),
+	};
+	if ! subs_type.is_subtitle () {
+		any_bail! ("Not a subtitle file: {subs_display} ({subs_type})");
+	}
+	if subs_type == detect::SidecarType::VobSubSub {
+		any_bail! ("Specify the vobsub index (.idx) file instead of the data: {subs_display}");
+	}
+	if subs_type == detect::SidecarType::VobSubIdx && ! subs_path.with_extension ("sub").try_exists () ? {
+		any_bail! ("Missing vobsub data (.sub) file for index: {subs_display}");
+	}
+	// matroska has no codec for microdvd, so convert it to subrip
+	let codec = match subs_type {
+		detect::SidecarType::MicroDvd => "srt",
+		_ => "copy",
+	};
+
+	let mut decoded = if subs_type.is_text_subtitle () {
+		let encoding = args.encoding.as_deref ().map (encoding::Encoding::for_label).transpose () ?;
+		let data = fs::read (subs_path) ?;
+		Some (encoding::decode (& data, encoding)
+			.with_context (|| any_err! ("Error decoding subtitles: {subs_display}")) ?)
+	} else { None };
+
+	// parse text subtitles we understand, to check them and to retime or convert them in memory,
+	// after which they are written out with the reencoded ones
+
+	let rewrite = retime.is_some () || args.convert_to.is_some ();
+	let parsed = match (decoded.as_ref (), subs::Format::from_sidecar (subs_type)) {
+		(Some (decoded), Some (format)) => Some (subs::Subtitles::parse (format, & decoded.text)),
+		_ => None,
+	};
+	let mut subtitles = match parsed {
+		Some (Ok (subtitles)) => Some (subtitles),
+		Some (Err (err)) if ! rewrite => {
+			eprintln! ("Warning: {subs_display}: {err:#}");
+			None
+		},
+		Some (Err (err)) => return Err (err).with_context (|| any_err! ("Error reading subtitles: {subs_display}")),
+		None if rewrite => any_bail! ("Unable to retime or convert {subs_type} subtitles: {subs_display}"),
+		None => None,
+	};
+	let mut converted = None;
+	let mut fonts = Vec::new ();
+	if let Some (subtitles) = subtitles.as_mut () {
+		let subs_name = subs_display.to_string ();
+		super::subs::report_problems (& subs_name, subtitles);
+		if let Some (retime) = retime {
+			super::subs::report_dropped (& subs_name, subtitles.retime (retime));
+		}
+		if let Some (format) = args.convert_to && format != subtitles.format {
+			* subtitles = subtitles.convert (format);
+			converted = Some (format);
+		}
+		if rewrite { decoded.as_mut ().unwrap ().text = subtitles.write () }
+		if subtitles.format == subs::Format::Ass { fonts = subs::ass::font_names (subtitles) }
+	}
+
+	// flags from the arguments apply to every file, in addition to those in the name
+
+	let name_info = NameInfo::parse (subs_path);
+	let guess = subtitles.as_ref ()
+		.and_then (|subtitles| subs::langid::identify (& subs::langid::subtitles_text (subtitles)));
+	let lang = args.lang.as_deref ().map (LanguageTag::parse).transpose () ?;
+	let language = match (lang.or (name_info.language), guess) {
+		(Some (language), Some (guess)) if guess.is_confident () && ! guess.matches (& language.bcp47 ()) => {
+			eprintln! ("Warning: {subs_display}: Tagged {language}, but looks like {guess}");
+			language
+		},
+		(Some (language), _) => language,
+		(None, Some (guess)) if guess.is_confident () => {
+			eprintln! ("Detected language: {subs_display}: {guess}");
+			LanguageTag::parse (guess.tag) ?
+		},
+		(None, Some (guess)) => any_bail! (
+			"Unable to work out language, might be {guess}, use --lang to specify it: {subs_display}"),
+		(None, None) => any_bail! (
+			"Unable to work out language from file name, use --lang to specify it: {subs_display}"),
+	};
+	Ok (NewSubs {
+		path: subs_path.to_owned (),
+		subs_type,
+		codec,
+		decoded,
+		rewritten: rewrite,
+		converted,
+		fonts,
+		language,
+		title: args.title.clone (),
+		disposition: media::Disposition {
+			enabled: true,
+			default: args.default || name_info.default,
+			forced: args.forced || name_info.forced,
+			hearing_impaired: args.hearing_impaired || name_info.hearing_impaired,
+			visual_impaired: args.visual_impaired,
+			descriptions: args.descriptions,
+			original: args.original,
+			commentary: args.commentary || name_info.commentary,
+		},
+	})
+
+}
+
+/// Find the files for fonts used by new ASS subtitles which aren't attached to the source already,
+/// warning about any which can't be found
+fn find_fonts (args: & Args, new_subs: & [NewSubs]) -> anyhow::Result <Vec <fonts::FontFile>> {
+	let is_matroska = matches! (
+		detect::FileType::identify_path (& args.source_path),
+		Ok (detect::FileType::Matroska));
+	let attached =
+		if is_matroska { super::subs::check_existing_fonts (& args.source_path) ? }
+		else { Vec::new () };
+	let mut needed: Vec <& str> = Vec::new ();
+	for font_name in new_subs.iter ().flat_map (|subs| & subs.fonts) {
+		if attached.iter ().any (|(_, names)| names.matches (font_name)) { continue }
+		if needed.iter ().any (|other| other.eq_ignore_ascii_case (font_name)) { continue }
+		needed.push (font_name);
+	}
+	if needed.is_empty () { return Ok (Vec::new ()) }
+	let Some (fonts_dir) = args.fonts.fonts_dir () else {
+		eprintln! ("Warning: Not attaching fonts used by ASS subtitles, use --fonts-dir to say where they are: {}",
+			needed.join (", "));
+		return Ok (Vec::new ());
+	};
+	// attach every face of a family, so bold and italic text is shown properly
+
+	let mut library = fonts::scan_dir (& fonts_dir) ?;
+	let mut found = Vec::new ();
+	for font_name in needed {
+		let matched: Vec <fonts::FontFile> = library.extract_if (.., |font| font.names.matches (font_name)).collect ();
+		if matched.is_empty () && ! found.iter ().any (|font: & fonts::FontFile| font.names.matches (font_name)) {
+			eprintln! ("Warning: Font not found in {}: {font_name}", fonts_dir.display ());
+		}
+		found.extend (matched);
+	}
+	Ok (found)
+}
+
+/// Metadata from the name of a sidecar subtitle file, eg "Movie.pt-BR.forced.srt"
+#[ derive (Default) ]
+struct NameInfo {
+	language: Option <LanguageTag>,
+	default: bool,
+	forced: bool,
+	hearing_impaired: bool,
+	commentary: bool,
+}
+
+impl NameInfo {
+
+	/// Work backwards through the dot separated parts of the name, before the extension, stopping
+	/// at the first which isn't a flag or language, so the rest of the name is never mistaken
+	/// for either
+	fn parse (path: & Path) -> Self {
+		let mut info = Self::default ();
+		let Some (stem) = path.file_stem () else { return info };
+		let stem = stem.to_string_lossy ();
+		let parts: Vec <& str> = stem.split ('.').collect ();
+		for & part in parts [1 .. ].iter ().rev () {
+			match part.to_ascii_lowercase ().as_str () {
+				"default" => info.default = true,
+				"forced" => info.forced = true,
+				"sdh" | "cc" => info.hearing_impaired = true,
+				"commentary" => info.commentary = true,
+				_ if info.language.is_none () => match LanguageTag::parse (part) {
+					Ok (language) => info.language = Some (language),
+					Err (_) => break,
+				},
+				_ => break,
+			}
+		}
+		info
+	}
+
+}
+
+/// Language from the name of a sidecar subtitle file, eg "fr" from "Movie.fr.forced.srt"
+pub (super) fn language_from_name (path: & Path) -> Option <String> {
+	NameInfo::parse (path).language.as_ref ().map (LanguageTag::bcp47)
+}
+
+fn print_table (new_subs: & [NewSubs]) {
+	let rows: Vec <[String; 6]> = new_subs.iter ()
+		.map (|subs| [
+			subs.path.display ().to_string (),
+			match subs.converted {
+				Some (format) => format! ("{} -> {format}", subs.subs_type),
+				None => subs.subs_type.to_string (),
+			},
+			subs.decoded.as_ref ().map (encoding::Decoded::describe).unwrap_or_default (),
+			subs.language.to_string (),
+			subs.title.clone ().unwrap_or_default (),
+			[
+				(subs.disposition.default, "default"),
+				(subs.disposition.forced, "forced"),
+				(subs.disposition.hearing_impaired, "hearing-impaired"),
+				(subs.disposition.visual_impaired, "visual-impaired"),
+				(subs.disposition.descriptions, "descriptions"),
+				(subs.disposition.original, "original"),
+				(subs.disposition.commentary, "commentary"),
+			].into_iter ()
+				.filter (|& (flag, _)| flag)
+				.map (|(_, name)| name)
+				.join (","),
+		])
+		.collect ();
+	super::info::print_table ("", [ "SUBS", "FORMAT", "ENCODING", "LANG", "TITLE", "FLAGS" ], & rows);
+}
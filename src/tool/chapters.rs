@@ -0,0 +1,206 @@
+use crate::chapters;
+use crate::ffmpeg;
+use crate::imports::*;
+use crate::matroska;
+use crate::media;
+
+#[ derive (Debug, clap::Args) ]
+#[ command (about = "Import, export and generate chapters") ]
+pub struct Args {
+
+	#[ command (subcommand) ]
+	command: Command,
+
+}
+
+#[ derive (Debug, clap::Subcommand) ]
+enum Command {
+	Export (ExportArgs),
+	Import (ImportArgs),
+	Generate (GenerateArgs),
+}
+
+#[ derive (Debug, clap::Args) ]
+#[ command (about = "Write chapters from a media file to a chapter file") ]
+struct ExportArgs {
+
+	#[ clap (name = "FILE", help = "Media file to read chapters from") ]
+	file: PathBuf,
+
+	#[ clap (long, short, value_name = "PATH", help = "Chapter file to write, default is stdout") ]
+	output: Option <PathBuf>,
+
+	#[ clap (long, value_enum, help = "Chapter format, default is based on output extension or ogm") ]
+	format: Option <chapters::Format>,
+
+}
+
+#[ derive (Debug, clap::Args) ]
+#[ command (about = "Replace the chapters in a media file with those from a chapter file") ]
+struct ImportArgs {
+
+	#[ clap (name = "FILE", help = "Media file to add chapters to") ]
+	file: PathBuf,
+
+	#[ clap (name = "CHAPTERS", help = "Chapter file to read") ]
+	chapters: PathBuf,
+
+	#[ clap (long, value_enum, help = "Chapter format, default is to detect from contents") ]
+	format: Option <chapters::Format>,
+
+	#[ clap (long, help = "Show the chapters without writing anything") ]
+	dry_run: bool,
+
+}
+
+#[ derive (Debug, clap::Args) ]
+#[ command (about = "Add evenly spaced chapters to media files") ]
+struct GenerateArgs {
+
+	#[ clap (name = "FILE", help = "Media files to add chapters to") ]
+	files: Vec <PathBuf>,
+
+	#[ clap (long, value_name = "MINUTES", help = "Interval between chapters") ]
+	every: f64,
+
+	#[ clap (long, help = "Replace existing chapters") ]
+	force: bool,
+
+	#[ clap (long, help = "Show the chapters without writing anything") ]
+	dry_run: bool,
+
+}
+
+pub fn invoke (args: Args) -> anyhow::Result <()> {
+	match args.command {
+		Command::Export (export_args) => invoke_export (export_args),
+		Command::Import (import_args) => invoke_import (import_args),
+		Command::Generate (generate_args) => invoke_generate (generate_args),
+	}
+}
+
+fn invoke_export (args: ExportArgs) -> anyhow::Result <()> {
+	let media = media::MediaInfo::probe (& args.file) ?;
+	let editions = chapters::read (& args.file, & media) ?;
+	if editions.is_empty () {
+		eprintln! ("No chapters in {}", args.file.display ());
+	}
+	let format = args.format
+		.or_else (|| args.output.as_deref ().and_then (chapters::Format::from_extension))
+		.unwrap_or (chapters::Format::Ogm);
+	let text = format.write (& editions, media.duration) ?;
+	match args.output.as_ref () {
+		Some (output) => fs::write (output, text) ?,
+		None => io::stdout ().write_all (text.as_bytes ()) ?,
+	}
+	Ok (())
+}
+
+fn invoke_import (args: ImportArgs) -> anyhow::Result <()> {
+	let chapters_display = args.chapters.display ();
+	let text = fs::read_to_string (& args.chapters)
+		.with_context (|| any_err! ("Error reading {chapters_display}")) ?;
+	let format = args.format
+		.or_else (|| chapters::Format::identify (& text))
+		.ok_or_else (|| any_err! ("Unable to identify chapter format: {chapters_display}")) ?;
+	let editions = format.read (& text)
+		.with_context (|| any_err! ("Error reading chapters: {chapters_display}")) ?;
+	chapters::check (& editions, "chapters")
+		.with_context (|| any_err! ("Error in {chapters_display}")) ?;
+	if args.dry_run {
+		print! ("{}", chapters::Format::Yaml.write (& editions, None) ?);
+		return Ok (());
+	}
+	let media = media::MediaInfo::probe (& args.file) ?;
+	write_chapters (& args.file, & media, & editions)
+}
+
+fn invoke_generate (args: GenerateArgs) -> anyhow::Result <()> {
+	for file in & args.files {
+		let file_display = file.display ();
+		let media = media::MediaInfo::probe (file) ?;
+		if ! media.chapters.is_empty () && ! args.force {
+			eprintln! ("Skipping {file_display}, it already has chapters, use --force to replace them");
+			continue;
+		}
+		let duration = media.duration
+			.ok_or_else (|| any_err! ("Unknown duration: {file_display}")) ?;
+		let editions = chapters::generate (duration, args.every * 60.0) ?;
+		if args.dry_run {
+			println! ("{file_display}:");
+			print! ("{}", chapters::write_ogm (& editions) ?);
+			continue;
+		}
+		write_chapters (file, & media, & editions) ?;
+	}
+	Ok (())
+}
+
+/// Write a copy of a media file with its chapters replaced, alongside the original
+fn write_chapters (
+	file: & Path,
+	media: & media::MediaInfo,
+	editions: & [chapters::Edition],
+) -> anyhow::Result <()> {
+	let dest_file = {
+		let mut val = file.file_stem ().unwrap ().to_owned ();
+		val.push ("-chapters.mkv");
+		file.with_file_name (val)
+	};
+	if fs::exists (& dest_file) ? {
+		any_bail! ("Destination file exists: {}", dest_file.display ());
+	}
+	// ffmpeg gives the tracks new UIDs, so chapters limited to certain tracks refer to them by
+	// position until the copy is made
+	let streams: Vec <& media::MediaStream> = media.streams.iter ()
+		.filter (|stream| matches! (stream.stream_type,
+			media::StreamType::Video | media::StreamType::Audio | media::StreamType::Subtitle))
+		.collect ();
+	let editions = editions.iter ().cloned ()
+		.map (|edition| edition.map_tracks (|track_uid| {
+			let track_idx = streams.iter ()
+				.position (|stream| stream.uid == Some (track_uid))
+				.ok_or_else (|| any_err! ("Chapter refers to missing track UID {track_uid}")) ?;
+			Ok (Some (track_idx))
+		}))
+		.collect::<anyhow::Result <Vec <_>>> () ?;
+	let mut command: Vec <OsString> = Vec::new ();
+	command.push ("-i".into ());
+	command.push ({
+		let mut val = OsString::from ("file:");
+		val.push (file);
+		val
+	});
+	command.push ("-map".into ());
+	command.push ("0".into ());
+	command.push ("-codec".into ());
+	command.push ("copy".into ());
+	command.push ("-map_chapters".into ());
+	command.push ("-1".into ());
+	command.push ("-metadata_header_padding".into ());
+	command.push (matroska::patch::HEADER_PADDING.to_string ().into ());
+	command.push ("-f".into ());
+	command.push ("matroska".into ());
+	command.push ({
+		let mut val = OsString::from ("file:");
+		val.push (& dest_file);
+		val
+	});
+	let file_display = file.to_string_lossy ();
+	ffmpeg::convert_progress (& file_display, media.duration_micros (), command) ?;
+	let track_updates: Vec <_> = streams.iter ()
+		.map (|stream| matroska::patch::TrackUpdate::for_stream (stream))
+		.collect ();
+	if let Err (err) = matroska::patch::update_tracks (& dest_file, & track_updates) {
+		eprintln! ("Warning: {err:#}");
+	}
+	let dest_track_uids: Vec <u64> = matroska::Reader::new (BufReader::new (File::open (& dest_file) ?)) ?
+		.tracks () ?
+		.entries.iter ()
+		.map (|track| track.uid)
+		.collect ();
+	let editions = editions.into_iter ()
+		.map (|edition| edition.map_tracks (|track_idx| Ok (dest_track_uids.get (track_idx).copied ())))
+		.collect::<anyhow::Result <Vec <_>>> () ?;
+	matroska::patch::replace_chapters (& dest_file, & chapters::to_matroska (& editions))
+}
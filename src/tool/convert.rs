@@ -1,6 +1,11 @@
+use crate::avi;
 use crate::detect;
 use crate::ffmpeg;
 use crate::imports::*;
+use crate::language;
+use crate::matroska;
+use crate::media;
+use crate::subs;
 
 #[ derive (Debug, clap::Args) ]
 #[ command (about = "Convert various file formats to matroska (mkv)" )]
@@ -12,6 +17,12 @@ pub struct Args {
 	#[ clap (long, help = "Skip all subtitle tracks") ]
 	skip_subs: bool,
 
+	#[ clap (long, value_enum, value_name = "FORMAT", help = "Convert text subtitle tracks to another format") ]
+	subs_format: Option <subs::Format>,
+
+	#[ clap (flatten) ]
+	bitmap_subs: super::subs::BitmapSubsArgs,
+
 }
 
 pub fn invoke (args: Args) -> anyhow::Result <()> {
@@ -29,17 +40,18 @@ fn invoke_one (args: & Args, file_path: & Path) -> anyhow::Result <()> {
 	let file_type = detect::FileType::identify_path (file_path)
 		.with_context (|| any_err! ("Error identifying file: {file_display}")) ?;
 	eprintln! ("{} (probing...)", file_path.display ());
-	let probe = ffmpeg::probe (file_path) ?;
+	let media = media::MediaInfo::probe (file_path) ?;
 	eprint! ("\x1b[A\x1b[J");
-	let strip_extension = match file_path.extension ().map (OsStr::as_encoded_bytes) {
-		Some (b"avi" | b"AVI") => true,
-		Some (b"m4v" | b"M4V") => true,
-		Some (b"mkv" | b"MKV") => true,
-		Some (b"mpg" | b"MPG") => true,
-		Some (b"mp4" | b"MP4") => true,
-		Some (b"vob" | b"VOB") => true,
-		_ => false,
-	};
+	let extension =
+		file_path.extension ()
+			.map (|extension| extension.to_string_lossy ().to_ascii_lowercase ());
+	let strip_extension = matches! (
+		extension.as_deref (),
+		Some (
+			"aac" | "ac3" | "asf" | "avi" | "flac" | "flv" | "m2t" | "m2ts" | "m4a" | "m4v"
+				| "mkv" | "mov" | "mp3" | "mp4" | "mpeg" | "mpg" | "mts" | "oga" | "ogg"
+				| "ogm" | "ogv" | "qt" | "ts" | "vob" | "wmv"
+		));
 	let mut dest_name =
 		if strip_extension { file_path.file_stem ().unwrap ().to_owned () }
 		else { file_name.to_owned () };
@@ -52,10 +64,34 @@ fn invoke_one (args: & Args, file_path: & Path) -> anyhow::Result <()> {
 	if dest_path.try_exists () ? {
 		any_bail! ("File already exists: {}", dest_path.display ());
 	}
+	// the headers only give hints, so convert without them if they can't be read
+	let avi_info = if let detect::FileType::Avi = file_type {
+		match avi::AviInfo::read (BufReader::new (File::open (file_path) ?)) {
+			Ok (avi_info) => Some (avi_info),
+			Err (err) => {
+				eprintln! ("Warning: Ignoring AVI headers which can't be read: {file_display}: {err:#}");
+				None
+			},
+		}
+	} else { None };
+	let packed_bitstream = avi_info.as_ref ().and_then (avi::AviInfo::packed_bitstream);
+	if let Some (user_data) = packed_bitstream {
+		eprintln! ("Unpacking packed bitstream video ({user_data})");
+	}
+	let vbr_mp3 = avi_info.as_ref ().is_some_and (avi::AviInfo::has_vbr_mp3);
+	if vbr_mp3 {
+		eprintln! ("Ignoring container timestamps for VBR MP3 audio");
+	}
+	let native_subs = match args.subs_format {
+		Some (format) if ! args.skip_subs && matches! (file_type, detect::FileType::Matroska) =>
+			convert_subs_native (file_path, format) ?,
+		_ => Vec::new (),
+	};
 	let mut command: Vec <OsString> = Vec::new ();
-	if file_type.needs_timestamp () {
+	let mut track_updates = Vec::new ();
+	if file_type.needs_timestamp () || vbr_mp3 {
 		command.push ("-fflags".into ());
-		command.push ("+genpts".into ());
+		command.push (if vbr_mp3 { "+genpts+igndts" } else { "+genpts" }.into ());
 	}
 	command.push ("-i".into ());
 	command.push ({
@@ -63,49 +99,141 @@ fn invoke_one (args: & Args, file_path: & Path) -> anyhow::Result <()> {
 		val.push (file_path);
 		val
 	});
-	command.push ("-map".into ());
-	command.push ("0:v:0".into ());
-	command.push ("-codec:v:0".into ());
-	command.push ("copy".into ());
-	let num_audio =
-		probe.streams.iter ()
-			.filter (|stream| stream.stream_type == ffmpeg::StreamType::Audio)
-			.count ();
-	for audio_idx in 0 .. num_audio {
+	for (_, temp) in & native_subs {
+		command.push ("-i".into ());
+		command.push ({
+			let mut val = OsString::from ("file:");
+			val.push (temp.path ());
+			val
+		});
+	}
+	let video_stream = media.streams_of_type (media::StreamType::Video).next ();
+	if video_stream.is_none () && ! file_type.is_audio_only () {
+		any_bail! ("No video stream found: {file_display}");
+	}
+	if let Some (video_stream) = video_stream {
+		command.push ("-map".into ());
+		command.push ("0:v:0".into ());
+		command.push ("-codec:v:0".into ());
+		command.push ("copy".into ());
+		push_stream_meta (& mut command, & mut track_updates, "v:0", video_stream);
+	}
+	if packed_bitstream.is_some () {
+		command.push ("-bsf:v:0".into ());
+		command.push ("mpeg4_unpack_bframes".into ());
+	}
+	for (audio_idx, audio_stream) in media.streams_of_type (media::StreamType::Audio).enumerate () {
 		command.push ("-map".into ());
 		command.push (format! ("0:a:{audio_idx}").into ());
 		command.push (format! ("-codec:a:{audio_idx}").into ());
 		command.push ("copy".into ());
+		push_stream_meta (& mut command, & mut track_updates, & format! ("a:{audio_idx}"), audio_stream);
 	}
 	if ! args.skip_subs {
 		let mut new_subs_idx = 0;
 		for (subs_idx, subs_stream) in
-				probe.streams.iter ()
-					.filter (|stream| stream.stream_type == ffmpeg::StreamType::Subtitle)
-					.enumerate () {
-			let Some (codec) = (match subs_stream.codec_name.as_ref ().map (String::as_str) {
-				Some ("ass") => Some ("copy"),
-				Some ("dvd_subtitle") => any_bail! ("Can't convert DVD subtitles to text, consider --skip-subs"),
-				Some ("mov_text") => Some ("srt"),
-				Some ("subrip") => Some ("copy"),
-				Some (codec) => any_bail! ("Unknown subtitle codec: {codec}"),
-				None => any_bail! ("No codec for subtitle track"),
-			}) else { continue };
+				media.streams_of_type (media::StreamType::Subtitle).enumerate () {
+			if subs_stream.is_bitmap_subtitle () && ! args.bitmap_subs.keep (subs_idx, subs_stream) {
+				continue;
+			}
+			let native_idx = native_subs.iter ().position (|& (native_idx, _)| native_idx == subs_idx);
+			let codec = match (subs_stream.codec.as_str (), args.subs_format) {
+				_ if native_idx.is_some () => "copy",
+				(codec, Some (format)) if subs_stream.is_text_subtitle () =>
+					if codec == format.name () { "copy" } else { format.name () },
+				("ass", _) => "copy",
+				("dvd_subtitle" | "hdmv_pgs_subtitle", _) => "copy",
+				("mov_text", _) => "srt",
+				("subrip", _) => "copy",
+				("webvtt", _) => "copy",
+				("unknown", _) => any_bail! ("No codec for subtitle track"),
+				(codec, _) => any_bail! ("Unknown subtitle codec: {codec}"),
+			};
 			command.push ("-map".into ());
-			command.push (format! ("0:s:{subs_idx}").into ());
+			command.push (match native_idx {
+				Some (native_idx) => format! ("{}:s:0", native_idx + 1),
+				None => format! ("0:s:{subs_idx}"),
+			}.into ());
 			command.push (format! ("-codec:s:{new_subs_idx}").into ());
 			command.push (codec.into ());
+			push_stream_meta (& mut command, & mut track_updates, & format! ("s:{new_subs_idx}"), subs_stream);
 			new_subs_idx += 1;
 		}
 	}
+	command.push ("-map_metadata".into ());
+	command.push ("0".into ());
+	command.push ("-map_chapters".into ());
+	command.push ("0".into ());
+	if let Some (title) = media.title.as_ref () {
+		command.push ("-metadata".into ());
+		command.push (format! ("title={title}").into ());
+	}
+	command.push ("-metadata_header_padding".into ());
+	command.push (matroska::patch::HEADER_PADDING.to_string ().into ());
 	command.push ("-format".into ());
 	command.push ("matroska".into ());
 	command.push ({
 		let mut val = OsString::from ("file:");
-		val.push (dest_path);
+		val.push (& dest_path);
 		val
 	});
 	let file_display = file_name.to_string_lossy ();
-	ffmpeg::convert_progress (& file_display, Some ((probe.duration * 1_000_000.0) as u64), command) ?;
+	ffmpeg::convert_progress (& file_display, media.duration_micros (), command) ?;
+	if let Err (err) = matroska::patch::update_tracks (& dest_path, & track_updates) {
+		eprintln! ("Warning: {err:#}");
+	}
 	Ok (())
 }
+
+/// Convert text subtitle tracks in a matroska file natively, which handles styles better than
+/// ffmpeg and checks them for problems, returning the subtitle index and a temporary file for each
+/// track converted
+fn convert_subs_native (
+	file_path: & Path,
+	format: subs::Format,
+) -> anyhow::Result <Vec <(usize, tempfile::NamedTempFile)>> {
+	let subs_tracks = subs::extract::subtitle_tracks (file_path) ?;
+	let mut native_subs = Vec::new ();
+	for (subs_idx, (track_number, codec_id)) in subs_tracks.iter ().enumerate () {
+		let Some (subs_format) = subs::extract::format_for_codec (codec_id) else { continue };
+		if subs_format == format { continue }
+		let subtitles = match subs::extract::read_track (file_path, * track_number) {
+			Ok (subtitles) => subtitles,
+			Err (err) => {
+				eprintln! ("Warning: Converting subtitle track {subs_idx} with ffmpeg instead: {err:#}");
+				continue;
+			},
+		};
+		let subs_name = format! ("subtitle track {subs_idx}");
+		super::subs::report_problems (& subs_name, & subtitles);
+		let mut temp = tempfile::Builder::new ()
+			.prefix ("jp-media-tool-subs-")
+			.suffix (& format! (".{}", format.extension ()))
+			.tempfile () ?;
+		temp.write_all (subtitles.convert (format).write ().as_bytes ()) ?;
+		temp.flush () ?;
+		native_subs.push ((subs_idx, temp));
+	}
+	Ok (native_subs)
+}
+
+/// Carry the language, title and dispositions of a source stream over to an output stream, adding
+/// a track update for the full language tag
+pub (super) fn push_stream_meta (
+	command: & mut Vec <OsString>,
+	track_updates: & mut Vec <matroska::patch::TrackUpdate>,
+	spec: & str,
+	stream: & media::MediaStream,
+) {
+	if let Some (language) = stream.language.as_ref () {
+		command.push (format! ("-metadata:s:{spec}").into ());
+		command.push (format! ("language={}", language::iso_639_2 (language)).into ());
+	}
+	track_updates.push (matroska::patch::TrackUpdate::for_stream (stream));
+	if let Some (title) = stream.title.as_ref () {
+		command.push (format! ("-metadata:s:{spec}").into ());
+		command.push (format! ("title={title}").into ());
+	}
+	command.push (format! ("-disposition:{spec}").into ());
+	command.push (stream.disposition.ffmpeg_value ().into ());
+}
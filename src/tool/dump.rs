@@ -2,6 +2,8 @@ use crate::ebml;
 use crate::imports::*;
 use crate::matroska;
 
+use super::OutputFormat;
+
 #[ derive (Debug, clap::Args) ]
 #[ command (about = "Display detailed information about a matroska (mkv) media file" )]
 pub struct Args {
@@ -33,110 +35,296 @@ pub struct Args {
 	#[ clap (long, help = "Show all information (generates a lot of text)") ]
 	show_all: bool,
 
+	#[ clap (long, value_enum, default_value_t, help = "Output format") ]
+	format: OutputFormat,
+
 }
 
-pub fn invoke (args: Args) -> anyhow::Result <()> {
+/// One file in the structured output, which is the serde form of the elements as they are read,
+/// so any change to the element structs changes the output too, and needs a new `OUTPUT_VERSION`
+/// and an updated `dump-example.yaml`
+#[ derive (Default, Serialize) ]
+struct DumpFile {
+	path: String,
+	head: Option <ebml::head::EbmlElem>,
+	seek_heads: Vec <matroska::SeekHeadElem>,
+	info: Option <matroska::InfoElem>,
+	tracks: Option <matroska::TracksElem>,
+	chapters: Option <matroska::ChaptersElem>,
+	tags: Option <matroska::TagsElem>,
+	cues: Option <matroska::CuesElem>,
+	clusters: Vec <matroska::ClusterElem>,
+}
+
+/// A top level element which was asked for
+enum DumpElem {
+	SeekHead (matroska::SeekHeadElem),
+	Info (Box <matroska::InfoElem>),
+	Tracks (matroska::TracksElem),
+	Chapters (matroska::ChaptersElem),
+	Tags (matroska::TagsElem),
+	Cluster (matroska::ClusterElem),
+	Cues (matroska::CuesElem),
+}
 
+pub fn invoke (args: Args) -> anyhow::Result <()> {
+	let structured = args.format != OutputFormat::Text;
+	let mut dump_files = Vec::new ();
 	for file_path in & args.files {
-		println! ("{}", file_path.display ());
+		if ! structured { println! ("{}", file_path.display ()); }
 		let file = BufReader::new (File::open (file_path) ?);
-		let mut reader = EbmlReader::new (file) ?;
+		let dump = dump_file (& args, file_path.to_string_lossy ().into_owned (), file) ?;
+		if structured { dump_files.push (dump); }
+	}
+	if structured {
+		super::write_output (args.format, "jp-media-tool/dump", & dump_files) ?;
+	}
+	Ok (())
+}
 
-		let (ebml_id, _, _) = reader.read () ?.ok_or_else (|| any_err! ("No ebml element")) ?;
-		anyhow::ensure! (ebml_id == ebml::head::elems::EBML, "Expected EBML, got 0x{ebml_id:x}");
-		let ebml = ebml::head::EbmlElem::read (& mut reader) ?;
-		if 1 < ebml.read_version {
-			any_bail! ("Unsupported EBML read version: {}", ebml.read_version);
-		}
-		if & ebml.doc_type != "matroska" {
-			any_bail! ("Unsupported document type: {} (expected: matroska)", ebml.doc_type);
+/// Read the elements asked for from a matroska file, printing each one as it is read for text
+/// output or else collecting them
+fn dump_file (args: & Args, path: String, src: impl BufRead + Seek) -> anyhow::Result <DumpFile> {
+
+	let structured = args.format != OutputFormat::Text;
+	let show = |flag: bool| flag || args.show_all;
+	let mut dump = DumpFile { path, .. DumpFile::default () };
+	let mut reader = EbmlReader::new (src) ?;
+
+	let (ebml_id, _, _) = reader.read () ?.ok_or_else (|| any_err! ("No ebml element")) ?;
+	anyhow::ensure! (ebml_id == ebml::head::elems::EBML, "Expected EBML, got 0x{ebml_id:x}");
+	let ebml = ebml::head::EbmlElem::read (& mut reader) ?;
+	if 1 < ebml.read_version {
+		any_bail! ("Unsupported EBML read version: {}", ebml.read_version);
+	}
+	if & ebml.doc_type != "matroska" {
+		any_bail! ("Unsupported document type: {} (expected: matroska)", ebml.doc_type);
+	}
+	if 4 < ebml.doc_type_read_version {
+		any_bail! ("Unsupported matroska read version: {}", ebml.doc_type_read_version);
+	}
+	if show (args.show_head) {
+		if structured { dump.head = Some (ebml); }
+		else { println! ("{ebml:#?}"); }
+	}
+
+	while let Some ((segment_id, _, _)) = reader.read () ? {
+		anyhow::ensure! (segment_id == matroska::elems::SEGMENT);
+		reader.nest ();
+		while let Some ((elem_id, elem_pos, elem_len)) = reader.read () ? {
+			let elem = match elem_id {
+				matroska::elems::SEEK_HEAD if show (args.show_head) =>
+					DumpElem::SeekHead (matroska::SeekHeadElem::read (& mut reader) ?),
+				matroska::elems::INFO if show (args.show_info) =>
+					DumpElem::Info (Box::new (matroska::InfoElem::read (& mut reader) ?)),
+				matroska::elems::TRACKS if show (args.show_tracks) =>
+					DumpElem::Tracks (matroska::TracksElem::read (& mut reader) ?),
+				matroska::elems::CHAPTERS if show (args.show_chapters) =>
+					DumpElem::Chapters (matroska::ChaptersElem::read (& mut reader) ?),
+				matroska::elems::TAGS if show (args.show_tags) =>
+					DumpElem::Tags (matroska::TagsElem::read (& mut reader) ?),
+				matroska::elems::CLUSTER if show (args.show_clusters) =>
+					DumpElem::Cluster (matroska::ClusterElem::read (& mut reader) ?),
+				matroska::elems::CUES if show (args.show_cues) =>
+					DumpElem::Cues (matroska::CuesElem::read (& mut reader) ?),
+				matroska::elems::SEEK_HEAD | matroska::elems::INFO | matroska::elems::TRACKS
+						| matroska::elems::CHAPTERS | matroska::elems::TAGS | matroska::elems::CLUSTER
+						| matroska::elems::CUES | ebml::head::elems::CRC32 | ebml::head::elems::VOID => {
+					reader.skip () ?;
+					continue;
+				},
+				_ => {
+					if ! structured {
+						println! ("Skipped: id=0x{elem_id:x}, pos=0x{elem_pos:x}, len={elem_len}");
+					}
+					reader.skip () ?;
+					continue;
+				},
+			};
+			if structured { dump.push (elem); }
+			else { elem.print (elem_pos, elem_len); }
 		}
-		if 4 < ebml.doc_type_read_version {
-			any_bail! ("Unsupported matroska read version: {}", ebml.doc_type_read_version);
+		reader.unnest () ?;
+	}
+
+	Ok (dump)
+
+}
+
+impl DumpFile {
+
+	fn push (& mut self, elem: DumpElem) {
+		match elem {
+			DumpElem::SeekHead (seek_head) => self.seek_heads.push (seek_head),
+			DumpElem::Info (info) => self.info = Some (* info),
+			DumpElem::Tracks (tracks) => self.tracks = Some (tracks),
+			DumpElem::Chapters (chapters) => self.chapters = Some (chapters),
+			DumpElem::Tags (tags) => self.tags = Some (tags),
+			DumpElem::Cluster (cluster) => self.clusters.push (cluster),
+			DumpElem::Cues (cues) => self.cues = Some (cues),
 		}
+	}
 
-		while let Some ((segment_id, _, _)) = reader.read () ? {
-			anyhow::ensure! (segment_id == matroska::elems::SEGMENT);
-			reader.nest ();
-			while let Some ((elem_id, elem_pos, elem_len)) = reader.read () ? {
-				match elem_id {
-					matroska::elems::SEEK_HEAD => {
-						if args.show_head || args.show_all {
-							println! ("Got seek head: start=0x{elem_pos:x}, len={elem_len}");
-							let seek_head = matroska::SeekHeadElem::read (& mut reader) ?;
-							println! ("{seek_head:#?}");
-						} else {
-							reader.skip () ?;
-						}
-					},
-					matroska::elems::INFO => {
-						if args.show_info || args.show_all {
-							println! ("Got segment info: start=0x{elem_pos:x}, len={elem_len}");
-							let info = matroska::InfoElem::read (& mut reader) ?;
-							println! ("{info:#?}");
-						} else {
-							reader.skip () ?;
-						}
-					},
-					matroska::elems::TRACKS => {
-						if args.show_tracks || args.show_all {
-							println! ("Got tracks: start=0x{elem_pos:x}, len={elem_len}");
-							let tracks = matroska::TracksElem::read (& mut reader) ?;
-							println! ("{tracks:#?}");
-						} else {
-							reader.skip () ?;
-						}
-					},
-					matroska::elems::CHAPTERS => {
-						if args.show_chapters || args.show_all {
-							println! ("Got chapters: start=0x{elem_pos:x}, len={elem_len}");
-							let chapters = matroska::ChaptersElem::read (& mut reader) ?;
-							println! ("{chapters:#?}");
-						} else {
-							reader.skip () ?;
-						}
-					},
-					matroska::elems::TAGS => {
-						if args.show_tags || args.show_all {
-							println! ("Got tags: start=0x{elem_pos:x}, len={elem_len}");
-							let tags = matroska::TagsElem::read (& mut reader) ?;
-							println! ("{tags:#?}");
-						} else {
-							reader.skip () ?;
-						}
-					},
-					matroska::elems::CLUSTER => {
-						if args.show_clusters || args.show_all {
-							println! ("Got cluster: start=0x{elem_pos:x}, len={elem_len}");
-							let cluster = matroska::ClusterElem::read (& mut reader) ?;
-							println! ("{cluster:#?}");
-						} else {
-							reader.skip () ?;
-						}
-					},
-					matroska::elems::CUES => {
-						if args.show_cues || args.show_all {
-							println! ("Got cues: start=0x{elem_pos:x}, len={elem_len}");
-							let cues = matroska::CuesElem::read (& mut reader) ?;
-							println! ("{cues:#?}");
-						} else {
-							reader.skip () ?;
-						}
-					},
-					ebml::head::elems::CRC32 | ebml::head::elems::VOID => {
-						reader.skip () ?;
-					},
-					_ => {
-						println! ("Skipped: id=0x{elem_id:x}, pos=0x{elem_pos:x}, len={elem_len}");
-						reader.skip () ?;
-					},
+}
+
+impl DumpElem {
+
+	fn print (& self, elem_pos: u64, elem_len: u64) {
+		let name = match self {
+			Self::SeekHead (_) => "seek head",
+			Self::Info (_) => "segment info",
+			Self::Tracks (_) => "tracks",
+			Self::Chapters (_) => "chapters",
+			Self::Tags (_) => "tags",
+			Self::Cluster (_) => "cluster",
+			Self::Cues (_) => "cues",
+		};
+		println! ("Got {name}: start=0x{elem_pos:x}, len={elem_len}");
+		match self {
+			Self::SeekHead (seek_head) => println! ("{seek_head:#?}"),
+			Self::Info (info) => println! ("{info:#?}"),
+			Self::Tracks (tracks) => {
+				println! ("{tracks:#?}");
+				for track in & tracks.entries {
+					match track.codec_config () {
+						Ok (Some (config)) => {
+							println! ("Track {num} codec config: {config:#?}", num = track.number);
+							if let Some (parameter_sets) = config.parameter_sets () {
+								println! ("Track {num} parameter sets: {parameter_sets}", num = track.number);
+							}
+						},
+						Ok (None) => (),
+						Err (err) => println! (
+							"Track {num} codec config: error: {err}",
+							num = track.number),
+					}
 				}
-			}
-			reader.unnest () ?;
+			},
+			Self::Chapters (chapters) => println! ("{chapters:#?}"),
+			Self::Tags (tags) => println! ("{tags:#?}"),
+			Self::Cluster (cluster) => println! ("{cluster:#?}"),
+			Self::Cues (cues) => println! ("{cues:#?}"),
 		}
+	}
+
+}
 
+#[ cfg (test) ]
+mod tests {
+
+	use crate::chapters;
+	use crate::ebml::head::elems as head_elems;
+	use crate::ebml::writer::EbmlWriter;
+	use crate::matroska::cluster::elems as cluster_elems;
+	use crate::matroska::cues::elems as cues_elems;
+	use crate::matroska::segment::elems as segment_elems;
+	use crate::matroska::tracks::elems as tracks_elems;
+	use crate::tags;
+
+	use super::*;
+
+	/// Small file with one of each element the dump shows
+	fn example_file () -> Vec <u8> {
+		let mut writer = EbmlWriter::new ();
+		writer.start (head_elems::EBML);
+		writer.unsigned (head_elems::EBML_VERSION, 1);
+		writer.unsigned (head_elems::EBML_READ_VERSION, 1);
+		writer.unsigned (head_elems::EBML_MAX_ID_LENGTH, 4);
+		writer.unsigned (head_elems::EBML_MAX_SIZE_LENGTH, 8);
+		writer.string (head_elems::EBML_DOC_TYPE, "matroska");
+		writer.unsigned (head_elems::EBML_DOC_TYPE_VERSION, 4);
+		writer.unsigned (head_elems::EBML_DOC_TYPE_READ_VERSION, 2);
+		writer.end ();
+		writer.start (matroska::elems::SEGMENT);
+		writer.start (matroska::elems::SEEK_HEAD);
+		writer.start (segment_elems::SEEK);
+		writer.unsigned (segment_elems::SEEK_ID, matroska::elems::INFO);
+		writer.unsigned (segment_elems::SEEK_POSITION, 100);
+		writer.end ();
+		writer.end ();
+		writer.start (matroska::elems::INFO);
+		writer.unsigned (segment_elems::TIMESTAMP_SCALE, 1_000_000);
+		writer.string (segment_elems::TITLE, "Example");
+		writer.string (segment_elems::MUXING_APP, "mux");
+		writer.string (segment_elems::WRITING_APP, "write");
+		writer.end ();
+		writer.start (matroska::elems::TRACKS);
+		writer.start (tracks_elems::TRACK_ENTRY);
+		writer.unsigned (tracks_elems::TRACK_NUMBER, 1);
+		writer.unsigned (tracks_elems::TRACK_UID, 11);
+		writer.unsigned (tracks_elems::TRACK_TYPE, 1);
+		writer.string (tracks_elems::CODEC_ID, "V_UNCOMPRESSED");
+		writer.start (tracks_elems::VIDEO);
+		writer.unsigned (tracks_elems::PIXEL_WIDTH, 320);
+		writer.unsigned (tracks_elems::PIXEL_HEIGHT, 240);
+		writer.end ();
+		writer.end ();
+		writer.start (tracks_elems::TRACK_ENTRY);
+		writer.unsigned (tracks_elems::TRACK_NUMBER, 2);
+		writer.unsigned (tracks_elems::TRACK_UID, 12);
+		writer.unsigned (tracks_elems::TRACK_TYPE, 0x11);
+		writer.string (tracks_elems::CODEC_ID, "S_TEXT/UTF8");
+		writer.string (tracks_elems::LANGUAGE, "ger");
+		writer.string (tracks_elems::LANGUAGE_BCP47, "de");
+		writer.end ();
+		writer.end ();
+		let editions: Vec <chapters::Edition> = serde_yaml::from_str (concat! (
+			"[ { uid: 1, names: [ { name: Main, language: en } ], chapters: [",
+			" { uid: 2, start: '0:00:00', names: [ { name: Start, language: en } ] },",
+			" { uid: 3, start: '0:00:01', tracks: [ 12 ], chapters: [ { uid: 4, start: '0:00:01.5' } ] } ] } ]",
+		)).unwrap ();
+		chapters::to_matroska (& editions).write (& mut writer);
+		let tags: Vec <tags::Tag> = serde_yaml::from_str (concat! (
+			"[ { level: 50, tags: [ { name: ARTIST, value: Someone } ] },",
+			" { level: 30, tracks: [ 12 ], tags: [ { name: TITLE, value: Subtitles, language: ger } ] } ]",
+		)).unwrap ();
+		tags::to_matroska (& tags).write (& mut writer);
+		writer.start (matroska::elems::CLUSTER);
+		writer.unsigned (cluster_elems::TIMESTAMP, 0);
+		writer.binary (cluster_elems::SIMPLE_BLOCK, & [ 0x81, 0x00, 0x00, 0x80, 0xaa, 0xbb ]);
+		writer.start (cluster_elems::BLOCK_GROUP);
+		writer.binary (cluster_elems::BLOCK, & [ 0x82, 0x03, 0xe8, 0x00, b'H', b'i' ]);
+		writer.unsigned (cluster_elems::BLOCK_DURATION, 500);
+		writer.end ();
+		writer.end ();
+		writer.start (matroska::elems::CUES);
+		writer.start (cues_elems::CUE_POINT);
+		writer.unsigned (cues_elems::CUE_TIME, 0);
+		writer.start (cues_elems::CUE_TRACK_POSITIONS);
+		writer.unsigned (cues_elems::CUE_TRACK, 1);
+		writer.unsigned (cues_elems::CUE_CLUSTER_POSITION, 200);
+		writer.end ();
+		writer.end ();
+		writer.end ();
+		writer.end ();
+		writer.into_bytes ()
 	}
 
-    Ok (())
+	/// The structured output is the serde form of the element structs, so this fails whenever one
+	/// of them changes. When it does, bump `OUTPUT_VERSION` and update `dump-example.yaml`.
+	#[ test ]
+	fn output_schema () {
+		let args = Args {
+			files: Vec::new (),
+			show_chapters: false,
+			show_clusters: false,
+			show_cues: false,
+			show_head: false,
+			show_info: false,
+			show_tags: false,
+			show_tracks: false,
+			show_all: true,
+			format: OutputFormat::Yaml,
+		};
+		let dump = dump_file (& args, "example.mkv".to_owned (), io::Cursor::new (example_file ())).unwrap ();
+		let doc = super::super::OutputDoc {
+			schema: "jp-media-tool/dump",
+			version: super::super::OUTPUT_VERSION,
+			files: & [ dump ],
+			summary: None::<()>,
+		};
+		let text = serde_yaml::to_string (& doc).unwrap ();
+		assert! (text == include_str! ("dump-example.yaml"), "Dump output changed:\n{text}");
+	}
 
 }
@@ -1,193 +1,800 @@
+use indexmap::IndexMap;
+
+use crate::chapters;
+use crate::detect;
 use crate::ffmpeg;
 use crate::imports::*;
+use crate::language;
+use crate::language::LanguageTag;
 use crate::matroska;
+use crate::media;
+use crate::subs;
+use crate::tags;
 
 #[ derive (Debug, clap::Args) ]
-#[ command (about = "Interactively edit metadata and tracks for matroska file" )]
+#[ command (about = "Edit metadata and tracks for matroska files, interactively or from a file" )]
 pub struct Args {
 
-	#[ clap (name = "FILE", help = "File to edit") ]
-	file: PathBuf,
+	#[ clap (name = "FILE", help = "Files to edit", required_unless_present = "apply") ]
+	files: Vec <PathBuf>,
+
+	#[ clap (long, value_name = "PATH", conflicts_with = "apply") ]
+	#[ clap (help = "Write metadata to YAML or JSON file instead of editing, \"-\" for stdout") ]
+	export: Option <PathBuf>,
+
+	#[ clap (long, value_name = "PATH") ]
+	#[ clap (help = "Apply metadata from YAML or JSON file without opening an editor") ]
+	apply: Option <PathBuf>,
 
 }
 
 pub fn invoke (args: Args) -> anyhow::Result <()> {
 
-	let (temp, duration_micros) = write_temp (& args) ?;
+	if let Some (apply_path) = args.apply.as_ref () {
+		let text = fs::read_to_string (apply_path)
+			.with_context (|| any_err! ("Error reading {}", apply_path.display ())) ?;
+		let Some (docs) = parse_docs (& text)
+			.with_context (|| any_err! ("Error in {}", apply_path.display ())) ?
+		else { any_bail! ("No metadata in {}", apply_path.display ()) };
+		return apply_docs (& args.files, docs);
+	}
+
+	let docs = export_docs (& args.files) ?;
+	let suggestions = language_suggestions (& args.files);
 
-	let nano_status =
-		process::Command::new ("nano")
-			.arg (temp.path ())
-			.status () ?;
-	if nano_status.code () != Some (0) {
-		any_bail! ("Editor did not exit cleanly, aborting");
+	if let Some (export_path) = args.export.as_ref () {
+		for suggestion in & suggestions {
+			eprintln! ("Suggestion: {suggestion}");
+		}
+		return write_docs (export_path, & docs);
 	}
 
-	perform_edits (& args, temp, duration_micros) ?;
+	let Some (docs) = edit_docs (& docs, & suggestions) ? else {
+		eprintln! ("Metadata file is empty, aborting");
+		return Ok (());
+	};
+	apply_docs (& args.files, docs)
 
-	Ok (())
+}
+
+/// Metadata for one file, or for several files keyed by path
+#[ derive (Debug, Serialize) ]
+#[ serde (untagged) ]
+pub (super) enum EditDocs {
+	Single (EditDoc),
+	Multi (EditMultiDoc),
+}
+
+#[ derive (Debug, Deserialize, Serialize) ]
+#[ serde (deny_unknown_fields) ]
+pub (super) struct EditMultiDoc {
+	pub (super) files: IndexMap <PathBuf, EditDoc>,
+}
+
+#[ derive (Clone, Debug, Deserialize, Serialize) ]
+#[ serde (deny_unknown_fields) ]
+pub (super) struct EditDoc {
+	#[ serde (default) ]
+	pub (super) title: String,
+	/// Tags with their targets, or `None` to keep the existing tags for the remaining tracks
+	#[ serde (default, skip_serializing_if = "Option::is_none") ]
+	pub (super) tags: Option <Vec <tags::Tag <TrackKey>>>,
+	pub (super) tracks: IndexMap <TrackKey, EditTrack>,
+	/// Chapter editions, or `None` to leave the chapters unchanged
+	#[ serde (default, skip_serializing_if = "Option::is_none") ]
+	pub (super) chapters: Option <Vec <chapters::Edition <TrackKey>>>,
+}
+
+#[ derive (Clone, Debug, Deserialize, Serialize) ]
+#[ serde (deny_unknown_fields) ]
+pub (super) struct EditTrack {
+	#[ serde (default) ]
+	pub (super) title: String,
+	/// Informational only, ignored when applying
+	#[ serde (default) ]
+	pub (super) codec: String,
+	#[ serde (default) ]
+	pub (super) language: String,
+	#[ serde (default = "default_true") ]
+	pub (super) flag_enabled: bool,
+	#[ serde (default) ]
+	pub (super) flag_default: bool,
+	#[ serde (default) ]
+	pub (super) flag_forced: bool,
+	#[ serde (default) ]
+	pub (super) flag_hearing_impaired: bool,
+	#[ serde (default) ]
+	pub (super) flag_visual_impaired: bool,
+	#[ serde (default) ]
+	pub (super) flag_text_descriptions: bool,
+	#[ serde (default) ]
+	pub (super) flag_original: bool,
+	#[ serde (default) ]
+	pub (super) flag_commentary: bool,
+}
+
+impl EditTrack {
+
+	pub (super) fn new (stream: & media::MediaStream) -> Self {
+		let disposition = stream.disposition;
+		Self {
+			title: stream.title.clone ().unwrap_or_default (),
+			codec: stream.codec_id.as_ref ().unwrap_or (& stream.codec).clone (),
+			language: stream.language.clone ().unwrap_or_default (),
+			flag_enabled: disposition.enabled,
+			flag_default: disposition.default,
+			flag_forced: disposition.forced,
+			flag_hearing_impaired: disposition.hearing_impaired,
+			flag_visual_impaired: disposition.visual_impaired,
+			flag_text_descriptions: disposition.descriptions,
+			flag_original: disposition.original,
+			flag_commentary: disposition.commentary,
+		}
+	}
+
+	fn disposition (& self) -> media::Disposition {
+		media::Disposition {
+			enabled: self.flag_enabled,
+			default: self.flag_default,
+			forced: self.flag_forced,
+			hearing_impaired: self.flag_hearing_impaired,
+			visual_impaired: self.flag_visual_impaired,
+			descriptions: self.flag_text_descriptions,
+			original: self.flag_original,
+			commentary: self.flag_commentary,
+		}
+	}
+
+}
+
+fn default_true () -> bool { true }
+
+/// Identifies a track by type and position within that type, eg "audio-1"
+#[ derive (Clone, Copy, Debug, Eq, Hash, PartialEq, serde_with::DeserializeFromStr, serde_with::SerializeDisplay) ]
+pub (super) struct TrackKey {
+	pub (super) stream_type: media::StreamType,
+	pub (super) index: usize,
+}
+
+impl TrackKey {
+
+	/// Key for an editable stream, or `None` for attachments and other streams we don't edit
+	pub (super) fn for_stream (stream: & media::MediaStream) -> Option <Self> {
+		matches! (stream.stream_type,
+				media::StreamType::Video | media::StreamType::Audio | media::StreamType::Subtitle)
+			.then_some (Self { stream_type: stream.stream_type, index: stream.type_index })
+	}
+
+}
+
+impl fmt::Display for TrackKey {
+	fn fmt (& self, fmtr: & mut fmt::Formatter) -> fmt::Result {
+		let label = match self.stream_type {
+			media::StreamType::Video => "video",
+			media::StreamType::Audio => "audio",
+			media::StreamType::Subtitle => "subs",
+			_ => unreachable! (),
+		};
+		write! (fmtr, "{label}-{}", self.index)
+	}
+}
+
+impl std::str::FromStr for TrackKey {
+	type Err = anyhow::Error;
+	fn from_str (val: & str) -> anyhow::Result <Self> {
+		let (stream_type, index) =
+			if let Some (index) = val.strip_prefix ("video-") { (media::StreamType::Video, index) }
+			else if let Some (index) = val.strip_prefix ("audio-") { (media::StreamType::Audio, index) }
+			else if let Some (index) = val.strip_prefix ("subs-") { (media::StreamType::Subtitle, index) }
+			else { any_bail! ("Invalid track \"{val}\", expected video-N, audio-N or subs-N") };
+		let index = index.parse ()
+			.map_err (|_| any_err! ("Invalid track \"{val}\", expected video-N, audio-N or subs-N")) ?;
+		Ok (Self { stream_type, index })
+	}
+}
+
+fn export_docs (files: & [PathBuf]) -> anyhow::Result <EditDocs> {
+	if let [file] = files {
+		return Ok (EditDocs::Single (export_doc (file) ?));
+	}
+	let mut multi = EditMultiDoc { files: IndexMap::new () };
+	for file in files {
+		multi.files.insert (file.clone (), export_doc (file) ?);
+	}
+	Ok (EditDocs::Multi (multi))
+}
+
+pub (super) fn export_doc (file: & Path) -> anyhow::Result <EditDoc> {
+	let media = media::MediaInfo::probe (file) ?;
+	let mut doc = EditDoc {
+		title: media.title.clone ().unwrap_or_default (),
+		tags: Some (source_tags (file, & media) ?),
+		tracks: IndexMap::new (),
+		chapters: Some (source_chapters (file, & media) ?),
+	};
+	for stream_type in [
+		media::StreamType::Video,
+		media::StreamType::Audio,
+		media::StreamType::Subtitle,
+	] {
+		for stream in media.streams_of_type (stream_type) {
+			doc.tracks.insert (
+				TrackKey { stream_type, index: stream.type_index },
+				EditTrack::new (stream));
+		}
+	}
+	Ok (doc)
+}
 
+/// Read tags from a media file, natively for matroska or from the probed information otherwise
+fn source_tags (
+	file: & Path,
+	media: & media::MediaInfo,
+) -> anyhow::Result <Vec <tags::Tag <TrackKey>>> {
+	if ! matches! (media.file_type, Some (detect::FileType::Matroska)) {
+		let simple_tags = |tags: & BTreeMap <String, String>| tags.iter ()
+			.map (|(name, value)| tags::SimpleTag {
+				name: name.clone (),
+				value: Some (value.clone ()),
+				binary: None,
+				language: None,
+				default: true,
+				tags: Vec::new (),
+			})
+			.collect::<Vec <_>> ();
+		let mut result = Vec::new ();
+		if ! media.tags.is_empty () {
+			result.push (tags::Tag {
+				level: 50,
+				target_type: None,
+				tracks: Vec::new (),
+				editions: Vec::new (),
+				chapters: Vec::new (),
+				attachments: Vec::new (),
+				tags: simple_tags (& media.tags),
+			});
+		}
+		for stream in & media.streams {
+			let Some (track_key) = TrackKey::for_stream (stream) else { continue };
+			if stream.tags.is_empty () { continue }
+			result.push (tags::Tag {
+				level: 50,
+				target_type: None,
+				tracks: vec! [ track_key ],
+				editions: Vec::new (),
+				chapters: Vec::new (),
+				attachments: Vec::new (),
+				tags: simple_tags (& stream.tags),
+			});
+		}
+		return Ok (result);
+	}
+	let mut result = Vec::new ();
+	for tag in tags::read (file) ? {
+		let tag = tag.map_tracks (|track_uid| Ok (
+			media.streams.iter ()
+				.find (|stream| stream.uid == Some (track_uid))
+				.and_then (TrackKey::for_stream))) ?;
+		result.extend (tag);
+	}
+	Ok (result)
 }
 
-fn write_temp (args: & Args) -> anyhow::Result <(tempfile::NamedTempFile, Option <u64>)> {
+/// Read chapters from a media file, referring to the tracks they are limited to by position
+fn source_chapters (
+	file: & Path,
+	media: & media::MediaInfo,
+) -> anyhow::Result <Vec <chapters::Edition <TrackKey>>> {
+	chapters::read (file, media) ?.into_iter ()
+		.map (|edition| edition.map_tracks (|track_uid| {
+			let track_key = media.streams.iter ()
+				.find (|stream| stream.uid == Some (track_uid))
+				.and_then (TrackKey::for_stream)
+				.ok_or_else (|| any_err! ("Chapter refers to missing track UID {track_uid}")) ?;
+			Ok (Some (track_key))
+		}))
+		.collect ()
+}
 
-	let file = BufReader::new (File::open (& args.file) ?);
+/// Read the chapters element from a matroska file as it is, if there is one
+fn source_chapters_elem (file_path: & Path) -> anyhow::Result <Option <matroska::ChaptersElem>> {
+	let file = BufReader::new (File::open (file_path) ?);
 	let mut reader = matroska::Reader::new (file) ?;
-	let file_info = reader.segment_info () ?;
-	let duration_micros = file_info.duration
-		.map (|duration| (file_info.timestamp_scale as f64 * duration / 1000.0) as u64);
-	let file_tracks = reader.tracks () ?;
-	let file_tags = reader.tags () ?;
-
-	let mut temp_value = serde_yaml::Mapping::new ();
-	temp_value.insert ("title".into (),
-		file_info.title.as_ref ().map (String::as_str).unwrap_or_default ().into ());
-	let mut temp_tags = serde_yaml::Mapping::new ();
-	for tag in & file_tags.tags {
-		if ! tag.targets.track_uids.is_empty ()
-				|| ! tag.targets.edition_uids.is_empty ()
-				|| ! tag.targets.chapter_uids.is_empty ()
-				|| ! tag.targets.attachment_uids.is_empty () {
-			continue;
-		}
-		for simple_tag in & tag.simple_tags {
-			let Some (string) = simple_tag.string.as_ref () else { continue };
-			temp_tags.insert (simple_tag.name.as_str ().into (), string.as_str ().into ());
-		}
-	}
-	temp_value.insert ("tags".into (), temp_tags.into ());
-
-	let mut tracks = serde_yaml::Mapping::new ();
-	for (track_type_label, track_type) in [ ("video", 1), ("audio", 2), ("subs", 17) ] {
-		for (track_idx, file_track) in file_tracks.entries.iter ()
-				.filter (|track| track.track_type == track_type)
-				.enumerate () {
-			let mut track = serde_yaml::Mapping::new ();
-			track.insert ("title".into (),
-				file_track.name.as_ref ().map (String::as_str).unwrap_or_default ().into ());
-			track.insert ("codec".into (), file_track.codec_id.as_str ().into ());
-			track.insert ("language".into (), file_track.language.as_str ().into ());
-			let mut tags = serde_yaml::Mapping::new ();
-			for tag in & file_tags.tags {
-				if ! tag.targets.track_uids.contains (& file_track.uid) { continue }
-				for simple_tag in & tag.simple_tags {
-					let Some (string) = simple_tag.string.as_ref () else { continue };
-					tags.insert (simple_tag.name.as_str ().into (), string.as_str ().into ());
-				}
-			}
-			track.insert ("tags".into (), tags.into ());
-			tracks.insert (format! ("{track_type_label}-{track_idx}").into (), track.into ());
+	if ! reader.has_seek (matroska::elems::CHAPTERS) { return Ok (None) }
+	Ok (Some (Arc::unwrap_or_clone (reader.chapters () ?)))
+}
+
+/// UIDs which tags can target, in the order they appear, so that targets can be carried across a
+/// remux by position
+#[ derive (Default) ]
+struct TargetUids {
+	tracks: Vec <Option <u64>>,
+	editions: Vec <Option <u64>>,
+	chapters: Vec <Option <u64>>,
+	attachments: Vec <Option <u64>>,
+}
+
+impl TargetUids {
+
+	/// Targets in the original file, or in the chapters from the document if they are replaced
+	fn source (file: & Path, media: & media::MediaInfo, doc: & EditDoc) -> anyhow::Result <Self> {
+		let mut targets =
+			if matches! (media.file_type, Some (detect::FileType::Matroska)) { Self::read (file) ? }
+			else { Self::default () };
+		if let Some (editions) = doc.chapters.as_ref () {
+			targets.set_chapters (editions);
+		}
+		Ok (targets)
+	}
+
+	fn read (file_path: & Path) -> anyhow::Result <Self> {
+		let file = BufReader::new (File::open (file_path) ?);
+		let mut reader = matroska::Reader::new (file) ?;
+		let mut targets = Self {
+			tracks: reader.tracks () ?.entries.iter ().map (|track| Some (track.uid)).collect (),
+			.. Self::default ()
+		};
+		if reader.has_seek (matroska::elems::CHAPTERS) {
+			let chapters = reader.chapters () ?;
+			targets.set_chapters (& chapters::from_matroska (& chapters) ?);
+		}
+		if reader.has_seek (matroska::elems::ATTACHMENTS) {
+			targets.attachments =
+				reader.attachments () ?.files.iter ().map (|file| Some (file.uid)).collect ();
+		}
+		Ok (targets)
+	}
+
+	fn set_chapters <Track> (& mut self, editions: & [chapters::Edition <Track>]) {
+		self.editions = editions.iter ().map (|edition| edition.uid).collect ();
+		self.chapters = editions.iter ()
+			.flat_map (|edition| edition.all_chapters ().into_iter ().map (|chapter| chapter.uid))
+			.collect ();
+	}
+
+	/// Check that every target of a tag exists, reporting the location of any problem
+	fn check <Track> (& self, tag: & tags::Tag <Track>, path: & str) -> anyhow::Result <()> {
+		for & edition_uid in & tag.editions {
+			any_ensure! (self.editions.contains (& Some (edition_uid)),
+				"{path}.editions: No edition with UID {edition_uid}");
+		}
+		for & chapter_uid in & tag.chapters {
+			any_ensure! (self.chapters.contains (& Some (chapter_uid)),
+				"{path}.chapters: No chapter with UID {chapter_uid}");
 		}
+		for & attachment_uid in & tag.attachments {
+			any_ensure! (self.attachments.contains (& Some (attachment_uid)),
+				"{path}.attachments: No attachment with UID {attachment_uid}");
+		}
+		Ok (())
+	}
+
+	/// Map the edition, chapter and attachment targets of a tag to the same positions in another
+	/// file, returning `None` if everything of some kind which the tag targeted is gone
+	fn retarget <Track> (& self, other: & Self, mut tag: tags::Tag <Track>) -> Option <tags::Tag <Track>> {
+		let map_uid = |from: & [Option <u64>], to: & [Option <u64>], uids: Vec <u64>| -> Option <Vec <u64>> {
+			if uids.is_empty () { return Some (uids) }
+			let mapped: Vec <u64> = uids.into_iter ()
+				.filter_map (|uid| {
+					let pos = from.iter ().position (|& from_uid| from_uid == Some (uid)) ?;
+					to.get (pos).copied ().flatten ()
+				})
+				.collect ();
+			(! mapped.is_empty ()).then_some (mapped)
+		};
+		tag.editions = map_uid (& self.editions, & other.editions, tag.editions) ?;
+		tag.chapters = map_uid (& self.chapters, & other.chapters, tag.chapters) ?;
+		tag.attachments = map_uid (& self.attachments, & other.attachments, tag.attachments) ?;
+		Some (tag)
 	}
-	temp_value.insert ("tracks".into (), tracks.into ());
 
-	let temp_value = serde_yaml::Value::from (temp_value);
-	let mut temp = tempfile::NamedTempFile::with_prefix ("jp-media-tool-edit-") ?;
-	serde_yaml::to_writer (BufWriter::new (& mut temp), & temp_value) ?;
+}
+
+/// Suggest language fixes for text subtitle tracks whose text looks like a different language to
+/// the one they are tagged with
+fn language_suggestions (files: & [PathBuf]) -> Vec <String> {
+	let mut suggestions = Vec::new ();
+	for file in files {
+		let track_guesses = match subs::langid::identify_tracks (file) {
+			Ok (track_guesses) => track_guesses,
+			Err (err) => {
+				eprintln! ("Warning: Unable to identify subtitle languages: {}: {err:#}", file.display ());
+				continue;
+			},
+		};
+		for track_guess in track_guesses {
+			let Some (guess) = track_guess.guess else { continue };
+			if ! guess.is_confident () || guess.matches (& track_guess.language) { continue }
+			let prefix = if files.len () == 1 { String::new () } else { format! ("{}: ", file.display ()) };
+			suggestions.push (format! (
+				"{prefix}subs-{} is tagged {}, but looks like {guess}",
+				track_guess.subs_idx,
+				track_guess.language));
+		}
+	}
+	suggestions
+}
+
+/// Write a metadata file, as JSON if it has that extension or YAML otherwise, "-" means stdout
+pub (super) fn write_docs (path: & Path, docs: & EditDocs) -> anyhow::Result <()> {
+	let json = path.extension ().is_some_and (|ext| ext.eq_ignore_ascii_case ("json"));
+	let mut writer: Box <dyn Write> =
+		if path == Path::new ("-") { Box::new (io::stdout ().lock ()) }
+		else { Box::new (BufWriter::new (File::create (path) ?)) };
+	if json {
+		serde_json::to_writer_pretty (& mut writer, docs) ?;
+		writeln! (writer) ?;
+	} else {
+		serde_yaml::to_writer (& mut writer, docs) ?;
+	}
+	writer.flush () ?;
+	Ok (())
+}
+
+/// Parse a metadata file, returning `None` if it is empty
+pub (super) fn parse_docs (text: & str) -> anyhow::Result <Option <EditDocs>> {
+	let value: serde_yaml::Value = serde_yaml::from_str (text) ?;
+	if value.is_null () { return Ok (None) }
+	any_ensure! (value.is_mapping (), "Expected a mapping at top level");
+	if value.get ("files").is_some () {
+		Ok (Some (EditDocs::Multi (serde_yaml::from_str (text) ?)))
+	} else {
+		Ok (Some (EditDocs::Single (serde_yaml::from_str (text) ?)))
+	}
+}
+
+/// Comment lines at the top of the temporary file, always exactly this many so that line numbers
+/// in error messages stay correct when it is replaced
+const EDIT_HEADER_LINES: usize = 2;
+
+/// Open the metadata in the user's editor, with any suggestions as comments at the end
+fn edit_docs (docs: & EditDocs, suggestions: & [String]) -> anyhow::Result <Option <EditDocs>> {
+	let mut temp =
+		tempfile::Builder::new ()
+			.prefix ("jp-media-tool-edit-")
+			.suffix (".yaml")
+			.tempfile () ?;
+	write! (temp,
+		"# Edit metadata, then save and exit to apply\n\
+		# Empty the file to abort\n") ?;
+	serde_yaml::to_writer (& mut temp, docs) ?;
+	if ! suggestions.is_empty () {
+		writeln! (temp) ?;
+		for suggestion in suggestions {
+			writeln! (temp, "# Suggestion: {suggestion}") ?;
+		}
+	}
 	temp.flush () ?;
+	loop {
+		run_editor (temp.path ()) ?;
+		let text = fs::read_to_string (temp.path ()) ?;
+		let err = match parse_docs (& text) {
+			Ok (docs) => return Ok (docs),
+			Err (err) => err,
+		};
+		let body =
+			if text.lines ().take (EDIT_HEADER_LINES).all (|line| line.starts_with ('#')) {
+				text.split_inclusive ('\n').skip (EDIT_HEADER_LINES).collect::<String> ()
+			} else { text };
+		let err = format! ("{err:#}").replace ('\n', " ");
+		fs::write (temp.path (), format! (
+			"# ERROR: {err}\n\
+			# Correct the error, then save and exit to retry, or empty the file to abort\n\
+			{body}")) ?;
+	}
+}
+
+/// Run the user's editor, taken from `$VISUAL` or `$EDITOR`
+fn run_editor (path: & Path) -> anyhow::Result <()> {
+	let editor =
+		env::var ("VISUAL").ok ().filter (|val| ! val.trim ().is_empty ())
+			.or_else (|| env::var ("EDITOR").ok ().filter (|val| ! val.trim ().is_empty ()))
+			.unwrap_or_else (|| "nano".to_owned ());
+	let mut editor_args = editor.split_whitespace ();
+	let editor_prog = editor_args.next ().unwrap ();
+	let editor_status =
+		process::Command::new (editor_prog)
+			.args (editor_args)
+			.arg (path)
+			.status ()
+			.with_context (|| any_err! ("Error running editor: {editor}")) ?;
+	if ! editor_status.success () {
+		any_bail! ("Editor did not exit cleanly, aborting");
+	}
+	Ok (())
+}
+
+fn apply_docs (files: & [PathBuf], docs: EditDocs) -> anyhow::Result <()> {
 
-	Ok ((temp, duration_micros))
+	// work out which document applies to each file
+
+	let file_docs: Vec <(PathBuf, EditDoc)> = match docs {
+		EditDocs::Single (doc) => {
+			any_ensure! (! files.is_empty (), "No files specified");
+			files.iter ().map (|file| (file.clone (), doc.clone ())).collect ()
+		},
+		EditDocs::Multi (mut multi) => {
+			if files.is_empty () {
+				multi.files.into_iter ().collect ()
+			} else {
+				let mut file_docs = Vec::new ();
+				for file in files {
+					let doc = multi.files.shift_remove (file)
+						.ok_or_else (|| any_err! ("No section for {} under files", file.display ())) ?;
+					file_docs.push ((file.clone (), doc));
+				}
+				file_docs
+			}
+		},
+	};
+
+	// check everything before writing anything
+
+	let mut edits = Vec::new ();
+	for (file, doc) in file_docs {
+		edits.push (prepare_edit (file, doc, "-edit.mkv", None) ?);
+	}
+
+	for edit in edits {
+		edit.perform () ?;
+	}
+
+	Ok (())
 
 }
 
-fn perform_edits (
-	args: & Args,
-	mut temp: tempfile::NamedTempFile,
-	duration_micros: Option <u64>,
-) -> anyhow::Result <()> {
+/// An edit which has been checked and is ready to write
+pub (super) struct PreparedEdit {
+	file: PathBuf,
+	dest_file: PathBuf,
+	doc: EditDoc,
+	doc_tags: Vec <tags::Tag <TrackKey>>,
+	targets: TargetUids,
+	media: media::MediaInfo,
+	attachments_from: Option <PathBuf>,
+}
 
-	let format_err = || any_err! ("Format error");
+impl PreparedEdit {
 
-	temp.seek (SeekFrom::Start (0)) ?;
-	let temp_value: serde_yaml::Value = serde_yaml::from_reader (BufReader::new (& mut temp)) ?;
-	let temp_value = temp_value.as_mapping ().ok_or_else (format_err) ?;
+	pub (super) fn dest_file (& self) -> & Path {
+		& self.dest_file
+	}
+
+	/// Write the copy, removing it again if any of the edits can't be applied
+	pub (super) fn perform (self) -> anyhow::Result <()> {
+		let result = perform_edits (
+				& self.file,
+				& self.dest_file,
+				& self.doc,
+				& self.media,
+				self.attachments_from.as_deref ())
+			.and_then (|()| write_tags (& self.dest_file, & self.doc, self.doc_tags, & self.targets));
+		if result.is_err () && fs::exists (& self.dest_file).unwrap_or (false) {
+			eprintln! ("Removing incomplete copy {}", self.dest_file.display ());
+			let _ = fs::remove_file (& self.dest_file);
+		}
+		result
+	}
+
+}
+
+/// Check a document against a file, ready to write a copy with the edits applied
+///
+/// The copy is named after the original with `dest_suffix` in place of the extension. Attachments
+/// are taken from `attachments_from` instead of the original if it is given, which must be a
+/// matroska file.
+pub (super) fn prepare_edit (
+	file: PathBuf,
+	mut doc: EditDoc,
+	dest_suffix: & str,
+	attachments_from: Option <PathBuf>,
+) -> anyhow::Result <PreparedEdit> {
+	let media = media::MediaInfo::probe (& file) ?;
+	any_ensure! (! doc.tracks.is_empty (), "{}: tracks: No tracks left to keep", file.display ());
+	for (track_key, track) in & doc.tracks {
+		any_ensure! (
+			media.streams_of_type (track_key.stream_type).nth (track_key.index).is_some (),
+			"{}: tracks.{track_key}: No such track",
+			file.display ());
+		// languages we don't know are still written as they are, as long as they are well formed
+		if ! track.language.is_empty () && ! language::is_well_formed (& track.language) {
+			LanguageTag::parse (& track.language)
+				.map_err (|err| any_err! ("{}: tracks.{track_key}.language: {err}", file.display ())) ?;
+		}
+	}
+	// chapters which haven't changed are copied from the original as they are
+	if doc.chapters.is_some () && doc.chapters == Some (source_chapters (& file, & media) ?) {
+		doc.chapters = None;
+	}
+	if let Some (editions) = doc.chapters.as_ref () {
+		chapters::check (editions, "chapters")
+			.with_context (|| any_err! ("{}", file.display ())) ?;
+		for edition in editions.iter ().cloned () {
+			edition.map_tracks (|track_key| Ok (doc.tracks.contains_key (& track_key).then_some (track_key)))
+				.with_context (|| any_err! ("{}: chapters", file.display ())) ?;
+		}
+	}
+	let mut targets = TargetUids::source (& file, & media, & doc) ?;
+	if let Some (attachments_from) = attachments_from.as_ref () {
+		targets.attachments = TargetUids::read (attachments_from) ?.attachments;
+	}
+	let doc_tags = match doc.tags.clone () {
+		Some (doc_tags) => {
+			tags::check (& doc_tags, "tags")
+				.with_context (|| any_err! ("{}", file.display ())) ?;
+			// tags follow the tracks they target, like the automatic ones do when there is no list
+			let mut kept_tags = Vec::new ();
+			for (tag_idx, tag) in doc_tags.into_iter ().enumerate () {
+				let Some (tag) = tag.map_tracks (|track_key|
+						Ok (doc.tracks.contains_key (& track_key).then_some (track_key))) ? else {
+					eprintln! ("{}: Dropping tags.{tag_idx}, none of its tracks are being kept", file.display ());
+					continue;
+				};
+				targets.check (& tag, & format! ("tags.{tag_idx}"))
+					.with_context (|| any_err! ("{}", file.display ())) ?;
+				kept_tags.push (tag);
+			}
+			kept_tags
+		},
+		None => {
+			let mut doc_tags = Vec::new ();
+			for tag in source_tags (& file, & media) ? {
+				let tag = tag.map_tracks (|track_key|
+					Ok (doc.tracks.contains_key (& track_key).then_some (track_key))) ?;
+				doc_tags.extend (tag.and_then (|tag| targets.retarget (& targets, tag)));
+			}
+			doc_tags
+		},
+	};
+	let dest_file = {
+		let mut val = file.file_stem ().unwrap ().to_owned ();
+		val.push (dest_suffix);
+		file.with_file_name (val)
+	};
+	if fs::exists (& dest_file) ? {
+		any_bail! ("Destination file exists: {}", dest_file.display ());
+	}
+	Ok (PreparedEdit { file, dest_file, doc, doc_tags, targets, media, attachments_from })
+}
+
+fn perform_edits (
+	file: & Path,
+	dest_file: & Path,
+	doc: & EditDoc,
+	media: & media::MediaInfo,
+	attachments_from: Option <& Path>,
+) -> anyhow::Result <()> {
 
 	let mut command: Vec <OsString> = Vec::new ();
 	command.push ("-i".into ());
 	command.push ({
 		let mut val = OsString::from ("file:");
-		val.push (& args.file);
+		val.push (file);
 		val
 	});
+	let attachments_input = match attachments_from {
+		Some (attachments_from) => {
+			command.push ("-i".into ());
+			command.push ({
+				let mut val = OsString::from ("file:");
+				val.push (attachments_from);
+				val
+			});
+			1
+		},
+		None => 0,
+	};
+	let matroska_source = matches! (media.file_type, Some (detect::FileType::Matroska));
+	command.push ("-map_chapters".into ());
+	command.push ((if doc.chapters.is_some () || matroska_source { "-1" } else { "0" }).into ());
 	command.push ("-map_metadata".into ());
 	command.push ("-1".into ());
-	let temp_title = temp_value.get ("title").ok_or_else (format_err) ?;
-	let temp_title = temp_title.as_str ().ok_or_else (format_err) ?;
 	command.push ("-metadata".into ());
-	command.push (format! ("title={temp_title}").into ());
-	if let Some (temp_tags) = temp_value.get ("tags") {
-		let temp_tags = temp_tags.as_mapping ().ok_or_else (format_err) ?;
-		for (tag_name, tag_value) in temp_tags {
-			let tag_name = tag_name.as_str ().ok_or_else (format_err) ?;
-			let tag_value = tag_value.as_str ().ok_or_else (format_err) ?;
-			command.push ("-metadata".into ());
-			command.push (format! ("{tag_name}={tag_value}").into ());
-		}
-	}
+	command.push (format! ("title={}", doc.title).into ());
 
-	let temp_tracks = temp_value.get ("tracks").ok_or_else (format_err) ?;
-	let temp_tracks = temp_tracks.as_mapping ().ok_or_else (format_err) ?;
 	let mut num_video = 0;
 	let mut num_audio = 0;
 	let mut num_subs = 0;
-	for (track_id, temp_track) in temp_tracks {
-		let track_id = track_id.as_str ().ok_or_else (format_err) ?;
-		let track_id_out;
-		if let Some (id) = track_id.strip_prefix ("video-") {
-			command.push ("-map".into ());
-			command.push (format! ("0:v:{id}").into ());
-			track_id_out = format! ("v:{num_video}");
-			num_video += 1;
-		} else if let Some (id) = track_id.strip_prefix ("audio-") {
-			command.push ("-map".into ());
-			command.push (format! ("0:a:{id}").into ());
-			track_id_out = format! ("a:{num_audio}");
-			num_audio += 1;
-		} else if let Some (id) = track_id.strip_prefix ("audio-") {
-			command.push ("-map".into ());
-			command.push (format! ("0:s:{id}").into ());
-			track_id_out = format! ("s:{num_subs}");
-			num_subs += 1;
-		} else { return Err (format_err ()) }
+	for (track_key, track) in & doc.tracks {
+		let TrackKey { stream_type, index } = * track_key;
+		let num_out = match stream_type {
+			media::StreamType::Video => & mut num_video,
+			media::StreamType::Audio => & mut num_audio,
+			media::StreamType::Subtitle => & mut num_subs,
+			_ => unreachable! (),
+		};
+		let spec = stream_type.specifier ();
+		let track_id_out = format! ("{spec}:{num_out}");
+		* num_out += 1;
+		command.push ("-map".into ());
+		command.push (format! ("0:{spec}:{index}").into ());
 		command.push (format! ("-codec:{track_id_out}").into ());
 		command.push ("copy".into ());
-		let temp_lang = temp_track.get ("language").ok_or_else (format_err) ?;
-		let temp_lang = temp_lang.as_str ().ok_or_else (format_err) ?;
-		if ! temp_lang.is_empty () {
+		command.push (format! ("-metadata:s:{track_id_out}").into ());
+		command.push (format! ("title={}", track.title).into ());
+		if ! track.language.is_empty () {
 			command.push (format! ("-metadata:s:{track_id_out}").into ());
-			command.push (format! ("language={temp_lang}").into ());
-		}
-		if let Some (temp_tags) = temp_track.get ("tags") {
-			let temp_tags = temp_tags.as_mapping ().ok_or_else (format_err) ?;
-			for (tag_name, tag_value) in temp_tags {
-				let tag_name = tag_name.as_str ().ok_or_else (format_err) ?;
-				let tag_value = tag_value.as_str ().ok_or_else (format_err) ?;
-				command.push (format! ("-metadata:s:{track_id_out}").into ());
-				command.push (format! ("{tag_name}={tag_value}").into ());
-			}
+			command.push (format! ("language={}", language::iso_639_2 (& track.language)).into ());
 		}
+		command.push (format! ("-disposition:{track_id_out}").into ());
+		command.push (track.disposition ().ffmpeg_value ().into ());
 	}
+	command.push ("-map".into ());
+	command.push (format! ("{attachments_input}:t?").into ());
+	command.push ("-codec:t".into ());
+	command.push ("copy".into ());
 
-	let dest_file = {
-		let mut val = args.file.file_stem ().unwrap ().to_owned ();
-		val.push ("-edit.mkv");
-		PathBuf::from (val)
-	};
-	if fs::exists (& dest_file) ? {
-		any_bail! ("Destination file exists: {}", dest_file.display ());
-	}
-
+	command.push ("-default_mode".into ());
+	command.push ("passthrough".into ());
+	command.push ("-metadata_header_padding".into ());
+	command.push (matroska::patch::HEADER_PADDING.to_string ().into ());
 	command.push ("-f".into ());
 	command.push ("matroska".into ());
 	command.push ({
 		let mut val = OsString::from ("file:");
-		val.push (& dest_file);
+		val.push (dest_file);
 		val
 	});
-	let file_display = args.file.to_string_lossy ();
-eprintln! ("{command:#?}");
-	ffmpeg::convert_progress (& file_display, duration_micros, command) ?;
+	let file_display = file.to_string_lossy ();
+	ffmpeg::convert_progress (& file_display, media.duration_micros (), command) ?;
+	// ffmpeg never writes FlagEnabled or LanguageBCP47, so they are set afterwards
+	let track_updates: Vec <_> = doc.tracks.values ()
+		.map (|track| matroska::patch::TrackUpdate {
+			enabled: Some (track.flag_enabled),
+			language: (! track.language.is_empty ()).then (|| track.language.clone ()),
+		})
+		.collect ();
+	matroska::patch::update_tracks (dest_file, & track_updates) ?;
+
+	// chapters are written natively, since ffmpeg only copies plain chapters from one edition, and
+	// any tracks they are limited to are pointed at the new track UIDs
+	let dest_track_uids = TargetUids::read (dest_file) ?.tracks;
+	let dest_track_uid = |track_key: TrackKey| doc.tracks.get_index_of (& track_key)
+		.and_then (|pos| dest_track_uids.get (pos).copied ().flatten ());
+	let new_chapters = match doc.chapters.as_ref () {
+		Some (editions) => {
+			let editions = editions.iter ().cloned ()
+				.map (|edition| edition.map_tracks (|track_key| Ok (dest_track_uid (track_key))))
+				.collect::<anyhow::Result <Vec <_>>> () ?;
+			Some (chapters::to_matroska (& editions))
+		},
+		None if matroska_source => source_chapters_elem (file) ?
+			.map (|mut chapters_elem| {
+				chapters_elem.map_track_uids (|track_uid| Ok (media.streams.iter ()
+					.find (|stream| stream.uid == Some (track_uid))
+					.and_then (TrackKey::for_stream)
+					.and_then (dest_track_uid))) ?;
+				anyhow::Ok (chapters_elem)
+			})
+			.transpose () ?,
+		None => None,
+	};
+	if let Some (new_chapters) = new_chapters {
+		matroska::patch::replace_chapters (dest_file, & new_chapters) ?;
+	}
 
 	Ok (())
 
 }
+
+/// Replace the tags written by ffmpeg with those from the document, pointing their targets at the
+/// UIDs in the new file
+fn write_tags (
+	dest_file: & Path,
+	doc: & EditDoc,
+	doc_tags: Vec <tags::Tag <TrackKey>>,
+	source_targets: & TargetUids,
+) -> anyhow::Result <()> {
+	let dest_targets = TargetUids::read (dest_file) ?;
+	let mut dest_tags = Vec::new ();
+	for tag in doc_tags {
+		let tag = tag.map_tracks (|track_key| Ok (
+			doc.tracks.get_index_of (& track_key)
+				.and_then (|pos| dest_targets.tracks.get (pos).copied ().flatten ()))) ?;
+		match tag.and_then (|tag| source_targets.retarget (& dest_targets, tag)) {
+			Some (tag) => dest_tags.push (tag),
+			None => eprintln! ("Dropping tag whose targets are missing from {}", dest_file.display ()),
+		}
+	}
+	tags::write (dest_file, & dest_tags)
+}
@@ -1,6 +1,13 @@
+use crate::avi;
 use crate::detect;
+use crate::glob;
+use crate::hdr;
 use crate::imports::*;
 use crate::matroska;
+use crate::mp4;
+use crate::matroska::tracks::TransferCharacteristics;
+
+use super::OutputFormat;
 
 #[ derive (Debug, clap::Args) ]
 #[ command (about = "Display summary information about a list of media files" )]
@@ -9,63 +16,378 @@ pub struct Args {
 	#[ clap (name = "FILE", help = "Files to show information about") ]
 	files: Vec <PathBuf>,
 
+	#[ clap (long, value_enum, default_value_t, help = "Output format") ]
+	format: OutputFormat,
+
+	#[ clap (long, short, help = "Scan directories recursively") ]
+	recursive: bool,
+
+	#[ clap (long, value_name = "PATTERN",
+		help = "Only include files matching glob pattern when scanning directories") ]
+	include: Vec <String>,
+
+	#[ clap (long, value_name = "PATTERN",
+		help = "Exclude files matching glob pattern when scanning directories") ]
+	exclude: Vec <String>,
+
+	#[ clap (long, value_enum, help = "Sort files (size and duration are largest first)") ]
+	sort: Option <SortOrder>,
+
+	#[ clap (long, help = "Show summary totals (default when scanning recursively)") ]
+	summary: bool,
+
+}
+
+#[ derive (Clone, Copy, Debug, Eq, PartialEq, clap::ValueEnum) ]
+enum SortOrder {
+	Codec,
+	Duration,
+	Size,
 }
 
 pub fn invoke (args: Args) -> anyhow::Result <()> {
+	let file_paths = collect_files (& args) ?;
+	let show_summary = args.summary || args.recursive;
+	if args.format != OutputFormat::Text {
+		let mut files: Vec <InfoFile> =
+			file_paths.iter ()
+				.map (|file_path| structured_info (file_path.as_path ()))
+				.collect ();
+		if let Some (sort) = args.sort {
+			files.sort_by (|left, right| compare_stats (sort, & left.stats, & right.stats));
+		}
+		let summary = show_summary.then (|| Summary::new (files.iter ().map (|file| & file.stats)));
+		return super::write_output_summary (args.format, "jp-media-tool/info", & files, summary);
+	}
+	let mut lines: Vec <(& Path, String, Stats)> =
+		file_paths.iter ()
+			.map (|file_path| {
+				let (desc, stats) = text_info (file_path);
+				(file_path.as_path (), desc, stats)
+			})
+			.collect ();
+	if let Some (sort) = args.sort {
+		lines.sort_by (|left, right| compare_stats (sort, & left.2, & right.2));
+	}
 	let max_len =
-		args.files.iter ()
-			.map (|file_path| file_path.to_string_lossy ().chars ().count ())
+		lines.iter ()
+			.map (|(file_path, _, _)| file_path.to_string_lossy ().chars ().count ())
 			.max ()
 			.unwrap_or (0);
-	for file_path in & args.files {
+	for (file_path, desc, _) in & lines {
 		let file_display = file_path.display ();
-		if file_path.is_dir () {
-			println! ("{file_display:<max_len$}  directory");
+		println! ("{file_display:<max_len$}  {desc}");
+	}
+	if show_summary {
+		let summary = Summary::new (lines.iter ().map (|(_, _, stats)| stats));
+		summary.print ();
+	}
+	Ok (())
+}
+
+fn text_info (file_path: & Path) -> (String, Stats) {
+	let mut stats = Stats::default ();
+	if file_path.is_dir () {
+		stats.container = "directory";
+		return ("directory".to_owned (), stats);
+	}
+	let mut file = match File::open (file_path) {
+		Ok (file) => BufReader::new (file),
+		Err (err) => {
+			stats.container = "error";
+			return (format! ("error: {err}"), stats);
+		},
+	};
+	stats.size = file.get_ref ().metadata ().ok ().map (|metadata| metadata.len ());
+	let file_type = detect::FileType::identify_reader (& mut file);
+	stats.container = container_name (& file_type);
+	let desc = match file_type {
+		Ok (detect::FileType::Matroska) => {
+			let info = matroska_info (& mut file, & mut stats)
+				.unwrap_or_else (|err| format! ("error: {err}"));
+			format! ("matroska {info}")
+		},
+		Ok (detect::FileType::Avi) => {
+			let info = avi_info (& mut file, & mut stats)
+				.unwrap_or_else (|err| format! ("error: {err}"));
+			format! ("avi {info}")
+		},
+		Ok (file_type) if file_type.is_iso_media () => {
+			let info = mp4_info (& mut file, & mut stats)
+				.unwrap_or_else (|err| format! ("error: {err}"));
+			format! ("{container} {info}", container = stats.container)
+		},
+		Ok (_) => stats.container.to_owned (),
+		Err (detect::IdentifyError::PartiallyRecognised (err)) => format! ("error: {err}"),
+		Err (detect::IdentifyError::NotRecognised) => "unknown".to_owned (),
+		Err (detect::IdentifyError::IoError (err)) => format! ("error: {err}"),
+	};
+	(desc, stats)
+}
+
+fn container_name (file_type: & Result <detect::FileType, detect::IdentifyError>) -> & 'static str {
+	match file_type {
+		Ok (detect::FileType::Aac) => "aac audio",
+		Ok (detect::FileType::Ac3) => "ac3 audio",
+		Ok (detect::FileType::AppleVideo) => "apple video",
+		Ok (detect::FileType::Asf) => "advanced systems format",
+		Ok (detect::FileType::Avi) => "audio video interleve",
+		Ok (detect::FileType::Flac) => "flac audio",
+		Ok (detect::FileType::Flv) => "flash video",
+		Ok (detect::FileType::IsoMedia) => "iso media",
+		Ok (detect::FileType::Matroska) => "matroska",
+		Ok (detect::FileType::Mp3) => "mp3 audio",
+		Ok (detect::FileType::Mpeg1) => "mpeg base media v1",
+		Ok (detect::FileType::Mpeg2) => "mpeg base media v2",
+		Ok (detect::FileType::MpegTs) => "mpeg transport stream",
+		Ok (detect::FileType::M2ts) => "bdav transport stream",
+		Ok (detect::FileType::Mp4v1) => "mpeg-4 v1",
+		Ok (detect::FileType::Mp4v2) => "mpeg-4 v2",
+		Ok (detect::FileType::Ogg) => "ogg",
+		Ok (detect::FileType::QuickTime) => "quicktime",
+		Err (detect::IdentifyError::NotRecognised) => "unknown",
+		Err (_) => "error",
+	}
+}
+
+/// Expand the list of files, scanning directories if requested
+fn collect_files (args: & Args) -> anyhow::Result <Vec <PathBuf>> {
+	let mut result = Vec::new ();
+	for file_path in & args.files {
+		if args.recursive && file_path.is_dir () {
+			scan_dir (args, file_path, file_path, & mut result) ?;
+		} else {
+			result.push (file_path.clone ());
+		}
+	}
+	Ok (result)
+}
+
+fn scan_dir (
+	args: & Args,
+	root: & Path,
+	dir_path: & Path,
+	result: & mut Vec <PathBuf>,
+) -> anyhow::Result <()> {
+	let mut entries: Vec <fs::DirEntry> =
+		fs::read_dir (dir_path)
+			.with_context (|| any_err! ("Error reading directory: {}", dir_path.display ())) ?
+			.try_collect () ?;
+	entries.sort_by_key (fs::DirEntry::file_name);
+	for entry in entries {
+		let entry_path = entry.path ();
+		let entry_type = entry.file_type () ?;
+		if entry_type.is_dir () {
+			scan_dir (args, root, & entry_path, result) ?;
+			continue;
+		}
+		if ! entry_path.is_file () { continue }
+		let rel_path = entry_path.strip_prefix (root).unwrap_or (& entry_path);
+		if ! args.include.is_empty ()
+				&& ! args.include.iter ().any (|pattern| pattern_matches (pattern, rel_path)) {
 			continue;
 		}
-		let mut file = BufReader::new (File::open (file_path) ?);
-		match detect::FileType::identify_reader (& mut file) {
-			Ok (detect::FileType::AppleVideo) => {
-				println! ("{file_display:<max_len$}  apple video");
-			},
-			Ok (detect::FileType::Avi) => {
-				println! ("{file_display:<max_len$}  audio video interleve");
-			},
-			Ok (detect::FileType::IsoMedia) => {
-				println! ("{file_display:<max_len$}  iso media");
-			},
-			Ok (detect::FileType::Matroska) => {
-				let info = matroska_info (& mut file)
-					.unwrap_or_else (|err| format! ("error: {err}"));
-				println! ("{file_display:<max_len$}  matroska {info}");
-			},
-			Ok (detect::FileType::Mpeg1) => {
-				println! ("{file_display:<max_len$}  mpeg base media v1");
-			},
-			Ok (detect::FileType::Mpeg2) => {
-				println! ("{file_display:<max_len$}  mpeg base media v2");
-			},
-			Ok (detect::FileType::Mp4v1) => {
-				println! ("{file_display:<max_len$}  mpeg-4 v1");
-			},
-			Ok (detect::FileType::Mp4v2) => {
-				println! ("{file_display:<max_len$}  mpeg-4 v2");
-			},
-			Err (detect::IdentifyError::PartiallyRecognised (err)) => {
-				println! ("{file_display:<max_len$}  error: {err}");
-			},
-			Err (detect::IdentifyError::NotRecognised) => {
-				println! ("{file_display:<max_len$}  unknown");
-			},
-			Err (detect::IdentifyError::IoError (err)) => {
-				println! ("{file_display:<max_len$}  error: {err}");
-			},
+		if args.exclude.iter ().any (|pattern| pattern_matches (pattern, rel_path)) {
+			continue;
 		}
+		result.push (entry_path);
 	}
-    Ok (())
+	Ok (())
 }
 
-fn fmt_size (size: u64) -> String {
+/// Patterns without a slash match the file name, otherwise they match the path relative to the
+/// directory being scanned
+fn pattern_matches (pattern: & str, rel_path: & Path) -> bool {
+	if pattern.contains ('/') {
+		glob::matches (pattern, & rel_path.to_string_lossy ())
+	} else {
+		let Some (file_name) = rel_path.file_name () else { return false };
+		glob::matches (pattern, & file_name.to_string_lossy ())
+	}
+}
+
+/// Statistics collected for each file, used for sorting and the summary totals
+#[ derive (Default, Serialize) ]
+struct Stats {
+	#[ serde (skip) ]
+	container: & 'static str,
+	size: Option <u64>,
+	duration: Option <f64>,
+	video_codec: Option <String>,
+	audio_codecs: Vec <String>,
+}
+
+fn compare_stats (sort: SortOrder, left: & Stats, right: & Stats) -> std::cmp::Ordering {
+	match sort {
+		SortOrder::Codec => left.video_codec.cmp (& right.video_codec),
+		SortOrder::Duration => right.duration.unwrap_or (0.0).total_cmp (& left.duration.unwrap_or (0.0)),
+		SortOrder::Size => right.size.cmp (& left.size),
+	}
+}
+
+#[ derive (Serialize) ]
+struct Summary {
+	files: u64,
+	size: u64,
+	duration: f64,
+	containers: BTreeMap <& 'static str, u64>,
+	video_codecs: BTreeMap <String, u64>,
+	audio_codecs: BTreeMap <String, u64>,
+}
+
+impl Summary {
+
+	fn new <'dat> (all_stats: impl IntoIterator <Item = & 'dat Stats>) -> Self {
+		let mut summary = Self {
+			files: 0,
+			size: 0,
+			duration: 0.0,
+			containers: BTreeMap::new (),
+			video_codecs: BTreeMap::new (),
+			audio_codecs: BTreeMap::new (),
+		};
+		for stats in all_stats {
+			if stats.container == "directory" { continue }
+			summary.files += 1;
+			summary.size += stats.size.unwrap_or (0);
+			summary.duration += stats.duration.unwrap_or (0.0);
+			* summary.containers.entry (stats.container).or_default () += 1;
+			if let Some (video_codec) = stats.video_codec.as_ref () {
+				* summary.video_codecs.entry (video_codec.clone ()).or_default () += 1;
+			}
+			for audio_codec in stats.audio_codecs.iter ().unique () {
+				* summary.audio_codecs.entry (audio_codec.clone ()).or_default () += 1;
+			}
+		}
+		summary
+	}
+
+	fn print (& self) {
+		println! ();
+		println! (
+			"total: {files} files, {size}, {duration}",
+			files = self.files,
+			size = fmt_size (self.size),
+			duration = fmt_duration (self.duration as u64));
+		let fmt_counts = |counts: & mut dyn Iterator <Item = (& str, & u64)>| {
+			counts
+				.sorted_by (|left, right| right.1.cmp (left.1).then (left.0.cmp (right.0)))
+				.map (|(name, count)| format! ("{name} {count}"))
+				.join (", ")
+		};
+		println! ("containers: {}", fmt_counts (& mut self.containers.iter ().map (|(name, count)| (* name, count))));
+		println! ("video: {}", fmt_counts (& mut self.video_codecs.iter ().map (|(name, count)| (name.as_str (), count))));
+		println! ("audio: {}", fmt_counts (& mut self.audio_codecs.iter ().map (|(name, count)| (name.as_str (), count))));
+	}
+
+}
+
+#[ derive (Default, Serialize) ]
+struct InfoFile {
+	path: String,
+	directory: bool,
+	file_type: Option <detect::FileType>,
+	#[ serde (flatten) ]
+	stats: Stats,
+	error: Option <String>,
+	segment_info: Option <Arc <matroska::InfoElem>>,
+	tracks: Option <Arc <matroska::TracksElem>>,
+	tags: Option <Arc <matroska::TagsElem>>,
+	chapters: Option <Arc <matroska::ChaptersElem>>,
+	mp4: Option <mp4::Mp4Info>,
+	avi: Option <avi::AviInfo>,
+}
+
+fn structured_info (file_path: & Path) -> InfoFile {
+	let mut result = InfoFile {
+		path: file_path.to_string_lossy ().into_owned (),
+		.. InfoFile::default ()
+	};
+	if file_path.is_dir () {
+		result.directory = true;
+		result.stats.container = "directory";
+		return result;
+	}
+	if let Err (err) = structured_info_real (file_path, & mut result) {
+		result.error = Some (format! ("{err:#}"));
+	}
+	result
+}
+
+fn structured_info_real (file_path: & Path, result: & mut InfoFile) -> anyhow::Result <()> {
+	let mut file = BufReader::new (File::open (file_path) ?);
+	result.stats.size = Some (file.get_ref ().metadata () ?.len ());
+	let file_type = detect::FileType::identify_reader (& mut file);
+	result.stats.container = container_name (& file_type);
+	let file_type = match file_type {
+		Ok (file_type) => file_type,
+		Err (detect::IdentifyError::NotRecognised) => return Ok (()),
+		Err (err) => return Err (err.into ()),
+	};
+	result.file_type = Some (file_type);
+	if let detect::FileType::Matroska = file_type {
+		let mut reader = matroska::Reader::new (file) ?;
+		let info = reader.segment_info () ?;
+		let tracks = reader.tracks () ?;
+		matroska_stats (& info, & tracks, & mut result.stats);
+		result.segment_info = Some (info);
+		result.tracks = Some (tracks);
+		if reader.has_seek (matroska::elems::TAGS) {
+			result.tags = Some (reader.tags () ?);
+		}
+		if reader.has_seek (matroska::elems::CHAPTERS) {
+			result.chapters = Some (reader.chapters () ?);
+		}
+	} else if let detect::FileType::Avi = file_type {
+		let info = avi::AviInfo::read (file) ?;
+		avi_stats (& info, & mut result.stats);
+		result.avi = Some (info);
+	} else if file_type.is_iso_media () {
+		let info = mp4::Mp4Info::read (file) ?;
+		mp4_stats (& info, & mut result.stats);
+		result.mp4 = Some (info);
+	}
+	Ok (())
+}
+
+fn matroska_stats (info: & matroska::InfoElem, tracks: & matroska::TracksElem, stats: & mut Stats) {
+	stats.duration = info.duration
+		.map (|duration| info.timestamp_scale as f64 * duration / 1_000_000_000.0);
+	stats.video_codec = tracks.entries.iter ()
+		.find (|track| track.track_type == matroska::TrackType::Video)
+		.map (|track| matroska_codec_name (& track.codec_id).to_owned ());
+	stats.audio_codecs = tracks.entries.iter ()
+		.filter (|track| track.track_type == matroska::TrackType::Audio)
+		.map (|track| matroska_codec_name (& track.codec_id).to_owned ())
+		.collect ();
+}
+
+fn mp4_stats (info: & mp4::Mp4Info, stats: & mut Stats) {
+	stats.duration = info.duration_secs ();
+	stats.video_codec = info.tracks_of_type (mp4::Mp4TrackType::Video).next ()
+		.map (mp4::Mp4Track::codec_name);
+	stats.audio_codecs = info.tracks_of_type (mp4::Mp4TrackType::Audio)
+		.map (mp4::Mp4Track::codec_name)
+		.collect ();
+}
+
+fn avi_stats (info: & avi::AviInfo, stats: & mut Stats) {
+	stats.duration = info.duration_secs ();
+	stats.video_codec = info.video_stream ().map (avi::AviStream::codec_name);
+	stats.audio_codecs = info.audio_streams ()
+		.map (avi::AviStream::codec_name)
+		.collect ();
+}
+
+fn fmt_duration (duration: u64) -> String {
+	format! (
+		"{hour}:{minute:02}:{second:02}",
+		hour = duration / 3600,
+		minute = duration / 60 % 60,
+		second = duration % 60)
+}
+
+pub (super) fn fmt_size (size: u64) -> String {
 	if size < 1024 {
 		format! ("{size}B")
 	} else if size < 1_048_576 {
@@ -79,7 +401,21 @@ fn fmt_size (size: u64) -> String {
 	}
 }
 
-fn matroska_info (mut file: impl BufRead + Seek) -> anyhow::Result <String> {
+/// Print rows in columns under a header, each line starting with `indent`
+pub (super) fn print_table <const COLS: usize> (indent: & str, header: [& str; COLS], rows: & [[String; COLS]]) {
+	let header = header.map (str::to_owned);
+	let widths: Vec <usize> = (0 .. COLS)
+		.map (|col| iter::once (& header).chain (rows).map (|row| row [col].chars ().count ()).max ().unwrap ())
+		.collect ();
+	for row in iter::once (& header).chain (rows) {
+		let line = row.iter ().zip (& widths)
+			.map (|(cell, & width)| format! ("{cell:<width$}"))
+			.join ("  ");
+		println! ("{indent}{}", line.trim_end ());
+	}
+}
+
+fn matroska_info (mut file: impl BufRead + Seek, stats: & mut Stats) -> anyhow::Result <String> {
 
 	let file_size = file.seek (SeekFrom::End (0)) ?;
 	let mut result = fmt_size (file_size);
@@ -87,19 +423,14 @@ fn matroska_info (mut file: impl BufRead + Seek) -> anyhow::Result <String> {
 	let mut reader = matroska::Reader::new (file) ?;
 	let info = reader.segment_info () ?;
 	let tracks = reader.tracks () ?;
+	matroska_stats (& info, & tracks, stats);
 
-	if let Some (duration_ticks) = info.duration {
-		let duration = (info.timestamp_scale as f64 * duration_ticks / 1_000_000_000.0) as u64;
-		let _ = write! (
-			& mut result,
-			", {hour}:{minute:02}:{second:02}",
-			hour = duration / 3600,
-			minute = duration / 60 % 60,
-			second = duration % 60);
+	if let Some (duration) = stats.duration {
+		let _ = write! (& mut result, ", {}", fmt_duration (duration as u64));
 	}
 
 	let Some (video_track) = tracks.entries.iter ()
-			.find (|track| track.track_type == 1)
+			.find (|track| track.track_type == matroska::TrackType::Video)
 		else { any_bail! ("No video track") };
 	let Some (video_track_video) = video_track.video.as_ref ()
 		else { any_bail! ("Video track details missing") };
@@ -107,28 +438,131 @@ fn matroska_info (mut file: impl BufRead + Seek) -> anyhow::Result <String> {
 	let _ = write! (
 		& mut result,
 		", {codec} {width}×{height}",
-		codec = matroska_codec_name (& video_track.codec_id),
+		codec = matroska_codec_desc (video_track),
 		width = video_track_video.pixel_width,
 		height = video_track_video.pixel_height);
 
+	if let Some (colour) = video_track_video.colour.as_ref ()
+			&& ! matches! (
+				colour.transfer_characteristics,
+				TransferCharacteristics::Unspecified | TransferCharacteristics::Bt709) {
+		let _ = write! (& mut result, " {}", colour.transfer_characteristics);
+	}
+
+	if let Ok (video_hdr) = hdr::HdrInfo::from_track (video_track)
+			&& let Some (badge) = video_hdr.badge () {
+		let _ = write! (& mut result, " [{badge}]");
+	}
+
 	for audio_track in tracks.entries.iter ()
-			.filter (|track| track.track_type == 2) {
+			.filter (|track| track.track_type == matroska::TrackType::Audio) {
 		result.push_str (& format! (
 			", {codec}",
-			codec = matroska_codec_name (& audio_track.codec_id)));
+			codec = matroska_codec_desc (audio_track)));
 	}
 
 	let num_subs = tracks.entries.iter ()
-		.filter (|track| track.track_type == 17)
+		.filter (|track| track.track_type == matroska::TrackType::Subtitle)
 		.count ();
+	push_num_subs (& mut result, num_subs);
+
+	Ok (result)
+
+}
+
+fn mp4_info (file: impl Read + Seek, stats: & mut Stats) -> anyhow::Result <String> {
+
+	let info = mp4::Mp4Info::read (file) ?;
+	mp4_stats (& info, stats);
+	let mut result = fmt_size (stats.size.unwrap_or (0));
+
+	if let Some (duration) = stats.duration {
+		let _ = write! (& mut result, ", {}", fmt_duration (duration as u64));
+	}
+
+	let Some (video_track) = info.tracks_of_type (mp4::Mp4TrackType::Video).next ()
+		else { any_bail! ("No video track") };
+
+	let _ = write! (
+		& mut result,
+		", {codec} {width}×{height}",
+		codec = mp4_codec_desc (video_track),
+		width = video_track.width,
+		height = video_track.height);
+
+	for audio_track in info.tracks_of_type (mp4::Mp4TrackType::Audio) {
+		result.push_str (& format! (
+			", {codec}",
+			codec = mp4_codec_desc (audio_track)));
+	}
+
+	let num_subs = info.tracks_of_type (mp4::Mp4TrackType::Subtitle).count ();
+	push_num_subs (& mut result, num_subs);
+
+	Ok (result)
+
+}
+
+fn avi_info (file: impl Read + Seek, stats: & mut Stats) -> anyhow::Result <String> {
+
+	let info = avi::AviInfo::read (file) ?;
+	avi_stats (& info, stats);
+	let mut result = fmt_size (stats.size.unwrap_or (0));
+
+	if let Some (duration) = stats.duration {
+		let _ = write! (& mut result, ", {}", fmt_duration (duration as u64));
+	}
+
+	let Some (video_stream) = info.video_stream () else { any_bail! ("No video track") };
+	let Some (video_format) = video_stream.video.as_ref () else { any_bail! ("Video format missing") };
+
+	let _ = write! (
+		& mut result,
+		", {codec} ({compression}) {width}×{height}",
+		codec = video_stream.codec_name (),
+		compression = video_format.compression.to_string ().trim_end (),
+		width = video_format.width,
+		height = video_format.height.unsigned_abs ());
+
+	if let Some (frame_rate) = info.frame_rate () {
+		let _ = write! (& mut result, " {frame_rate:.3}fps");
+	}
+
+	if info.packed_bitstream ().is_some () {
+		result.push_str (" [packed]");
+	}
+
+	for audio_stream in info.audio_streams () {
+		let _ = write! (& mut result, ", {codec}", codec = audio_stream.codec_name ());
+		if audio_stream.is_vbr_mp3 () {
+			result.push_str (" [vbr]");
+		}
+	}
+
+	Ok (result)
+
+}
+
+fn push_num_subs (result: & mut String, num_subs: usize) {
 	if 1 == num_subs {
 		result.push_str (", subs");
 	} else if 1 < num_subs {
 		result.push_str (& format! (", {num_subs} subs"));
 	}
+}
 
-	Ok (result)
+fn mp4_codec_desc (track: & mp4::Mp4Track) -> String {
+	match track.codec_config () {
+		Ok (Some (config)) => config.to_string (),
+		Ok (None) | Err (_) => track.codec_name (),
+	}
+}
 
+fn matroska_codec_desc (track: & matroska::tracks::TrackEntryElem) -> String {
+	match track.codec_config () {
+		Ok (Some (config)) => config.to_string (),
+		Ok (None) | Err (_) => matroska_codec_name (& track.codec_id).to_owned (),
+	}
 }
 
 fn matroska_codec_name (codec_id: & str) -> & str {
@@ -0,0 +1,249 @@
+use indexmap::IndexMap;
+
+use crate::detect;
+use crate::imports::*;
+use crate::language;
+use crate::media;
+
+use super::edit::EditDoc;
+use super::edit::EditDocs;
+use super::edit::EditTrack;
+use super::edit::TrackKey;
+
+#[ derive (Debug, clap::Args) ]
+#[ command (about = "Copy metadata between files, or save it to a file and load it back") ]
+pub struct Args {
+
+	#[ command (subcommand) ]
+	command: Command,
+
+}
+
+#[ derive (Debug, clap::Subcommand) ]
+enum Command {
+	Copy (CopyArgs),
+	Export (ExportArgs),
+	Import (ImportArgs),
+}
+
+#[ derive (Debug, clap::Args) ]
+#[ command (about = "Copy title, tags, chapters, attachments and track metadata from one file to another") ]
+struct CopyArgs {
+
+	#[ clap (name = "SRC", help = "File to copy metadata from") ]
+	src: PathBuf,
+
+	#[ clap (name = "DEST", help = "File to copy metadata to, a copy is written alongside it") ]
+	dest: PathBuf,
+
+	#[ clap (long, help = "Keep the attachments in the destination file") ]
+	no_attachments: bool,
+
+	#[ command (flatten) ]
+	transfer: TransferArgs,
+
+}
+
+#[ derive (Debug, clap::Args) ]
+#[ command (about = "Write metadata from a media file to a YAML or JSON file") ]
+struct ExportArgs {
+
+	#[ clap (name = "FILE", help = "Media file to read metadata from") ]
+	file: PathBuf,
+
+	#[ clap (long, short, value_name = "PATH", default_value = "-") ]
+	#[ clap (help = "File to write, JSON if it has that extension or YAML otherwise, default is stdout") ]
+	output: PathBuf,
+
+}
+
+#[ derive (Debug, clap::Args) ]
+#[ command (about = "Apply metadata from a YAML or JSON file, matching tracks against the media file") ]
+struct ImportArgs {
+
+	#[ clap (name = "FILE", help = "Media file to apply metadata to, a copy is written alongside it") ]
+	file: PathBuf,
+
+	#[ clap (name = "METADATA", help = "Metadata file written by export or edit") ]
+	metadata: PathBuf,
+
+	#[ command (flatten) ]
+	transfer: TransferArgs,
+
+}
+
+#[ derive (Debug, clap::Args) ]
+struct TransferArgs {
+
+	#[ clap (long = "match", value_enum, default_value_t, help = "How to match tracks between files") ]
+	match_mode: MatchMode,
+
+	#[ clap (long, help = "Keep the chapters in the destination file") ]
+	no_chapters: bool,
+
+	#[ clap (long, help = "Keep the tags in the destination file") ]
+	no_tags: bool,
+
+	#[ clap (long, help = "Show the matched tracks and resulting metadata without writing anything") ]
+	dry_run: bool,
+
+}
+
+#[ derive (Clone, Copy, Debug, Default, Eq, PartialEq, clap::ValueEnum) ]
+enum MatchMode {
+	/// Match tracks of the same type in the order they appear
+	#[ default ]
+	Order,
+	/// Match tracks of the same type and language, preferring the same codec
+	Language,
+}
+
+pub fn invoke (args: Args) -> anyhow::Result <()> {
+	match args.command {
+		Command::Copy (copy_args) => invoke_copy (copy_args),
+		Command::Export (export_args) => invoke_export (export_args),
+		Command::Import (import_args) => invoke_import (import_args),
+	}
+}
+
+fn invoke_copy (args: CopyArgs) -> anyhow::Result <()> {
+	let src_media = media::MediaInfo::probe (& args.src) ?;
+	let attachments_from =
+		(! args.no_attachments
+			&& matches! (src_media.file_type, Some (detect::FileType::Matroska))
+			&& ! src_media.attachments.is_empty ())
+		.then (|| args.src.clone ());
+	let src_doc = super::edit::export_doc (& args.src) ?;
+	transfer (& args.dest, src_doc, & args.transfer, attachments_from)
+}
+
+fn invoke_export (args: ExportArgs) -> anyhow::Result <()> {
+	let doc = super::edit::export_doc (& args.file) ?;
+	super::edit::write_docs (& args.output, & EditDocs::Single (doc))
+}
+
+fn invoke_import (args: ImportArgs) -> anyhow::Result <()> {
+	let metadata_display = args.metadata.display ();
+	let text = fs::read_to_string (& args.metadata)
+		.with_context (|| any_err! ("Error reading {metadata_display}")) ?;
+	let docs = super::edit::parse_docs (& text)
+		.with_context (|| any_err! ("Error in {metadata_display}")) ?
+		.ok_or_else (|| any_err! ("No metadata in {metadata_display}")) ?;
+	let src_doc = match docs {
+		EditDocs::Single (doc) => doc,
+		EditDocs::Multi (mut multi) => multi.files.shift_remove (& args.file)
+			.ok_or_else (|| any_err! ("No section for {} under files", args.file.display ())) ?,
+	};
+	transfer (& args.file, src_doc, & args.transfer, None)
+}
+
+/// Apply metadata from another file to `dest`, matching up the tracks first
+fn transfer (
+	dest: & Path,
+	src_doc: EditDoc,
+	args: & TransferArgs,
+	attachments_from: Option <PathBuf>,
+) -> anyhow::Result <()> {
+
+	let dest_doc = super::edit::export_doc (dest) ?;
+	let matches = match_tracks (& src_doc.tracks, & dest_doc.tracks, args.match_mode);
+	report_matches (& src_doc.tracks, & matches);
+
+	let tracks = matches.iter ()
+		.map (|& (dest_key, src_key)| {
+			let dest_track = & dest_doc.tracks [& dest_key];
+			let track = match src_key {
+				Some (src_key) => EditTrack {
+					codec: dest_track.codec.clone (),
+					.. src_doc.tracks [& src_key].clone ()
+				},
+				None => dest_track.clone (),
+			};
+			(dest_key, track)
+		})
+		.collect ();
+
+	// tags can only follow tracks which were matched, and chapters and attachments which are copied
+
+	let dest_key = |src_key| Ok (
+		matches.iter ()
+			.find (|& & (_, match_key)| match_key == Some (src_key))
+			.map (|& (dest_key, _)| dest_key));
+	let tags = src_doc.tags.filter (|_| ! args.no_tags).map (|src_tags| {
+		let mut tags = Vec::new ();
+		for tag in src_tags {
+			if args.no_chapters && (! tag.editions.is_empty () || ! tag.chapters.is_empty ()) { continue }
+			if attachments_from.is_none () && ! tag.attachments.is_empty () { continue }
+			tags.extend (tag.map_tracks (dest_key) ?);
+		}
+		anyhow::Ok (tags)
+	}).transpose () ?;
+	let chapters = src_doc.chapters.filter (|_| ! args.no_chapters)
+		.map (|src_chapters| src_chapters.into_iter ()
+			.map (|edition| edition.map_tracks (dest_key))
+			.collect::<anyhow::Result <Vec <_>>> ())
+		.transpose () ?;
+
+	let doc = EditDoc {
+		title: src_doc.title,
+		tags,
+		tracks,
+		chapters,
+	};
+
+	if args.dry_run {
+		super::edit::write_docs (Path::new ("-"), & EditDocs::Single (doc)) ?;
+		return Ok (());
+	}
+
+	let edit = super::edit::prepare_edit (dest.to_owned (), doc, "-meta.mkv", attachments_from) ?;
+	eprintln! ("Writing {}", edit.dest_file ().display ());
+	edit.perform ()
+
+}
+
+/// Find a source track for each destination track, in destination order
+fn match_tracks (
+	src_tracks: & IndexMap <TrackKey, EditTrack>,
+	dest_tracks: & IndexMap <TrackKey, EditTrack>,
+	match_mode: MatchMode,
+) -> Vec <(TrackKey, Option <TrackKey>)> {
+	let mut used = Vec::new ();
+	let mut matches = Vec::new ();
+	for (& dest_key, dest_track) in dest_tracks {
+		let src_key = match match_mode {
+			MatchMode::Order => src_tracks.contains_key (& dest_key).then_some (dest_key),
+			MatchMode::Language => {
+				let mut candidates = src_tracks.iter ()
+					.filter (|& (src_key, src_track)|
+						src_key.stream_type == dest_key.stream_type
+							&& ! used.contains (src_key)
+							&& language::same_language (& src_track.language, & dest_track.language));
+				let first = candidates.clone ().next ().map (|(& src_key, _)| src_key);
+				candidates
+					.find (|& (_, src_track)| src_track.codec == dest_track.codec)
+					.map (|(& src_key, _)| src_key)
+					.or (first)
+			},
+		};
+		used.extend (src_key);
+		matches.push ((dest_key, src_key));
+	}
+	matches
+}
+
+fn report_matches (
+	src_tracks: & IndexMap <TrackKey, EditTrack>,
+	matches: & [(TrackKey, Option <TrackKey>)],
+) {
+	for & (dest_key, src_key) in matches {
+		match src_key {
+			Some (src_key) => eprintln! ("Track {dest_key}: metadata from {src_key}"),
+			None => eprintln! ("Track {dest_key}: no match, keeping existing metadata"),
+		}
+	}
+	for src_key in src_tracks.keys () {
+		if matches.iter ().any (|& (_, match_key)| match_key == Some (* src_key)) { continue }
+		eprintln! ("Track {src_key} in source was not matched");
+	}
+}
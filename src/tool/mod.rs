@@ -1,11 +1,16 @@
 use clap::Parser as _;
 
+use crate::imports::*;
+
 mod add_subs;
+mod chapters;
 mod convert;
 mod dump;
 mod edit;
 mod info;
+mod meta;
 mod remaster;
+mod subs;
 
 #[ derive (clap::Parser) ]
 struct MainArgs {
@@ -16,21 +21,74 @@ struct MainArgs {
 #[ derive (clap::Subcommand) ]
 enum Command {
 	AddSubs (add_subs::Args),
+	Chapters (chapters::Args),
 	Convert (convert::Args),
 	Dump (dump::Args),
 	Edit (edit::Args),
 	Info (info::Args),
+	Meta (meta::Args),
 	Remaster (remaster::Args),
+	Subs (subs::Args),
 }
 
 pub fn main () -> anyhow::Result <()> {
 	let main_args = MainArgs::parse ();
 	match main_args.command {
 		Command::AddSubs (add_subs_args) => add_subs::invoke (add_subs_args),
+		Command::Chapters (chapters_args) => chapters::invoke (chapters_args),
 		Command::Convert (convert_args) => convert::invoke (convert_args),
 		Command::Dump (dump_args) => dump::invoke (dump_args),
 		Command::Edit (edit_args) => edit::invoke (edit_args),
 		Command::Info (info_args) => info::invoke (info_args),
+		Command::Meta (meta_args) => meta::invoke (meta_args),
 		Command::Remaster (remaster_args) => remaster::invoke (remaster_args),
+		Command::Subs (subs_args) => subs::invoke (subs_args),
+	}
+}
+
+/// Version of the schema used for machine readable output, increment on incompatible changes
+const OUTPUT_VERSION: u64 = 1;
+
+#[ derive (Clone, Copy, Debug, Default, Eq, PartialEq, clap::ValueEnum) ]
+enum OutputFormat {
+	#[ default ]
+	Text,
+	Json,
+	Yaml,
+}
+
+#[ derive (Serialize) ]
+struct OutputDoc <'dat, Item: Serialize, Summary: Serialize> {
+	schema: & 'static str,
+	version: u64,
+	files: & 'dat [Item],
+	#[ serde (skip_serializing_if = "Option::is_none") ]
+	summary: Option <Summary>,
+}
+
+fn write_output <Item: Serialize> (
+	format: OutputFormat,
+	schema: & 'static str,
+	files: & [Item],
+) -> anyhow::Result <()> {
+	write_output_summary (format, schema, files, None::<()>)
+}
+
+fn write_output_summary <Item: Serialize, Summary: Serialize> (
+	format: OutputFormat,
+	schema: & 'static str,
+	files: & [Item],
+	summary: Option <Summary>,
+) -> anyhow::Result <()> {
+	let doc = OutputDoc { schema, version: OUTPUT_VERSION, files, summary };
+	let mut stdout = io::stdout ().lock ();
+	match format {
+		OutputFormat::Text => unreachable! (),
+		OutputFormat::Json => {
+			serde_json::to_writer_pretty (& mut stdout, & doc) ?;
+			writeln! (stdout) ?;
+		},
+		OutputFormat::Yaml => serde_yaml::to_writer (& mut stdout, & doc) ?,
 	}
+	Ok (())
 }
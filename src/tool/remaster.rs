@@ -1,6 +1,10 @@
+use crate::codec::CodecConfig;
 use crate::ffmpeg;
 use crate::imports::*;
+use crate::language;
+use crate::language::LanguageTag;
 use crate::matroska;
+use crate::media;
 
 #[ derive (Debug, clap::Args) ]
 #[ command (about = "Reencode video as x265 (optionally) and audio as opus" )]
@@ -16,6 +20,9 @@ pub struct Args {
 		help = "Video quality setting (CRF) for x265 encoder (lower is better)") ]
 	video_quality: Option <i32>,
 
+	#[ clap (long, help = "Copy video instead of reencoding when it is already 10-bit HEVC") ]
+	video_keep_hevc: bool,
+
 	#[ clap (long, help = "Apply denoise filter") ]
 	video_denoise: Option <String>,
 
@@ -37,6 +44,13 @@ pub struct Args {
 	#[ clap (long, help = "Apply crop filter") ]
 	video_crop: Option <String>,
 
+	#[ clap (long, value_name = "LANG", default_values = [ "eng", "spa" ]) ]
+	#[ clap (help = "Keep audio in this language, as an ISO 639 code or BCP 47 tag, or \"all\", original audio is always kept") ]
+	audio_lang: Vec <String>,
+
+	#[ clap (flatten) ]
+	bitmap_subs: super::subs::BitmapSubsArgs,
+
 	#[ clap (long, help = "Show more detailed information" ) ]
 	verbose: bool,
 
@@ -76,6 +90,9 @@ impl fmt::Display for VideoPreset {
 }
 
 pub fn invoke (args: Args) -> anyhow::Result <()> {
+	for audio_lang in args.audio_lang.iter ().filter (|& audio_lang| audio_lang != "all") {
+		LanguageTag::parse (audio_lang).with_context (|| any_err! ("Invalid --audio-lang")) ?;
+	}
 	for file_path in & args.files {
 		if ! invoke_one (& args, file_path) ? { break }
 	}
@@ -96,11 +113,8 @@ fn invoke_one (args: & Args, file_path: & Path) -> anyhow::Result <bool> {
 
 	// read source file
 
-	let file = BufReader::new (File::open (file_path) ?);
-	let mut reader = matroska::Reader::new (file) ?;
-	let info = reader.segment_info () ?;
-	let duration_micros = info.duration.map (|duration| (info.timestamp_scale as f64 * duration / 1000.0) as u64);
-	let tracks = reader.tracks () ?;
+	let media = media::MediaInfo::probe (file_path) ?;
+	let duration_micros = media.duration_micros ();
 
 	// start building command
 
@@ -115,20 +129,32 @@ fn invoke_one (args: & Args, file_path: & Path) -> anyhow::Result <bool> {
 	let mut dest_name = file_path.file_stem ().unwrap ().to_owned ();
 	dest_name.push ("-remaster");
 
+	let mut track_updates = Vec::new ();
+
 	// do video
 
-	let video_tracks: Vec <_> =
-		tracks.entries.iter ()
-			.filter (|track| track.track_type == 1)
-			.collect ();
-	let Some (video_track) = video_tracks.get (0) else {
-		any_bail! ("No video tracks found");
-	};
-	let Some (ref video_track_video) = video_track.video else {
+	let Some (video_stream) = media.streams_of_type (media::StreamType::Video).next () else {
 		any_bail! ("No video tracks found");
 	};
 
-	if let Some (& video_quality) = args.video_quality.as_ref () {
+	let video_config = video_stream.codec_config.as_ref ();
+	if args.verbose && let Some (video_config) = video_config {
+		eprintln! ("Source video track 0 ({video_config})");
+	}
+	let video_hdr = video_stream.hdr.as_ref ();
+	if args.verbose && let Some (badge) = video_hdr.and_then (|video_hdr| video_hdr.badge ()) {
+		eprintln! ("Source video track 0 is {badge}");
+	}
+	let video_is_hevc_10bit = matches! (
+		video_config,
+		Some (CodecConfig::Hevc (config)) if 10 <= config.bit_depth_luma);
+	let video_quality = args.video_quality.filter (|_| {
+		if ! (args.video_keep_hevc && video_is_hevc_10bit) { return true }
+		if args.verbose { eprintln! ("Copy video track 0 as it is already 10-bit HEVC"); }
+		false
+	});
+
+	if let Some (video_quality) = video_quality {
 		let video_preset = & args.video_preset;
 		dest_name.push (format! ("-x265-{video_preset}-{video_quality}"));
 		command.push ("-map".into ());
@@ -141,6 +167,25 @@ fn invoke_one (args: & Args, file_path: & Path) -> anyhow::Result <bool> {
 		command.push (format! ("{video_quality}").into ());
 		command.push ("-pix_fmt:v:0".into ());
 		command.push ("yuv420p10le".into ());
+		if let Some (video_hdr) = video_hdr {
+			if let Some (dolby_vision) = video_hdr.dolby_vision.as_ref () {
+				if dolby_vision.needs_rpu () {
+					eprintln! ("Warning: Dolby Vision profile 5 has no compatible base layer, colours \
+						will be wrong after reencoding");
+				} else {
+					eprintln! ("Warning: Dolby Vision layers will be lost when reencoding");
+				}
+			}
+			if video_hdr.hdr10_plus {
+				eprintln! ("Warning: HDR10+ dynamic metadata will be lost when reencoding");
+			}
+			let x265_params = video_hdr.x265_params ();
+			if ! x265_params.is_empty () {
+				command.push ("-x265-params:v:0".into ());
+				command.push (x265_params.join (":").into ());
+			}
+			command.extend (video_hdr.ffmpeg_colour_args ("v:0"));
+		}
 		command.push ("-map_metadata:s:v:0".into ());
 		command.push ("0:s:v:0".into ());
 		if let Some (ref video_aspect) = args.video_aspect {
@@ -164,8 +209,9 @@ fn invoke_one (args: & Args, file_path: & Path) -> anyhow::Result <bool> {
 			};
 			// TODO don't always round down
 			// TODO verify if 4 is the right rounding here
-			let old_w = video_track_video.pixel_width;
-			let old_h = video_track_video.pixel_height;
+			let (Some (old_w), Some (old_h)) = (video_stream.width, video_stream.height) else {
+				any_bail! ("Video dimensions unknown, unable to rescale");
+			};
 			if target_w < old_w || target_h < old_h {
 				let mut new_w = target_w;
 				let mut new_h = target_w * old_h / old_w / 4 * 4;
@@ -209,35 +255,34 @@ fn invoke_one (args: & Args, file_path: & Path) -> anyhow::Result <bool> {
 		command.push ("0:s:v:0".into ());
 	}
 
+	track_updates.push (track_update (video_stream));
+
 	// do audio
 
-	let audio_tracks: Vec <_> =
-		tracks.entries.iter ()
-			.filter (|track| track.track_type == 2)
-			.collect ();
+	let audio_streams: Vec <_> = media.streams_of_type (media::StreamType::Audio).collect ();
 	let mut audio_mappings = Vec::new ();
 
-	for (src_idx, & track) in audio_tracks.iter ().enumerate () {
-		if ! matches! (track.language.as_str (), "eng" | "esp")
-				&& track.flag_original != Some (true) {
-			if args.verbose { eprintln! ("Skip audio track {src_idx} ({})", track_meta (track)); }
+	for (src_idx, & stream) in audio_streams.iter ().enumerate () {
+		let language = stream_language (stream).unwrap_or ("und");
+		let wanted = args.audio_lang.iter ()
+			.any (|wanted| wanted == "all" || language::same_language (language, wanted));
+		if ! wanted && ! stream.disposition.original {
+			if args.verbose { eprintln! ("Skip audio track {src_idx} ({})", stream.describe ()); }
 			continue;
 		}
-		if args.verbose { eprintln! ("Include audio track {src_idx} ({})", track_meta (track)); }
+		if args.verbose { eprintln! ("Include audio track {src_idx} ({})", stream.describe ()); }
 		audio_mappings.push (src_idx);
 	}
 
-	if audio_mappings.is_empty () && ! audio_tracks.is_empty () {
+	if audio_mappings.is_empty () && ! audio_streams.is_empty () {
 		if args.verbose { eprintln! ("Adding first audio track as none were selected"); }
 		audio_mappings.push (0);
 	}
 
 	for (dest_idx, & src_idx) in audio_mappings.iter ().enumerate () {
-		let track = & audio_tracks [src_idx];
-		let Some (track_audio) = track.audio.as_ref () else {
-			any_bail! ("Audio track {src_idx} has no audio settings");
-		};
-		if track.codec_id == "A_OPUS" {
+		let stream = & audio_streams [src_idx];
+		track_updates.push (track_update (stream));
+		if stream.codec == "opus" {
 			command.push ("-map".into ());
 			command.push (format! ("0:a:{src_idx}").into ());
 			command.push (format! ("-c:a:{dest_idx}").into ());
@@ -245,12 +290,15 @@ fn invoke_one (args: & Args, file_path: & Path) -> anyhow::Result <bool> {
 			command.push (format! ("-map_metadata:s:a:{dest_idx}").into ());
 			command.push (format! ("0:s:a:{src_idx}").into ());
 		} else {
+			let Some (channels) = stream.channels else {
+				any_bail! ("Audio track {src_idx} has no channel count");
+			};
 			command.push ("-map".into ());
 			command.push (format! ("0:a:{src_idx}").into ());
 			command.push (format! ("-c:a:{dest_idx}").into ());
 			command.push ("libopus".into ());
 			command.push (format! ("-b:a:{dest_idx}").into ());
-			command.push (match track_audio.channels {
+			command.push (match channels {
 				1 => "64k",
 				2 => "128k",
 				3 => "192k",
@@ -258,13 +306,13 @@ fn invoke_one (args: & Args, file_path: & Path) -> anyhow::Result <bool> {
 				5 => "256k",
 				6 => "256k",
 				8 => "320k",
-				_ => any_bail! ("Unable to map {} audio channels", track_audio.channels),
+				_ => any_bail! ("Unable to map {channels} audio channels"),
 			}.into ());
-			if track_audio.channels == 5 {
+			if channels == 5 {
 				command.push (format! ("-filter:a:{dest_idx}").into ());
 				command.push ("channelmap=channel_layout=5.0".into ());
 			}
-			if track_audio.channels == 6 {
+			if channels == 6 {
 				command.push (format! ("-filter:a:{dest_idx}").into ());
 				command.push ("channelmap=channel_layout=5.1".into ());
 			}
@@ -275,23 +323,23 @@ fn invoke_one (args: & Args, file_path: & Path) -> anyhow::Result <bool> {
 
 	// do subtitles
 
-	let subtitle_tracks: Vec <_> =
-		tracks.entries.iter ()
-			.filter (|track| track.track_type == 17)
-			.collect ();
 	let mut dest_idx = 0;
-	for (src_idx, & track) in subtitle_tracks.iter ().enumerate () {
-		if ! track.codec_id.starts_with ("S_TEXT/") {
-			if args.verbose { eprintln! ("Skip subtitle track {src_idx} ({})", track.codec_id); }
+	for (src_idx, stream) in media.streams_of_type (media::StreamType::Subtitle).enumerate () {
+		if stream.is_bitmap_subtitle () {
+			if ! args.bitmap_subs.keep (src_idx, stream) { continue }
+		} else if ! stream.is_text_subtitle () {
+			eprintln! ("Warning: Skipping subtitle track {src_idx} with unsupported codec: {}", stream.codec);
 			continue;
+		} else if args.verbose {
+			eprintln! ("Include subtitle track {src_idx} ({})", stream.describe ());
 		}
-		if args.verbose { eprintln! ("Include subtitle track {src_idx} ({})", track_meta (track)); }
 		command.push ("-map".into ());
 		command.push (format! ("0:s:{src_idx}").into ());
 		command.push (format! ("-c:s:{dest_idx}").into ());
-		command.push ("copy".into ());
+		command.push (stream.matroska_copy_codec ().into ());
 		command.push (format! ("-map_metadata:s:s:{dest_idx}").into ());
 		command.push (format! ("0:s:s:{src_idx}").into ());
+		track_updates.push (track_update (stream));
 		dest_idx += 1;
 	}
 
@@ -302,29 +350,37 @@ fn invoke_one (args: & Args, file_path: & Path) -> anyhow::Result <bool> {
 	if dest_path.try_exists () ? {
 		any_bail! ("File already exists: {}", dest_path.display ());
 	}
+	command.push ("-metadata_header_padding".into ());
+	command.push (matroska::patch::HEADER_PADDING.to_string ().into ());
 	command.push ({
 		let mut val = OsString::from ("file:");
-		val.push (dest_path);
+		val.push (& dest_path);
 		val
 	});
 
 	let file_display = file_name.to_string_lossy ();
 	ffmpeg::convert_progress (& file_display, duration_micros, command) ?;
+	if let Err (err) = matroska::patch::update_tracks (& dest_path, & track_updates) {
+		eprintln! ("Warning: {err:#}");
+	}
 
     Ok (true)
 
 }
 
-fn track_meta (track: & matroska::tracks::TrackEntryElem) -> String {
-	let mut result = String::new ();
-	result.push_str (& track.language);
-	if ! track.flag_enabled { result.push_str (", disabled"); }
-	if ! track.flag_default { result.push_str (", non-default"); }
-	if track.flag_forced { result.push_str (", forced"); }
-	if track.flag_hearing_impaired.unwrap_or (false) { result.push_str (", hearing impaired"); }
-	if track.flag_visual_impaired.unwrap_or (false) { result.push_str (", visual impaired"); }
-	if track.flag_text_descriptions.unwrap_or (false) { result.push_str (", text_descriptions"); }
-	if track.flag_original.unwrap_or (false) { result.push_str (", original"); }
-	if track.flag_commentary.unwrap_or (false) { result.push_str (", commentary"); }
-	result
+/// Track update for a copied stream, with the language corrected by `stream_language`
+fn track_update (stream: & media::MediaStream) -> matroska::patch::TrackUpdate {
+	matroska::patch::TrackUpdate {
+		language: stream_language (stream).map (str::to_owned),
+		.. matroska::patch::TrackUpdate::for_stream (stream)
+	}
+}
+
+/// Language of a source stream, correcting "esp" for spanish, which some older muxers write but
+/// isn't an ISO 639 code
+fn stream_language (stream: & media::MediaStream) -> Option <& str> {
+	match stream.language.as_deref () {
+		Some (language) if language.eq_ignore_ascii_case ("esp") => Some ("spa"),
+		language => language,
+	}
 }
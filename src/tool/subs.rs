@@ -0,0 +1,604 @@
+use crate::detect;
+use crate::ffmpeg;
+use crate::fonts;
+use crate::imports::*;
+use crate::language;
+use crate::matroska;
+use crate::media;
+use crate::subs;
+use crate::subs::encoding;
+use crate::subs::retime::Retime;
+
+#[ derive (Debug, clap::Args) ]
+#[ command (about = "Work with subtitle files and the text subtitle tracks in matroska files") ]
+pub struct Args {
+
+	#[ command (subcommand) ]
+	command: Command,
+
+}
+
+#[ derive (Debug, clap::Subcommand) ]
+enum Command {
+	Check (CheckCommandArgs),
+	Convert (ConvertCommandArgs),
+	Fonts (FontsCommandArgs),
+	Forced (ForcedCommandArgs),
+	Retime (RetimeCommandArgs),
+}
+
+#[ derive (Debug, clap::Args) ]
+#[ command (about = "Check subtitles for overlaps, negative durations, empty events, unclosed tags and the wrong language") ]
+struct CheckCommandArgs {
+
+	#[ clap (name = "FILE", required = true, help = "SRT, ASS or WebVTT files, or matroska files with text subtitles") ]
+	files: Vec <PathBuf>,
+
+}
+
+#[ derive (Debug, clap::Args) ]
+#[ command (about = "Convert subtitles between SRT, ASS and WebVTT, keeping formatting where possible") ]
+struct ConvertCommandArgs {
+
+	#[ clap (name = "FILE", help = "SRT, ASS or WebVTT file to convert") ]
+	file: PathBuf,
+
+	#[ clap (long, value_enum, help = "Format to convert to") ]
+	to: subs::Format,
+
+	#[ clap (long, short, value_name = "PATH", help = "File to write, default is to change the extension") ]
+	output: Option <PathBuf>,
+
+}
+
+#[ derive (Debug, clap::Args) ]
+#[ command (about = "List the fonts used by ASS subtitles, and whether they are attached or can be found") ]
+struct FontsCommandArgs {
+
+	#[ clap (name = "FILE", help = "ASS file, or a matroska file with ASS subtitle tracks") ]
+	file: PathBuf,
+
+	#[ command (flatten) ]
+	fonts: FontsArgs,
+
+}
+
+#[ derive (Debug, clap::Args) ]
+#[ command (about = "Find subtitle tracks which only cover foreign dialogue and should be flagged as forced") ]
+struct ForcedCommandArgs {
+
+	#[ clap (name = "FILE", required = true, help = "Matroska files to analyse") ]
+	files: Vec <PathBuf>,
+
+	#[ clap (long, help = "Set flag_forced on likely forced tracks, writing a copy alongside each file") ]
+	apply: bool,
+
+}
+
+#[ derive (Debug, clap::Args) ]
+#[ command (about = "Shift or stretch the timing of subtitles, writing a retimed copy alongside them") ]
+struct RetimeCommandArgs {
+
+	#[ clap (name = "FILE", help = "SRT, ASS or WebVTT file, or a matroska file with text subtitles") ]
+	file: PathBuf,
+
+	#[ clap (long, short, value_name = "PATH", help = "File to write, default is to add -retimed to the name") ]
+	output: Option <PathBuf>,
+
+	#[ clap (long, value_name = "INDEX") ]
+	#[ clap (help = "Subtitle track to retime in a matroska file, counting from zero, default is all text tracks") ]
+	track: Vec <usize>,
+
+	#[ command (flatten) ]
+	retime: RetimeArgs,
+
+}
+
+/// Where to find fonts used by ASS subtitles, shared with add-subs
+#[ derive (Debug, clap::Args) ]
+pub struct FontsArgs {
+
+	#[ clap (long, value_name = "DIR") ]
+	#[ clap (help = "Directory to look for fonts used by ASS subtitles in, default is $JP_MEDIA_TOOL_FONTS") ]
+	fonts_dir: Option <PathBuf>,
+
+}
+
+impl FontsArgs {
+
+	pub fn fonts_dir (& self) -> Option <PathBuf> {
+		self.fonts_dir.clone ()
+			.or_else (|| env::var_os ("JP_MEDIA_TOOL_FONTS").filter (|val| ! val.is_empty ()).map (PathBuf::from))
+	}
+
+}
+
+/// Which bitmap subtitle tracks to keep, shared with convert and remaster
+#[ derive (Debug, clap::Args) ]
+pub struct BitmapSubsArgs {
+
+	#[ clap (long, value_name = "LANG") ]
+	#[ clap (help = "Drop bitmap subtitles (VobSub and PGS) in this language, as an ISO 639 code or BCP 47 tag, \"und\" for none or \"all\"") ]
+	drop_bitmap_subs: Vec <String>,
+
+}
+
+impl BitmapSubsArgs {
+
+	/// Decide whether to copy a bitmap subtitle track, and report it either way
+	pub fn keep (& self, subs_idx: usize, stream: & media::MediaStream) -> bool {
+		let language = stream.language.as_deref ().unwrap_or ("und");
+		let drop = self.drop_bitmap_subs.iter ()
+			.any (|val| val == "all" || language::same_language (val, language));
+		eprintln! ("{} bitmap subtitle track {subs_idx} ({}, {})",
+			if drop { "Drop" } else { "Keep" }, stream.codec, stream.describe ());
+		! drop
+	}
+
+}
+
+/// Options to retime text subtitles, shared with add-subs
+#[ derive (Debug, clap::Args) ]
+#[ command (next_help_heading = "Retiming") ]
+pub struct RetimeArgs {
+
+	#[ clap (long, value_name = "TIME", allow_hyphen_values = true) ]
+	#[ clap (help = "Shift subtitles by seconds or [H:]MM:SS.mmm, negative to make them earlier") ]
+	offset: Option <String>,
+
+	#[ clap (long, value_name = "FROM:TO") ]
+	#[ clap (help = "Convert from one frame rate to another, eg 25:23.976 or 25:24000/1001") ]
+	fps: Option <String>,
+
+	#[ clap (long, value_name = "SUBS=VIDEO", conflicts_with_all = [ "offset", "fps" ]) ]
+	#[ clap (help = "Move a time in the subtitles to a time in the video, given twice to fit both points") ]
+	anchor: Vec <String>,
+
+}
+
+impl RetimeArgs {
+
+	/// Combined retime from the options, or `None` if none were given
+	pub fn to_retime (& self) -> anyhow::Result <Option <Retime>> {
+		if ! self.anchor.is_empty () {
+			any_ensure! (self.anchor.len () == 2, "Specify --anchor exactly twice");
+			let anchors: Vec <(subs::Time, subs::Time)> = self.anchor.iter ()
+				.map (|anchor| {
+					let (subs_time, video_time) = anchor.split_once ('=')
+						.ok_or_else (|| any_err! ("Anchor must be SUBS=VIDEO: {anchor}")) ?;
+					Ok ((parse_time (subs_time) ?, parse_time (video_time) ?))
+				})
+				.collect::<anyhow::Result <_>> () ?;
+			return Ok (Some (Retime::anchors (anchors [0], anchors [1]) ?));
+		}
+		let mut retime = None;
+		if let Some (fps) = self.fps.as_ref () {
+			let (from, to) = fps.split_once (':')
+				.ok_or_else (|| any_err! ("Frame rates must be FROM:TO: {fps}")) ?;
+			retime = Some (Retime::frame_rates (parse_frame_rate (from) ?, parse_frame_rate (to) ?) ?);
+		}
+		if let Some (offset) = self.offset.as_ref () {
+			let offset = Retime::offset (parse_time (offset) ?.0 as f64);
+			retime = Some (retime.map_or (offset, |retime: Retime| retime.then (offset)));
+		}
+		Ok (retime)
+	}
+
+}
+
+fn parse_time (val: & str) -> anyhow::Result <subs::Time> {
+	subs::retime::parse_time (val).ok_or_else (|| any_err! ("Invalid time: {val}"))
+}
+
+/// Parse a frame rate as a number or a ratio, eg "23.976" or "24000/1001"
+fn parse_frame_rate (val: & str) -> anyhow::Result <f64> {
+	let parse = |val: & str| val.trim ().parse::<f64> ().ok ().filter (|val| val.is_finite () && 0.0 < * val);
+	let frame_rate = match val.split_once ('/') {
+		Some ((num, den)) => parse (num).zip (parse (den)).map (|(num, den)| num / den),
+		None => parse (val),
+	};
+	frame_rate.ok_or_else (|| any_err! ("Invalid frame rate: {val}"))
+}
+
+pub fn invoke (args: Args) -> anyhow::Result <()> {
+	match args.command {
+		Command::Check (check_args) => invoke_check (check_args),
+		Command::Convert (convert_args) => invoke_convert (convert_args),
+		Command::Fonts (fonts_args) => invoke_fonts (fonts_args),
+		Command::Forced (forced_args) => invoke_forced (forced_args),
+		Command::Retime (retime_args) => invoke_retime (retime_args),
+	}
+}
+
+fn invoke_check (args: CheckCommandArgs) -> anyhow::Result <()> {
+	let mut num_checked = 0;
+	let mut num_failed = 0;
+	for file in & args.files {
+		let file_display = file.display ();
+
+		// check each text track in matroska files, against the language it is tagged with, or
+		// files against the language in their name
+
+		let mut items = Vec::new ();
+		if is_matroska (file) ? {
+			let media = media::MediaInfo::probe (file) ?;
+			for (subs_idx, (track_number, codec_id)) in subs::extract::subtitle_tracks (file) ?.iter ().enumerate () {
+				if subs::extract::format_for_codec (codec_id).is_none () { continue }
+				let subtitles = subs::extract::read_track (file, * track_number)
+					.with_context (|| any_err! ("Error reading subtitle track {subs_idx}: {file_display}")) ?;
+				let language = media.streams_of_type (media::StreamType::Subtitle).nth (subs_idx)
+					.and_then (|stream| stream.language.clone ())
+					.unwrap_or_else (|| "und".to_owned ());
+				items.push ((format! ("{file_display} subs-{subs_idx}"), subtitles, Some (language)));
+			}
+			any_ensure! (! items.is_empty (), "No text subtitle tracks in {file_display}");
+		} else {
+			items.push ((file_display.to_string (), read_file (file) ?, super::add_subs::language_from_name (file)));
+		}
+
+		for (name, subtitles, language) in items {
+			let problems = subtitles.validate ();
+			let guess = subs::langid::identify (& subs::langid::subtitles_text (& subtitles));
+			let wrong_language = language.as_deref ().zip (guess)
+				.is_some_and (|(language, guess)| guess.is_confident () && ! guess.matches (language));
+			let num_problems = problems.len () + usize::from (wrong_language);
+			num_checked += 1;
+			if num_problems == 0 {
+				println! ("{name}: {} events, no problems", subtitles.events.len ());
+			} else {
+				println! ("{name}: {} events, {num_problems} problems", subtitles.events.len ());
+				num_failed += 1;
+			}
+			for problem in & problems {
+				println! ("  {problem}");
+			}
+			match (language.as_deref (), guess) {
+				(_, None) => println! ("  Language: not enough text to tell"),
+				(Some (language), Some (guess)) if wrong_language =>
+					println! ("  Language: tagged {language}, but looks like {guess}"),
+				(Some (language), Some (guess)) if ! guess.matches (language) =>
+					println! ("  Language: tagged {language}, might be {guess}"),
+				(_, Some (guess)) => println! ("  Language: {guess}"),
+			}
+		}
+
+	}
+	any_ensure! (num_failed == 0, "Problems found in {num_failed} of {num_checked} subtitles");
+	Ok (())
+}
+
+fn invoke_convert (args: ConvertCommandArgs) -> anyhow::Result <()> {
+	let subtitles = read_file (& args.file) ?;
+	any_ensure! (subtitles.format != args.to,
+		"Subtitles are already {}: {}", args.to, args.file.display ());
+	report_problems (& args.file.display ().to_string (), & subtitles);
+	let dest_path = args.output.clone ().unwrap_or_else (|| args.file.with_extension (args.to.extension ()));
+	if dest_path.try_exists () ? {
+		any_bail! ("File already exists: {}", dest_path.display ());
+	}
+	eprintln! ("Writing {}", dest_path.display ());
+	fs::write (& dest_path, subtitles.convert (args.to).write ()) ?;
+	Ok (())
+}
+
+fn invoke_fonts (args: FontsCommandArgs) -> anyhow::Result <()> {
+	let library = match args.fonts.fonts_dir () {
+		Some (fonts_dir) => fonts::scan_dir (& fonts_dir) ?,
+		None => Vec::new (),
+	};
+	if ! is_matroska (& args.file) ? {
+		let subtitles = read_file (& args.file) ?;
+		any_ensure! (subtitles.format == subs::Format::Ass, "Not ASS subtitles: {}", args.file.display ());
+		print_fonts (& subs::ass::font_names (& subtitles), & [], & library);
+		return Ok (());
+	}
+	let attached = attached_fonts (& args.file) ?;
+	let mut num_tracks = 0;
+	for (subs_idx, (track_number, codec_id)) in subs::extract::subtitle_tracks (& args.file) ?.iter ().enumerate () {
+		if subs::extract::format_for_codec (codec_id) != Some (subs::Format::Ass) { continue }
+		let subtitles = subs::extract::read_track (& args.file, * track_number)
+			.with_context (|| any_err! ("Error reading subtitle track {subs_idx}")) ?;
+		println! ("Subtitle track {subs_idx}:");
+		print_fonts (& subs::ass::font_names (& subtitles), & attached, & library);
+		num_tracks += 1;
+	}
+	any_ensure! (num_tracks != 0, "No ASS subtitle tracks in {}", args.file.display ());
+	Ok (())
+}
+
+fn print_fonts (font_names: & [String], attached: & [(String, fonts::FontNames)], library: & [fonts::FontFile]) {
+	let width = font_names.iter ().map (|name| name.chars ().count ()).max ().unwrap_or_default ();
+	for name in font_names {
+		let status =
+			if let Some ((file_name, _)) = attached.iter ().find (|(_, names)| names.matches (name)) {
+				format! ("attached as {file_name}")
+			} else {
+				// show the face named exactly first, which is usually the regular one
+				let mut found: Vec <& fonts::FontFile> = library.iter ().filter (|font| font.names.matches (name)).collect ();
+				found.sort_by_key (|font| ! font.names.faces.iter ().any (|face| face.eq_ignore_ascii_case (name)));
+				match found.len () {
+					0 => "missing".to_owned (),
+					1 => format! ("found at {}", found [0].path.display ()),
+					num => format! ("found at {} and {} more", found [0].path.display (), num - 1),
+				}
+			};
+		println! ("  {name:<width$}  {status}");
+	}
+}
+
+/// File name and font names of each font attached to a matroska file
+pub (super) fn attached_fonts (file_path: & Path) -> anyhow::Result <Vec <(String, fonts::FontNames)>> {
+	let mut file = BufReader::new (File::open (file_path) ?);
+	let mut reader = matroska::Reader::new (& mut file) ?;
+	if ! reader.has_seek (matroska::elems::ATTACHMENTS) { return Ok (Vec::new ()) }
+	let attachments = reader.attachments () ?;
+	drop (reader);
+	let mut attached = Vec::new ();
+	for attachment in & attachments.files {
+		let by_name = attachment.name.rsplit_once ('.')
+			.is_some_and (|(_, ext)| matches! (ext.to_ascii_lowercase ().as_str (), "otc" | "otf" | "ttc" | "ttf"));
+		if ! fonts::is_font_media_type (& attachment.media_type) && ! by_name { continue }
+		file.seek (SeekFrom::Start (attachment.data.start)) ?;
+		let mut data = vec! [0; (attachment.data.end - attachment.data.start) as usize];
+		file.read_exact (& mut data) ?;
+		match fonts::read_names (io::Cursor::new (data)) {
+			Ok ((_, names)) => attached.push ((attachment.name.clone (), names)),
+			Err (err) => eprintln! ("Warning: Ignoring attached font {}: {err:#}", attachment.name),
+		}
+	}
+	Ok (attached)
+}
+
+/// Report the fonts used by existing ASS tracks in a matroska file, warning about any which aren't
+/// attached, and return the attached fonts
+pub (super) fn check_existing_fonts (file_path: & Path) -> anyhow::Result <Vec <(String, fonts::FontNames)>> {
+	let attached = attached_fonts (file_path) ?;
+	for (subs_idx, (track_number, codec_id)) in subs::extract::subtitle_tracks (file_path) ?.iter ().enumerate () {
+		if subs::extract::format_for_codec (codec_id) != Some (subs::Format::Ass) { continue }
+		let font_names = match subs::extract::read_track (file_path, * track_number) {
+			Ok (subtitles) => subs::ass::font_names (& subtitles),
+			Err (err) => {
+				eprintln! ("Warning: Unable to check fonts for subtitle track {subs_idx}: {err:#}");
+				continue;
+			},
+		};
+		eprintln! ("Fonts used by subtitle track {subs_idx}: {}", font_names.join (", "));
+		let missing: Vec <& String> = font_names.iter ()
+			.filter (|name| ! attached.iter ().any (|(_, names)| names.matches (name)))
+			.collect ();
+		if ! missing.is_empty () {
+			eprintln! ("Warning: Fonts used by subtitle track {subs_idx} are not attached: {}", missing.iter ().join (", "));
+		}
+	}
+	Ok (attached)
+}
+
+fn is_matroska (file_path: & Path) -> anyhow::Result <bool> {
+	match detect::FileType::identify_path (file_path) {
+		Ok (file_type) => Ok (matches! (file_type, detect::FileType::Matroska)),
+		Err (detect::IdentifyError::NotRecognised) => Ok (false),
+		Err (err) => Err (err).with_context (|| any_err! ("Error identifying {}", file_path.display ())),
+	}
+}
+
+fn invoke_forced (args: ForcedCommandArgs) -> anyhow::Result <()> {
+
+	// analyse every file first, and check the edits before writing anything
+
+	let mut edits = Vec::new ();
+	for file_path in & args.files {
+		any_ensure! (is_matroska (file_path) ?, "Not a matroska file: {}", file_path.display ());
+		let (stats, runtime) = subs::forced::read_stats (file_path)
+			.with_context (|| any_err! ("Error reading subtitle tracks: {}", file_path.display ())) ?;
+		let reasons = subs::forced::likely_forced (& stats, runtime);
+		println! ("{}:", file_path.display ());
+		print_forced (& stats, & reasons, runtime);
+		let to_flag: Vec <usize> = stats.iter ().zip (& reasons)
+			.filter (|(track, reason)| reason.is_some () && ! track.flag_forced)
+			.map (|(track, _)| track.subs_idx)
+			.collect ();
+		if to_flag.is_empty () { continue }
+		let keys = to_flag.iter ().map (|& subs_idx| format! ("subs-{subs_idx}")).join (", ");
+		if ! args.apply {
+			eprintln! ("Use --apply to set flag_forced on {keys}");
+			continue;
+		}
+		let mut doc = super::edit::export_doc (file_path) ?;
+		for & subs_idx in & to_flag {
+			let track_key = super::edit::TrackKey { stream_type: media::StreamType::Subtitle, index: subs_idx };
+			let track = doc.tracks.get_mut (& track_key)
+				.ok_or_else (|| any_err! ("No track {track_key}: {}", file_path.display ())) ?;
+			track.flag_forced = true;
+		}
+		edits.push (super::edit::prepare_edit (file_path.clone (), doc, "-forced.mkv", None) ?);
+	}
+
+	for edit in edits {
+		eprintln! ("Writing {}", edit.dest_file ().display ());
+		edit.perform () ?;
+	}
+
+	Ok (())
+
+}
+
+fn print_forced (
+	stats: & [subs::forced::TrackStats],
+	reasons: & [Option <subs::forced::ForcedReason>],
+	runtime: subs::Time,
+) {
+	let rows: Vec <[String; 7]> = stats.iter ().zip (reasons)
+		.map (|(track, reason)| [
+			format! ("subs-{}", track.subs_idx),
+			track.language.clone (),
+			track.codec_id.clone (),
+			track.events.to_string (),
+			if 0 < runtime.0 {
+				format! ("{:.1}%", track.coverage.0 as f64 * 100.0 / runtime.0 as f64)
+			} else { String::new () },
+			super::info::fmt_size (track.bytes),
+			match (track.flag_forced, reason) {
+				(true, _) => "flagged".to_owned (),
+				(false, Some (reason)) => format! ("likely, {reason}"),
+				(false, None) => String::new (),
+			},
+		])
+		.collect ();
+	super::info::print_table ("  ", [ "TRACK", "LANG", "CODEC", "EVENTS", "SHOWN", "SIZE", "FORCED" ], & rows);
+}
+
+fn invoke_retime (args: RetimeCommandArgs) -> anyhow::Result <()> {
+	let retime = args.retime.to_retime () ?
+		.ok_or_else (|| any_err! ("Specify --offset, --fps or --anchor")) ?;
+	eprintln! ("Retiming: {retime}");
+	if is_matroska (& args.file) ? {
+		retime_matroska (& args, retime)
+	} else {
+		any_ensure! (args.track.is_empty (), "Only matroska files have tracks to choose from");
+		retime_file (& args, retime)
+	}
+}
+
+/// Read a text subtitle file in any encoding
+fn read_file (file: & Path) -> anyhow::Result <subs::Subtitles> {
+	let file_display = file.display ();
+	let subs_type = detect::SidecarType::identify_path (file)
+		.with_context (|| any_err! ("Error identifying subtitles: {file_display}")) ?;
+	let format = subs::Format::from_sidecar (subs_type)
+		.ok_or_else (|| any_err! ("Unsupported subtitle format: {file_display} ({subs_type})")) ?;
+	let decoded = encoding::decode (& fs::read (file) ?, None)
+		.with_context (|| any_err! ("Error decoding subtitles: {file_display}")) ?;
+	subs::Subtitles::parse (format, & decoded.text)
+		.with_context (|| any_err! ("Error reading subtitles: {file_display}"))
+}
+
+fn retime_file (args: & RetimeCommandArgs, retime: Retime) -> anyhow::Result <()> {
+	let mut subtitles = read_file (& args.file) ?;
+	report_dropped (& args.file.display ().to_string (), subtitles.retime (retime));
+	let dest_path = dest_path (args, & format! ("-retimed.{}", subtitles.format.extension ())) ?;
+	eprintln! ("Writing {}", dest_path.display ());
+	fs::write (& dest_path, subtitles.write ()) ?;
+	Ok (())
+}
+
+fn retime_matroska (args: & RetimeCommandArgs, retime: Retime) -> anyhow::Result <()> {
+
+	// find text subtitle tracks, with their index among the subtitle tracks as used by ffmpeg
+
+	let subs_tracks = subs::extract::subtitle_tracks (& args.file) ?;
+	for & track_idx in & args.track {
+		let Some ((_, codec_id)) = subs_tracks.get (track_idx) else {
+			any_bail! ("No subtitle track {track_idx}");
+		};
+		any_ensure! (subs::extract::format_for_codec (codec_id).is_some (),
+			"Unable to retime {codec_id} subtitles in track {track_idx}");
+	}
+	let selected: Vec <(usize, u64, subs::Format)> = subs_tracks.iter ()
+		.enumerate ()
+		.filter (|& (subs_idx, _)| args.track.is_empty () || args.track.contains (& subs_idx))
+		.filter_map (|(subs_idx, & (track_number, ref codec_id))|
+			subs::extract::format_for_codec (codec_id).map (|format| (subs_idx, track_number, format)))
+		.collect ();
+	any_ensure! (! selected.is_empty (), "No text subtitle tracks in {}", args.file.display ());
+
+	// read and retime each track into a temporary file
+
+	let mut temps = Vec::new ();
+	for & (subs_idx, track_number, format) in & selected {
+		let mut subtitles = subs::extract::read_track (& args.file, track_number)
+			.with_context (|| any_err! ("Error reading subtitle track {subs_idx}")) ?;
+		report_dropped (& format! ("track {subs_idx}"), subtitles.retime (retime));
+		let mut temp = tempfile::Builder::new ()
+			.prefix ("jp-media-tool-subs-")
+			.suffix (& format! (".{}", format.extension ()))
+			.tempfile () ?;
+		temp.write_all (subtitles.write ().as_bytes ()) ?;
+		temp.flush () ?;
+		temps.push (temp);
+	}
+
+	// copy everything else, replacing the retimed tracks and keeping their metadata
+
+	let media = media::MediaInfo::probe (& args.file) ?;
+	let mut command: Vec <OsString> = Vec::new ();
+	for input in iter::once (args.file.as_path ()).chain (temps.iter ().map (|temp| temp.path ())) {
+		command.push ("-i".into ());
+		command.push ({
+			let mut val = OsString::from ("file:");
+			val.push (input);
+			val
+		});
+	}
+	// map in source order, so only the retimed tracks need their metadata set, everything else
+	// has it copied by ffmpeg
+	let mut out_idx = 0;
+	let mut track_updates = Vec::new ();
+	for stream in & media.streams {
+		if ! matches! (stream.stream_type,
+			media::StreamType::Video | media::StreamType::Audio | media::StreamType::Subtitle) { continue }
+		let temp_idx = (stream.stream_type == media::StreamType::Subtitle).then (||
+			selected.iter ().position (|& (selected_idx, _, _)| selected_idx == stream.type_index)).flatten ();
+		command.push ("-map".into ());
+		match temp_idx {
+			Some (temp_idx) => {
+				command.push (format! ("{}:s:0", temp_idx + 1).into ());
+				super::convert::push_stream_meta (
+					& mut command, & mut track_updates, & out_idx.to_string (), stream);
+			},
+			None => {
+				command.push (format! ("0:{}", stream.index).into ());
+				track_updates.push (matroska::patch::TrackUpdate::for_stream (stream));
+			},
+		}
+		out_idx += 1;
+	}
+	command.push ("-map".into ());
+	command.push ("0:t?".into ());
+	command.push ("-codec".into ());
+	command.push ("copy".into ());
+	command.push ("-metadata_header_padding".into ());
+	command.push (matroska::patch::HEADER_PADDING.to_string ().into ());
+	command.push ("-f".into ());
+	command.push ("matroska".into ());
+	let dest_path = dest_path (args, "-retimed.mkv") ?;
+	eprintln! ("Writing {}", dest_path.display ());
+	command.push ({
+		let mut val = OsString::from ("file:");
+		val.push (& dest_path);
+		val
+	});
+	let file_display = args.file.to_string_lossy ();
+	ffmpeg::convert_progress (& file_display, media.duration_micros (), command) ?;
+	drop (temps);
+	if let Err (err) = matroska::patch::update_tracks (& dest_path, & track_updates) {
+		eprintln! ("Warning: {err:#}");
+	}
+
+	Ok (())
+
+}
+
+fn dest_path (args: & RetimeCommandArgs, suffix: & str) -> anyhow::Result <PathBuf> {
+	let dest_path = args.output.clone ().unwrap_or_else (|| {
+		let mut dest_name = args.file.file_stem ().unwrap_or_default ().to_owned ();
+		dest_name.push (suffix);
+		args.file.with_file_name (dest_name)
+	});
+	if dest_path.try_exists () ? {
+		any_bail! ("File already exists: {}", dest_path.display ());
+	}
+	Ok (dest_path)
+}
+
+/// Print a warning for each problem found in subtitles, which don't stop them being used
+pub (super) fn report_problems (name: & str, subtitles: & subs::Subtitles) {
+	for problem in subtitles.validate () {
+		eprintln! ("Warning: {name}: {problem}");
+	}
+}
+
+pub (super) fn report_dropped (name: & str, num_dropped: usize) {
+	if 0 < num_dropped {
+		eprintln! ("Dropped {num_dropped} events from {name} which now end before the start");
+	}
+}